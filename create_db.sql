@@ -3,19 +3,8 @@ USE archie;
 
 SET TIME_ZONE='+00:00';
 
-CREATE TABLE guestbook
-(
-    id              INT NOT NULL AUTO_INCREMENT,
-    dateSubmitted   DATETIME,
-    guestName       VARCHAR(50)  NOT NULL,
-    guestNote       VARCHAR(1000),
-    PRIMARY KEY     (id) 
-);
-
-CREATE TABLE hitLog
-(
-    id              INT NOT NULL AUTO_INCREMENT,
-    hitTime         TIMESTAMP,
-    userAgent       VARCHAR(150),
-    PRIMARY KEY     (id) 
-);
\ No newline at end of file
+-- The tables are created by the server itself, the first time it starts,
+-- from the migrations in custom-backend/migrations/mysql/.
+-- To set them up without starting the server, run:
+--
+--     DB_URL=mysql://... archie-server migrate up
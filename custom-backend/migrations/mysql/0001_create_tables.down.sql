@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS hitLog;
+DROP TABLE IF EXISTS guestbook;
@@ -0,0 +1,18 @@
+-- The original schema, from create_db.sql. IF NOT EXISTS lets this
+-- double as the baseline for databases set up before migrations existed.
+CREATE TABLE IF NOT EXISTS guestbook
+(
+    id              INT NOT NULL AUTO_INCREMENT,
+    dateSubmitted   DATETIME,
+    guestName       VARCHAR(50)  NOT NULL,
+    guestNote       VARCHAR(1000),
+    PRIMARY KEY     (id) 
+);
+
+CREATE TABLE IF NOT EXISTS hitLog
+(
+    id              INT NOT NULL AUTO_INCREMENT,
+    hitTime         TIMESTAMP,
+    userAgent       VARCHAR(150),
+    PRIMARY KEY     (id) 
+);
@@ -0,0 +1 @@
+ALTER TABLE guestbook MODIFY guestName VARCHAR(50) NOT NULL;
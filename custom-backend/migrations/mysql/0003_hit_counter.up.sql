@@ -0,0 +1,11 @@
+-- A running total of hitLog, kept in a single row (id = 1), so the count
+-- doesn't need a COUNT(*) over the whole log. Each server replica adds its
+-- own hits to it as it flushes them, so it covers every replica.
+CREATE TABLE hitCounter
+(
+    id              INT NOT NULL,
+    total           BIGINT NOT NULL,
+    PRIMARY KEY     (id)
+);
+
+INSERT INTO hitCounter (id, total) SELECT 1, COUNT(*) FROM hitLog;
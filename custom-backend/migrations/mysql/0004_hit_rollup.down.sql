@@ -0,0 +1,2 @@
+DROP INDEX hitLog_hitTime ON hitLog;
+DROP TABLE hitDaily;
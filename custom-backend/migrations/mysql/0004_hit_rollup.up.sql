@@ -0,0 +1,21 @@
+-- Hits per day and user agent, added to as hits are flushed, so /hits/stats
+-- doesn't have to go through the whole of hitLog. userAgent is part of the
+-- key, so hits logged without one are kept under ''.
+CREATE TABLE hitDaily
+(
+    day             DATE NOT NULL,
+    userAgent       VARCHAR(150) NOT NULL,
+    hits            BIGINT NOT NULL,
+    firstHit        DATETIME NOT NULL,
+    lastHit         DATETIME NOT NULL,
+    PRIMARY KEY     (day, userAgent)
+);
+
+INSERT INTO hitDaily (day, userAgent, hits, firstHit, lastHit)
+    SELECT DATE(hitTime), COALESCE(userAgent, ''), COUNT(*), MIN(hitTime), MAX(hitTime)
+    FROM hitLog
+    WHERE hitTime IS NOT NULL
+    GROUP BY DATE(hitTime), COALESCE(userAgent, '');
+
+-- hourly stats are still counted from hitLog, over a limited range
+CREATE INDEX hitLog_hitTime ON hitLog (hitTime);
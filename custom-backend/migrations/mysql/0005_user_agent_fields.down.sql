@@ -0,0 +1,9 @@
+ALTER TABLE hitCounter
+    DROP COLUMN bots;
+
+ALTER TABLE hitLog
+    DROP COLUMN browser,
+    DROP COLUMN browserVersion,
+    DROP COLUMN os,
+    DROP COLUMN device,
+    DROP COLUMN isBot;
@@ -0,0 +1,12 @@
+-- What the server makes of each hit's user agent (see utils/user_agent.rs).
+-- Hits logged before this are left with NULLs, and aren't counted as bots.
+ALTER TABLE hitLog
+    ADD COLUMN browser          VARCHAR(50),
+    ADD COLUMN browserVersion   VARCHAR(30),
+    ADD COLUMN os               VARCHAR(50),
+    ADD COLUMN device           VARCHAR(10),
+    ADD COLUMN isBot            BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- how many of the total were bots, so GET /hits can leave them out
+ALTER TABLE hitCounter
+    ADD COLUMN bots             BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,16 @@
+-- The daily salts for the visitor hashes (see storage/visitors.rs). Only
+-- today's and tomorrow's are ever kept; older ones are deleted as they expire.
+CREATE TABLE visitorSalt
+(
+    day             DATE NOT NULL PRIMARY KEY,
+    salt            VARCHAR(32) NOT NULL
+);
+
+-- One row per visitor per day. The hashes can't be traced back to 
+-- anyone once the day's salt is gone, so these are kept.
+CREATE TABLE visitorDaily
+(
+    day             DATE NOT NULL,
+    visitorHash     VARCHAR(64) NOT NULL,
+    PRIMARY KEY     (day, visitorHash)
+);
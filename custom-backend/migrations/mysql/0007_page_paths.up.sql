@@ -0,0 +1,6 @@
+-- Which page each hit was for, and where the visitor came from, now that
+-- the server logs the page views itself (see srv_io/page_views.rs). Hits
+-- POSTed to /hits don't say, so those, and the older ones, are left NULL.
+ALTER TABLE hitLog
+    ADD COLUMN path             VARCHAR(200),
+    ADD COLUMN referrer         VARCHAR(200);
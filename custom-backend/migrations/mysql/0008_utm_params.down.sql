@@ -0,0 +1,4 @@
+ALTER TABLE hitLog
+    DROP COLUMN utmSource,
+    DROP COLUMN utmMedium,
+    DROP COLUMN utmCampaign;
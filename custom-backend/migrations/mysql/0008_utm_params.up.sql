@@ -0,0 +1,7 @@
+-- The utm_* parameters from the query string of each page view, so visits
+-- from a campaign can be told apart. hitLog.referrer, from 0007, only gets
+-- the referring host from here on, not the whole URL.
+ALTER TABLE hitLog
+    ADD COLUMN utmSource        VARCHAR(100),
+    ADD COLUMN utmMedium        VARCHAR(100),
+    ADD COLUMN utmCampaign      VARCHAR(100);
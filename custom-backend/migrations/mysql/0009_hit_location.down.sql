@@ -0,0 +1,3 @@
+ALTER TABLE hitLog
+    DROP COLUMN country,
+    DROP COLUMN region;
@@ -0,0 +1,6 @@
+-- Where each hit came from, as ISO codes looked up from the client's IP
+-- (see src/utils/geoip.rs). Left NULL when GeoIP is off, or the IP
+-- isn't in the database. The IP itself still isn't kept anywhere.
+ALTER TABLE hitLog
+    ADD COLUMN country          VARCHAR(2),
+    ADD COLUMN region           VARCHAR(3);
@@ -0,0 +1 @@
+ALTER TABLE guestbook DROP INDEX guestbook_search;
@@ -0,0 +1,13 @@
+-- For GET /guestbook/search (see storage/entry_search.rs). The ngram parser
+-- splits text into runs of characters rather than words, so it works for
+-- scripts that don't put spaces between words, like Chinese.
+--
+-- The stopword list is made of English words, and with ngrams, any token
+-- with one of them in it is left out of the index ("in" would take "Linus"
+-- with it), so it's turned off while the index is built. The setting at
+-- that point is the one the index keeps.
+SET SESSION innodb_ft_enable_stopword = OFF;
+
+ALTER TABLE guestbook ADD FULLTEXT INDEX guestbook_search (guestName, guestNote) WITH PARSER ngram;
+
+SET SESSION innodb_ft_enable_stopword = ON;
@@ -0,0 +1,3 @@
+ALTER TABLE guestbook
+    DROP INDEX guestbook_status,
+    DROP COLUMN status;
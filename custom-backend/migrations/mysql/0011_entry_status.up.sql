@@ -0,0 +1,6 @@
+-- Whether each entry is shown (see GUESTBOOK_MODERATION in db_io.rs): 
+-- 'pending', 'approved', or 'rejected'. Everything from before this
+-- went live as soon as it was sent, so it's all approved.
+ALTER TABLE guestbook
+    ADD COLUMN status           VARCHAR(8) NOT NULL DEFAULT 'approved',
+    ADD INDEX guestbook_status (status, dateSubmitted, id);
@@ -0,0 +1,14 @@
+-- The audit trail for the admin area (see srv_io/admin_auth.rs): every try
+-- at logging in, and everything done once logged in. Unlike the hit log,
+-- these keep the IP, since there are only ever a few of them, and who it
+-- was is the whole point. entryId is for the ones about a guestbook entry.
+CREATE TABLE adminAudit
+(
+    id              INT NOT NULL AUTO_INCREMENT,
+    eventTime       DATETIME NOT NULL,
+    action          VARCHAR(20) NOT NULL,
+    clientIp        VARCHAR(45) NOT NULL,
+    entryId         INT,
+    detail          VARCHAR(200),
+    PRIMARY KEY     (id)
+);
@@ -0,0 +1,19 @@
+-- For the admin's edits to the guestbook (see srv_io/admin_io.rs). Deleting
+-- an entry only sets deletedAt, so it can be restored, and every entry that
+-- has it set is left out everywhere but the admin's list of them. Pinned
+-- entries go first in the whole guestbook. guestbookEdit keeps what each
+-- entry said before every edit, oldest first by id.
+ALTER TABLE guestbook
+    ADD COLUMN deletedAt        DATETIME,
+    ADD COLUMN pinned           BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE guestbookEdit
+(
+    id              INT NOT NULL AUTO_INCREMENT,
+    entryId         INT NOT NULL,
+    editTime        DATETIME NOT NULL,
+    oldName         VARCHAR(100) NOT NULL,
+    oldNote         VARCHAR(1000),
+    PRIMARY KEY     (id),
+    INDEX guestbookEdit_entry (entryId, id)
+);
@@ -0,0 +1,7 @@
+-- What the spam filter made of each entry (see utils/spam_filter.rs):
+-- accept, hold, or drop, and why, if it wasn't accepted. Entries from
+-- before the filter was there have neither. Dropped entries are kept,
+-- rejected, so the admin can see what the filter's been turning away.
+ALTER TABLE guestbook
+    ADD COLUMN spamVerdict      VARCHAR(10),
+    ADD COLUMN spamReason       VARCHAR(200);
@@ -0,0 +1,6 @@
+-- The SHA-256 (in hex) of the token each entry's author was given, so they
+-- can fix or take back their entry for a little while after posting it (see
+-- srv_io/author_edits.rs). The token itself is never kept. Entries from
+-- before this, or from while it was turned off, don't have one.
+ALTER TABLE guestbook
+    ADD COLUMN editTokenHash    CHAR(64);
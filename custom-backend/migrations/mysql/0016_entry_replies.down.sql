@@ -0,0 +1,3 @@
+ALTER TABLE guestbook
+    DROP INDEX guestbook_parent,
+    DROP COLUMN parentId;
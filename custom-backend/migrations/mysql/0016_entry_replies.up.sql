@@ -0,0 +1,7 @@
+-- Replies to other entries (see srv_io/replies.rs) have the ID of the one
+-- they reply to, which can be a reply itself; everything from before this
+-- is a top-level entry. Like guestbookEdit.entryId, it isn't a foreign key,
+-- since entries are only ever soft deleted.
+ALTER TABLE guestbook
+    ADD COLUMN parentId         INT,
+    ADD INDEX guestbook_parent (parentId);
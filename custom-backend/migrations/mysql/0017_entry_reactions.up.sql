@@ -0,0 +1,14 @@
+-- Emoji reactions to guestbook entries (see srv_io/reactions.rs), one row
+-- per emoji per visitor per entry, so leaving the same one twice is a no-op.
+-- visitorHash isn't the one in visitorDaily; it's hashed again with the
+-- entry's ID, so it can't be matched up with that, or across entries.
+-- The emoji are compared byte for byte, since the default collation
+-- would take some of them (and their variation selectors) as the same.
+CREATE TABLE guestbookReaction
+(
+    entryId         INT NOT NULL,
+    emoji           VARCHAR(16) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin NOT NULL,
+    visitorHash     VARCHAR(64) NOT NULL,
+    reactedAt       DATETIME NOT NULL,
+    PRIMARY KEY     (entryId, emoji, visitorHash)
+);
@@ -0,0 +1,17 @@
+-- Postgres folds unquoted names to lowercase, so hitLog and the
+-- camelCase columns are all stored lowercase (hitlog, datesubmitted, ...).
+-- Everything is left unquoted so the queries can use the same names as MySQL.
+CREATE TABLE IF NOT EXISTS guestbook
+(
+    id              SERIAL PRIMARY KEY,
+    dateSubmitted   TIMESTAMP,
+    guestName       VARCHAR(50)  NOT NULL,
+    guestNote       VARCHAR(1000)
+);
+
+CREATE TABLE IF NOT EXISTS hitLog
+(
+    id              SERIAL PRIMARY KEY,
+    hitTime         TIMESTAMP,
+    userAgent       VARCHAR(150)
+);
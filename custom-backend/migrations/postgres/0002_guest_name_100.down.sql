@@ -0,0 +1 @@
+ALTER TABLE guestbook ALTER COLUMN guestName TYPE VARCHAR(50);
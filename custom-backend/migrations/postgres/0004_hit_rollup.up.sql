@@ -0,0 +1,18 @@
+-- see the MySQL version of this migration
+CREATE TABLE hitDaily
+(
+    day             DATE NOT NULL,
+    userAgent       VARCHAR(150) NOT NULL,
+    hits            BIGINT NOT NULL,
+    firstHit        TIMESTAMP NOT NULL,
+    lastHit         TIMESTAMP NOT NULL,
+    PRIMARY KEY     (day, userAgent)
+);
+
+INSERT INTO hitDaily (day, userAgent, hits, firstHit, lastHit)
+    SELECT CAST(hitTime AS DATE), COALESCE(userAgent, ''), COUNT(*), MIN(hitTime), MAX(hitTime)
+    FROM hitLog
+    WHERE hitTime IS NOT NULL
+    GROUP BY CAST(hitTime AS DATE), COALESCE(userAgent, '');
+
+CREATE INDEX hitLog_hitTime ON hitLog (hitTime);
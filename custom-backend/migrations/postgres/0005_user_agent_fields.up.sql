@@ -0,0 +1,10 @@
+-- see the MySQL version of this migration
+ALTER TABLE hitLog
+    ADD COLUMN browser          VARCHAR(50),
+    ADD COLUMN browserVersion   VARCHAR(30),
+    ADD COLUMN os               VARCHAR(50),
+    ADD COLUMN device           VARCHAR(10),
+    ADD COLUMN isBot            BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE hitCounter
+    ADD COLUMN bots             BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+DROP TABLE visitorDaily;
+DROP TABLE visitorSalt;
@@ -0,0 +1,4 @@
+-- see the MySQL version of this migration
+ALTER TABLE hitLog
+    ADD COLUMN path             VARCHAR(200),
+    ADD COLUMN referrer         VARCHAR(200);
@@ -0,0 +1,5 @@
+-- see the MySQL version of this migration
+ALTER TABLE hitLog
+    ADD COLUMN utmSource        VARCHAR(100),
+    ADD COLUMN utmMedium        VARCHAR(100),
+    ADD COLUMN utmCampaign      VARCHAR(100);
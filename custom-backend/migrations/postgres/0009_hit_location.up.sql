@@ -0,0 +1,4 @@
+-- see the MySQL version of this migration
+ALTER TABLE hitLog
+    ADD COLUMN country          VARCHAR(2),
+    ADD COLUMN region           VARCHAR(3);
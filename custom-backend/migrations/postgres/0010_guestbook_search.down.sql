@@ -0,0 +1,2 @@
+-- pg_trgm is left in, in case anything else has come to use it
+DROP INDEX guestbook_search;
@@ -0,0 +1,8 @@
+-- See the MySQL version of this migration. Postgres's own full-text search
+-- needs words to be split up by spaces, so this uses trigrams instead,
+-- which the ILIKE's in the search query can use. pg_trgm ships with
+-- Postgres, and is trusted, so the owner of the database can add it.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX guestbook_search ON guestbook
+    USING GIN ((guestName || ' ' || COALESCE(guestNote, '')) gin_trgm_ops);
@@ -0,0 +1,2 @@
+DROP INDEX guestbook_status;
+ALTER TABLE guestbook DROP COLUMN status;
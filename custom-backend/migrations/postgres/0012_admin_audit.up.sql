@@ -0,0 +1,10 @@
+-- see the MySQL version of this migration
+CREATE TABLE adminAudit
+(
+    id              SERIAL PRIMARY KEY,
+    eventTime       TIMESTAMP NOT NULL,
+    action          VARCHAR(20) NOT NULL,
+    clientIp        VARCHAR(45) NOT NULL,
+    entryId         INT,
+    detail          VARCHAR(200)
+);
@@ -0,0 +1,4 @@
+DROP TABLE guestbookEdit;
+ALTER TABLE guestbook
+    DROP COLUMN deletedAt,
+    DROP COLUMN pinned;
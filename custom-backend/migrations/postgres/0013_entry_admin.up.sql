@@ -0,0 +1,14 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook
+    ADD COLUMN deletedAt        TIMESTAMP,
+    ADD COLUMN pinned           BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE guestbookEdit
+(
+    id              SERIAL PRIMARY KEY,
+    entryId         INT NOT NULL,
+    editTime        TIMESTAMP NOT NULL,
+    oldName         VARCHAR(100) NOT NULL,
+    oldNote         VARCHAR(1000)
+);
+CREATE INDEX guestbookEdit_entry ON guestbookEdit (entryId, id);
@@ -0,0 +1,3 @@
+ALTER TABLE guestbook
+    DROP COLUMN spamVerdict,
+    DROP COLUMN spamReason;
@@ -0,0 +1,4 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook
+    ADD COLUMN spamVerdict      VARCHAR(10),
+    ADD COLUMN spamReason       VARCHAR(200);
@@ -0,0 +1,3 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook
+    ADD COLUMN editTokenHash    CHAR(64);
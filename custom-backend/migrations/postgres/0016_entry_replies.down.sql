@@ -0,0 +1,2 @@
+DROP INDEX guestbook_parent;
+ALTER TABLE guestbook DROP COLUMN parentId;
@@ -0,0 +1,3 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook ADD COLUMN parentId INT;
+CREATE INDEX guestbook_parent ON guestbook (parentId);
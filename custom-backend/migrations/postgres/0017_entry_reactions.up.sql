@@ -0,0 +1,9 @@
+-- see the MySQL version of this migration
+CREATE TABLE guestbookReaction
+(
+    entryId         INT NOT NULL,
+    emoji           VARCHAR(16) NOT NULL,
+    visitorHash     VARCHAR(64) NOT NULL,
+    reactedAt       TIMESTAMP NOT NULL,
+    PRIMARY KEY     (entryId, emoji, visitorHash)
+);
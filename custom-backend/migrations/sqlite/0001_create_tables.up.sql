@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS guestbook
+(
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    dateSubmitted   DATETIME,
+    guestName       VARCHAR(50)  NOT NULL,
+    guestNote       VARCHAR(1000)
+);
+
+CREATE TABLE IF NOT EXISTS hitLog
+(
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    hitTime         TIMESTAMP,
+    userAgent       VARCHAR(150)
+);
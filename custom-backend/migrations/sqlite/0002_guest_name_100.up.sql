@@ -0,0 +1,2 @@
+-- SQLite doesn't enforce VARCHAR lengths, so there's nothing to change here.
+-- This only exists to keep the version numbers the same across backends.
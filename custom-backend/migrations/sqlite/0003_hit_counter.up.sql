@@ -0,0 +1,8 @@
+-- see the MySQL version of this migration
+CREATE TABLE hitCounter
+(
+    id              INTEGER PRIMARY KEY,
+    total           INTEGER NOT NULL
+);
+
+INSERT INTO hitCounter (id, total) SELECT 1, COUNT(*) FROM hitLog;
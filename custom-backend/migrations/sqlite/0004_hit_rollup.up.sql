@@ -0,0 +1,20 @@
+-- see the MySQL version of this migration
+CREATE TABLE hitDaily
+(
+    day             DATE NOT NULL,
+    userAgent       VARCHAR(150) NOT NULL,
+    hits            INTEGER NOT NULL,
+    firstHit        DATETIME NOT NULL,
+    lastHit         DATETIME NOT NULL,
+    PRIMARY KEY     (day, userAgent)
+);
+
+-- SQLite has no date types; the times are text, like '2025-02-28 04:22:49',
+-- and date() cuts them down to '2025-02-28', which is how rusqlite stores a NaiveDate
+INSERT INTO hitDaily (day, userAgent, hits, firstHit, lastHit)
+    SELECT date(hitTime), COALESCE(userAgent, ''), COUNT(*), MIN(hitTime), MAX(hitTime)
+    FROM hitLog
+    WHERE hitTime IS NOT NULL
+    GROUP BY date(hitTime), COALESCE(userAgent, '');
+
+CREATE INDEX hitLog_hitTime ON hitLog (hitTime);
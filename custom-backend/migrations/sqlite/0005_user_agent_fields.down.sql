@@ -0,0 +1,7 @@
+ALTER TABLE hitCounter DROP COLUMN bots;
+
+ALTER TABLE hitLog DROP COLUMN browser;
+ALTER TABLE hitLog DROP COLUMN browserVersion;
+ALTER TABLE hitLog DROP COLUMN os;
+ALTER TABLE hitLog DROP COLUMN device;
+ALTER TABLE hitLog DROP COLUMN isBot;
@@ -0,0 +1,9 @@
+-- see the MySQL version of this migration; SQLite only 
+-- takes one column per ALTER TABLE
+ALTER TABLE hitLog ADD COLUMN browser          VARCHAR(50);
+ALTER TABLE hitLog ADD COLUMN browserVersion   VARCHAR(30);
+ALTER TABLE hitLog ADD COLUMN os               VARCHAR(50);
+ALTER TABLE hitLog ADD COLUMN device           VARCHAR(10);
+ALTER TABLE hitLog ADD COLUMN isBot            BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE hitCounter ADD COLUMN bots         INTEGER NOT NULL DEFAULT 0;
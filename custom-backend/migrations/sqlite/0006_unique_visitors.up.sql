@@ -0,0 +1,13 @@
+-- see the MySQL version of this migration
+CREATE TABLE visitorSalt
+(
+    day             DATE NOT NULL PRIMARY KEY,
+    salt            VARCHAR(32) NOT NULL
+);
+
+CREATE TABLE visitorDaily
+(
+    day             DATE NOT NULL,
+    visitorHash     VARCHAR(64) NOT NULL,
+    PRIMARY KEY     (day, visitorHash)
+);
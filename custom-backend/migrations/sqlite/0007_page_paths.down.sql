@@ -0,0 +1,2 @@
+ALTER TABLE hitLog DROP COLUMN path;
+ALTER TABLE hitLog DROP COLUMN referrer;
@@ -0,0 +1,3 @@
+-- see the MySQL version of this migration
+ALTER TABLE hitLog ADD COLUMN path             VARCHAR(200);
+ALTER TABLE hitLog ADD COLUMN referrer         VARCHAR(200);
@@ -0,0 +1,3 @@
+ALTER TABLE hitLog DROP COLUMN utmSource;
+ALTER TABLE hitLog DROP COLUMN utmMedium;
+ALTER TABLE hitLog DROP COLUMN utmCampaign;
@@ -0,0 +1,4 @@
+-- see the MySQL version of this migration
+ALTER TABLE hitLog ADD COLUMN utmSource        VARCHAR(100);
+ALTER TABLE hitLog ADD COLUMN utmMedium        VARCHAR(100);
+ALTER TABLE hitLog ADD COLUMN utmCampaign      VARCHAR(100);
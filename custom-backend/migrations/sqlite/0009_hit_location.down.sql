@@ -0,0 +1,2 @@
+ALTER TABLE hitLog DROP COLUMN country;
+ALTER TABLE hitLog DROP COLUMN region;
@@ -0,0 +1,3 @@
+-- see the MySQL version of this migration
+ALTER TABLE hitLog ADD COLUMN country          VARCHAR(2);
+ALTER TABLE hitLog ADD COLUMN region           VARCHAR(3);
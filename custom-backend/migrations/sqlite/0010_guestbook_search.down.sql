@@ -0,0 +1,4 @@
+DROP TRIGGER guestbookSearch_insert;
+DROP TRIGGER guestbookSearch_delete;
+DROP TRIGGER guestbookSearch_update;
+DROP TABLE guestbookSearch;
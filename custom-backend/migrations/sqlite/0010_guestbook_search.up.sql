@@ -0,0 +1,22 @@
+-- See the MySQL version of this migration. The trigram tokenizer does the
+-- same job as MySQL's ngram parser. The FTS table only has the index, and
+-- reads the text from guestbook itself, so the triggers keep it in step.
+CREATE VIRTUAL TABLE guestbookSearch USING fts5(
+    guestName, guestNote, content='guestbook', content_rowid='id', tokenize='trigram'
+);
+INSERT INTO guestbookSearch (guestbookSearch) VALUES ('rebuild');
+
+CREATE TRIGGER guestbookSearch_insert AFTER INSERT ON guestbook BEGIN
+    INSERT INTO guestbookSearch (rowid, guestName, guestNote) VALUES (new.id, new.guestName, new.guestNote);
+END;
+
+CREATE TRIGGER guestbookSearch_delete AFTER DELETE ON guestbook BEGIN
+    INSERT INTO guestbookSearch (guestbookSearch, rowid, guestName, guestNote) 
+        VALUES ('delete', old.id, old.guestName, old.guestNote);
+END;
+
+CREATE TRIGGER guestbookSearch_update AFTER UPDATE ON guestbook BEGIN
+    INSERT INTO guestbookSearch (guestbookSearch, rowid, guestName, guestNote) 
+        VALUES ('delete', old.id, old.guestName, old.guestNote);
+    INSERT INTO guestbookSearch (rowid, guestName, guestNote) VALUES (new.id, new.guestName, new.guestNote);
+END;
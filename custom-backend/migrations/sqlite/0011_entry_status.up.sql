@@ -0,0 +1,3 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook ADD COLUMN status VARCHAR(8) NOT NULL DEFAULT 'approved';
+CREATE INDEX guestbook_status ON guestbook (status, dateSubmitted, id);
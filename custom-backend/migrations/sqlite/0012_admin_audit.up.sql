@@ -0,0 +1,10 @@
+-- see the MySQL version of this migration
+CREATE TABLE adminAudit
+(
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    eventTime       DATETIME NOT NULL,
+    action          VARCHAR(20) NOT NULL,
+    clientIp        VARCHAR(45) NOT NULL,
+    entryId         INTEGER,
+    detail          VARCHAR(200)
+);
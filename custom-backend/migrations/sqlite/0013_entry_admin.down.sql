@@ -0,0 +1,3 @@
+DROP TABLE guestbookEdit;
+ALTER TABLE guestbook DROP COLUMN deletedAt;
+ALTER TABLE guestbook DROP COLUMN pinned;
@@ -0,0 +1,13 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook ADD COLUMN deletedAt DATETIME;
+ALTER TABLE guestbook ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE guestbookEdit
+(
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    entryId         INTEGER NOT NULL,
+    editTime        DATETIME NOT NULL,
+    oldName         VARCHAR(100) NOT NULL,
+    oldNote         VARCHAR(1000)
+);
+CREATE INDEX guestbookEdit_entry ON guestbookEdit (entryId, id);
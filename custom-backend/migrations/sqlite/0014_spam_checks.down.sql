@@ -0,0 +1,2 @@
+ALTER TABLE guestbook DROP COLUMN spamVerdict;
+ALTER TABLE guestbook DROP COLUMN spamReason;
@@ -0,0 +1,3 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook ADD COLUMN spamVerdict VARCHAR(10);
+ALTER TABLE guestbook ADD COLUMN spamReason VARCHAR(200);
@@ -0,0 +1 @@
+ALTER TABLE guestbook DROP COLUMN editTokenHash;
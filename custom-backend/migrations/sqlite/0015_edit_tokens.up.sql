@@ -0,0 +1,2 @@
+-- see the MySQL version of this migration
+ALTER TABLE guestbook ADD COLUMN editTokenHash CHAR(64);
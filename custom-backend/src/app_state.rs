@@ -0,0 +1,90 @@
+// Everything the handlers share across requests lives here, and gets
+// handed to them through axum's `State` extractor. It's built once,
+// in main(), before the server starts listening.
+//
+// new() leaves out the admin, has moderation, the rate limits, the spam
+// filter, the guestbook's challenge, authors' edits, guests' replies, and
+// reactions off, and trusts no proxies; main() (or a test) fills those in with
+// `AppState { ..AppState::new(...) }`.
+use std::{sync::Arc, time::Duration};
+use crate::{
+    srv_io::{
+        admin_auth::AdminAuth, bot_check::ProofOfWork, page_views::{PageViewConfig, RecentViews}, 
+        rate_limit::RateLimits, reactions::ReactionEmoji, replies::ReplyConfig
+    },
+    storage::{hit_counter::HitCounter, AuditStore, GuestbookStore, HitStore, Stores},
+    utils::{client_ip::TrustedProxies, geoip::GeoIp, spam_filter::SpamFilter, user_agent::UaRules},
+};
+#[cfg(test)]
+use crate::storage::{mem_store::MemStore, HitTotals};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub guestbook: Arc<dyn GuestbookStore>,
+    pub hits: Arc<HitCounter>,          // which writes to stores.hits on its own
+    pub hit_log: Arc<dyn HitStore>,     // only read from, for the stats
+    pub audit: Arc<dyn AuditStore>,     // the admin's audit trail
+    pub ua_rules: Arc<UaRules>,
+    pub geoip: Arc<GeoIp>,
+    pub recent_views: Arc<RecentViews>, // for deduping page views
+    pub legacy_post: bool,              // whether POST /hits is routed
+    pub admin: Arc<AdminAuth>,          // the /admin routes are only there if it's enabled
+    pub moderation: bool,               // GUESTBOOK_MODERATION; whether new entries wait for approval
+    pub rate_limits: Arc<RateLimits>,   // the routes are only limited if theirs is enabled
+    pub proxies: Arc<TrustedProxies>,   // TRUSTED_PROXIES; whose headers say who the client is
+    pub spam: Arc<SpamFilter>,          // what new entries go through
+    pub pow: Arc<ProofOfWork>,          // GET /guestbook/challenge is only there if it's enabled
+    pub edit_grace: Option<Duration>,   // AUTHOR_EDIT_GRACE_SECS; how long authors can change their entries
+    pub replies: ReplyConfig,           // who can reply to entries, and how deep threads go
+    pub reactions: Arc<ReactionEmoji>,  // REACTION_EMOJI; the reactions routes are only there if it's enabled
+}
+
+impl AppState {
+    pub fn new(
+        stores: &Stores,
+        hits: Arc<HitCounter>,
+        ua_rules: Arc<UaRules>,
+        geoip: Arc<GeoIp>,
+        page_views: &PageViewConfig,
+    ) -> AppState {
+        AppState {
+            guestbook: stores.guestbook.clone(),
+            hits,
+            hit_log: stores.hits.clone(),
+            audit: stores.audit.clone(),
+            ua_rules,
+            geoip,
+            recent_views: Arc::new(RecentViews::new(page_views.dedupe_window)),
+            legacy_post: page_views.legacy_post,
+            admin: Arc::new(AdminAuth::disabled()),
+            moderation: false,
+            rate_limits: Arc::new(RateLimits::disabled()),
+            proxies: Arc::new(TrustedProxies::none()),
+            spam: Arc::new(SpamFilter::disabled()),
+            pow: Arc::new(ProofOfWork::disabled()),
+            edit_grace: None,
+            replies: ReplyConfig::owner_only(),
+            reactions: Arc::new(ReactionEmoji::disabled()),
+        }
+    }
+}
+
+// What the handlers' tests start from: new() on an in-memory store, with
+// the built-in UA rules and no GeoIP. The hit counter and the store come
+// back too, for the tests to set up and look at; anything else a test needs
+// goes in with `AppState { ..state }`.
+#[cfg(test)]
+impl AppState {
+    pub fn for_tests() -> (AppState, Arc<HitCounter>, Arc<MemStore>) {
+        AppState::for_tests_with(MemStore::new(), HitTotals::default())
+    }
+
+    // for a store that already has something in it, and the totals of its hit log
+    pub fn for_tests_with(mem_store: MemStore, totals: HitTotals) -> (AppState, Arc<HitCounter>, Arc<MemStore>) {
+        let mem_store = Arc::new(mem_store);
+        let stores = Stores { db: mem_store.clone(), guestbook: mem_store.clone(), hits: mem_store.clone(), audit: mem_store.clone() };
+        let hit_counter = HitCounter::with_totals(stores.hits.clone(), totals);
+        let state = AppState::new(&stores, hit_counter.clone(), Arc::new(UaRules::built_in()), Arc::new(GeoIp::disabled()), &PageViewConfig::default());
+        (state, hit_counter, mem_store)
+    }
+}
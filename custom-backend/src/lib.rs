@@ -1,3 +1,6 @@
+pub mod app_state;
+pub mod routes;
 pub mod srv_io;
+pub mod storage;
 pub mod utils;
 pub mod types;
\ No newline at end of file
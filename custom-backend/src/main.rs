@@ -1,18 +1,27 @@
 use std::{
     net::SocketAddr,
-    str::FromStr
-};
-use axum::{
-    routing::{get, post}, 
-    Router
+    str::FromStr,
+    sync::Arc,
+    time::Duration
 };
 use axum_server::{Handle, tls_rustls::RustlsConfig};
-use tower_http::compression::{CompressionLayer, predicate::{Predicate, NotForContentType, SizeAbove}};
-use tracing::info;
+use tracing::{info, debug, error, warn};
 
 use custom_backend::{
-    srv_io::{vite_get, db_io, lb_app_io},
-    utils::{init_utils::*, shutdown},
+    app_state::AppState,
+    routes::build_router,
+    srv_io::{
+        admin_auth::{self, AdminAuth}, author_edits, bot_check::ProofOfWork, page_views::PageViewConfig, 
+        rate_limit::RateLimits, reactions::ReactionEmoji, replies::ReplyConfig
+    },
+    storage::{
+        Database, DbPoolConfig,
+        migrations::{self, MigrationStatus, prepare_schema},
+        hit_counter::HitCounter,
+        resilience::{DbResilienceConfig, connect_with_retry},
+        self_check::{self, SchemaMismatch},
+    },
+    utils::{client_ip::TrustedProxies, geoip::GeoIp, init_utils::*, shutdown, spam_filter::SpamFilter, user_agent::UaRules},
 };
 
 #[derive(vite_rs::Embed)]
@@ -45,10 +54,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if prelog { println!("[ PRE-LOG ]: Loading log file path from environment..."); }
     
 
-    let log_file_path = match get_env_var("SERVER_LOG") {
-        Ok(filepath) => filepath,
-        Err(_) => String::new()
-    };
+    let log_file_path = get_env_var("SERVER_LOG").unwrap_or_default();
     
     if log_file_path.is_empty() || log_file_path == "stdout" {
         
@@ -69,33 +75,151 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Reading initialization options...");
 
-    let use_tls = match process_cli_args()? {
+    let run_mode = process_cli_args()?;
+    let use_tls = match run_mode {
         RunMode::PrintHelp => return Ok(()),
-        RunMode::NoTls     => false,
-        RunMode::Tls       => true
+        RunMode::HashPassword => return print_password_hash(),
+        RunMode::Tls       => true,
+        _                  => false
+    };
+
+
+
+    info!("Connecting to database...");
+
+    let pool_cfg = DbPoolConfig::from_env();
+    debug!("Database pool settings: {pool_cfg:?}");
+    let res_cfg = DbResilienceConfig::from_env();
+    debug!("Database retry/timeout settings: {res_cfg:?}");
+    let stores = connect_with_retry(&get_env_var("DB_URL")?, &pool_cfg, &res_cfg).await?;
+    info!("Connected to {} database!", stores.db.backend_name());
+
+    // the modes that only touch the database, then quit
+    match run_mode {
+        RunMode::Migrate(cmd) => {
+            run_migrate_cmd(stores.db.as_ref(), cmd).await?;
+            stores.db.close().await?;
+            return Ok(());
+        },
+        RunMode::Check => {
+            run_check(stores.db.as_ref()).await?;
+            stores.db.close().await?;
+            return Ok(());
+        },
+        _ => {}
+    }
+
+    info!("Checking database schema...");
+    if let Err(schema_err) = prepare_schema(stores.db.as_ref(), get_env_var_or("DB_AUTO_MIGRATE", true)).await {
+        error!("{schema_err}");
+        return Err(schema_err.into());
+    }
+    let problems = self_check::find_problems(stores.db.as_ref()).await?;
+    if !problems.is_empty() {
+        let mismatch = SchemaMismatch(problems);
+        error!("{mismatch}");
+        return Err(mismatch.into());
+    }
+    info!("Database schema and privileges look good!");
+
+    let ua_rules = UaRules::from_env()?;
+    info!("Loaded {} user agent rules.", ua_rules.rule_count());
+    let geoip = GeoIp::from_env()?;
+    if geoip.is_enabled() {
+        info!("Loaded the GeoIP database; hits will get a country.");
+    }
+
+    let guarded_stores = stores.guarded(&res_cfg);
+    let hit_counter = HitCounter::start(guarded_stores.hits.clone()).await?;
+    let hit_flusher = hit_counter.spawn_flusher(
+        Duration::from_millis(get_env_var_or("DB_HIT_FLUSH_MS", 1000).max(1))
+    );
+    info!("Hit counter started at {}.", hit_counter.count());
+    let page_views = PageViewConfig::from_env();
+    if page_views.legacy_post {
+        info!("HIT_LEGACY_POST is on; clients can still POST /hits.");
+    }
+    let admin = AdminAuth::from_env()?;
+    if admin.is_enabled() {
+        info!("ADMIN_PASSWORD_HASH is set; the /admin routes are on, with {}-minute sessions.", admin.session_length().as_secs() / 60);
+    }
+    let moderation = get_env_var_or("GUESTBOOK_MODERATION", false);
+    if moderation && !admin.is_enabled() {
+        warn!("GUESTBOOK_MODERATION is on, but without ADMIN_PASSWORD_HASH, no new entries can be approved.");
+    }
+    let rate_limits = RateLimits::from_env();
+    for limiter in [&rate_limits.guestbook_posts, &rate_limits.searches, &rate_limits.reactions, &rate_limits.challenges] {
+        match limiter.config() {
+            Some(config) => info!("Rate limiting {} to bursts of {}, and {} a minute after that.", limiter.route(), config.burst, config.per_min),
+            None => info!("There's no rate limit on {}.", limiter.route()),
+        }
+    }
+    let proxies = TrustedProxies::from_env()?;
+    if !proxies.is_empty() {
+        info!("Trusting {} proxies' headers for the client's IP.", proxies.len());
+    }
+    let spam = SpamFilter::from_env()?;
+    if spam.is_enabled() {
+        // a database that's down now shouldn't stop the server; it'll be retrained after the next approval
+        if let Err(e) = spam.retrain(guarded_stores.guestbook.as_ref()).await {
+            warn!("Couldn't train the spam filter from the guestbook: {e}");
+        }
+        match spam.bayes_trained_on() {
+            Some((spam_entries, ham_entries)) => info!(
+                "Spam filter is on, with {} checks; it's learned from {spam_entries} rejected and {ham_entries} approved entries.",
+                spam.check_count()
+            ),
+            None => info!("Spam filter is on, with {} checks.", spam.check_count()),
+        }
+    } else {
+        info!("SPAM_FILTER is off; new entries aren't checked for spam.");
+    }
+    let pow = ProofOfWork::from_env();
+    if pow.is_enabled() {
+        info!("New entries need a {}-bit proof of work, good for {} seconds.", pow.difficulty(), pow.ttl().as_secs());
+    } else {
+        info!("GUESTBOOK_POW_DIFFICULTY is 0; new entries don't need a proof of work.");
+    }
+    let edit_grace = author_edits::grace_from_env();
+    match edit_grace {
+        Some(grace) => info!("Authors can change or delete their entries for {} seconds after posting them.", grace.as_secs()),
+        None => info!("AUTHOR_EDIT_GRACE_SECS is 0; authors can't change their entries."),
+    }
+    let replies = ReplyConfig::from_env();
+    if replies.is_enabled(&admin) {
+        let who = match (replies.from_guests, admin.is_enabled()) {
+            (true, true)  => "Guests and the admin",
+            (true, false) => "Guests",
+            _             => "The admin",
+        };
+        info!("{who} can reply to entries, up to {} replies deep.", replies.max_depth);
+    } else {
+        info!("Nobody can reply to entries (see GUESTBOOK_REPLY_DEPTH and GUESTBOOK_GUEST_REPLIES).");
+    }
+    let reactions = ReactionEmoji::from_env();
+    if reactions.is_enabled() {
+        info!("Visitors can react to entries with {}.", reactions.list().join(" "));
+    } else {
+        info!("REACTION_EMOJI is empty; nobody can react to entries.");
+    }
+    let app_state = AppState {
+        admin: Arc::new(admin),
+        moderation,
+        rate_limits: Arc::new(rate_limits),
+        proxies: Arc::new(proxies),
+        spam: Arc::new(spam),
+        pow: Arc::new(pow),
+        edit_grace,
+        replies,
+        reactions: Arc::new(reactions),
+        ..AppState::new(&guarded_stores, hit_counter.clone(), Arc::new(ua_rules), Arc::new(geoip), &page_views)
     };
 
 
 
     info!("Defining routes...");
 
-    let api = Router::new()
-        .route("/hits", get(db_io::get_hit_count))
-        .route("/hits", post(db_io::log_hit))
-        .route("/guestbook/entries", get(db_io::get_guestbook))
-        .route("/guestbook/entries", post(db_io::update_guestbook))
-        .route("/lb-list-conv/conv", get(lb_app_io::convert_lb_list));
-    
-    let routes = Router::new()
-        .route("/", get(vite_get::serve_statics))
-        .route("/{*path}", get(vite_get::serve_statics))
-        .merge(api)
-        .layer(
-            CompressionLayer::new().compress_when(
-                // compressing SSE's causes the front-end not to be updated until all events are received
-                SizeAbove::new(2048).and(NotForContentType::new("text/event-stream"))
-            )
-        );        
+    let routes = build_router(app_state);
 
 
     // Start Vite dev server (on debug only).
@@ -118,7 +242,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         info!("Serving on {:?}! (no TLS)", addr);
         let listener = tokio::net::TcpListener::bind(addr).await?;
-        axum::serve(listener, routes)
+        // the client's address is needed for the visitor hashes
+        axum::serve(listener, routes.into_make_service_with_connect_info::<SocketAddr>())
             .with_graceful_shutdown(shutdown::on_signal(None))
             .await?;
 
@@ -137,9 +262,76 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         info!("Serving securely on {:?}!", addr);
         axum_server::bind_rustls(addr, auth_config)
             .handle(svr_handle)
-            .serve(routes.into_make_service())
+            .serve(routes.into_make_service_with_connect_info::<SocketAddr>())
             .await?;
     }
 
+    // write out any hits still in the buffer
+    hit_counter.stop();
+    hit_flusher.await?;
+    stores.db.close().await?;
+
+    Ok(())
+}
+
+
+// for `archie-server migrate ...`; results go to stdout, since
+// these are run by hand, and the log may be going to a file
+async fn run_migrate_cmd(db: &dyn Database, cmd: MigrateCmd) -> Result<(), Box<dyn std::error::Error>> {
+
+    match cmd {
+        MigrateCmd::Up => {
+            let done = migrations::migrate_up(db).await?;
+            println!("Applied {} migration(s): {done:?}", done.len());
+        },
+        MigrateCmd::Down(steps) => {
+            let done = migrations::migrate_down(db, steps).await?;
+            println!("Reverted {} migration(s): {done:?}", done.len());
+        },
+        MigrateCmd::Status => {
+            let MigrationStatus { applied, pending, unknown } = migrations::status(db).await?;
+            for migration in applied {
+                println!("  applied   {}", migration.name);
+            }
+            for migration in pending {
+                println!("  pending   {}", migration.name);
+            }
+            for version in unknown {
+                println!("  UNKNOWN   version {version} (not in this build)");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// for `archie-server hash-password`; the password is read from stdin,
+// rather than taken as an argument, so it stays out of the shell history
+fn print_password_hash() -> Result<(), Box<dyn std::error::Error>> {
+
+    eprintln!("Type the admin password, then press Enter:");
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+
+    let hash = admin_auth::hash_password(password.trim_end_matches(['\r', '\n']))?;
+    println!("{hash}");
+
+    Ok(())
+}
+
+// for `archie-server --check`; the same checks as startup, 
+// except pending migrations are reported rather than applied
+async fn run_check(db: &dyn Database) -> Result<(), Box<dyn std::error::Error>> {
+
+    if let Some(latest) = migrations::status(db).await?.applied.last() {
+        println!("Schema version: {}", latest.version);
+    }
+
+    let problems = self_check::find_problems(db).await?;
+    if !problems.is_empty() {
+        return Err(SchemaMismatch(problems).into());
+    }
+
+    println!("All checks passed.");
     Ok(())
 }
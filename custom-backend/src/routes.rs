@@ -0,0 +1,109 @@
+// The router lives in the library (rather than in main()) so that
+// tests can spin up the whole app in-process.
+use axum::{
+    middleware,
+    routing::{delete, get, patch, post}, 
+    Router
+};
+use tower_http::compression::{CompressionLayer, predicate::{Predicate, NotForContentType, SizeAbove}};
+
+use crate::{
+    app_state::AppState,
+    srv_io::{vite_get, admin_auth, admin_io, author_edits, bot_check, db_io, lb_app_io, page_views, rate_limit, reactions, replies, stats_io},
+};
+
+pub fn build_router(app_state: AppState) -> Router {
+
+    let mut api = Router::new()
+        .route("/hits", get(db_io::get_hit_count));
+    // page views are logged by track_page_views() now, so
+    // clients only get to POST their own if this is on
+    if app_state.legacy_post {
+        api = api.route("/hits", post(db_io::log_hit));
+    }
+    // nobody could log in without a password anyway
+    if app_state.admin.is_enabled() {
+        let mut admin = Router::new()
+            .route("/logout", post(admin_io::log_out))
+            .route("/session", get(admin_io::get_session))
+            .route("/audit", get(admin_io::get_audit_trail))
+            .route("/guestbook/entries", get(admin_io::get_entries_by_status))
+            .route("/guestbook/entries/{id}/approve", post(admin_io::approve_entry))
+            .route("/guestbook/entries/{id}/reject", post(admin_io::reject_entry))
+            .route("/guestbook/entries/{id}", delete(admin_io::delete_entry).patch(admin_io::edit_entry))
+            .route("/guestbook/entries/{id}/restore", post(admin_io::restore_entry))
+            .route("/guestbook/entries/{id}/edits", get(admin_io::get_entry_edits))
+            .route("/guestbook/entries/{id}/pin", post(admin_io::pin_entry))
+            .route("/guestbook/entries/{id}/unpin", post(admin_io::unpin_entry))
+            .route("/guestbook/deleted", get(admin_io::get_deleted_entries));
+        if app_state.replies.max_depth > 0 {
+            admin = admin.route("/guestbook/entries/{id}/replies", post(replies::post_owner_reply));
+        }
+        let admin = admin
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), admin_auth::require_admin))
+            // after the layer, so it's the one route that doesn't need a session
+            .route("/login", post(admin_io::log_in));
+        api = api.nest("/admin", admin);
+    }
+    // the limits need ConnectInfo, so they're only on the routes when they're on
+    let mut post_entry = post(db_io::update_guestbook);
+    let mut post_reply = post(replies::post_reply);
+    if app_state.rate_limits.guestbook_posts.is_enabled() {
+        post_entry = post_entry.layer(middleware::from_fn_with_state(app_state.clone(), rate_limit::limit_guestbook_posts));
+        post_reply = post_reply.layer(middleware::from_fn_with_state(app_state.clone(), rate_limit::limit_guestbook_posts));
+    }
+    let mut search = get(db_io::search_guestbook);
+    if app_state.rate_limits.searches.is_enabled() {
+        search = search.layer(middleware::from_fn_with_state(app_state.clone(), rate_limit::limit_searches));
+    }
+    let mut post_reaction = post(reactions::post_reaction);
+    if app_state.rate_limits.reactions.is_enabled() {
+        post_reaction = post_reaction.layer(middleware::from_fn_with_state(app_state.clone(), rate_limit::limit_reactions));
+    }
+    // there's nothing to hand out if new entries don't need one
+    if app_state.pow.is_enabled() {
+        let mut get_challenge = get(bot_check::get_challenge);
+        if app_state.rate_limits.challenges.is_enabled() {
+            get_challenge = get_challenge.layer(middleware::from_fn_with_state(app_state.clone(), rate_limit::limit_challenges));
+        }
+        api = api.route("/guestbook/challenge", get_challenge);
+    }
+    // and nobody has a token to use these with
+    if app_state.edit_grace.is_some() {
+        api = api.route("/guestbook/entries/{id}", patch(author_edits::edit_own_entry).delete(author_edits::delete_own_entry));
+    }
+    // the owner's replies go under /admin, with the rest of what they can do
+    if app_state.replies.from_guests() {
+        api = api.route("/guestbook/entries/{id}/replies", post_reply);
+    }
+    if app_state.reactions.is_enabled() {
+        api = api
+            .route("/guestbook/reactions", get(reactions::get_reaction_options))
+            .route("/guestbook/entries/{id}/reactions", post_reaction);
+    }
+    let api = api
+        .route("/hits/stats", get(stats_io::get_hit_stats))
+        .route("/hits/pages", get(stats_io::get_top_pages))
+        .route("/hits/referrers", get(stats_io::get_top_referrers))
+        .route("/hits/countries", get(stats_io::get_top_countries))
+        .route("/hits/visitors", get(stats_io::get_unique_visitors))
+        .route("/guestbook/entries", get(db_io::get_guestbook_entries))
+        .route("/guestbook/entries", post_entry)
+        .route("/guestbook/search", search)
+        .route("/lb-list-conv/conv", get(lb_app_io::convert_lb_list))
+        .with_state(app_state.clone());
+
+    let statics = Router::new()
+        .route("/", get(vite_get::serve_statics))
+        .route("/{*path}", get(vite_get::serve_statics))
+        .layer(middleware::from_fn_with_state(app_state, page_views::track_page_views));
+    
+    statics
+        .merge(api)
+        .layer(
+            CompressionLayer::new().compress_when(
+                // compressing SSE's causes the front-end not to be updated until all events are received
+                SizeAbove::new(2048).and(NotForContentType::new("text/event-stream"))
+            )
+        )
+}
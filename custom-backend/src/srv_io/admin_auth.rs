@@ -0,0 +1,608 @@
+// Who gets to use the /admin routes: whoever knows the password whose argon2
+// hash is in ADMIN_PASSWORD_HASH (make one with `archie-server hash-password`).
+// Without it set, there's no admin, and the /admin routes aren't there at all.
+//
+// POST /admin/login with the password starts a session, which lives in an
+// HttpOnly, Secure, SameSite=Strict cookie that's only sent back to /admin,
+// and runs out ADMIN_SESSION_MINS after logging in. Browsers only keep Secure
+// cookies from localhost over plain HTTP, so running --no-tls anywhere else
+// means there's no logging in. Sessions are only kept in memory, so a restart
+// logs the admin out (and with more than one replica, the admin has to stick
+// to one of them).
+//
+// Every login attempt counts against the IP it came from (the client's,
+// from client_ip(), not a proxy's, and the whole /64 for IPv6; see
+// utils/client_ip.rs), until one gets it right. After MAX_LOGIN_ATTEMPTS in
+// LOGIN_WINDOW, the IP is locked out for LOCKOUT, and its attempts are turned
+// away without checking them. So spreading the guesses over lots of IPs
+// doesn't get around that, they all count towards MAX_FAILED_LOGINS too, and
+// past that, every login's turned away for LOCKOUT, the admin's included.
+//
+// Everything under /admin but the login goes through require_admin() (see
+// routes.rs). Handlers take an Admin too, which says where the request came
+// from, for the audit trail in adminAudit: every login, failed or not,
+// every lockout, and everything done once logged in.
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request},
+    http::{header::{COOKIE, RETRY_AFTER}, request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use mysql_common::chrono::{SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use tokio::task::spawn_blocking;
+use tracing::{error, info, warn};
+use crate::{
+    app_state::AppState,
+    types::db_io_types::{AuditAction, AuditEvent},
+    utils::{client_ip::client_key, init_utils::get_env_var_or, user_agent::truncate_chars},
+};
+
+
+pub const SESSION_COOKIE: &str = "archie_admin";
+const SESSION_TOKEN_BYTES: usize = 32;
+
+// anything shorter could be guessed, argon2 or not
+pub const MIN_PASSWORD_CHARS: usize = 12;
+
+pub const MAX_LOGIN_ATTEMPTS: u32 = 5;
+pub const LOGIN_WINDOW: Duration = Duration::from_secs(15 * 60);
+pub const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+// from everywhere, in LOGIN_WINDOW
+pub const MAX_FAILED_LOGINS: u32 = 100;
+// how many IPs' attempts are kept track of at once, so a flood
+// of them from everywhere can't use up all the memory
+const MAX_TRACKED_IPS: usize = 10_000;
+
+// the widths of adminAudit's columns
+pub const MAX_ACTION_CHARS: usize = 20;
+pub const MAX_IP_CHARS: usize = 45;         // the longest an IPv6 address can be written
+pub const MAX_DETAIL_CHARS: usize = 200;
+
+
+#[derive(Debug)]
+pub enum AdminAuthError {
+    BadPasswordHash(String),    // ADMIN_PASSWORD_HASH isn't an argon2 hash
+    PasswordTooShort,
+    HashingFailed(argon2::password_hash::Error),
+}
+
+impl fmt::Display for AdminAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadPasswordHash(why) => write!(f, "ADMIN_PASSWORD_HASH isn't an argon2 hash ({why}); make one with `archie-server hash-password`"),
+            Self::PasswordTooShort => write!(f, "The admin password needs to be at least {MIN_PASSWORD_CHARS} characters"),
+            Self::HashingFailed(hash_err) => write!(f, "Could not hash the password: {hash_err}"),
+        }
+    }
+}
+
+impl std::error::Error for AdminAuthError {}
+
+
+// for `archie-server hash-password`, and the tests
+pub fn hash_password(password: &str) -> Result<String, AdminAuthError> {
+
+    if password.chars().count() < MIN_PASSWORD_CHARS {
+        return Err(AdminAuthError::PasswordTooShort);
+    }
+
+    let mut salt = [0u8; 16];
+    // unwrap only panics if the OS has no source of randomness at all
+    getrandom::fill(&mut salt).unwrap();
+    let salt = SaltString::encode_b64(&salt).map_err(AdminAuthError::HashingFailed)?;
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(AdminAuthError::HashingFailed)
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum LoginError {
+    WrongPassword,
+    // just_now is for the attempt that started the lockout,
+    // so only that one goes in the audit trail
+    LockedOut { retry_after: Duration, just_now: bool },
+}
+
+impl IntoResponse for LoginError {
+    fn into_response(self) -> Response {
+
+        match self {
+            LoginError::WrongPassword => (
+                StatusCode::UNAUTHORIZED, "That's not the admin password."
+            ).into_response(),
+            LoginError::LockedOut { retry_after, .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.as_millis().div_ceil(1000).max(1).to_string())],
+                "Too many tries at the admin password. Try again later."
+            ).into_response(),
+        }
+    }
+}
+
+// the attempts from one IP (or from everywhere) within the current window
+#[derive(Debug, Clone, Copy)]
+struct LoginAttempts {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl LoginAttempts {
+    fn starting(now: Instant) -> LoginAttempts {
+        LoginAttempts { count: 0, since: now, locked_until: None }
+    }
+
+    fn is_stale(&self, now: Instant) -> bool {
+        match self.locked_until {
+            Some(until) => until <= now,
+            None => now.duration_since(self.since) >= LOGIN_WINDOW,
+        }
+    }
+
+    // starts a new window if this one's over, and says if it's locked
+    // out, locking it out first if it's just had its last try
+    fn check(&mut self, max_attempts: u32, now: Instant) -> Result<(), LoginError> {
+
+        if self.is_stale(now) {
+            *self = LoginAttempts::starting(now);
+        }
+        if let Some(until) = self.locked_until {
+            return Err(LoginError::LockedOut { retry_after: until - now, just_now: false });
+        }
+        if self.count >= max_attempts {
+            self.locked_until = Some(now + LOCKOUT);
+            return Err(LoginError::LockedOut { retry_after: LOCKOUT, just_now: true });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct LoginTracker {
+    by_client: HashMap<IpAddr, LoginAttempts>,     // by client_key()
+    overall: LoginAttempts,
+}
+
+
+pub struct AdminAuth {
+    password_hash: Option<String>,      // checked to be an argon2 hash when it's read
+    session_length: Duration,
+    // by the SHA-256 of the token, so one can't be guessed from how long
+    // a lookup takes; when each runs out
+    sessions: Mutex<HashMap<[u8; 32], Instant>>,
+    attempts: Mutex<LoginTracker>,
+    max_failed_logins: u32,     // MAX_FAILED_LOGINS, but for the tests
+}
+
+impl AdminAuth {
+
+    pub fn disabled() -> AdminAuth {
+        AdminAuth {
+            password_hash: None,
+            session_length: Duration::ZERO,
+            sessions: Mutex::new(HashMap::new()),
+            attempts: Mutex::new(LoginTracker { by_client: HashMap::new(), overall: LoginAttempts::starting(Instant::now()) }),
+            max_failed_logins: MAX_FAILED_LOGINS,
+        }
+    }
+
+    pub fn with_password_hash(password_hash: &str, session_length: Duration) -> Result<AdminAuth, AdminAuthError> {
+
+        let parsed = PasswordHash::new(password_hash)
+            .map_err(|hash_err| AdminAuthError::BadPasswordHash(hash_err.to_string()))?;
+        if argon2::Algorithm::try_from(parsed.algorithm).is_err() {
+            return Err(AdminAuthError::BadPasswordHash(format!("it's {}", parsed.algorithm)));
+        }
+
+        Ok(AdminAuth { password_hash: Some(password_hash.to_string()), session_length, ..AdminAuth::disabled() })
+    }
+
+    // the hash in ADMIN_PASSWORD_HASH, if it's set
+    pub fn from_env() -> Result<AdminAuth, AdminAuthError> {
+
+        if !get_env_var_or("ADMIN_TOKEN", String::new()).is_empty() {
+            warn!("ADMIN_TOKEN isn't used anymore; set ADMIN_PASSWORD_HASH instead.");
+        }
+
+        let password_hash: String = get_env_var_or("ADMIN_PASSWORD_HASH", String::new());
+        if password_hash.is_empty() {
+            return Ok(AdminAuth::disabled());
+        }
+
+        let session_mins: u64 = get_env_var_or("ADMIN_SESSION_MINS", 60).max(1);
+        AdminAuth::with_password_hash(&password_hash, Duration::from_secs(session_mins * 60))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    pub fn session_length(&self) -> Duration {
+        self.session_length
+    }
+
+    // Returns the new session's token, or why there isn't one. The password
+    // is checked on the blocking thread pool, since argon2 is slow on purpose.
+    pub async fn log_in(&self, client: IpAddr, password: String, now: Instant) -> Result<String, LoginError> {
+
+        self.count_attempt(client, now)?;
+
+        let Some(password_hash) = self.password_hash.clone() else { return Err(LoginError::WrongPassword); };
+        let is_right = spawn_blocking(move || {
+            // the hash was checked when it was read, so this always parses
+            PasswordHash::new(&password_hash)
+                .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        }).await.unwrap_or(false);
+
+        if !is_right {
+            return Err(LoginError::WrongPassword);
+        }
+
+        // only the failed ones count
+        let mut attempts = self.lock_attempts();
+        attempts.by_client.remove(&client_key(client));
+        attempts.overall.count = attempts.overall.count.saturating_sub(1);
+        drop(attempts);
+
+        Ok(self.start_session(now))
+    }
+
+    // Counted before the password is checked, so a flood of attempts
+    // at once can't all get in under the limit
+    fn count_attempt(&self, client: IpAddr, now: Instant) -> Result<(), LoginError> {
+
+        let client = client_key(client);
+        let mut attempts = self.lock_attempts();
+        let LoginTracker { by_client, overall } = &mut *attempts;
+
+        if by_client.len() >= MAX_TRACKED_IPS && !by_client.contains_key(&client) {
+            make_room(by_client, now);
+            // Everyone it knows about is locked out. Not counting this one
+            // would let it guess as much as it likes, so it has to wait too.
+            if by_client.len() >= MAX_TRACKED_IPS {
+                return Err(LoginError::LockedOut { retry_after: LOCKOUT, just_now: false });
+            }
+        }
+
+        let tries = by_client.entry(client).or_insert_with(|| LoginAttempts::starting(now));
+        tries.check(MAX_LOGIN_ATTEMPTS, now)?;
+        let everyone = overall.check(self.max_failed_logins, now);
+        if let Err(LoginError::LockedOut { retry_after, just_now: true }) = everyone {
+            warn!("Over {} failed admin logins lately; turning all of them away for {retry_after:?}.", self.max_failed_logins);
+        }
+        everyone?;
+
+        tries.count += 1;
+        overall.count += 1;
+        Ok(())
+    }
+
+    fn start_session(&self, now: Instant) -> String {
+
+        let mut bytes = [0u8; SESSION_TOKEN_BYTES];
+        // unwrap only panics if the OS has no source of randomness at all
+        getrandom::fill(&mut bytes).unwrap();
+        let token: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+
+        let mut sessions = self.lock_sessions();
+        sessions.retain(|_, expires| *expires > now);
+        sessions.insert(Sha256::digest(&token).into(), now + self.session_length);
+
+        token
+    }
+
+    // how long the session has left, if it hasn't run out
+    pub fn session_left(&self, token: &str, now: Instant) -> Option<Duration> {
+
+        let key: [u8; 32] = Sha256::digest(token).into();
+        let mut sessions = self.lock_sessions();
+
+        match sessions.get(&key) {
+            Some(expires) if *expires > now => Some(*expires - now),
+            Some(_) => { sessions.remove(&key); None },
+            None => None,
+        }
+    }
+
+    pub fn end_session(&self, token: &str) {
+        self.lock_sessions().remove(&<[u8; 32]>::from(Sha256::digest(token)));
+    }
+
+    // nothing is ever left half-changed in either of these by a panic
+    fn lock_sessions(&self) -> MutexGuard<'_, HashMap<[u8; 32], Instant>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_attempts(&self) -> MutexGuard<'_, LoginTracker> {
+        self.attempts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+
+// Forgets the IPs whose windows are over, and then the ones that started
+// trying longest ago, a tenth of them at a time. Lockouts are kept, or
+// whoever's locked out would only have to try from enough other IPs to
+// get out of it.
+fn make_room(by_client: &mut HashMap<IpAddr, LoginAttempts>, now: Instant) {
+
+    by_client.retain(|_, tries| !tries.is_stale(now));
+    if by_client.len() < MAX_TRACKED_IPS {
+        return;
+    }
+
+    let mut unlocked: Vec<(Instant, IpAddr)> = by_client.iter()
+        .filter(|(_, tries)| tries.locked_until.is_none())
+        .map(|(client, tries)| (tries.since, *client))
+        .collect();
+    unlocked.sort_unstable();
+    let excess = by_client.len() + 1 - MAX_TRACKED_IPS + MAX_TRACKED_IPS / 10;
+    for (_, client) in unlocked.into_iter().take(excess) {
+        by_client.remove(&client);
+    }
+    warn!("Over {MAX_TRACKED_IPS} IPs tried to log in as the admin lately; forgetting the oldest that aren't locked out.");
+}
+
+
+// the Set-Cookie for a new session, or for ending one
+pub fn session_cookie(token: &str, max_age: Duration) -> String {
+    format!("{SESSION_COOKIE}={token}; Path=/admin; Max-Age={}; HttpOnly; Secure; SameSite=Strict", max_age.as_secs())
+}
+
+pub fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get_all(COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token)
+}
+
+
+// Adds an event to the audit trail. What was done isn't undone if this
+// fails, so the event goes in the server's log either way.
+pub async fn audit(state: &AppState, client: IpAddr, action: AuditAction, entry_id: Option<String>, detail: Option<String>) {
+
+    let event = AuditEvent {
+        time_stamp: Utc::now().naive_utc().trunc_subsecs(0),
+        action,
+        client_ip: client.to_string(),
+        entry_id,
+        detail: detail.map(|detail| truncate_chars(&detail, MAX_DETAIL_CHARS).to_string()),
+    };
+    let described = format!("Admin audit: {} from {}{}{}.",
+        action.as_str(), event.client_ip,
+        event.entry_id.as_deref().map(|id| format!(", entry {id}")).unwrap_or_default(),
+        event.detail.as_deref().map(|detail| format!(" ({detail})")).unwrap_or_default(),
+    );
+    match action {
+        AuditAction::LoginFailed | AuditAction::LoginThrottled => warn!("{described}"),
+        _ => info!("{described}"),
+    }
+
+    if let Err(db_err) = state.audit.log_event(&event).await {
+        error!("Could not add {} to the admin audit trail: {db_err:?}", action.as_str());
+    }
+}
+
+
+// Proof that the request came with a live admin session
+#[derive(Debug, Clone, PartialEq)]
+pub struct Admin {
+    pub client: IpAddr,     // from client_ip() (see utils/client_ip.rs), for the audit trail
+    pub session_left: Duration,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NotAdmin;
+
+impl IntoResponse for NotAdmin {
+    fn into_response(self) -> Response {
+        (StatusCode::UNAUTHORIZED, "This needs an admin login.").into_response()
+    }
+}
+
+impl FromRequestParts<AppState> for Admin {
+
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Admin, Response> {
+
+        let ConnectInfo(peer) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await
+            .map_err(IntoResponse::into_response)?;
+        let client = state.proxies.client_ip(peer.ip(), &parts.headers);
+
+        session_token(&parts.headers)
+            .and_then(|token| state.admin.session_left(token, Instant::now()))
+            .map(|session_left| Admin { client, session_left })
+            .ok_or_else(|| NotAdmin.into_response())
+    }
+}
+
+// for the /admin routes (but the login), so none of them can
+// be added without the check, even if a handler leaves out Admin
+pub async fn require_admin(_admin: Admin, req: Request, next: Next) -> Response {
+    next.run(req).await
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn test_auth() -> AdminAuth {
+        AdminAuth::with_password_hash(&hash_password(PASSWORD).unwrap(), Duration::from_secs(3600)).unwrap()
+    }
+
+    #[test]
+    fn reading_hashes() {
+        let hash = hash_password(PASSWORD).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password(PASSWORD).unwrap());     // a new salt each time
+        assert!(AdminAuth::with_password_hash(&hash, Duration::from_secs(60)).unwrap().is_enabled());
+
+        assert!(matches!(hash_password("hunter2"), Err(AdminAuthError::PasswordTooShort)));
+        assert!(matches!(AdminAuth::with_password_hash(PASSWORD, Duration::from_secs(60)), Err(AdminAuthError::BadPasswordHash(_))));
+        // a PHC string, but not argon2
+        let scrypt = "$scrypt$ln=16,r=8,p=1$aM15713r3Xsvxbi31lqr1Q$nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E";
+        assert!(matches!(AdminAuth::with_password_hash(scrypt, Duration::from_secs(60)), Err(AdminAuthError::BadPasswordHash(_))));
+        assert!(!AdminAuth::disabled().is_enabled());
+    }
+
+    #[tokio::test]
+    async fn sessions_run_out() {
+        let auth = test_auth();
+        let client = IpAddr::from([203, 0, 113, 7]);
+        let start = Instant::now();
+
+        assert_eq!(auth.log_in(client, String::from("not it"), start).await, Err(LoginError::WrongPassword));
+        let token = auth.log_in(client, String::from(PASSWORD), start).await.unwrap();
+        assert_eq!(token.len(), SESSION_TOKEN_BYTES * 2);
+        assert_eq!(auth.session_left(&token, start + Duration::from_secs(600)), Some(Duration::from_secs(3000)));
+        assert_eq!(auth.session_left(&token, start + Duration::from_secs(3600)), None);
+        assert_eq!(auth.session_left(&token, start), None);    // and it's gone for good
+        assert_eq!(auth.session_left("", start), None);
+
+        let token = auth.log_in(client, String::from(PASSWORD), start).await.unwrap();
+        auth.end_session(&token);
+        assert_eq!(auth.session_left(&token, start), None);
+    }
+
+    #[tokio::test]
+    async fn locking_out_guessers() {
+        let auth = test_auth();
+        let guesser = IpAddr::from([198, 51, 100, 1]);
+        let start = Instant::now();
+
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            assert_eq!(auth.log_in(guesser, String::from("guess"), start).await, Err(LoginError::WrongPassword));
+        }
+        // even the right one is turned away now
+        assert_eq!(
+            auth.log_in(guesser, String::from(PASSWORD), start).await,
+            Err(LoginError::LockedOut { retry_after: LOCKOUT, just_now: true })
+        );
+        assert_eq!(
+            auth.log_in(guesser, String::from(PASSWORD), start + Duration::from_secs(60)).await,
+            Err(LoginError::LockedOut { retry_after: LOCKOUT - Duration::from_secs(60), just_now: false })
+        );
+        // but not anyone else's
+        assert!(auth.log_in(IpAddr::from([203, 0, 113, 7]), String::from(PASSWORD), start).await.is_ok());
+
+        assert!(auth.log_in(guesser, String::from(PASSWORD), start + LOCKOUT).await.is_ok());
+
+        // getting it right starts the count over, and so does a new window
+        let later = start + LOCKOUT * 2;
+        for (at, then_right) in [(later, true), (later, false), (later + LOGIN_WINDOW, true)] {
+            for _ in 1..MAX_LOGIN_ATTEMPTS {
+                assert_eq!(auth.log_in(guesser, String::from("guess"), at).await, Err(LoginError::WrongPassword));
+            }
+            if then_right {
+                assert!(auth.log_in(guesser, String::from(PASSWORD), at).await.is_ok());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn ipv6_guessers_share_their_64() {
+        let auth = test_auth();
+        let start = Instant::now();
+
+        // a new address for every guess doesn't get it any more of them
+        for n in 0..MAX_LOGIN_ATTEMPTS {
+            let hopping: IpAddr = format!("2001:db8:1:2::{:x}", n + 1).parse().unwrap();
+            assert_eq!(auth.log_in(hopping, String::from("guess"), start).await, Err(LoginError::WrongPassword));
+        }
+        assert_eq!(
+            auth.log_in("2001:db8:1:2:ffff::9".parse().unwrap(), String::from(PASSWORD), start).await,
+            Err(LoginError::LockedOut { retry_after: LOCKOUT, just_now: true })
+        );
+        assert!(auth.log_in("2001:db8:1:3::1".parse().unwrap(), String::from(PASSWORD), start).await.is_ok());
+    }
+
+    #[test]
+    fn lockouts_outlast_a_flood() {
+        let auth = AdminAuth { max_failed_logins: u32::MAX, ..test_auth() };
+        let guesser = IpAddr::from([198, 51, 100, 1]);
+        let start = Instant::now();
+
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            auth.count_attempt(guesser, start).unwrap();
+        }
+        assert!(auth.count_attempt(guesser, start).is_err());
+
+        // more IPs than it keeps track of, all still in their windows
+        let later = start + Duration::from_secs(60);
+        for n in 0..MAX_TRACKED_IPS as u32 {
+            auth.count_attempt(IpAddr::from((n + 1).to_be_bytes()), later).unwrap();
+        }
+        assert!(auth.lock_attempts().by_client.len() <= MAX_TRACKED_IPS);
+        assert_eq!(
+            auth.count_attempt(guesser, later),
+            Err(LoginError::LockedOut { retry_after: LOCKOUT - Duration::from_secs(60), just_now: false })
+        );
+        // the newest are still counted
+        assert!(auth.lock_attempts().by_client.contains_key(&IpAddr::from((MAX_TRACKED_IPS as u32).to_be_bytes())));
+    }
+
+    #[tokio::test]
+    async fn capping_failed_logins_everywhere() {
+        let auth = test_auth();
+        let start = Instant::now();
+
+        // a few guesses each, from lots of IPs
+        for n in 0..MAX_FAILED_LOGINS {
+            auth.count_attempt(IpAddr::from([198, 51, (n / 2) as u8, 1]), start).unwrap();
+        }
+        assert_eq!(
+            auth.count_attempt(IpAddr::from([198, 51, 200, 1]), start),
+            Err(LoginError::LockedOut { retry_after: LOCKOUT, just_now: true })
+        );
+        // even the admin has to wait it out
+        assert_eq!(
+            auth.log_in(IpAddr::from([203, 0, 113, 7]), String::from(PASSWORD), start).await,
+            Err(LoginError::LockedOut { retry_after: LOCKOUT, just_now: false })
+        );
+        assert!(auth.log_in(IpAddr::from([203, 0, 113, 7]), String::from(PASSWORD), start + LOCKOUT).await.is_ok());
+
+        // logging in doesn't count towards it
+        let later = start + LOCKOUT * 2;
+        for n in 1..MAX_FAILED_LOGINS {
+            auth.count_attempt(IpAddr::from([198, 51, (n / 2) as u8, 1]), later).unwrap();
+        }
+        assert!(auth.log_in(IpAddr::from([203, 0, 113, 7]), String::from(PASSWORD), later).await.is_ok());
+        assert_eq!(auth.count_attempt(IpAddr::from([198, 51, 200, 1]), later), Ok(()));
+    }
+
+    #[test]
+    fn reading_cookies() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_token(&headers), None);
+        headers.append(COOKIE, HeaderValue::from_static("theme=dark; archie_admin_old=nope"));
+        headers.append(COOKIE, HeaderValue::from_static("lang=en; archie_admin=abc123"));
+        assert_eq!(session_token(&headers), Some("abc123"));
+
+        assert_eq!(
+            session_cookie("abc123", Duration::from_secs(3600)),
+            "archie_admin=abc123; Path=/admin; Max-Age=3600; HttpOnly; Secure; SameSite=Strict"
+        );
+    }
+}
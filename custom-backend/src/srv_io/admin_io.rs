@@ -0,0 +1,685 @@
+// The /admin routes (see admin_auth.rs for who gets to use them).
+//
+//     POST /admin/login                                {"password": ...}; sets the session cookie
+//     POST /admin/logout
+//     GET  /admin/session                              when the session runs out
+//     GET  /admin/audit?limit=50                       the audit trail, newest first
+//
+// And the moderation queue. With GUESTBOOK_MODERATION on, new entries come
+// in pending, and only show up once they're approved:
+//
+//     GET  /admin/guestbook/entries?status=pending     the queue, oldest first
+//     POST /admin/guestbook/entries/{id}/approve
+//     POST /admin/guestbook/entries/{id}/reject
+//
+// Either one can be undone by the other, so a rejected entry can still be
+// approved later on, or an approved one taken back down. Entries the spam
+// filter held are in the queue too, and the ones it dropped are rejected;
+// the list says what it made of each, and it learns from what's approved
+// and rejected here (see utils/spam_filter.rs).
+//
+// And cleaning up after the fact, whatever an entry's status:
+//
+//     DELETE /admin/guestbook/entries/{id}             hides it, but keeps it, so it can be restored
+//     POST   /admin/guestbook/entries/{id}/restore
+//     GET    /admin/guestbook/deleted                  most recently deleted first
+//     PATCH  /admin/guestbook/entries/{id}             {"name": ..., "note": ...}; either can be left out
+//     GET    /admin/guestbook/entries/{id}/edits       what it said before each edit, oldest first
+//     POST   /admin/guestbook/entries/{id}/pin         pinned entries go first in GET /guestbook
+//     POST   /admin/guestbook/entries/{id}/unpin
+//     POST   /admin/guestbook/entries/{id}/replies     replying as the owner (see replies.rs)
+//
+// Pinning only changes the order of the whole guestbook; the pages and the
+// search results are still in order of time. Everything here is audited.
+use std::{net::SocketAddr, time::{Duration, Instant}};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header::SET_COOKIE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json
+};
+use axum_extra::extract::Query;
+use mysql_common::chrono::{SubsecRound, Utc};
+use tracing::info;
+use crate::{
+    app_state::AppState,
+    srv_io::{
+        admin_auth::{audit, session_cookie, session_token, Admin, LoginError},
+        db_io::{DbError, MAX_NAME_BYTES, MAX_NOTE_BYTES},
+    },
+    types::db_io_types::{
+        AdminLogin, AdminSession, AuditAction, AuditTrail, EntryChanges, EntryHistory, EntryStatus, 
+        Guestbook, GuestbookEntry, ModerationReceipt
+    },
+};
+
+
+pub const DEFAULT_AUDIT_EVENTS: u64 = 50;
+pub const MAX_AUDIT_EVENTS: u64 = 500;
+
+#[derive(Debug)]
+pub enum ModerationError {
+    DbError(DbError),
+    NoSuchEntry(i64),
+    NothingToChange,
+    TooLong(&'static str, usize),  // the field, and its limit in bytes
+}
+
+impl From<DbError> for ModerationError {
+    fn from(db_err: DbError) -> Self {
+        Self::DbError(db_err)
+    }
+}
+
+impl IntoResponse for ModerationError {
+    fn into_response(self) -> Response {
+
+        match self {
+            ModerationError::DbError(db_err) => db_err.into_response(),
+            ModerationError::NoSuchEntry(id) => (
+                StatusCode::NOT_FOUND, format!("There's no guestbook entry with the ID {id}.")
+            ).into_response(),
+            ModerationError::NothingToChange => (
+                StatusCode::BAD_REQUEST, "An edit needs a new name or note (or both)."
+            ).into_response(),
+            ModerationError::TooLong(field, max_bytes) => (
+                StatusCode::PAYLOAD_TOO_LARGE, format!("{field} too long! The database limits it to {max_bytes} bytes.")
+            ).into_response(),
+        }
+    }
+}
+
+fn session_of(session_left: Duration) -> AdminSession {
+    let expires_at = Utc::now().naive_utc() + session_left;
+    AdminSession { expires_at: expires_at.trunc_subsecs(0) }
+}
+
+pub async fn log_in(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(login): Json<AdminLogin>
+) -> Result<Response, LoginError> {
+
+    let client = state.proxies.client_ip(peer.ip(), &headers);
+
+    match state.admin.log_in(client, login.password, Instant::now()).await {
+        Ok(token) => {
+            audit(&state, client, AuditAction::Login, None, None).await;
+            let session_length = state.admin.session_length();
+            Ok((
+                [(SET_COOKIE, session_cookie(&token, session_length))],
+                Json(session_of(session_length))
+            ).into_response())
+        },
+        Err(login_err) => {
+            match login_err {
+                LoginError::WrongPassword => audit(&state, client, AuditAction::LoginFailed, None, None).await,
+                LoginError::LockedOut { retry_after, just_now: true } => {
+                    let detail = format!("locked out for {} minutes", retry_after.as_secs() / 60);
+                    audit(&state, client, AuditAction::LoginThrottled, None, Some(detail)).await
+                },
+                // the rest of the lockout isn't audited, or anyone could fill up the table
+                LoginError::LockedOut { .. } => {},
+            }
+            Err(login_err)
+        }
+    }
+}
+
+pub async fn log_out(
+    admin: Admin,
+    State(state): State<AppState>,
+    headers: HeaderMap
+) -> Response {
+
+    if let Some(token) = session_token(&headers) {
+        state.admin.end_session(token);
+    }
+    audit(&state, admin.client, AuditAction::Logout, None, None).await;
+
+    (StatusCode::NO_CONTENT, [(SET_COOKIE, session_cookie("", Duration::ZERO))]).into_response()
+}
+
+pub async fn get_session(admin: Admin) -> Json<AdminSession> {
+    Json(session_of(admin.session_left))
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct AuditParams {
+    limit: Option<u64>,
+}
+
+pub async fn get_audit_trail(
+    _admin: Admin,
+    State(state): State<AppState>,
+    Query(params): Query<AuditParams>
+) -> Result<Json<AuditTrail>, DbError> {
+
+    let limit = params.limit.unwrap_or(DEFAULT_AUDIT_EVENTS).clamp(1, MAX_AUDIT_EVENTS);
+    let events = state.audit.recent_events(limit).await?;
+
+    Ok(Json(AuditTrail { events }))
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct StatusParams {
+    status: Option<EntryStatus>,    // pending if it's left out
+}
+
+pub async fn get_entries_by_status(
+    _admin: Admin,
+    State(state): State<AppState>,
+    Query(params): Query<StatusParams>
+) -> Result<Json<Guestbook>, DbError> {
+
+    let entries = state.guestbook.entries_with_status(params.status.unwrap_or(EntryStatus::Pending)).await?;
+
+    Ok(Json(Guestbook { guestbook: entries }))
+}
+
+pub async fn approve_entry(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>
+) -> Result<Json<ModerationReceipt>, ModerationError> {
+    set_status(admin, state, id, EntryStatus::Approved).await
+}
+
+pub async fn reject_entry(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>
+) -> Result<Json<ModerationReceipt>, ModerationError> {
+    set_status(admin, state, id, EntryStatus::Rejected).await
+}
+
+async fn set_status(
+    admin: Admin,
+    state: AppState,
+    id: i64,
+    status: EntryStatus
+) -> Result<Json<ModerationReceipt>, ModerationError> {
+
+    if !state.guestbook.set_status(id, status).await? {
+        return Err(ModerationError::NoSuchEntry(id));
+    }
+
+    info!("Guestbook entry {id} is now {}.", status.as_str());
+    let action = match status {
+        EntryStatus::Rejected => AuditAction::Reject,
+        _                     => AuditAction::Approve,
+    };
+    audit(&state, admin.client, action, Some(id.to_string()), None).await;
+
+    // the spam filter learns from what's approved and rejected
+    state.spam.clone().retrain_soon(state.guestbook.clone());
+
+    Ok(Json(ModerationReceipt { id: id.to_string(), status }))
+}
+
+pub async fn delete_entry(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>
+) -> Result<StatusCode, ModerationError> {
+
+    let now = Utc::now().naive_utc().trunc_subsecs(0);
+    if !state.guestbook.set_deleted(id, Some(now)).await? {
+        return Err(ModerationError::NoSuchEntry(id));
+    }
+
+    info!("Guestbook entry {id} was deleted.");
+    audit(&state, admin.client, AuditAction::Delete, Some(id.to_string()), None).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn restore_entry(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>
+) -> Result<StatusCode, ModerationError> {
+
+    if !state.guestbook.set_deleted(id, None).await? {
+        return Err(ModerationError::NoSuchEntry(id));
+    }
+
+    info!("Guestbook entry {id} was restored.");
+    audit(&state, admin.client, AuditAction::Restore, Some(id.to_string()), None).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_deleted_entries(
+    _admin: Admin,
+    State(state): State<AppState>
+) -> Result<Json<Guestbook>, DbError> {
+
+    let entries = state.guestbook.deleted_entries().await?;
+
+    Ok(Json(Guestbook { guestbook: entries }))
+}
+
+pub async fn edit_entry(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(mut changes): Json<EntryChanges>
+) -> Result<Json<GuestbookEntry>, ModerationError> {
+
+    // the same rules as a new entry (see db_io::update_guestbook())
+    if changes.name.is_none() && changes.note.is_none() {
+        return Err(ModerationError::NothingToChange);
+    }
+    if changes.name.as_ref().is_some_and(|name| name.len() > MAX_NAME_BYTES) {
+        return Err(ModerationError::TooLong("Name", MAX_NAME_BYTES));
+    }
+    if changes.note.as_ref().is_some_and(|note| note.len() > MAX_NOTE_BYTES) {
+        return Err(ModerationError::TooLong("Note", MAX_NOTE_BYTES));
+    }
+    if changes.name.as_deref() == Some("") {
+        changes.name = Some(String::from("(anonymous)"));
+    }
+
+    let now = Utc::now().naive_utc().trunc_subsecs(0);
+    let Some(edited) = state.guestbook.edit_entry(id, &changes, None, now).await? else {
+        return Err(ModerationError::NoSuchEntry(id));
+    };
+
+    info!("Guestbook entry {id} was edited.");
+    let edited_fields: Vec<&str> = [("name", &changes.name), ("note", &changes.note)].into_iter()
+        .filter_map(|(field, change)| change.as_ref().map(|_| field))
+        .collect();
+    audit(&state, admin.client, AuditAction::Edit, Some(id.to_string()), Some(edited_fields.join(", "))).await;
+
+    Ok(Json(edited))
+}
+
+pub async fn get_entry_edits(
+    _admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>
+) -> Result<Json<EntryHistory>, ModerationError> {
+
+    let Some(edits) = state.guestbook.entry_edits(id).await? else {
+        return Err(ModerationError::NoSuchEntry(id));
+    };
+
+    Ok(Json(EntryHistory { id: id.to_string(), edits }))
+}
+
+pub async fn pin_entry(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>
+) -> Result<StatusCode, ModerationError> {
+    set_pinned(admin, state, id, true).await
+}
+
+pub async fn unpin_entry(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>
+) -> Result<StatusCode, ModerationError> {
+    set_pinned(admin, state, id, false).await
+}
+
+async fn set_pinned(
+    admin: Admin,
+    state: AppState,
+    id: i64,
+    pinned: bool
+) -> Result<StatusCode, ModerationError> {
+
+    if !state.guestbook.set_pinned(id, pinned).await? {
+        return Err(ModerationError::NoSuchEntry(id));
+    }
+
+    let (action, done) = if pinned { (AuditAction::Pin, "pinned") } else { (AuditAction::Unpin, "unpinned") };
+    info!("Guestbook entry {id} was {done}.");
+    audit(&state, admin.client, action, Some(id.to_string()), None).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    // These go through the whole router, so the auth is tested along with them
+    use super::*;
+    use std::{net::SocketAddr, sync::Arc};
+    use axum::{body::Body, extract::ConnectInfo, http::{header, Request}, Router};
+    use tower::ServiceExt;
+    use crate::{
+        routes::build_router,
+        srv_io::admin_auth::{hash_password, AdminAuth, MAX_LOGIN_ATTEMPTS},
+        types::db_io_types::{EntryReceipt, GuestbookEntry},
+        utils::client_ip::{TrustedProxies, X_FORWARDED_FOR},
+    };
+
+    const PASSWORD: &str = "a password for the tests";
+
+    fn test_admin() -> AdminAuth {
+        AdminAuth::with_password_hash(&hash_password(PASSWORD).unwrap(), Duration::from_secs(3600)).unwrap()
+    }
+
+    fn test_app(admin: AdminAuth) -> Router {
+        test_app_behind(admin, TrustedProxies::none())
+    }
+
+    fn test_app_behind(admin: AdminAuth, proxies: TrustedProxies) -> Router {
+        let (state, _, _) = AppState::for_tests();
+        build_router(AppState {
+            admin: Arc::new(admin),
+            moderation: true,
+            proxies: Arc::new(proxies),
+            ..state
+        })
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(app: &Router, req: Request<Body>) -> (StatusCode, Option<T>) {
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, mysql_common::serde_json::from_slice(&body).ok())
+    }
+
+    fn from_client(mut req: Request<Body>, ip: [u8; 4]) -> Request<Body> {
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from((ip, 54321))));
+        req
+    }
+
+    fn login(password: &str, ip: [u8; 4]) -> Request<Body> {
+        from_client(
+            Request::post("/admin/login")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(format!(r#"{{"password": "{password}"}}"#)))
+                .unwrap(),
+            ip
+        )
+    }
+
+    // logs in, and returns the session cookie
+    async fn log_in_as_admin(app: &Router) -> String {
+        let resp = app.clone().oneshot(login(PASSWORD, [203, 0, 113, 7])).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let set_cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    fn as_admin(path: &str, method: &str, cookie: &str) -> Request<Body> {
+        from_client(
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::COOKIE, cookie)
+                .body(Body::empty())
+                .unwrap(),
+            [203, 0, 113, 7]
+        )
+    }
+
+    fn post_entry(name: &str) -> Request<Body> {
+        Request::post("/guestbook/entries")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"name": "{name}", "note": "hi"}}"#)))
+            .unwrap()
+    }
+
+    fn names(guestbook: Option<Guestbook>) -> Vec<String> {
+        guestbook.unwrap().guestbook.into_iter().map(|entry: GuestbookEntry| entry.name).collect()
+    }
+
+    async fn actions(app: &Router, cookie: &str) -> Vec<(AuditAction, Option<String>)> {
+        let (_, trail) = send::<AuditTrail>(app, as_admin("/admin/audit", "GET", cookie)).await;
+        trail.unwrap().events.into_iter().map(|event| (event.action, event.entry_id)).collect()
+    }
+
+    #[tokio::test]
+    async fn moderating_entries() {
+
+        let app = test_app(test_admin());
+        let cookie = log_in_as_admin(&app).await;
+        let shown = || Request::get("/guestbook/entries").body(Body::empty()).unwrap();
+
+        for name in ["Ada", "Linus", "Grace"] {
+            let (status, receipt) = send::<EntryReceipt>(&app, post_entry(name)).await;
+            assert_eq!((status, receipt.unwrap().status), (StatusCode::OK, EntryStatus::Pending));
+        }
+        assert!(names(send(&app, shown()).await.1).is_empty());
+
+        let (status, pending) = send(&app, as_admin("/admin/guestbook/entries", "GET", &cookie)).await;
+        assert_eq!((status, names(pending)), (StatusCode::OK, vec![String::from("Ada"), String::from("Linus"), String::from("Grace")]));
+
+        let (status, receipt) = send(&app, as_admin("/admin/guestbook/entries/1/approve", "POST", &cookie)).await;
+        assert_eq!((status, receipt), (StatusCode::OK, Some(ModerationReceipt { id: String::from("1"), status: EntryStatus::Approved })));
+        send::<ModerationReceipt>(&app, as_admin("/admin/guestbook/entries/2/reject", "POST", &cookie)).await;
+        assert_eq!(names(send(&app, shown()).await.1), vec!["Ada"]);
+
+        let (_, pending) = send(&app, as_admin("/admin/guestbook/entries", "GET", &cookie)).await;
+        assert_eq!(names(pending), vec!["Grace"]);
+        let (_, rejected) = send(&app, as_admin("/admin/guestbook/entries?status=rejected", "GET", &cookie)).await;
+        assert_eq!(names(rejected), vec!["Linus"]);
+
+        // and changing its mind
+        send::<ModerationReceipt>(&app, as_admin("/admin/guestbook/entries/2/approve", "POST", &cookie)).await;
+        let mut shown_now = names(send(&app, shown()).await.1);
+        shown_now.sort();
+        assert_eq!(shown_now, vec!["Ada", "Linus"]);
+
+        let (status, _) = send::<ModerationReceipt>(&app, as_admin("/admin/guestbook/entries/99/approve", "POST", &cookie)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        assert_eq!(actions(&app, &cookie).await, vec![
+            (AuditAction::Approve, Some(String::from("2"))),
+            (AuditAction::Reject, Some(String::from("2"))),
+            (AuditAction::Approve, Some(String::from("1"))),
+            (AuditAction::Login, None),
+        ]);
+    }
+
+    fn with_json(mut req: Request<Body>, json: &str) -> Request<Body> {
+        req.headers_mut().insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+        *req.body_mut() = Body::from(json.to_string());
+        req
+    }
+
+    #[tokio::test]
+    async fn cleaning_up_entries() {
+
+        let app = test_app(test_admin());
+        let cookie = log_in_as_admin(&app).await;
+        let shown = || Request::get("/guestbook/entries").body(Body::empty()).unwrap();
+
+        for name in ["Ada", "Linus", "Grace"] {
+            send::<EntryReceipt>(&app, post_entry(name)).await;
+        }
+        for id in 1..=3 {
+            send::<ModerationReceipt>(&app, as_admin(&format!("/admin/guestbook/entries/{id}/approve"), "POST", &cookie)).await;
+        }
+        assert_eq!(names(send(&app, shown()).await.1).len(), 3);
+
+        let (status, _) = send::<()>(&app, as_admin("/admin/guestbook/entries/2", "DELETE", &cookie)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(names(send(&app, shown()).await.1).iter().all(|name| name != "Linus"));
+        let (_, deleted) = send(&app, as_admin("/admin/guestbook/deleted", "GET", &cookie)).await;
+        assert_eq!(names(deleted), vec!["Linus"]);
+        assert_eq!(send::<()>(&app, as_admin("/admin/guestbook/entries/2/restore", "POST", &cookie)).await.0, StatusCode::NO_CONTENT);
+        assert_eq!(names(send(&app, shown()).await.1).len(), 3);
+
+        let edit = |id: &str, json: &str| with_json(as_admin(&format!("/admin/guestbook/entries/{id}"), "PATCH", &cookie), json);
+        let (status, edited) = send::<GuestbookEntry>(&app, edit("1", r#"{"name": "Ada Lovelace"}"#)).await;
+        assert_eq!((status, edited.unwrap().name), (StatusCode::OK, String::from("Ada Lovelace")));
+        let (status, edited) = send::<GuestbookEntry>(&app, edit("1", r#"{"name": "", "note": "bye"}"#)).await;
+        let edited = edited.unwrap();
+        assert_eq!((status, edited.name.as_str(), edited.note.as_str()), (StatusCode::OK, "(anonymous)", "bye"));
+        assert_eq!(send::<()>(&app, edit("1", "{}")).await.0, StatusCode::BAD_REQUEST);
+        let too_long = format!(r#"{{"note": "{}"}}"#, "a".repeat(MAX_NOTE_BYTES + 1));
+        assert_eq!(send::<()>(&app, edit("1", &too_long)).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(send::<()>(&app, edit("99", r#"{"name": "Nobody"}"#)).await.0, StatusCode::NOT_FOUND);
+
+        let (status, history) = send::<EntryHistory>(&app, as_admin("/admin/guestbook/entries/1/edits", "GET", &cookie)).await;
+        let old: Vec<(String, String)> = history.unwrap().edits.into_iter().map(|edit| (edit.old_name, edit.old_note)).collect();
+        assert_eq!((status, old), (StatusCode::OK, vec![
+            (String::from("Ada"), String::from("hi")),
+            (String::from("Ada Lovelace"), String::from("hi")),
+        ]));
+
+        // the oldest one, pinned to the top
+        assert_eq!(send::<()>(&app, as_admin("/admin/guestbook/entries/1/pin", "POST", &cookie)).await.0, StatusCode::NO_CONTENT);
+        let (_, guestbook) = send::<Guestbook>(&app, shown()).await;
+        let top = guestbook.unwrap().guestbook.remove(0);
+        assert_eq!((top.name.as_str(), top.pinned), ("(anonymous)", Some(true)));
+        send::<()>(&app, as_admin("/admin/guestbook/entries/1/unpin", "POST", &cookie)).await;
+        let (_, guestbook) = send::<Guestbook>(&app, shown()).await;
+        assert!(guestbook.unwrap().guestbook.iter().all(|entry| entry.pinned.is_none()));
+        assert_eq!(send::<()>(&app, as_admin("/admin/guestbook/entries/99/pin", "POST", &cookie)).await.0, StatusCode::NOT_FOUND);
+
+        let id = |id: &str| Some(String::from(id));
+        assert_eq!(actions(&app, &cookie).await[..7], [
+            (AuditAction::Unpin, id("1")),
+            (AuditAction::Pin, id("1")),
+            (AuditAction::Edit, id("1")),
+            (AuditAction::Edit, id("1")),
+            (AuditAction::Restore, id("2")),
+            (AuditAction::Delete, id("2")),
+            (AuditAction::Approve, id("3")),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn logging_in_and_out() {
+
+        let app = test_app(test_admin());
+
+        let (status, _) = send::<AdminSession>(&app, login("not the password", [198, 51, 100, 1])).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let resp = app.clone().oneshot(login(PASSWORD, [203, 0, 113, 7])).await.unwrap();
+        let set_cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+        assert!(set_cookie.ends_with("; Path=/admin; Max-Age=3600; HttpOnly; Secure; SameSite=Strict"));
+        let cookie = set_cookie.split(';').next().unwrap();
+
+        let (status, session) = send::<AdminSession>(&app, as_admin("/admin/session", "GET", cookie)).await;
+        assert_eq!(status, StatusCode::OK);
+        let left = session.unwrap().expires_at - Utc::now().naive_utc();
+        assert!(left.num_minutes() >= 59 && left.num_minutes() <= 60);
+
+        // along with whatever other cookies the browser has for the site
+        let with_others = format!("theme=dark; {cookie}");
+        assert_eq!(send::<AdminSession>(&app, as_admin("/admin/session", "GET", &with_others)).await.0, StatusCode::OK);
+
+        let resp = app.clone().oneshot(as_admin("/admin/logout", "POST", cookie)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(resp.headers()[header::SET_COOKIE].to_str().unwrap().starts_with("archie_admin=; Path=/admin; Max-Age=0;"));
+        assert_eq!(send::<AdminSession>(&app, as_admin("/admin/session", "GET", cookie)).await.0, StatusCode::UNAUTHORIZED);
+
+        let cookie = log_in_as_admin(&app).await;
+        assert_eq!(actions(&app, &cookie).await, vec![
+            (AuditAction::Login, None),
+            (AuditAction::Logout, None),
+            (AuditAction::Login, None),
+            (AuditAction::LoginFailed, None),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn audited_behind_a_proxy() {
+
+        // everything comes from the proxy, so it's who it forwarded for that's audited
+        let app = test_app_behind(test_admin(), TrustedProxies::parse("203.0.113.7").unwrap());
+        let forwarded_for = |mut req: Request<Body>, client: &str| {
+            req.headers_mut().insert(X_FORWARDED_FOR, client.parse().unwrap());
+            req
+        };
+
+        send::<AdminSession>(&app, forwarded_for(login("not the password", [203, 0, 113, 7]), "198.51.100.1")).await;
+        let resp = app.clone().oneshot(forwarded_for(login(PASSWORD, [203, 0, 113, 7]), "198.51.100.2")).await.unwrap();
+        let cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap().split(';').next().unwrap().to_string();
+        send::<()>(&app, forwarded_for(as_admin("/admin/logout", "POST", &cookie), "198.51.100.3")).await;
+
+        let cookie = log_in_as_admin(&app).await;
+        let (_, trail) = send::<AuditTrail>(&app, as_admin("/admin/audit", "GET", &cookie)).await;
+        let clients: Vec<(AuditAction, String)> = trail.unwrap().events.into_iter().map(|event| (event.action, event.client_ip)).collect();
+        assert_eq!(clients, vec![
+            (AuditAction::Login, String::from("203.0.113.7")),
+            (AuditAction::Logout, String::from("198.51.100.3")),
+            (AuditAction::Login, String::from("198.51.100.2")),
+            (AuditAction::LoginFailed, String::from("198.51.100.1")),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn guessing_gets_locked_out() {
+
+        let app = test_app(test_admin());
+        let guesser = [198, 51, 100, 1];
+
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            assert_eq!(send::<AdminSession>(&app, login("guess", guesser)).await.0, StatusCode::UNAUTHORIZED);
+        }
+        for _ in 0..3 {
+            let resp = app.clone().oneshot(login(PASSWORD, guesser)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+            let retry_after: u64 = resp.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+            assert!((890..=900).contains(&retry_after));
+        }
+
+        // only the start of the lockout is in the trail
+        let cookie = log_in_as_admin(&app).await;
+        let trail = actions(&app, &cookie).await;
+        assert_eq!(trail[..2], [(AuditAction::Login, None), (AuditAction::LoginThrottled, None)]);
+        assert_eq!(trail.len(), 2 + MAX_LOGIN_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn locked_out_behind_a_proxy() {
+
+        let proxy = [203, 0, 113, 7];
+        let app = test_app_behind(test_admin(), TrustedProxies::parse("203.0.113.7").unwrap());
+        let login_for = |password: &str, client: &str| {
+            let mut req = login(password, proxy);
+            req.headers_mut().insert(X_FORWARDED_FOR, client.parse().unwrap());
+            req
+        };
+
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            assert_eq!(send::<AdminSession>(&app, login_for("guess", "198.51.100.1")).await.0, StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(send::<AdminSession>(&app, login_for(PASSWORD, "198.51.100.1")).await.0, StatusCode::TOO_MANY_REQUESTS);
+        // it's the guesser that's locked out, not everyone else behind the proxy
+        assert_eq!(send::<AdminSession>(&app, login_for(PASSWORD, "198.51.100.2")).await.0, StatusCode::OK);
+        assert_eq!(send::<AdminSession>(&app, login(PASSWORD, proxy)).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn only_for_the_admin() {
+
+        let app = test_app(test_admin());
+        send::<EntryReceipt>(&app, post_entry("Ada")).await;
+
+        let no_cookie = from_client(Request::post("/admin/guestbook/entries/1/approve").body(Body::empty()).unwrap(), [203, 0, 113, 7]);
+        assert_eq!(send::<ModerationReceipt>(&app, no_cookie).await.0, StatusCode::UNAUTHORIZED);
+
+        for req in [
+            as_admin("/admin/guestbook/entries/1/approve", "POST", "archie_admin=not-a-session"),
+            as_admin("/admin/guestbook/entries", "GET", "archie_admin="),
+            as_admin("/admin/audit", "GET", "somethingelse=1"),
+            as_admin("/admin/logout", "POST", ""),
+        ] {
+            assert_eq!(send::<ModerationReceipt>(&app, req).await.0, StatusCode::UNAUTHORIZED);
+        }
+        let cookie = log_in_as_admin(&app).await;
+        let (_, pending) = send(&app, as_admin("/admin/guestbook/entries", "GET", &cookie)).await;
+        assert_eq!(names(pending), vec!["Ada"]);
+
+        // and without a password set, the routes aren't there, and all
+        // that's left is the front end, which only takes GET's
+        let app = test_app(AdminAuth::disabled());
+        for req in [login(PASSWORD, [203, 0, 113, 7]), as_admin("/admin/guestbook/entries/1/approve", "POST", &cookie)] {
+            assert_eq!(send::<ModerationReceipt>(&app, req).await.0, StatusCode::METHOD_NOT_ALLOWED);
+        }
+    }
+}
@@ -0,0 +1,467 @@
+// Lets guests fix a typo in their entry, or take it back, for a little while
+// after posting it. update_guestbook() hands each new entry's author a random
+// token in the receipt, and only keeps its SHA-256. With the token sent back
+// as `Authorization: Bearer {token}`,
+//
+//     PATCH  /guestbook/entries/{id}       {"name": ..., "note": ...}; either can be left out
+//     DELETE /guestbook/entries/{id}
+//
+// change or delete that entry, and only that one, until AUTHOR_EDIT_GRACE_SECS
+// (default 900) after it was posted. 0 turns it off, and then there are no
+// tokens, and no routes either.
+//
+// Edits follow the same rules as a new entry, and go through the spam filter
+// again; with moderation on, an approved entry has to be approved again once
+// it's been changed. They're kept in the entry's history, like the admin's
+// (see admin_io.rs), and deleted entries are only hidden, so the admin can
+// still see what was there, and restore it.
+use std::time::{Duration, Instant};
+use axum::{
+    extract::{Path, State},
+    http::{header::{AUTHORIZATION, WWW_AUTHENTICATE}, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json
+};
+use mysql_common::chrono::{NaiveDateTime, SubsecRound, TimeDelta, Utc};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+use crate::{
+    app_state::AppState,
+    srv_io::db_io::{DbError, MAX_NAME_BYTES, MAX_NOTE_BYTES},
+    storage::{AuthorToken, EditReview},
+    types::db_io_types::{AuthorEditError, AuthorEditFailed, EntryChanges, EntryStatus, GuestbookEntry, SpamVerdict},
+    utils::init_utils::get_env_var_or,
+};
+
+
+pub const DEFAULT_GRACE_SECS: u64 = 900;
+const TOKEN_BYTES: usize = 32;
+
+
+// None if AUTHOR_EDIT_GRACE_SECS is 0
+pub fn grace_from_env() -> Option<Duration> {
+    match get_env_var_or("AUTHOR_EDIT_GRACE_SECS", DEFAULT_GRACE_SECS) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+// a new token for an entry's author, and the hash of it that's kept
+pub fn new_token() -> (String, String) {
+
+    let mut bytes = [0u8; TOKEN_BYTES];
+    // unwrap only panics if the OS has no source of randomness at all
+    getrandom::fill(&mut bytes).unwrap();
+    let token: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+// The tokens are random enough that a plain SHA-256 is all it takes to keep
+// them from being used by anyone who gets a look at the database. Hex, so
+// it fits in a CHAR(64).
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token).iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// when the entry's author can't change it anymore
+pub fn editable_until(posted: NaiveDateTime, grace: Duration) -> NaiveDateTime {
+    posted + TimeDelta::from_std(grace).unwrap_or(TimeDelta::MAX)
+}
+
+
+#[derive(Debug)]
+pub enum OwnEntryError {
+    DbError(DbError),
+    Refused(AuthorEditError),
+}
+
+impl From<DbError> for OwnEntryError {
+    fn from(db_err: DbError) -> Self {
+        Self::DbError(db_err)
+    }
+}
+
+impl From<AuthorEditError> for OwnEntryError {
+    fn from(err: AuthorEditError) -> Self {
+        Self::Refused(err)
+    }
+}
+
+impl AuthorEditError {
+
+    pub fn message(&self) -> String {
+        match self {
+            AuthorEditError::MissingToken    => String::from("This needs the entry's edit token, as Authorization: Bearer {token}."),
+            AuthorEditError::InvalidToken    => String::from("That isn't this entry's edit token."),
+            AuthorEditError::ExpiredToken    => String::from("It's too late to change this entry. Ask the site's admin instead!"),
+            AuthorEditError::NoSuchEntry     => String::from("There's no guestbook entry with that ID."),
+            AuthorEditError::NothingToChange => String::from("An edit needs a new name or note (or both)."),
+            AuthorEditError::NameTooLong     => format!("Name too long! The database limits it to {MAX_NAME_BYTES} bytes."),
+            AuthorEditError::NoteTooLong     => format!("Note too long! The database limits it to {MAX_NOTE_BYTES} bytes."),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            AuthorEditError::MissingToken    => StatusCode::UNAUTHORIZED,
+            AuthorEditError::InvalidToken    => StatusCode::FORBIDDEN,
+            AuthorEditError::ExpiredToken    => StatusCode::FORBIDDEN,
+            AuthorEditError::NoSuchEntry     => StatusCode::NOT_FOUND,
+            AuthorEditError::NothingToChange => StatusCode::BAD_REQUEST,
+            AuthorEditError::NameTooLong     => StatusCode::PAYLOAD_TOO_LARGE,
+            AuthorEditError::NoteTooLong     => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl IntoResponse for OwnEntryError {
+    fn into_response(self) -> Response {
+
+        match self {
+            OwnEntryError::DbError(db_err) => db_err.into_response(),
+            OwnEntryError::Refused(err) => {
+                let mut resp = (err.status(), Json(AuthorEditFailed { error: err, message: err.message() })).into_response();
+                if err == AuthorEditError::MissingToken {
+                    resp.headers_mut().insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+                }
+                resp
+            }
+        }
+    }
+}
+
+
+// whatever follows "Bearer ", if there is anything
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(AUTHORIZATION)?
+        .to_str().ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+// Whether the token is the entry's, and still good; the entry's
+// status and what it says now, if it is. The token's checked before
+// the time, so only its author finds out it's too late.
+async fn authorize(state: &AppState, id: i64, headers: &HeaderMap, now: NaiveDateTime) -> Result<AuthorToken, OwnEntryError> {
+
+    // the routes aren't there with it off, so this is just in case
+    let Some(grace) = state.edit_grace else {
+        return Err(AuthorEditError::ExpiredToken.into());
+    };
+    let token = bearer_token(headers).ok_or(AuthorEditError::MissingToken)?;
+
+    let author = match state.guestbook.author_token(id).await? {
+        Some(author) if !author.deleted => author,
+        _ => return Err(AuthorEditError::NoSuchEntry.into()),
+    };
+    if author.token_hash.as_deref() != Some(hash_token(token).as_str()) {
+        debug!("Someone tried to change guestbook entry {id} without its token.");
+        return Err(AuthorEditError::InvalidToken.into());
+    }
+    if author.posted.is_none_or(|posted| editable_until(posted, grace) <= now) {
+        return Err(AuthorEditError::ExpiredToken.into());
+    }
+
+    Ok(author)
+}
+
+pub async fn edit_own_entry(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Json(mut changes): Json<EntryChanges>
+) -> Result<Json<GuestbookEntry>, OwnEntryError> {
+
+    let now = Utc::now().naive_utc().trunc_subsecs(0);
+    let author = authorize(&state, id, &headers, now).await?;
+
+    // the same rules as a new entry (see db_io::update_guestbook())
+    if changes.name.is_none() && changes.note.is_none() {
+        return Err(AuthorEditError::NothingToChange.into());
+    }
+    if changes.name.as_ref().is_some_and(|name| name.len() > MAX_NAME_BYTES) {
+        return Err(AuthorEditError::NameTooLong.into());
+    }
+    if changes.note.as_ref().is_some_and(|note| note.len() > MAX_NOTE_BYTES) {
+        return Err(AuthorEditError::NoteTooLong.into());
+    }
+    if changes.name.as_deref() == Some("") {
+        changes.name = Some(String::from("(anonymous)"));
+    }
+
+    // Otherwise it'd be easy to get something past the filter, or the admin,
+    // by changing it afterwards. It's only ever made less visible here, so a
+    // rejected entry stays that way, however it's changed. The verdict's
+    // saved with the edit, so the admin sees why it's back in the queue.
+    let status = author.status;
+    let spam = state.spam.check(&GuestbookEntry {
+        id: Some(id.to_string()),
+        time_stamp: author.posted,
+        name: changes.name.clone().unwrap_or(author.name),
+        note: changes.note.clone().unwrap_or(author.note),
+        pinned: None,
+        spam: None,
+        parent_id: None,
+        replies: None,
+        reactions: None,
+    }, Instant::now());
+    let new_status = match (status, spam.verdict) {
+        (_, SpamVerdict::Drop) => EntryStatus::Rejected,
+        (EntryStatus::Approved, SpamVerdict::Hold) => EntryStatus::Pending,
+        (EntryStatus::Approved, _) if state.moderation => EntryStatus::Pending,
+        _ => status,
+    };
+
+    let review = EditReview { status: new_status, spam };
+    let Some(edited) = state.guestbook.edit_entry(id, &changes, Some(&review), now).await? else {
+        return Err(AuthorEditError::NoSuchEntry.into());
+    };
+
+    let spam = review.spam;
+    match spam.verdict {
+        SpamVerdict::Accept => info!("Guestbook entry {id} was edited by its author."),
+        _ => info!(
+            "Guestbook entry {id} was edited by its author, and is {} now ({}).",
+            new_status.as_str(), spam.reason.unwrap_or_default()
+        ),
+    }
+
+    Ok(Json(edited))
+}
+
+pub async fn delete_own_entry(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap
+) -> Result<StatusCode, OwnEntryError> {
+
+    let now = Utc::now().naive_utc().trunc_subsecs(0);
+    authorize(&state, id, &headers, now).await?;
+
+    if !state.guestbook.set_deleted(id, Some(now)).await? {
+        return Err(AuthorEditError::NoSuchEntry.into());
+    }
+
+    info!("Guestbook entry {id} was deleted by its author.");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::Arc;
+    use axum::{body::Body, http::{header, Request}, Router};
+    use tower::ServiceExt;
+    use crate::{
+        routes::build_router,
+        storage::{mem_store::MemStore, GuestbookStore},
+        types::db_io_types::{EntryReceipt, Guestbook},
+        utils::spam_filter::{BannedRules, SpamFilter},
+    };
+
+    fn test_app(edit_grace: Option<Duration>, moderation: bool) -> (Router, Arc<MemStore>) {
+        let (state, _, mem_store) = AppState::for_tests();
+        let app = build_router(AppState {
+            edit_grace,
+            moderation,
+            spam: Arc::new(SpamFilter::disabled().with_check(BannedRules::built_in())),
+            ..state
+        });
+        (app, mem_store)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(app: &Router, req: Request<Body>) -> (StatusCode, Option<T>) {
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, mysql_common::serde_json::from_slice(&body).ok())
+    }
+
+    async fn post_entry(app: &Router, name: &str) -> EntryReceipt {
+        let req = Request::post("/guestbook/entries")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"name": "{name}", "note": "hi"}}"#)))
+            .unwrap();
+        send(app, req).await.1.unwrap()
+    }
+
+    fn as_author(method: &str, id: &str, token: Option<&str>, json: &str) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(format!("/guestbook/entries/{id}"))
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        req.body(Body::from(json.to_string())).unwrap()
+    }
+
+    async fn refused(app: &Router, req: Request<Body>) -> (StatusCode, AuthorEditError) {
+        let (status, failed) = send::<AuthorEditFailed>(app, req).await;
+        let failed = failed.unwrap();
+        assert_eq!(failed.message, failed.error.message());
+        (status, failed.error)
+    }
+
+    async fn shown(app: &Router) -> Vec<(String, String)> {
+        let (_, guestbook) = send::<Guestbook>(app, Request::get("/guestbook/entries").body(Body::empty()).unwrap()).await;
+        // sorted, since they're often posted in the same second
+        let mut shown: Vec<_> = guestbook.unwrap().guestbook.into_iter().map(|entry| (entry.name, entry.note)).collect();
+        shown.sort();
+        shown
+    }
+
+    #[tokio::test]
+    async fn changing_own_entries() {
+        let (app, mem_store) = test_app(Some(Duration::from_secs(600)), false);
+
+        let receipt = post_entry(&app, "Sam").await;
+        let token = receipt.edit_token.clone().unwrap();
+        assert_eq!(token.len(), 2 * TOKEN_BYTES);
+        assert_eq!(receipt.editable_until, Some(receipt.time_stamp + TimeDelta::seconds(600)));
+        let other = post_entry(&app, "Alex").await;
+        assert_ne!(other.edit_token, receipt.edit_token);
+        // only the hash is kept
+        let kept = mem_store.author_token(receipt.id.parse().unwrap()).await.unwrap().unwrap();
+        assert_eq!(kept.token_hash, Some(hash_token(&token)));
+
+        let typo_fix = r#"{"note": "hi there!"}"#;
+        assert_eq!(
+            refused(&app, as_author("PATCH", &receipt.id, None, typo_fix)).await,
+            (StatusCode::UNAUTHORIZED, AuthorEditError::MissingToken)
+        );
+        let resp = app.clone().oneshot(as_author("PATCH", &receipt.id, None, typo_fix)).await.unwrap();
+        assert_eq!(resp.headers()[WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(
+            refused(&app, as_author("PATCH", &receipt.id, Some("not-the-token"), typo_fix)).await,
+            (StatusCode::FORBIDDEN, AuthorEditError::InvalidToken)
+        );
+        // a token's only good for its own entry
+        assert_eq!(
+            refused(&app, as_author("PATCH", &other.id, Some(&token), typo_fix)).await,
+            (StatusCode::FORBIDDEN, AuthorEditError::InvalidToken)
+        );
+        assert_eq!(
+            refused(&app, as_author("PATCH", "99", Some(&token), typo_fix)).await,
+            (StatusCode::NOT_FOUND, AuthorEditError::NoSuchEntry)
+        );
+        assert_eq!(
+            refused(&app, as_author("PATCH", &receipt.id, Some(&token), "{}")).await,
+            (StatusCode::BAD_REQUEST, AuthorEditError::NothingToChange)
+        );
+        let overlong = format!(r#"{{"note": "{}"}}"#, "a".repeat(MAX_NOTE_BYTES + 1));
+        assert_eq!(
+            refused(&app, as_author("PATCH", &receipt.id, Some(&token), &overlong)).await,
+            (StatusCode::PAYLOAD_TOO_LARGE, AuthorEditError::NoteTooLong)
+        );
+
+        let (status, edited) = send::<GuestbookEntry>(&app, as_author("PATCH", &receipt.id, Some(&token), typo_fix)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(edited.unwrap().note, "hi there!");
+        // and again, as long as there's time
+        let (status, _) = send::<GuestbookEntry>(&app, as_author("PATCH", &receipt.id, Some(&token), r#"{"name": ""}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(shown(&app).await, vec![
+            (String::from("(anonymous)"), String::from("hi there!")),
+            (String::from("Alex"), String::from("hi")),
+        ]);
+        let edits = mem_store.entry_edits(receipt.id.parse().unwrap()).await.unwrap().unwrap();
+        assert_eq!(edits.iter().map(|edit| edit.old_note.as_str()).collect::<Vec<_>>(), vec!["hi", "hi there!"]);
+
+        // deleted entries are gone for their authors too
+        let resp = app.clone().oneshot(as_author("DELETE", &other.id, Some(&token), "")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = app.clone().oneshot(as_author("DELETE", &receipt.id, Some(&token), "")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(shown(&app).await, vec![(String::from("Alex"), String::from("hi"))]);
+        assert_eq!(
+            refused(&app, as_author("DELETE", &receipt.id, Some(&token), "")).await,
+            (StatusCode::NOT_FOUND, AuthorEditError::NoSuchEntry)
+        );
+        assert_eq!(mem_store.deleted_entries().await.unwrap()[0].id, Some(receipt.id));
+    }
+
+    #[tokio::test]
+    async fn too_late_to_change() {
+        let (app, mem_store) = test_app(Some(Duration::from_secs(600)), false);
+
+        let (token, hash) = new_token();
+        let posted = Utc::now().naive_utc().trunc_subsecs(0) - TimeDelta::seconds(600);
+        let entry = GuestbookEntry { id: None, time_stamp: Some(posted), name: String::from("Sam"), note: String::from("hi"), pinned: None, spam: None, parent_id: None, replies: None, reactions: None };
+        let id = mem_store.add_entry(&entry, EntryStatus::Approved, Some(&hash)).await.unwrap();
+
+        assert_eq!(
+            refused(&app, as_author("PATCH", &id, Some(&token), r#"{"note": "bye"}"#)).await,
+            (StatusCode::FORBIDDEN, AuthorEditError::ExpiredToken)
+        );
+        assert_eq!(
+            refused(&app, as_author("DELETE", &id, Some(&token), "")).await,
+            (StatusCode::FORBIDDEN, AuthorEditError::ExpiredToken)
+        );
+        // but only its author gets told that
+        assert_eq!(
+            refused(&app, as_author("DELETE", &id, Some(&new_token().0), "")).await,
+            (StatusCode::FORBIDDEN, AuthorEditError::InvalidToken)
+        );
+        assert_eq!(shown(&app).await, vec![(String::from("Sam"), String::from("hi"))]);
+    }
+
+    #[tokio::test]
+    async fn edits_are_checked_again() {
+        let (app, mem_store) = test_app(Some(Duration::from_secs(600)), false);
+        let status_of = |id: &str| {
+            let mem_store = mem_store.clone();
+            let id: i64 = id.parse().unwrap();
+            async move { mem_store.author_token(id).await.unwrap().unwrap().status }
+        };
+        // what the admin sees in the queue, saved along with the edit
+        let verdict_of = |id: &str, status| {
+            let (mem_store, id) = (mem_store.clone(), Some(id.to_string()));
+            async move {
+                let entries = mem_store.entries_with_status(status).await.unwrap();
+                entries.into_iter().find(|entry| entry.id == id).unwrap().spam.unwrap().verdict
+            }
+        };
+
+        let receipt = post_entry(&app, "Sam").await;
+        let token = receipt.edit_token.unwrap();
+        let (status, _) = send::<GuestbookEntry>(&app, as_author("PATCH", &receipt.id, Some(&token), r#"{"note": "cheap viagra"}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(status_of(&receipt.id).await, EntryStatus::Rejected);
+        assert_eq!(verdict_of(&receipt.id, EntryStatus::Rejected).await, SpamVerdict::Drop);
+        // and there's no getting it back by changing it again, though the verdict's for what it says now
+        send::<GuestbookEntry>(&app, as_author("PATCH", &receipt.id, Some(&token), r#"{"note": "hi"}"#)).await;
+        assert_eq!(status_of(&receipt.id).await, EntryStatus::Rejected);
+        assert_eq!(verdict_of(&receipt.id, EntryStatus::Rejected).await, SpamVerdict::Accept);
+
+        let receipt = post_entry(&app, "Alex").await;
+        send::<GuestbookEntry>(&app, as_author("PATCH", &receipt.id, Some(&receipt.edit_token.unwrap()), r#"{"note": "ask me about crypto"}"#)).await;
+        assert_eq!(status_of(&receipt.id).await, EntryStatus::Pending);
+        assert_eq!(verdict_of(&receipt.id, EntryStatus::Pending).await, SpamVerdict::Hold);
+
+        // with moderation on, approved entries have to be approved again
+        let (app, mem_store) = test_app(Some(Duration::from_secs(600)), true);
+        let receipt = post_entry(&app, "Sam").await;
+        let id: i64 = receipt.id.parse().unwrap();
+        mem_store.set_status(id, EntryStatus::Approved).await.unwrap();
+        send::<GuestbookEntry>(&app, as_author("PATCH", &receipt.id, Some(&receipt.edit_token.unwrap()), r#"{"note": "bye"}"#)).await;
+        assert_eq!(mem_store.author_token(id).await.unwrap().unwrap().status, EntryStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn turned_off() {
+        let (app, _) = test_app(None, false);
+
+        let receipt = post_entry(&app, "Sam").await;
+        assert_eq!((receipt.edit_token, receipt.editable_until), (None, None));
+        // there's no such route, so it's only the statics that'd have it
+        let resp = app.clone().oneshot(as_author("DELETE", &receipt.id, Some("anything"), "")).await.unwrap();
+        assert!(!resp.status().is_success());
+        assert_eq!(shown(&app).await, vec![(String::from("Sam"), String::from("hi"))]);
+    }
+}
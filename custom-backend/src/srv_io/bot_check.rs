@@ -0,0 +1,401 @@
+// Makes it a little more work for bots to sign the guestbook. Before posting,
+// the page asks GET /guestbook/challenge for a proof-of-work challenge, and
+// has to find a nonce where the SHA-256 of "{token}:{nonce}" starts with
+// enough zero bits (like hashcash). That takes a browser a moment, but adds
+// up for anyone posting thousands of entries. Each token's only good once,
+// and only until it expires, so they can't be solved ahead of time in bulk.
+//
+// There's also a honeypot: a "website" field in the form that's hidden from
+// people, so anything that fills it in is a bot that's filling in every field.
+//
+// Both are checked in update_guestbook(), before anything's written. The
+// challenge is set with:
+//
+//     GUESTBOOK_POW_DIFFICULTY    how many zero bits (default 16; 0 turns it off)
+//     GUESTBOOK_POW_TTL_SECS      how long a challenge is good for (default 300)
+//     GUESTBOOK_POW_SECRET        what the tokens are signed with
+//
+// Nothing's kept for the challenges that are handed out. A token is a random
+// ID and when it expires, signed (HMAC-SHA256) with the secret, so it can be
+// checked without having been kept, by any replica with the same secret.
+// Only the IDs of the ones that have been used are kept, until they expire.
+// Without the secret, each run makes up its own, so with more than one
+// replica, a challenge has to be solved for the one that handed it out.
+// GET /guestbook/challenge is rate limited (see rate_limit.rs).
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use hmac::{Hmac, KeyInit, Mac};
+use mysql_common::chrono::{NaiveDateTime, SubsecRound, TimeDelta, Utc};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use crate::{
+    app_state::AppState,
+    types::db_io_types::{BotCheckError, BotCheckFailed, GuestbookPost, PowChallenge, PowSolution},
+    utils::init_utils::get_env_var_or,
+};
+
+
+pub const DEFAULT_DIFFICULTY: u32 = 16;
+pub const DEFAULT_TTL_SECS: u64 = 300;
+// each bit doubles the work, so any more than this would
+// have people waiting on the page for minutes
+pub const MAX_DIFFICULTY: u32 = 28;
+// how many used tokens are kept at once, so using lots
+// of them can't use up all the memory
+const MAX_SPENT: usize = 10_000;
+
+
+pub struct ProofOfWork {
+    difficulty: u32,    // 0 means there's no challenge
+    ttl: Duration,
+    secret: Vec<u8>,    // what the tokens are signed with
+    spent: Mutex<HashMap<String, i64>>,     // the used tokens' IDs, and when they expire
+}
+
+impl ProofOfWork {
+
+    // with a secret of its own
+    pub fn new(difficulty: u32, ttl: Duration) -> ProofOfWork {
+        let mut secret = vec![0u8; 32];
+        getrandom::fill(&mut secret).unwrap();
+        ProofOfWork::with_secret(difficulty, ttl, secret)
+    }
+
+    pub fn with_secret(difficulty: u32, ttl: Duration, secret: Vec<u8>) -> ProofOfWork {
+        ProofOfWork { difficulty: difficulty.min(MAX_DIFFICULTY), ttl, secret, spent: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn disabled() -> ProofOfWork {
+        ProofOfWork::new(0, Duration::ZERO)
+    }
+
+    pub fn from_env() -> ProofOfWork {
+
+        let difficulty = get_env_var_or("GUESTBOOK_POW_DIFFICULTY", DEFAULT_DIFFICULTY);
+        let mut ttl = get_env_var_or("GUESTBOOK_POW_TTL_SECS", DEFAULT_TTL_SECS);
+        let secret = get_env_var_or("GUESTBOOK_POW_SECRET", String::new());
+
+        if difficulty > MAX_DIFFICULTY {
+            warn!("GUESTBOOK_POW_DIFFICULTY can be at most {MAX_DIFFICULTY}; using that instead.");
+        }
+        if ttl == 0 {
+            warn!("GUESTBOOK_POW_TTL_SECS needs to be more than 0; using {DEFAULT_TTL_SECS} instead.");
+            ttl = DEFAULT_TTL_SECS;
+        }
+
+        if secret.is_empty() {
+            ProofOfWork::new(difficulty, Duration::from_secs(ttl))
+        } else {
+            ProofOfWork::with_secret(difficulty, Duration::from_secs(ttl), secret.into_bytes())
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.difficulty > 0
+    }
+
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn issue(&self, now: NaiveDateTime) -> PowChallenge {
+
+        let mut id = [0u8; 16];
+        getrandom::fill(&mut id).unwrap();
+
+        // rounded down, so the client never thinks it has longer than it does
+        let ttl = TimeDelta::from_std(self.ttl).unwrap_or(TimeDelta::MAX);
+        let expires_at = now.trunc_subsecs(0) + ttl;
+
+        let signed = format!("{}.{}", to_hex(&id), expires_at.and_utc().timestamp());
+        let token = format!("{signed}.{}", to_hex(&self.sign(&signed).finalize().into_bytes()));
+
+        PowChallenge { token, difficulty: self.difficulty, expires_at }
+    }
+
+    // Whatever happens, the token can't be used again, so a
+    // wrong answer means starting over with a new challenge
+    pub fn verify(&self, solution: Option<&PowSolution>, now: NaiveDateTime) -> Result<(), BotCheckError> {
+
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let solution = solution.ok_or(BotCheckError::MissingChallenge)?;
+
+        let (id, expires) = self.read_token(&solution.token).ok_or(BotCheckError::UnknownChallenge)?;
+        let now = now.and_utc().timestamp();
+        if expires <= now {
+            return Err(BotCheckError::ExpiredChallenge);
+        }
+        if !self.spend(id, expires, now) {
+            return Err(BotCheckError::ReusedChallenge);
+        }
+
+        if leading_zero_bits(&solution.token, solution.nonce) < self.difficulty {
+            return Err(BotCheckError::WrongSolution);
+        }
+        Ok(())
+    }
+
+    fn sign(&self, signed: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes a key of any length");
+        mac.update(signed.as_bytes());
+        mac
+    }
+
+    // the token's ID and when it expires, if it was signed with the secret
+    fn read_token<'a>(&self, token: &'a str) -> Option<(&'a str, i64)> {
+
+        let (signed, signature) = token.rsplit_once('.')?;
+        // compared in constant time, so there's no working it out a byte at a time
+        self.sign(signed).verify_slice(&from_hex(signature)?).ok()?;
+
+        let (id, expires) = signed.split_once('.')?;
+        Some((id, expires.parse().ok()?))
+    }
+
+    // false if it's been spent already
+    fn spend(&self, id: &str, expires: i64, now: i64) -> bool {
+
+        let mut spent = self.lock_spent();
+
+        if spent.len() >= MAX_SPENT && !spent.contains_key(id) {
+            spent.retain(|_, expires| *expires > now);
+            // they all last as long, so the one that expires first is the oldest; it
+            // could be used again, but only until it expires, and only after
+            // MAX_SPENT more were solved in the meantime
+            if spent.len() >= MAX_SPENT {
+                warn!("Over {MAX_SPENT} guestbook challenges were used lately; forgetting the oldest.");
+                let oldest = spent.iter().min_by_key(|(_, expires)| **expires).map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    spent.remove(&oldest);
+                }
+            }
+        }
+
+        spent.insert(id.to_string(), expires).is_none()
+    }
+
+    fn lock_spent(&self) -> MutexGuard<'_, HashMap<String, i64>> {
+        // nothing's ever left half-changed in there, so a poisoned lock is fine to use
+        self.spent.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn leading_zero_bits(token: &str, nonce: u64) -> u32 {
+
+    let hash = Sha256::digest(format!("{token}:{nonce}"));
+
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+// Both checks, honeypot first, since there's no point spending
+// a challenge on a bot. An empty (or blank) website's fine.
+pub fn check_post(pow: &ProofOfWork, post: &GuestbookPost, now: NaiveDateTime) -> Result<(), BotCheckError> {
+
+    if post.website.as_deref().is_some_and(|website| !website.trim().is_empty()) {
+        return Err(BotCheckError::Honeypot);
+    }
+    pow.verify(post.challenge.as_ref(), now)
+}
+
+
+impl BotCheckError {
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            BotCheckError::MissingChallenge => "This entry needs a solved challenge from /guestbook/challenge.",
+            BotCheckError::UnknownChallenge => "That challenge isn't one this server handed out. Try again with a new one.",
+            BotCheckError::ReusedChallenge  => "That challenge has been used already. Try again with a new one.",
+            BotCheckError::ExpiredChallenge => "That challenge has expired. Try again with a new one.",
+            BotCheckError::WrongSolution    => "That isn't a solution to the challenge.",
+            BotCheckError::Honeypot         => "This entry looks like it was sent by a bot.",
+        }
+    }
+}
+
+impl IntoResponse for BotCheckError {
+    fn into_response(self) -> Response {
+
+        debug!("Turned away a new guestbook entry: {self:?}.");
+
+        (
+            StatusCode::BAD_REQUEST,
+            Json(BotCheckFailed { error: self, message: self.message().to_string() })
+        ).into_response()
+    }
+}
+
+// only routed if the challenge is on (see routes.rs)
+pub async fn get_challenge(State(state): State<AppState>) -> Json<PowChallenge> {
+    Json(state.pow.issue(Utc::now().naive_utc()))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::types::db_io_types::GuestbookEntry;
+
+    fn solve(challenge: &PowChallenge) -> PowSolution {
+        let nonce = (0..).find(|&nonce| leading_zero_bits(&challenge.token, nonce) >= challenge.difficulty).unwrap();
+        PowSolution { token: challenge.token.clone(), nonce }
+    }
+
+    fn wrong_answer(challenge: &PowChallenge) -> PowSolution {
+        let nonce = (0..).find(|&nonce| leading_zero_bits(&challenge.token, nonce) < challenge.difficulty).unwrap();
+        PowSolution { token: challenge.token.clone(), nonce }
+    }
+
+    #[test]
+    fn zero_bits() {
+        // sha256("abc:0") is 5f36ef..., and sha256("abc:22421") is 00002ae1...
+        assert_eq!(leading_zero_bits("abc", 0), 1);
+        assert_eq!(leading_zero_bits("abc", 22421), 18);
+    }
+
+    #[test]
+    fn solving_challenges() {
+        let pow = ProofOfWork::new(8, Duration::from_secs(60));
+        let start = Utc::now().naive_utc();
+
+        let challenge = pow.issue(start);
+        assert_eq!(challenge.difficulty, 8);
+        assert!(challenge.expires_at > start);
+        assert_ne!(pow.issue(start).token, challenge.token);
+
+        let solution = solve(&challenge);
+        assert_eq!(pow.verify(Some(&solution), start), Ok(()));
+        // only the once
+        assert_eq!(pow.verify(Some(&solution), start), Err(BotCheckError::ReusedChallenge));
+
+        assert_eq!(pow.verify(None, start), Err(BotCheckError::MissingChallenge));
+        let made_up = PowSolution { token: "0".repeat(32), nonce: 1 };
+        assert_eq!(pow.verify(Some(&made_up), start), Err(BotCheckError::UnknownChallenge));
+
+        // a wrong answer spends it too
+        let challenge = pow.issue(start);
+        assert_eq!(pow.verify(Some(&wrong_answer(&challenge)), start), Err(BotCheckError::WrongSolution));
+        assert_eq!(pow.verify(Some(&solve(&challenge)), start), Err(BotCheckError::ReusedChallenge));
+
+        let challenge = pow.issue(start);
+        let late = start.trunc_subsecs(0) + TimeDelta::seconds(60);
+        assert_eq!(pow.verify(Some(&solve(&challenge)), late), Err(BotCheckError::ExpiredChallenge));
+        assert_eq!(pow.verify(Some(&solve(&challenge)), late), Err(BotCheckError::ExpiredChallenge));
+
+        // with it off, anything goes
+        let disabled = ProofOfWork::disabled();
+        assert!(!disabled.is_enabled());
+        assert_eq!(disabled.verify(None, start), Ok(()));
+        assert_eq!(ProofOfWork::new(99, Duration::from_secs(1)).difficulty(), MAX_DIFFICULTY);
+    }
+
+    #[test]
+    fn signed_tokens() {
+        let secret = b"a secret the replicas share".to_vec();
+        let pow = ProofOfWork::with_secret(4, Duration::from_secs(60), secret.clone());
+        let start = Utc::now().naive_utc();
+
+        // any replica with the same secret can check them
+        let replica = ProofOfWork::with_secret(4, Duration::from_secs(60), secret);
+        assert_eq!(replica.verify(Some(&solve(&pow.issue(start))), start), Ok(()));
+        let stranger = ProofOfWork::new(4, Duration::from_secs(60));
+        assert_eq!(stranger.verify(Some(&solve(&pow.issue(start))), start), Err(BotCheckError::UnknownChallenge));
+
+        // but there's no changing when one expires, or its ID
+        let challenge = pow.issue(start);
+        let (id, expires) = pow.read_token(&challenge.token).unwrap();
+        let (_, signature) = challenge.token.rsplit_once('.').unwrap();
+        for token in [
+            format!("{id}.{}.{signature}", expires + 3600),
+            format!("{}.{expires}.{signature}", "0".repeat(32)),
+            format!("{id}.{expires}"),
+            format!("{id}.{expires}.{}", "zz".repeat(32)),
+            format!("{id}.{expires}.{}é", &signature[2..]),
+        ] {
+            let forged = PowChallenge { token, ..challenge.clone() };
+            assert_eq!(pow.verify(Some(&solve(&forged)), start), Err(BotCheckError::UnknownChallenge));
+        }
+        assert_eq!(pow.verify(Some(&solve(&challenge)), start), Ok(()));
+    }
+
+    #[test]
+    fn forgetting_spent_challenges() {
+        let pow = ProofOfWork::new(1, Duration::from_secs(10));
+        let start = Utc::now().naive_utc().trunc_subsecs(0);
+        let spend = |at: NaiveDateTime| pow.verify(Some(&solve(&pow.issue(at))), at);
+
+        // they're only kept until they'd have expired anyway
+        for _ in 0..MAX_SPENT {
+            spend(start).unwrap();
+        }
+        spend(start + TimeDelta::seconds(10)).unwrap();
+        assert_eq!(pow.lock_spent().len(), 1);
+
+        // and with too many that haven't, the oldest goes first
+        let next = start + TimeDelta::seconds(11);
+        let latest = pow.issue(next);
+        pow.verify(Some(&solve(&latest)), next).unwrap();
+        for _ in 2..MAX_SPENT {
+            spend(next).unwrap();
+        }
+        assert_eq!(pow.lock_spent().len(), MAX_SPENT);
+        spend(next).unwrap();
+        assert_eq!(pow.lock_spent().len(), MAX_SPENT);
+        assert!(pow.lock_spent().values().all(|expires| *expires == (next + TimeDelta::seconds(10)).and_utc().timestamp()));
+        assert_eq!(pow.verify(Some(&solve(&latest)), next), Err(BotCheckError::ReusedChallenge));
+    }
+
+    #[test]
+    fn honeypot() {
+        let pow = ProofOfWork::disabled();
+        let now = Utc::now().naive_utc();
+        let entry = GuestbookEntry { id: None, time_stamp: None, name: "Someone".to_string(), note: "Hello!".to_string(), pinned: None, spam: None, parent_id: None, replies: None, reactions: None };
+
+        let post = |website: Option<&str>| GuestbookPost { website: website.map(str::to_string), ..entry.clone().into() };
+        assert_eq!(check_post(&pow, &post(None), now), Ok(()));
+        assert_eq!(check_post(&pow, &post(Some("")), now), Ok(()));
+        assert_eq!(check_post(&pow, &post(Some("  ")), now), Ok(()));
+        assert_eq!(check_post(&pow, &post(Some("http://spam.example")), now), Err(BotCheckError::Honeypot));
+
+        // and it's checked before the challenge, which is left unspent
+        let pow = ProofOfWork::new(4, Duration::from_secs(60));
+        let solution = solve(&pow.issue(now));
+        let bot = GuestbookPost { challenge: Some(solution.clone()), ..post(Some("http://spam.example")) };
+        assert_eq!(check_post(&pow, &bot, now), Err(BotCheckError::Honeypot));
+        let person = GuestbookPost { challenge: Some(solution), ..post(None) };
+        assert_eq!(check_post(&pow, &person, now), Ok(()));
+    }
+}
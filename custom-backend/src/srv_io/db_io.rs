@@ -1,43 +1,121 @@
-use std::env; 
 use axum::{
-    body::Body, http::StatusCode, response::{Html, IntoResponse, Response}, Json
+    body::Body, 
+    extract::{ConnectInfo, State}, 
+    http::{HeaderMap, StatusCode}, 
+    response::{Html, IntoResponse, Response}, 
+    Json
 };
-use mysql::*;
-use mysql::prelude::*;
-use mysql_common::chrono::Utc;
+use axum_extra::extract::Query;
+use mysql_common::chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc, SubsecRound};
+use std::{net::SocketAddr, time::{Duration, Instant}};
+use tokio::task::JoinError;
 use tracing::{info, debug, error};
-use crate::utils::err_handling::make_500_resp;
+use crate::app_state::AppState;
+use crate::srv_io::{author_edits, bot_check, page_views::record_hit, reactions, replies};
+use crate::storage::{
+    entry_pages::{EntryKey, EntryPageQuery}, 
+    entry_search::{self, SearchQuery, MIN_TERM_CHARS}, 
+    LoggedHit
+};
+use crate::utils::err_handling::{make_500_resp, make_503_resp};
 use crate::types::db_io_types::*;
 
 // wrapper to implement IntoResponse
 #[derive(Debug)]
 pub enum DbError { 
-    UrlError(mysql::UrlError),
-    GenError(mysql::Error), 
+    UrlError(mysql_async::UrlError),
+    GenError(mysql_async::Error), 
+    SqliteError(rusqlite::Error),
+    PgError(tokio_postgres::Error),
+    PoolTimedOut,                   // no connection became free in time
+    QueryTimedOut,                  // took longer than DB_QUERY_TIMEOUT_MS
+    Unavailable(Duration),          // the circuit breaker is open; try again after this long
+    UnknownScheme(String),          // DB_URL doesn't start with one the server knows
+    TaskError(JoinError),           // a query on the blocking thread pool panicked
 }
 
-impl From<mysql::Error> for DbError {
-    fn from(mysql_err: mysql::Error) -> Self {
+impl From<mysql_async::Error> for DbError {
+    fn from(mysql_err: mysql_async::Error) -> Self {
         Self::GenError(mysql_err)
     }
 }
 
-impl From<mysql::UrlError> for DbError {
-    fn from(url_err: mysql::UrlError) -> Self {
+impl From<mysql_async::UrlError> for DbError {
+    fn from(url_err: mysql_async::UrlError) -> Self {
         Self::UrlError(url_err)
     }
 }
 
+impl From<rusqlite::Error> for DbError {
+    fn from(sqlite_err: rusqlite::Error) -> Self {
+        Self::SqliteError(sqlite_err)
+    }
+}
+
+impl From<tokio_postgres::Error> for DbError {
+    fn from(pg_err: tokio_postgres::Error) -> Self {
+        Self::PgError(pg_err)
+    }
+}
+
+impl From<JoinError> for DbError {
+    fn from(join_err: JoinError) -> Self {
+        Self::TaskError(join_err)
+    }
+}
+
+// lets DbError bubble up out of main() during startup
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::UrlError(e) => write!(f, "invalid database URL: {e}"),
+            DbError::GenError(e) => write!(f, "database error: {e}"),
+            DbError::SqliteError(e) => write!(f, "SQLite error: {e}"),
+            DbError::PgError(e) => write!(f, "PostgreSQL error: {e}"),
+            DbError::PoolTimedOut => write!(f, "timed out waiting for a database connection"),
+            DbError::QueryTimedOut => write!(f, "database query timed out"),
+            DbError::Unavailable(retry_after) => write!(
+                f, "database unavailable (circuit breaker open for another {retry_after:?})"
+            ),
+            DbError::UnknownScheme(s) => write!(
+                f, "unsupported database URL scheme \"{s}\" (expected mysql://, postgres://, sqlite://, or memory://)"
+            ),
+            DbError::TaskError(e) => write!(f, "database task failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
 
 impl IntoResponse for DbError {
 
     fn into_response(self) -> Response {
 
+        // the breaker logs when it opens, so there's no need 
+        // to log every request it turns away
+        if let DbError::Unavailable(retry_after) = self {
+            debug!("Database unavailable; sending 503.");
+            return make_503_resp(retry_after);
+        }
+
         error!("Error in database I/O: {self:?}");
         make_500_resp()
     }
 }
 
+// Same limits as the guestbook page. These are in bytes (String.len()), while
+// VARCHAR(n) counts characters, so anything under them fits in the columns.
+pub const MAX_NAME_BYTES: usize = 100;
+pub const MAX_NOTE_BYTES: usize = 1000;
+// hitLog.userAgent is a VARCHAR(150); longer ones are cut down to fit
+pub const MAX_USER_AGENT_CHARS: usize = 150;
+// and likewise hitLog.path and hitLog.referrer, which are VARCHAR(200)'s
+pub const MAX_PATH_CHARS: usize = 200;
+pub const MAX_REFERRER_CHARS: usize = 200;
+// and hitLog.utmSource, utmMedium, and utmCampaign, which are VARCHAR(100)'s
+pub const MAX_UTM_CHARS: usize = 100;
+
 #[derive(Debug, PartialEq)]
 pub enum UserError { 
     NameTooLong,
@@ -51,11 +129,12 @@ pub enum UserError {
 pub enum DbOrUserError {
     DbError(DbError),
     UserError(UserError),
+    BotCheck(BotCheckError),
 }
 
-impl From<mysql::Error> for DbOrUserError {
-    fn from(mysql_err: mysql::Error) -> DbOrUserError {
-        DbOrUserError::DbError(DbError::from(mysql_err))
+impl From<DbError> for DbOrUserError {
+    fn from(db_err: DbError) -> DbOrUserError {
+        DbOrUserError::DbError(db_err)
     }
 }
 
@@ -65,21 +144,28 @@ impl From<UserError> for DbOrUserError {
     }
 }
 
+impl From<BotCheckError> for DbOrUserError {
+    fn from(bot_err: BotCheckError) -> DbOrUserError {
+        DbOrUserError::BotCheck(bot_err)
+    }
+}
+
 impl IntoResponse for DbOrUserError {
     fn into_response(self) -> Response {
         
         match self {
             DbOrUserError::DbError(e) => DbError::into_response(e),
+            DbOrUserError::BotCheck(e) => BotCheckError::into_response(e),
             DbOrUserError::UserError(ue) => {
 
                 let (entry_field, db_limit) = match ue {
                     UserError::NameTooLong => { 
                         error!("A user entered an overlong name.");
-                        ("Name", 100)  
+                        ("Name", MAX_NAME_BYTES)
                     },
                     UserError::NoteTooLong => { 
                         error!("A user entered an overlong note.");
-                        ("Note", 1000) 
+                        ("Note", MAX_NOTE_BYTES)
                     }
                 };
 
@@ -111,170 +197,449 @@ impl IntoResponse for DbOrUserError {
 
 
 
-fn get_db_conn_pool() -> Result<mysql::Pool, mysql::Error> {
-    
-    // gets URL from the environment to preserve security,
-    // since it contains a plain-text password
-    let url = env::var_os("DB_URL")
-        .unwrap()       // These two unwraps do not panic
-        .into_string()
-        .unwrap();
+// For paging through the guestbook (see storage/entry_pages.rs):
+//
+//     /guestbook/entries?limit=20&before=57&order=desc
+//
+// before and after each take an entry ID, or a time (2025-03-13T03:37:05, 
+// or just 2025-03-13 for midnight), and order is desc (newest first, like
+// the unpaged one) or asc. They're all optional, but sending any of them
+// gets a GuestbookPage back instead of the whole Guestbook.
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
 
-    debug!("DB URL to connect with: {url}");
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct GuestbookParams {
+    limit: Option<usize>,
+    before: Option<String>,
+    after: Option<String>,
+    order: Option<SortOrder>,
+}
 
-    let opts = Opts::from_url(&url)?;
+impl GuestbookParams {
+    fn is_paged(&self) -> bool {
+        self.limit.is_some() || self.before.is_some() || self.after.is_some() || self.order.is_some()
+    }
+}
 
-    Pool::new(opts)
+#[derive(Debug)]
+pub enum GuestbookError {
+    DbError(DbError),
+    BadRequest(String), // a cursor or search it can't use; sent back as the body of the 400
 }
 
+impl From<DbError> for GuestbookError {
+    fn from(db_err: DbError) -> Self {
+        Self::DbError(db_err)
+    }
+}
 
-// it is a simpler, albeit slower, design to establish the connection every
-// time the function is called
-pub async fn get_guestbook() -> Result<Json::<Guestbook>, DbError> {
+impl IntoResponse for GuestbookError {
+    fn into_response(self) -> Response {
 
-    let buf_pool = get_db_conn_pool()?;
-    let mut conn = buf_pool.get_conn()?;
-    
-    let guestbook_table = conn.query_map(
-        "
-        SELECT id, dateSubmitted, guestName, guestNote 
-        FROM guestbook
-        ORDER BY dateSubmitted DESC", // let the DB do the sorting
-        |(id, time_stamp, name, note)| {
-            GuestbookEntry {id: Some(id), time_stamp: Some(time_stamp), name, note}
+        match self {
+            GuestbookError::DbError(db_err) => db_err.into_response(),
+            GuestbookError::BadRequest(msg) => {
+                debug!("Rejected a guestbook request: {msg}");
+                (StatusCode::BAD_REQUEST, msg).into_response()
+            }
         }
-    )?;
+    }
+}
+
+// which side of the cursor the page is on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bound {
+    Before,
+    After,
+}
+
+async fn cursor_key(state: &AppState, cursor: &str, bound: Bound) -> Result<EntryKey, GuestbookError> {
+
+    if let Ok(id) = cursor.parse::<i64>() {
+        return state.guestbook.get_entry(id).await?
+            .as_ref()
+            .and_then(EntryKey::of)
+            .ok_or_else(|| GuestbookError::BadRequest(format!("There's no guestbook entry with the ID {id}.")));
+    }
+
+    let time = cursor.parse::<NaiveDateTime>().ok()
+        .or_else(|| NaiveDateTime::parse_from_str(cursor, "%Y-%m-%d %H:%M:%S").ok())
+        .or_else(|| cursor.parse::<NaiveDate>().ok().map(|day| day.and_time(NaiveTime::MIN)))
+        .ok_or_else(|| GuestbookError::BadRequest(format!(
+            "\"{cursor}\" isn't an entry ID, or a time like 2025-03-13T03:37:05."
+        )))?;
+
+    Ok(match bound {
+        Bound::Before => EntryKey::just_before(time),
+        Bound::After  => EntryKey::just_after(time),
+    })
+}
+
+// GET /guestbook/entries, paged or not
+pub async fn get_guestbook_entries(
+    State(state): State<AppState>,
+    Query(params): Query<GuestbookParams>
+) -> Response {
+
+    if params.is_paged() {
+        get_guestbook_page(State(state), Query(params)).await.into_response()
+    } else {
+        get_guestbook(State(state)).await.into_response()
+    }
+}
+
+pub async fn get_guestbook_page(
+    State(state): State<AppState>,
+    Query(params): Query<GuestbookParams>
+) -> Result<Json<GuestbookPage>, GuestbookError> {
+
+    let after = match &params.after {
+        Some(cursor) => cursor_key(&state, cursor, Bound::After).await?,
+        None => EntryKey::first(),
+    };
+    let before = match &params.before {
+        Some(cursor) => cursor_key(&state, cursor, Bound::Before).await?,
+        None => EntryKey::last(),
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    // one more than asked for, to tell if there's another page
+    let query = EntryPageQuery { after, before, order: params.order.unwrap_or(SortOrder::Desc), limit: limit + 1 };
+    let mut entries = state.guestbook.get_page(&query).await?;
+    let next_cursor = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().and_then(|entry| entry.id.clone())
+    } else {
+        None
+    };
+
+    // the same as the whole guestbook, but with just this page's replies
+    let page_size = entries.len();
+    let page_replies = replies::replies_to(&state, &entries).await?;
+    entries.extend(page_replies);
+    reactions::add_counts(&state, &mut entries).await?;
+    let entries = replies::nest(entries, state.replies.max_depth);
+
+    debug!("GET /guestbook page of {page_size} successful.");
+    Ok(Json(GuestbookPage { entries, next_cursor }))
+}
+
+// For searching the guestbook (see storage/entry_search.rs):
+//
+//     /guestbook/search?q=nice+choice&limit=10
+//
+// Entries have to have every term in q in them, in their name or note.
+pub const DEFAULT_SEARCH_RESULTS: usize = 20;
+pub const MAX_SEARCH_RESULTS: usize = 100;
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct SearchParams {
+    q: Option<String>,
+    limit: Option<usize>,
+}
+
+pub async fn search_guestbook(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>
+) -> Result<Json<GuestbookSearch>, GuestbookError> {
+
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_RESULTS).clamp(1, MAX_SEARCH_RESULTS);
+    let query = SearchQuery::parse(params.q.as_deref().unwrap_or_default(), limit)
+        .ok_or_else(|| GuestbookError::BadRequest(format!(
+            "There's nothing to search for; q needs a word of at least {MIN_TERM_CHARS} characters."
+        )))?;
+
+    let mut candidates = state.guestbook.search_entries(&query).await?;
+    reactions::add_counts(&state, &mut candidates).await?;
+    let results = entry_search::rank(&query, candidates);
+
+    debug!("GET /guestbook/search found {} entries.", results.len());
+    Ok(Json(GuestbookSearch { terms: query.terms, results }))
+}
+
+pub async fn get_guestbook(State(state): State<AppState>) -> Result<Json::<Guestbook>, DbError> {
+
+    // with their reactions (see reactions.rs), and the replies
+    // nested under what they reply to (see replies.rs)
+    let mut guestbook_table = state.guestbook.get_entries().await?;
+    reactions::add_counts(&state, &mut guestbook_table).await?;
+    let guestbook_table = replies::nest(guestbook_table, state.replies.max_depth);
 
     debug!("GET /guestbook successful.");
 
     Ok(Json(Guestbook {guestbook: guestbook_table}))
 }
 
-pub async fn update_guestbook(Json(mut form_entry): Json<GuestbookEntry>) -> Result<Json<EntryReceipt>, DbOrUserError> {
+pub async fn update_guestbook(
+    State(state): State<AppState>,
+    Json(post): Json<GuestbookPost>
+) -> Result<Json<EntryReceipt>, DbOrUserError> {
+
+    // the honeypot and the challenge (see bot_check.rs) come
+    // first, so bots don't get anywhere near the database
+    bot_check::check_post(&state.pow, &post, Utc::now().naive_utc())?;
+
+    add_new_entry(&state, post.entry, None, false).await.map(Json)
+}
+
+// Everything a new entry goes through once it's past the bot check, for
+// update_guestbook() and for replies (see replies.rs), which have the ID
+// of the entry they reply to. The site owner's replies are the only ones
+// that skip the spam filter and the moderation queue.
+pub async fn add_new_entry(
+    state: &AppState,
+    mut form_entry: GuestbookEntry,
+    parent_id: Option<i64>,
+    by_owner: bool
+) -> Result<EntryReceipt, DbOrUserError> {
 
     // the first two conditionals are redundancies to catch entries that exceed
     // hard-coded VARCHAR limits, since the client-side Javascript is designed
     // to catch them as well. But they are included here just in case the API
     // is accessed outside the browser's JS.
     
-    if form_entry.name.len() > 150 {  // MySQL uses bytes for length definitions, as does String.len() in Rust
+    if form_entry.name.len() > MAX_NAME_BYTES {
         
         return Err(DbOrUserError::UserError(UserError::NameTooLong));
     }
     
-    if form_entry.note.len() > 1000 {  // MySQL uses bytes for length definitions, as does String.len() in Rust
+    if form_entry.note.len() > MAX_NOTE_BYTES {
         
         return Err(DbOrUserError::UserError(UserError::NoteTooLong));
     }
 
-
-    // db connection setup
-    let buf_pool = get_db_conn_pool()?;
-    let mut conn = buf_pool.get_conn()?;
-
     // also redundant, since this is delegated to the client JS, 
     // but included just in case
     if form_entry.name.is_empty() { form_entry.name = String::from("(anonymous)"); }
 
-    // while the UTC_TIMESTAMP() in the query will theorietically not be precisely
-    // the same as Utc::now() invoked here, they will be close enough
-    // and it simplifies the code.
-    form_entry.time_stamp = Some(Utc::now().naive_utc());
-    let _: Option<Row> = conn.exec_first(
-        r"INSERT INTO guestbook (dateSubmitted, guestName, guestNote)
-                VALUES (UTC_TIMESTAMP(), :name, :note)",
-        params! {
-            "name"      => &form_entry.name, 
-            "note"      => &form_entry.note
-        }
-    )?;
-
-    let new_entry_id: String = match conn.query_first(
-        "SELECT LAST_INSERT_ID()")? {
-        Some(id) => id,
-        None => { String::from("0") }
+    // truncated, since the DB only stores whole seconds, 
+    // and the receipt should match what's stored
+    let time_stamp = Utc::now().naive_utc().trunc_subsecs(0);
+    form_entry.time_stamp = Some(time_stamp);
+    // whatever the client sent for these, they're the server's to set
+    form_entry.parent_id = parent_id.map(|id| id.to_string());
+    form_entry.replies = None;
+    form_entry.reactions = None;
+
+    // with GUESTBOOK_MODERATION on, it waits for an admin (see admin_io.rs),
+    // unless the admin wrote it
+    let status = if state.moderation && !by_owner { EntryStatus::Pending } else { EntryStatus::Approved };
+
+    // and it might be spam (see spam_filter.rs). Dropped entries are kept, 
+    // but rejected, and the receipt's the same as if they weren't.
+    let spam = if by_owner {
+        SpamDecision { verdict: SpamVerdict::Accept, reason: None }
+    } else {
+        state.spam.check(&form_entry, Instant::now())
     };
-
-    info!("New entry in the guestbook from {}!", form_entry.name);
-    Ok(Json(EntryReceipt {
-        // nwrapping here is safe, because I set this field to Some()
-        // a few lines before in this function
-        time_stamp: form_entry.time_stamp.unwrap(), 
+    let stored_status = match spam.verdict {
+        SpamVerdict::Drop   => EntryStatus::Rejected,
+        SpamVerdict::Hold   => EntryStatus::Pending,
+        SpamVerdict::Accept => status,
+    };
+    let reason = spam.reason.clone().unwrap_or_default();
+    form_entry.spam = (state.spam.is_enabled() && !by_owner).then_some(spam.clone());
+    // and its author gets a token to change it with, for a while (see author_edits.rs)
+    let edit_token = state.edit_grace.map(|_| author_edits::new_token());
+    let edit_token_hash = edit_token.as_ref().map(|(_, hash)| hash.as_str());
+    let new_entry_id = state.guestbook.add_entry(&form_entry, stored_status, edit_token_hash).await?;
+
+    let what = match parent_id {
+        Some(parent_id) => format!("reply to guestbook entry {parent_id}"),
+        None => String::from("entry in the guestbook"),
+    };
+    match (spam.verdict, stored_status) {
+        (SpamVerdict::Drop, _)      => info!("Dropped a new {what} from {} as spam ({reason}).", form_entry.name),
+        (SpamVerdict::Hold, _)      => info!("New {what} from {}, held for review ({reason}).", form_entry.name),
+        (_, EntryStatus::Pending)   => info!("New {what} from {}, waiting to be approved.", form_entry.name),
+        _                           => info!("New {what} from {}!", form_entry.name),
+    }
+    Ok(EntryReceipt {
+        time_stamp, 
         id: new_entry_id,
-    }))
+        status: if spam.verdict == SpamVerdict::Drop { status } else { stored_status },
+        edit_token: edit_token.map(|(token, _)| token),
+        editable_until: state.edit_grace.map(|grace| author_edits::editable_until(time_stamp, grace)),
+    })
 }
 
 
-// adds new hit info to database
-pub async fn get_hit_count() -> Result<String, DbError> {
-    
-    let buf_pool = get_db_conn_pool()?;
-    let mut conn = buf_pool.get_conn()?;
-    
-    conn.query_first::<String, &str>("LOCK TABLE hitLog READ")?;
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct HitCountParams {
+    #[serde(default)]
+    exclude_bots: bool,     // GET /hits?exclude_bots=true
+}
 
-    let hits = match conn.query_first::<String, &str>(
-        r"SELECT COUNT(*) AS hit_count FROM hitLog"
-    )? {
-        Some(hits_count) => hits_count,
-        None => String::from("0")
+// both of these only touch the in-memory counter; 
+// the hits are written to the database in the background
+pub async fn get_hit_count(
+    State(state): State<AppState>,
+    Query(params): Query<HitCountParams>
+) -> String {
+    
+    let hits = if params.exclude_bots {
+        state.hits.count_without_bots()
+    } else {
+        state.hits.count()
     };
-
-    conn.query_first::<String, &str>("UNLOCK TABLES")?;
     debug!("Page hit count retrieved.");
 
-    Ok(hits)   
+    hits.to_string()
 }
 
 
-pub async fn log_hit(Json(page_hit): Json<WebpageHit>) -> Result<Response, DbError> {
-
-    let buf_pool = get_db_conn_pool()?;
-    let mut conn = buf_pool.get_conn()?;
-
-    // this function runs async of get_hit_count(), which is called immediately after this one
-    // through a GET request to /hits. In practice, this means the hit count it returned was
-    // 1 behind the DB.
-    // So, I'm setting a read/write lock on the table to block the GET that comes on the heels 
-    // of this INSERT. There is some slight overhead for this, unsuprisingly, but that's 
-    // acceptable to me.
-    conn.query_first::<String, &str>("LOCK TABLE hitLog READ")?;
-    conn.query_first::<String, &str>("LOCK TABLE hitLog WRITE")?;
-    debug!("table lock successful");
-    conn.exec_first::<String, &str, Params>(
-        r"INSERT INTO hitLog (hitTime, userAgent) VALUES (:time_stamp, :user_agent);",
-        params! {
-            "time_stamp" => page_hit.time_stamp, 
-            "user_agent" => &page_hit.user_agent
-        }
-    )?;
-    debug!("prep'd statement successful");
-    conn.query_first::<String, &str>("UNLOCK TABLES")?;
-    debug!("table unlock successful");
-    
-    info!("New visit from: {}", page_hit.user_agent);
+// Only routed if HIT_LEGACY_POST is on. The server logs page views itself now
+// (see page_views.rs), so this is just for clients that still POST their own.
+pub async fn log_hit(
+    State(state): State<AppState>, 
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(page_hit): Json<WebpageHit>
+) -> Response {
+
+    let client = state.proxies.client_ip(peer.ip(), &headers);
+    let logged_hit = LoggedHit::new(page_hit, &state.ua_rules);
+    record_hit(&state, client, &headers, logged_hit);
 
-    Ok(Response::new(Body::empty()))  // return 200 OK
+    Response::new(Body::empty())  // return 200 OK
 }
 
 
 #[cfg(test)]
 mod tests {
 
+    // These run against the in-memory store, preloaded with the same 
+    // data as the demo database, so they don't need a database running.
+    // The tests for the storage backends themselves are in crate::storage.
     use super::*;
+    use std::sync::Arc;
     use axum::http::StatusCode;
     use mysql_common::chrono::{Utc, NaiveDateTime, SubsecRound};
+    use crate::{
+        storage::{mem_store::MemStore, GuestbookStore, HitStore, HitTotals},
+        utils::spam_filter::{BannedRules, LinkLimit, SpamFilter},
+    };
+
+    fn demo_guestbook() -> Vec<GuestbookEntry> {
+        vec![
+            GuestbookEntry {
+                id: Some(String::from("4")),
+                time_stamp: Some(NaiveDateTime::parse_from_str(
+                    "2025-04-20 13:03:59", "%Y-%m-%d %H:%M:%S").unwrap()),
+                name:       String::from("约翰·塞纳"),
+                note:       String::from("我很喜欢冰淇淋"),
+                pinned: None,
+                spam: None,
+                parent_id: None,
+                replies: None,
+                reactions: None,
+            },
+            GuestbookEntry {
+                id: Some(String::from("3")),
+                time_stamp: Some(NaiveDateTime::parse_from_str(
+                    "2025-03-13 03:37:05", "%Y-%m-%d %H:%M:%S").unwrap()),
+                name:       String::from("Linus"),
+                note:       String::from("nice os choice!"),
+                pinned: None,
+                spam: None,
+                parent_id: None,
+                replies: None,
+                reactions: None,
+            },
+            GuestbookEntry {
+                id: Some(String::from("2")),
+                time_stamp: Some(NaiveDateTime::parse_from_str(
+                    "2025-02-28 04:30:57", "%Y-%m-%d %H:%M:%S").unwrap()),
+                name:       String::from("(anonymous)"),
+                note:       String::from("you'll never know..."),
+                pinned: None,
+                spam: None,
+                parent_id: None,
+                replies: None,
+                reactions: None,
+            },
+            
+            GuestbookEntry {
+                id: Some(String::from("1")),
+                time_stamp: Some(NaiveDateTime::parse_from_str(
+                    "2025-02-28 04:22:49", "%Y-%m-%d %H:%M:%S").unwrap()),
+                name:       String::from("Ada"),
+                note:       String::from("It's so nice to be here!"),
+                pinned: None,
+                spam: None,
+                parent_id: None,
+                replies: None,
+                reactions: None,
+            },
+        ]
+    }
+
+    // the demo DB's hit log has 6 hits in it
+    fn demo_hit_log() -> Vec<WebpageHit> {
+        vec![WebpageHit::default(); 6]
+    }
+
+    // returns the store too, so tests can look at what got written to it
+    fn test_state() -> (AppState, Arc<MemStore>) {
+        let (state, _, mem_store) = AppState::for_tests_with(MemStore::with_data(demo_guestbook(), demo_hit_log()), HitTotals { all: 6, bots: 0 });
+        (state, mem_store)
+    }
+
+    fn client() -> ConnectInfo<SocketAddr> {
+        ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 54321)))
+    }
 
     #[tokio::test]
     async fn hit_counting() {
 
-        let hits = get_hit_count().await.unwrap();
-
-        // there are precisely 6 hits in the demo DB's log,
-        // and since a lock is requested on the table, 
+        let hits = get_hit_count(State(test_state().0), Query(HitCountParams::default())).await;
         assert_eq!(hits, "6");
     }
 
+    #[tokio::test]
+    async fn hit_counting_without_bots() {
+
+        let (state, _) = test_state();
+        let bot_hit = WebpageHit { time_stamp: Utc::now().naive_utc(), user_agent: String::from("curl/8.5.0"), ..WebpageHit::default() };
+        log_hit(State(state.clone()), client(), HeaderMap::new(), Json(bot_hit)).await;
+
+        let all = get_hit_count(State(state.clone()), Query(HitCountParams { exclude_bots: false })).await;
+        let humans = get_hit_count(State(state), Query(HitCountParams { exclude_bots: true })).await;
+        assert_eq!((all.as_str(), humans.as_str()), ("7", "6"));
+    }
+
+    #[tokio::test]
+    async fn visitors_and_opt_outs() -> Result<(), DbError> {
+
+        let (state, mem_store) = test_state();
+        state.hits.flush().await?;     // picks up today's salt
+        let log = |client, headers: &[(&'static str, &'static str)], user_agent: &str| {
+            let headers = headers.iter().map(|(name, val)| (name.parse().unwrap(), val.parse().unwrap())).collect();
+            let page_hit = WebpageHit { time_stamp: Utc::now().naive_utc(), user_agent: String::from(user_agent), ..WebpageHit::default() };
+            log_hit(State(state.clone()), client, headers, Json(page_hit))
+        };
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:136.0) Gecko/20100101 Firefox/136.0";
+
+        log(client(), &[], firefox).await;
+        log(client(), &[("DNT", "0")], firefox).await;     // same visitor
+        log(ConnectInfo(SocketAddr::from(([198, 51, 100, 1], 443))), &[], firefox).await;
+        log(client(), &[], "Mozilla/5.0 (Windows NT 10.0; Win64; x64)").await;
+        // none of these are visitors
+        log(client(), &[("DNT", "1")], "opted out").await;
+        log(client(), &[("Sec-GPC", "1")], "opted out").await;
+        log(client(), &[], "curl/8.5.0").await;
+        state.hits.flush().await?;
+
+        let today = Utc::now().date_naive();
+        assert_eq!(mem_store.unique_visitors(today).await?, vec![(today, 3)]);
+        assert_eq!(get_hit_count(State(state), Query(HitCountParams::default())).await, "13");
+        // no IPs anywhere
+        assert!(mem_store.hit_log().iter().all(|logged| !format!("{logged:?}").contains("203.0.113.7")));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn log_hit_normal() -> Result<(), DbError>{
         
@@ -282,31 +647,22 @@ mod tests {
             time_stamp: Utc::now()
                 .naive_utc()
                 .trunc_subsecs(0),    // truncation happens after db insertion/retrieval
-            user_agent: String::from("a user agent string no one uses")
+            user_agent: String::from("a user agent string no one uses"),
+            ..WebpageHit::default()
         };
 
-        let sent_resp = log_hit(Json(page_hit_normal.clone())).await.unwrap();
+        let (state, mem_store) = test_state();
+        let sent_resp = log_hit(State(state.clone()), client(), HeaderMap::new(), Json(page_hit_normal.clone())).await;
         assert_eq!(sent_resp.status(), StatusCode::OK);  // make sure a successful call sends 200
 
-        // connect
-        let mut conn = get_db_conn_pool()?.get_conn()?;
-
-        // no other entries in the demo database have a user agent like the above
-        let latest_hit = conn.query_map(
-            format!(
-                "SELECT hitTime, userAgent FROM hitLog 
-                WHERE hitTime = STR_TO_DATE('{}', '%Y-%m-%d %H:%i:%S')
-                AND userAgent = '{}'", 
-                /* note that the MYSQL date format does not follow strftime format */
-                page_hit_normal.time_stamp.format("%Y-%m-%d %H:%M:%S").to_string(),
-                page_hit_normal.user_agent,
-            ),
-            |(hit_time, user_agent): (NaiveDateTime, String)| {
-                WebpageHit { time_stamp: hit_time, user_agent, }
-            }
-        )?;
+        // the new hit is counted right away
+        let hits = get_hit_count(State(state.clone()), Query(HitCountParams::default())).await;
+        assert_eq!(hits, "7");
 
-        assert_eq!(latest_hit[0], page_hit_normal);
+        // but only written on the next flush
+        assert_ne!(mem_store.hit_log().last().map(|logged| &logged.hit), Some(&page_hit_normal));
+        state.hits.flush().await?;
+        assert_eq!(mem_store.hit_log().last().map(|logged| &logged.hit), Some(&page_hit_normal));
 
         Ok(())
     }
@@ -314,41 +670,9 @@ mod tests {
     #[tokio::test]
     async fn getting_guestbook() -> Result<(), DbError> {
 
-        let demo_guestbook = Guestbook {
-            guestbook: vec![
-                GuestbookEntry {
-                    id: Some(String::from("4")),
-                    time_stamp: Some(NaiveDateTime::parse_from_str(
-                        "2025-04-20 13:03:59", "%Y-%m-%d %H:%M:%S").unwrap()),
-                    name:       String::from("约翰·塞纳"),
-                    note:       String::from("我很喜欢冰淇淋")
-                },
-                GuestbookEntry {
-                    id: Some(String::from("3")),
-                    time_stamp: Some(NaiveDateTime::parse_from_str(
-                        "2025-03-13 03:37:05", "%Y-%m-%d %H:%M:%S").unwrap()),
-                    name:       String::from("Linus"),
-                    note:       String::from("nice os choice!")
-                },
-                GuestbookEntry {
-                    id: Some(String::from("2")),
-                    time_stamp: Some(NaiveDateTime::parse_from_str(
-                        "2025-02-28 04:30:57", "%Y-%m-%d %H:%M:%S").unwrap()),
-                    name:       String::from("(anonymous)"),
-                    note:       String::from("you'll never know...")
-                },
-                
-                GuestbookEntry {
-                    id: Some(String::from("1")),
-                    time_stamp: Some(NaiveDateTime::parse_from_str(
-                        "2025-02-28 04:22:49", "%Y-%m-%d %H:%M:%S").unwrap()),
-                    name:       String::from("Ada"),
-                    note:       String::from("It's so nice to be here!")
-                },
-            ]
-        };
+        let demo_guestbook = Guestbook { guestbook: demo_guestbook() };
 
-        let gotten_guestbook = get_guestbook().await.unwrap();
+        let gotten_guestbook = get_guestbook(State(test_state().0)).await.unwrap();
         assert_eq!(gotten_guestbook.0, demo_guestbook);
 
         Ok(())
@@ -357,58 +681,141 @@ mod tests {
     #[tokio::test]
     async fn post_null_entry() -> Result<(), DbOrUserError> {
 
-        // allows for SQL query to filter all entries later
-        // than the start of this function
-        let proc_start = Utc::now().naive_utc();
+        let proc_start = Utc::now().naive_utc().trunc_subsecs(0);
         let mut null_entry = GuestbookEntry {
-            id: Some(String::from("7")), 
+            id: Some(String::from("5")), 
             time_stamp: None,
             name: String::new(),
-            note: String::new()
+            note: String::new(),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         };
 
-        // Running this in series, this entry should be ID == 7,
-        // since the last auto-increment ID insert in this connection
-        // since DB initialization (and that's the counter measured)
-        // happened with posting the 6th hit, so the auto-increment counter
-        // is now at 7.
-        let receipt = update_guestbook(Json(null_entry.clone())).await.unwrap();
-        assert_eq!(receipt.0.id, "7");
-
-        // check to see if the entry was posted with 
-        let mut conn = get_db_conn_pool()?.get_conn()?;
-
-        // no other entries in the demo database have a null user agent
-        let fetched_entry = conn.query_map(
-            format!(
-                "SELECT id, guestName, guestNote FROM guestbook
-                WHERE dateSubmitted >= STR_TO_DATE('{}', '%Y-%m-%d %H:%i:%S')
-                AND guestName = '(anonymous)'", 
-                proc_start.format("%Y-%m-%d %H:%M:%S").to_string()
-            ),
-            |(id, name, note): (String, String, String)| {
-                // timestamp not needed if the query finds an entry in terms of proc_start
-                // ID should be 5.
-                GuestbookEntry { id: Some(id), time_stamp: None, name, note }
-            }
-        )?;
+        // there are 4 entries in the demo guestbook, so this one gets ID 5
+        let (state, _) = test_state();
+        let receipt = update_guestbook(State(state.clone()), Json(null_entry.clone().into())).await.unwrap();
+        assert_eq!(receipt.0.id, "5");
+        assert!(receipt.0.time_stamp >= proc_start);
+
+        // newest entry comes first
+        let fetched_entry = get_guestbook(State(state)).await?.0.guestbook.remove(0);
 
         null_entry.name = String::from("(anonymous)");
-        assert_eq!(fetched_entry[0], null_entry);
+        null_entry.time_stamp = Some(receipt.0.time_stamp);
+        assert_eq!(fetched_entry, null_entry);
 
         Ok(())
     }
 
+    fn paged(limit: Option<usize>, before: Option<&str>, after: Option<&str>, order: Option<SortOrder>) -> Query<GuestbookParams> {
+        Query(GuestbookParams { limit, before: before.map(String::from), after: after.map(String::from), order })
+    }
+
+    fn ids(page: &GuestbookPage) -> Vec<&str> {
+        page.entries.iter().map(|entry| entry.id.as_deref().unwrap()).collect()
+    }
+
     #[tokio::test]
-    async fn post_valid_entry() -> Result<(), DbOrUserError> {
+    async fn paging_through_guestbook() {
+
+        let (state, _) = test_state();
+        let get_page = |params| get_guestbook_page(State(state.clone()), params);
+
+        // newest first, following nextCursor to the end
+        let first = get_page(paged(Some(3), None, None, None)).await.unwrap().0;
+        assert_eq!((ids(&first), first.next_cursor.as_deref()), (vec!["4", "3", "2"], Some("2")));
+        let second = get_page(paged(Some(3), Some("2"), None, None)).await.unwrap().0;
+        assert_eq!((ids(&second), second.next_cursor.as_deref()), (vec!["1"], None));
+
+        // and the other way
+        let first = get_page(paged(Some(2), None, None, Some(SortOrder::Asc))).await.unwrap().0;
+        assert_eq!((ids(&first), first.next_cursor.as_deref()), (vec!["1", "2"], Some("2")));
+        let second = get_page(paged(Some(2), None, Some("2"), Some(SortOrder::Asc))).await.unwrap().0;
+        assert_eq!((ids(&second), second.next_cursor.as_deref()), (vec!["3", "4"], None));
+
+        // by time, between two entries
+        let between = get_page(paged(None, Some("2025-04-01"), Some("2025-02-28T04:22:49"), None)).await.unwrap().0;
+        assert_eq!(ids(&between), vec!["3", "2"]);
+        let before = get_page(paged(None, Some("2025-02-28 04:30:57"), None, None)).await.unwrap().0;
+        assert_eq!(ids(&before), vec!["1"]);
+
+        // the limit is kept in bounds
+        let huge = get_page(paged(Some(5000), None, None, None)).await.unwrap().0;
+        assert_eq!(huge.entries.len(), 4);
+        let none = get_page(paged(Some(0), None, None, None)).await.unwrap().0;
+        assert_eq!((ids(&none), none.next_cursor.as_deref()), (vec!["4"], Some("4")));
+
+        for bad in ["99", "yesterday", "2025-02-30"] {
+            let Err(err) = get_page(paged(None, Some(bad), None, None)).await
+            else { panic!("{bad} shouldn't work as a cursor") };
+            assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        }
+    }
 
-        // allows for SQL query to filter all entries later
-        // than the start of this function
-        let proc_start = Utc::now().naive_utc();
+    #[tokio::test]
+    async fn unpaged_unless_asked() {
+
+        let (state, _) = test_state();
+        let body = |resp: Response| async {
+            axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap()
+        };
+
+        let resp = get_guestbook_entries(State(state.clone()), Query(GuestbookParams::default())).await;
+        let whole: Guestbook = mysql_common::serde_json::from_slice(&body(resp).await).unwrap();
+        assert_eq!(whole, Guestbook { guestbook: demo_guestbook() });
+
+        let resp = get_guestbook_entries(State(state), paged(None, None, None, Some(SortOrder::Desc))).await;
+        let page: GuestbookPage = mysql_common::serde_json::from_slice(&body(resp).await).unwrap();
+        assert_eq!(page, GuestbookPage { entries: demo_guestbook(), next_cursor: None });
+    }
+
+    #[tokio::test]
+    async fn searching_guestbook() {
+
+        let (state, _) = test_state();
+        let search = |q: &str, limit: Option<usize>| search_guestbook(
+            State(state.clone()), 
+            Query(SearchParams { q: Some(q.to_string()), limit })
+        );
+
+        let found = search("NICE", None).await.unwrap().0;
+        assert_eq!(found.terms, vec!["NICE"]);
+        let ids: Vec<&str> = found.results.iter().map(|result| result.entry.id.as_deref().unwrap()).collect();
+        assert_eq!(ids, vec!["3", "1"]);    // the same score, so newest first
+        assert_eq!(found.results[1].snippet, vec![
+            TextPart { text: String::from("It's so "), highlight: false },
+            TextPart { text: String::from("nice"), highlight: true },
+            TextPart { text: String::from(" to be here!"), highlight: false },
+        ]);
+
+        // no spaces to split on, but any part of a word matches
+        let found = search("冰淇淋", None).await.unwrap().0;
+        assert_eq!(found.results.len(), 1);
+        assert_eq!(found.results[0].entry.name, "约翰·塞纳");
+
+        assert_eq!(search("nice linus", None).await.unwrap().0.results.len(), 1);
+        assert_eq!(search("nice", Some(1)).await.unwrap().0.results.len(), 1);
+        assert!(search("nobody", None).await.unwrap().0.results.is_empty());
+
+        for bad in ["", "a", "  ++ \"\" "] {
+            let Err(err) = search(bad, None).await
+            else { panic!("\"{bad}\" shouldn't be searchable") };
+            assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        }
+        let Err(err) = search_guestbook(State(state), Query(SearchParams::default())).await
+        else { panic!("a search needs q") };
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn post_valid_entry() -> Result<(), DbOrUserError> {
 
         // gonna get real weird with it
-        let valid_entry = GuestbookEntry {
-            id: Some(String::from("8")), 
+        let mut valid_entry = GuestbookEntry {
+            id: Some(String::from("5")), 
             time_stamp: None,
             name: String::from("Lettuce % % \\% \\' break some sTuff ⌠ 	⌡ 	⌢ 	⌣ 	⌤"),
             note: String::from(
@@ -421,34 +828,118 @@ mod tests {
                 another in a spirit of brotherhood.' in Malayalam. It comes from the \
                 UN's Universal Declaration on Human Rights)"
             ),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         };
 
-        // run in series, this entry should have ID == 8, see post_null_entry()
-        let receipt = update_guestbook(Json(valid_entry.clone())).await.unwrap();
-        assert_eq!(receipt.0.id, "8");  
+        // see post_null_entry() for why this is 5
+        let (state, _) = test_state();
+        let receipt = update_guestbook(State(state.clone()), Json(valid_entry.clone().into())).await.unwrap();
+        assert_eq!(receipt.0.id, "5");  
 
-        // check to see if the entry was posted with 
-        let mut conn = get_db_conn_pool()?.get_conn()?;
+        let fetched_entry = get_guestbook(State(state)).await?.0.guestbook.remove(0);
 
-        // no other entries in the demo database have a null user agent
-        let fetched_entry = conn.query_map(
-            format!(
-                "SELECT id, guestName, guestNote FROM guestbook
-                WHERE dateSubmitted >= STR_TO_DATE('{}', '%Y-%m-%d %H:%i:%S')
-                AND guestName LIKE 'Lettuce%'", 
-                proc_start.format("%Y-%m-%d %H:%M:%S").to_string()
-            ),
-            |(id, name, note): (String, String, String)| {
-                // timestamp not needed if the query finds an entry in terms of proc_start
-                GuestbookEntry { id: Some(id), time_stamp: None, name, note }
-            }
-        )?;
-
-        assert_eq!(fetched_entry[0], valid_entry);
+        valid_entry.time_stamp = Some(receipt.0.time_stamp);
+        assert_eq!(fetched_entry, valid_entry);
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn spam_filtering() {
+
+        let (state, mem_store) = test_state();
+        let state = AppState {
+            spam: Arc::new(SpamFilter::disabled().with_check(BannedRules::built_in()).with_check(LinkLimit { max_links: 1 })),
+            ..state
+        };
+        let post = |name: &str, note: &str| update_guestbook(State(state.clone()), Json(GuestbookEntry {
+            id: None, time_stamp: None, name: String::from(name), note: String::from(note), pinned: None, spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
+        }.into()));
+
+        let accepted = post("Sam", "Love the photos!").await.unwrap().0;
+        assert_eq!(accepted.status, EntryStatus::Approved);
+        // dropped, but whoever sent it can't tell
+        let dropped = post("Deals", "Cheap viagra").await.unwrap().0;
+        assert_eq!(dropped.status, EntryStatus::Approved);
+        let held = post("Sam", "https://a.example and https://b.example").await.unwrap().0;
+        assert_eq!(held.status, EntryStatus::Pending);
+
+        let shown = get_guestbook(State(state.clone())).await.unwrap().0.guestbook;
+        assert_eq!((shown.len(), shown[0].id.as_deref(), shown[0].spam.as_ref()), (5, Some(accepted.id.as_str()), None));
+
+        let rejected = mem_store.entries_with_status(EntryStatus::Rejected).await.unwrap();
+        assert_eq!(rejected[0].id, Some(dropped.id));
+        assert_eq!(rejected[0].spam, Some(SpamDecision { verdict: SpamVerdict::Drop, reason: Some(String::from("banned word \"viagra\"")) }));
+        let pending = mem_store.entries_with_status(EntryStatus::Pending).await.unwrap();
+        assert_eq!(pending[0].id, Some(held.id));
+        assert_eq!(pending[0].spam.as_ref().map(|spam| spam.verdict), Some(SpamVerdict::Hold));
+        let approved = mem_store.entries_with_status(EntryStatus::Approved).await.unwrap();
+        assert_eq!(approved.last().unwrap().spam, Some(SpamDecision { verdict: SpamVerdict::Accept, reason: None }));
+
+        // and held entries still wait with moderation on, like everything else
+        let state = AppState { moderation: true, ..state };
+        let receipt = update_guestbook(State(state), Json(GuestbookEntry {
+            id: None, time_stamp: None, name: String::from("Deals"), note: String::from("cheap viagra"), pinned: None, spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
+        }.into())).await.unwrap().0;
+        assert_eq!(receipt.status, EntryStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn bot_checks() {
+        use axum::{body::{to_bytes, Body}, http::{header, Request}};
+        use sha2::{Digest, Sha256};
+        use tower::ServiceExt;
+        use crate::{routes::build_router, srv_io::bot_check::ProofOfWork};
+
+        let (state, mem_store) = test_state();
+        let state = AppState { pow: Arc::new(ProofOfWork::new(4, Duration::from_secs(60))), ..state };
+        let app = build_router(state);
+
+        let resp = app.clone().oneshot(Request::get("/guestbook/challenge").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let challenge: PowChallenge = mysql_common::serde_json::from_slice(&to_bytes(resp.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(challenge.difficulty, 4);
+        // the same as the page does it
+        let nonce = (0u64..).find(|nonce| Sha256::digest(format!("{}:{nonce}", challenge.token))[0] < 0x10).unwrap();
+
+        let post = |body: String| Request::post("/guestbook/entries")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let solved = format!(r#"{{"name": "Sam", "note": "Hi!", "challenge": {{"token": "{}", "nonce": {nonce}}}}}"#, challenge.token);
+
+        let resp = app.clone().oneshot(post(solved.clone())).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // each of these is turned away before anything's written
+        for (body, error) in [
+            (solved, BotCheckError::ReusedChallenge),
+            (String::from(r#"{"name": "Sam", "note": "Hi!"}"#), BotCheckError::MissingChallenge),
+            (String::from(r#"{"name": "Sam", "note": "Hi!", "website": "http://spam.example"}"#), BotCheckError::Honeypot),
+        ] {
+            let resp = app.clone().oneshot(post(body)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let failed: BotCheckFailed = mysql_common::serde_json::from_slice(&to_bytes(resp.into_body(), usize::MAX).await.unwrap()).unwrap();
+            assert_eq!(failed.error, error);
+            assert_eq!(failed.message, error.message());
+        }
+        assert_eq!(mem_store.get_entries().await.unwrap().len(), 5);
+
+        // and without the challenge on, there's no route for it
+        let resp = build_router(test_state().0).oneshot(Request::get("/guestbook/challenge").body(Body::empty()).unwrap()).await.unwrap();
+        assert_ne!(resp.status(), StatusCode::OK);
+    }
+
     #[tokio::test]
     async fn post_overlong_entry_note()-> Result<(), DbOrUserError> {
 
@@ -470,17 +961,23 @@ mod tests {
                 data exceed 1KB. And now it does, with all these extra characters \
                 to put it over the finsh line."
             ),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         };
 
         // this part is good as long as it doesn't panic (which it would on anOk variant here)
-        let name_len_err = update_guestbook(Json(overlong_entry.clone()))
+        let name_len_err = update_guestbook(State(test_state().0), Json(overlong_entry.clone().into()))
             .await
             .unwrap_err();
 
-        // gotta do it the hard way, because mysql::Error, a part of DbOrUserError,
+        // gotta do it the hard way, because mysql_async::Error, a part of DbOrUserError,
         // doesn't impl PartialEq
         match name_len_err {
             DbOrUserError::DbError(e) => panic!("Wrong error! {:?}", e),
+            DbOrUserError::BotCheck(e) => panic!("Wrong error! {:?}", e),
             DbOrUserError::UserError(e) => {
                 assert_eq!(e, UserError::NoteTooLong);
                 Ok(())
@@ -499,22 +996,41 @@ mod tests {
                 "A name മനുഷ്യരെല്ലാവരും തുല്യാവകാശങ്ങളോടും that is too ᎦᏬᏂᎯᏍᏗ long. \
                 so long, in fact, I needed to add all this stuff!"),
             note: String::from("a brief note"),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         };
 
         // this part is good as long as it doesn't panic (which it would on anOk variant here)
-        let name_len_err = update_guestbook(Json(overlong_name.clone()))
+        let name_len_err = update_guestbook(State(test_state().0), Json(overlong_name.clone().into()))
             .await
             .unwrap_err();
 
-        // gotta do it the hard way, because mysql::Error, a part of DbOrUserError,
+        // gotta do it the hard way, because mysql_async::Error, a part of DbOrUserError,
         // doesn't impl PartialEq
         match name_len_err {
             DbOrUserError::DbError(e) => panic!("Wrong error! {:?}", e),
+            DbOrUserError::BotCheck(e) => panic!("Wrong error! {:?}", e),
             DbOrUserError::UserError(e) => {
                 assert_eq!(e, UserError::NameTooLong);
                 Ok(())
             }
         }
     }
+
+    #[test]
+    fn unavailable_db_sends_503() {
+
+        let resp = DbError::Unavailable(Duration::from_millis(2500)).into_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        // rounded up, so clients don't come back too early
+        assert_eq!(resp.headers()["retry-after"], "3");
+
+        let resp = DbError::QueryTimedOut.into_response();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(resp.headers().get("retry-after").is_none());
+    }
     
 }
\ No newline at end of file
@@ -197,7 +197,7 @@ fn receive_list_row(conn: &mut TcpStream, total_rows: usize) -> Event {
     // all error messages sent from the Python app start with a double-hyphen
     if  row_data.starts_with("--") {
 
-        return build_python_app_err_event(row_data);
+        build_python_app_err_event(row_data)
     }
     else if row_data.starts_with("done!") {
 
@@ -1,3 +1,12 @@
+pub mod admin_auth;
+pub mod admin_io;
+pub mod author_edits;
+pub mod bot_check;
 pub mod db_io;
 pub mod lb_app_io;
+pub mod page_views;
+pub mod rate_limit;
+pub mod reactions;
+pub mod replies;
+pub mod stats_io;
 pub mod vite_get;
\ No newline at end of file
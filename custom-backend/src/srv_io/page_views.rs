@@ -0,0 +1,466 @@
+// Hits are logged by the server itself, as it serves the pages, rather than
+// by the browser POSTing a WebpageHit to /hits after the page loads. That
+// way the time stamp is the server's, and the count can't be run up with
+// curl, or at least not without downloading a page each time.
+//
+// track_page_views() wraps the static routes, and counts a view whenever one
+// of them sends back an HTML page (a 304 for one counts too, since that's a
+// page the browser already had). Assets, 404s, and so on are left alone.
+// Reloading a page doesn't count again, as long as it's within
+// HIT_DEDUPE_SECS of the last view of it from the same IP and user agent.
+//
+// Along with the path, each view keeps the host of the page the visitor came
+// from (links within the site don't count), and any utm_source, utm_medium,
+// and utm_campaign in the query string, for telling campaigns apart.
+//
+// POST /hits is still there for clients that haven't caught up, but only if
+// HIT_LEGACY_POST is set to true. The front end doesn't use it anymore.
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use axum::{
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    middleware::Next,
+    response::Response,
+};
+use mysql_common::chrono::{SubsecRound, Utc};
+use tracing::{info, warn};
+use crate::{
+    app_state::AppState,
+    storage::LoggedHit,
+    types::db_io_types::WebpageHit,
+    utils::init_utils::get_env_var_or,
+};
+
+
+// Past this many clients in the window, the ones whose window is up are
+// cleared out; if that doesn't get it under, every one of them is, so
+// the map can't grow forever under a flood
+const MAX_TRACKED_VIEWS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageViewConfig {
+    pub dedupe_window: Duration,    // HIT_DEDUPE_SECS; 0 counts every view
+    pub legacy_post: bool,          // HIT_LEGACY_POST; whether POST /hits is routed at all
+}
+
+impl Default for PageViewConfig {
+    fn default() -> PageViewConfig {
+        PageViewConfig {
+            dedupe_window: Duration::from_secs(300),
+            legacy_post: false,
+        }
+    }
+}
+
+impl PageViewConfig {
+
+    pub fn from_env() -> PageViewConfig {
+
+        let defaults = PageViewConfig::default();
+
+        PageViewConfig {
+            dedupe_window: Duration::from_secs(
+                get_env_var_or("HIT_DEDUPE_SECS", defaults.dedupe_window.as_secs())
+            ),
+            legacy_post: get_env_var_or("HIT_LEGACY_POST", defaults.legacy_post),
+        }
+    }
+}
+
+
+// When each client last viewed each page. The key is a hash of the IP, user
+// agent, and path, with a key that's random for each run, so no IPs are kept.
+pub struct RecentViews {
+    window: Duration,
+    hasher: RandomState,
+    last_seen: Mutex<HashMap<u64, Instant>>,
+}
+
+impl RecentViews {
+
+    pub fn new(window: Duration) -> RecentViews {
+        RecentViews { window, hasher: RandomState::new(), last_seen: Mutex::new(HashMap::new()) }
+    }
+
+    // true if the same client viewed the same page within the window; either
+    // way, this view is remembered, so the window starts over from now
+    fn is_repeat(&self, client: IpAddr, user_agent: &str, path: &str, now: Instant) -> bool {
+
+        if self.window.is_zero() {
+            return false;
+        }
+
+        let key = self.hasher.hash_one((client, user_agent, path));
+        let mut last_seen = self.lock_views();
+
+        if last_seen.len() >= MAX_TRACKED_VIEWS {
+            last_seen.retain(|_, seen| now.duration_since(*seen) < self.window);
+            if last_seen.len() >= MAX_TRACKED_VIEWS {
+                warn!("Over {MAX_TRACKED_VIEWS} clients viewed pages within the last {:?}; forgetting them all.", self.window);
+                last_seen.clear();
+            }
+        }
+
+        last_seen.insert(key, now)
+            .is_some_and(|seen| now.duration_since(seen) < self.window)
+    }
+
+    // nothing is ever left half-changed in here by a panic
+    fn lock_views(&self) -> MutexGuard<'_, HashMap<u64, Instant>> {
+        self.last_seen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+
+// Do Not Track, or Global Privacy Control
+fn opted_out(headers: &HeaderMap) -> bool {
+    ["DNT", "Sec-GPC"].iter().any(|name| headers.get(*name).is_some_and(|val| val == "1"))
+}
+
+// Fills in the visitor (see storage/visitors.rs) and location (see
+// utils/geoip.rs), then hands the hit to the counter. For both the
+// middleware and POST /hits. The client is the one from client_ip(), 
+// not the peer, which could just be a proxy. The IP goes no further.
+pub fn record_hit(state: &AppState, client: IpAddr, headers: &HeaderMap, mut logged_hit: LoggedHit) {
+
+    if !logged_hit.agent.is_bot && !opted_out(headers) {
+        logged_hit.visitor = state.hits.visitor(client, &logged_hit.hit.user_agent);
+    }
+    logged_hit.location = state.geoip.locate(client);
+
+    let to_page = logged_hit.hit.path.as_ref().map(|path| format!(" to {path}")).unwrap_or_default();
+    if logged_hit.agent.is_bot {
+        info!("New visit{to_page} from a bot ({}): {}", logged_hit.agent.browser, logged_hit.hit.user_agent);
+    } else {
+        info!("New visit{to_page} from: {}", logged_hit.hit.user_agent);
+    }
+    state.hits.record(logged_hit);
+}
+
+fn is_page_view(method: &Method, path: &str, resp: &Response) -> bool {
+
+    let content_type = header_str(resp.headers(), header::CONTENT_TYPE);
+    let is_html = |content_type: &str| content_type.starts_with("text/html");
+
+    method == Method::GET && match resp.status() {
+        StatusCode::OK => content_type.is_some_and(is_html),
+        // a 304 has no body, so it usually doesn't say what it's
+        // not sending, and the path has to go by instead
+        StatusCode::NOT_MODIFIED => content_type.map_or_else(|| is_html_path(path), is_html),
+        _ => false,
+    }
+}
+
+// the pages are "/", the paths in serve_statics()'s url_map, which have
+// no extension, and anything else that ends in .html
+fn is_html_path(path: &str) -> bool {
+    let file = path.rsplit('/').next().unwrap_or_default();
+    !file.contains('.') || file.ends_with(".html")
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|val| val.to_str().ok())
+}
+
+// The host of a Referer, in lowercase, and without any "www." or port, so
+// every link from a site is counted together. None if there's no host in it.
+// A bare host is taken as is, so this can be run on its own output.
+pub fn referrer_host(referrer: &str) -> Option<String> {
+
+    let uri: Uri = referrer.trim().parse().ok()?;
+    let host = uri.host()?.trim_end_matches('.').to_lowercase();
+    let host = host.strip_prefix("www.").map(str::to_string).unwrap_or(host);
+
+    (!host.is_empty()).then_some(host)
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct UtmParams {
+    utm_source: Option<String>,
+    utm_medium: Option<String>,
+    utm_campaign: Option<String>,
+}
+
+pub async fn track_page_views(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+
+    // taken before the request is handed on, since serve_statics() rewrites
+    // some of the paths, and these should be the ones the client asked for
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    // a query string that won't parse just doesn't count as a campaign
+    let utm = Query::<UtmParams>::try_from_uri(req.uri()).map(|Query(utm)| utm).unwrap_or_default();
+    // HTTP/2 requests have the host in the URI instead
+    let own_host = req.uri().host().or(header_str(req.headers(), header::HOST)).and_then(referrer_host);
+    let headers = req.headers().clone();
+    let client = state.proxies.client_ip(peer.ip(), &headers);
+
+    let resp = next.run(req).await;
+    if !is_page_view(&method, &path, &resp) {
+        return resp;
+    }
+
+    let user_agent = header_str(&headers, header::USER_AGENT).unwrap_or_default();
+    if state.recent_views.is_repeat(client, user_agent, &path, Instant::now()) {
+        return resp;
+    }
+
+    let page_hit = WebpageHit {
+        time_stamp: Utc::now().naive_utc().trunc_subsecs(0),
+        user_agent: user_agent.to_string(),
+        path: Some(path),
+        referrer: header_str(&headers, header::REFERER).map(str::to_string),
+        utm_source: utm.utm_source,
+        utm_medium: utm.utm_medium,
+        utm_campaign: utm.utm_campaign,
+    };
+    let mut logged_hit = LoggedHit::new(page_hit, &state.ua_rules);
+    // someone going from one page to another isn't a referral
+    if logged_hit.hit.referrer.is_some() && logged_hit.hit.referrer == own_host {
+        logged_hit.hit.referrer = None;
+    }
+    record_hit(&state, client, &headers, logged_hit);
+
+    resp
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::Arc;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use crate::{
+        routes::build_router,
+        storage::{hit_counter::HitCounter, mem_store::MemStore},
+        utils::client_ip::{TrustedProxies, X_FORWARDED_FOR},
+    };
+
+    fn test_state(config: &PageViewConfig) -> (AppState, Arc<HitCounter>, Arc<MemStore>) {
+        let (state, hit_counter, mem_store) = AppState::for_tests();
+        let state = AppState {
+            recent_views: Arc::new(RecentViews::new(config.dedupe_window)),
+            legacy_post: config.legacy_post,
+            ..state
+        };
+        (state, hit_counter, mem_store)
+    }
+
+    fn from_client(mut req: Request) -> Request {
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 54321))));
+        req
+    }
+
+    #[test]
+    fn dedupes_within_the_window() {
+        let views = RecentViews::new(Duration::from_secs(60));
+        let client = IpAddr::from([203, 0, 113, 7]);
+        let start = Instant::now();
+
+        assert!(!views.is_repeat(client, "Firefox", "/", start));
+        assert!(views.is_repeat(client, "Firefox", "/", start + Duration::from_secs(59)));
+        // a different page, user agent, or IP isn't a repeat
+        assert!(!views.is_repeat(client, "Firefox", "/guestbook", start));
+        assert!(!views.is_repeat(client, "Chrome", "/", start));
+        assert!(!views.is_repeat(IpAddr::from([203, 0, 113, 8]), "Firefox", "/", start));
+        // the window restarts with each view
+        assert!(!views.is_repeat(client, "Firefox", "/", start + Duration::from_secs(59 + 60)));
+
+        let no_window = RecentViews::new(Duration::ZERO);
+        assert!(!no_window.is_repeat(client, "Firefox", "/", start));
+        assert!(!no_window.is_repeat(client, "Firefox", "/", start));
+    }
+
+    // a stand-in for serve_statics(), which needs the built front end
+    async fn fake_statics(req: Request) -> Response {
+        let (status, content_type) = match req.uri().path() {
+            "/" | "/guestbook"  => (StatusCode::OK, Some("text/html")),
+            "/script.js"        => (StatusCode::OK, Some("text/javascript")),
+            // like ServeDir, with no Content-Type on a 304
+            "/cached" | "/pages/cached.html" | "/cached.js" => (StatusCode::NOT_MODIFIED, None),
+            "/cached-with-type" => (StatusCode::NOT_MODIFIED, Some("text/html")),
+            _                   => (StatusCode::NOT_FOUND, Some("text/html")),
+        };
+        let mut resp = Response::builder().status(status);
+        if let Some(content_type) = content_type {
+            resp = resp.header(header::CONTENT_TYPE, content_type);
+        }
+        resp.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn counts_html_pages_only() {
+        let (state, hit_counter, mem_store) = test_state(&PageViewConfig::default());
+        let app = Router::new()
+            .route("/{*path}", get(fake_statics))
+            .route("/", get(fake_statics))
+            .layer(middleware::from_fn_with_state(state, track_page_views));
+
+        let get_page = |path: &str, user_agent: &str| {
+            let req = Request::get(path)
+                .header(header::USER_AGENT, user_agent)
+                .header(header::REFERER, "https://example.com/links")
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(from_client(req))
+        };
+
+        for (path, user_agent) in [
+            ("/", "Firefox"),
+            ("/", "Firefox"),           // a reload
+            ("/guestbook", "Firefox"),
+            ("/cached", "Firefox"),
+            ("/pages/cached.html", "Firefox"),
+            ("/cached.js", "Firefox"),
+            ("/cached-with-type", "Firefox"),
+            ("/script.js", "Firefox"),
+            ("/wp-login.php", "Firefox"),
+            ("/", "curl/8.5.0"),
+        ] {
+            get_page(path, user_agent).await.unwrap();
+        }
+        hit_counter.flush().await.unwrap();
+
+        let logged: Vec<(String, String)> = mem_store.hit_log().into_iter()
+            .map(|logged| (logged.hit.path.unwrap(), logged.hit.user_agent))
+            .collect();
+        assert_eq!(logged, vec![
+            (String::from("/"), String::from("Firefox")),
+            (String::from("/guestbook"), String::from("Firefox")),
+            (String::from("/cached"), String::from("Firefox")),
+            (String::from("/pages/cached.html"), String::from("Firefox")),
+            (String::from("/cached-with-type"), String::from("Firefox")),
+            (String::from("/"), String::from("curl/8.5.0")),
+        ]);
+        assert_eq!(hit_counter.count_without_bots(), 5);
+        assert_eq!(mem_store.hit_log()[0].hit.referrer.as_deref(), Some("example.com"));
+    }
+
+    #[tokio::test]
+    async fn behind_a_proxy() {
+        let (mut state, hit_counter, mem_store) = test_state(&PageViewConfig::default());
+        state.proxies = Arc::new(TrustedProxies::parse("203.0.113.7").unwrap());
+        hit_counter.flush().await.unwrap();     // picks up today's salt
+        let app = Router::new()
+            .route("/", get(fake_statics))
+            .layer(middleware::from_fn_with_state(state, track_page_views));
+
+        // everyone comes through the proxy, so it's who it forwarded for that counts
+        for forwarded_for in ["198.51.100.1", "198.51.100.2", "198.51.100.1"] {
+            let req = Request::get("/")
+                .header(header::USER_AGENT, "Firefox")
+                .header(X_FORWARDED_FOR, forwarded_for)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(from_client(req)).await.unwrap();
+        }
+        hit_counter.flush().await.unwrap();
+
+        let logged = mem_store.hit_log();
+        assert_eq!(logged.len(), 2);
+        assert!(logged[0].visitor.is_some());
+        assert_ne!(logged[0].visitor, logged[1].visitor);
+    }
+
+    #[tokio::test]
+    async fn legacy_post_is_opt_in() {
+        let post_hit = || from_client(Request::post("/hits")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"timeStamp": "2025-07-07T21:22:00", "userAgent": "mozilla"}"#))
+            .unwrap());
+
+        let (state, hit_counter, _) = test_state(&PageViewConfig::default());
+        let resp = build_router(state).oneshot(post_hit()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(hit_counter.count(), 0);
+
+        let (state, hit_counter, _) = test_state(&PageViewConfig { legacy_post: true, ..PageViewConfig::default() });
+        let resp = build_router(state).oneshot(post_hit()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hit_counter.count(), 1);
+    }
+
+    #[test]
+    fn referrer_hosts() {
+        assert_eq!(referrer_host("https://www.Example.com:8443/links?page=2").as_deref(), Some("example.com"));
+        assert_eq!(referrer_host("https://news.example.org./").as_deref(), Some("news.example.org"));
+        assert_eq!(referrer_host("android-app://com.google.android.gm/").as_deref(), Some("com.google.android.gm"));
+        // bare hosts are fine, so it can run on its own output
+        assert_eq!(referrer_host("example.com").as_deref(), Some("example.com"));
+        assert_eq!(referrer_host("localhost:4949").as_deref(), Some("localhost"));
+        assert_eq!(referrer_host("/just/a/path"), None);
+        assert_eq!(referrer_host("not a url"), None);
+        assert_eq!(referrer_host(""), None);
+    }
+
+    #[tokio::test]
+    async fn referrers_and_campaigns() {
+        let (state, hit_counter, mem_store) = test_state(&PageViewConfig { dedupe_window: Duration::ZERO, ..PageViewConfig::default() });
+        let app = Router::new()
+            .route("/{*path}", get(fake_statics))
+            .route("/", get(fake_statics))
+            .layer(middleware::from_fn_with_state(state, track_page_views));
+
+        for (uri, referrer) in [
+            ("/?utm_source=newsletter&utm_medium=email&utm_campaign=spring", None),
+            ("/guestbook", Some("http://archie.example:4949/")),    // from another page of the site
+            ("/?utm_source=&utm_campaign=%20", Some("https://www.example.com/links")),
+            ("/?utm_source=a&utm_source=b", None),
+        ] {
+            let mut req = Request::get(uri).header(header::HOST, "archie.example:4949");
+            if let Some(referrer) = referrer {
+                req = req.header(header::REFERER, referrer);
+            }
+            app.clone().oneshot(from_client(req.body(Body::empty()).unwrap())).await.unwrap();
+        }
+        hit_counter.flush().await.unwrap();
+
+        let logged: Vec<WebpageHit> = mem_store.hit_log().into_iter().map(|logged| logged.hit).collect();
+        let campaign = |hit: &WebpageHit| (hit.utm_source.clone(), hit.utm_medium.clone(), hit.utm_campaign.clone());
+        assert_eq!(logged.len(), 4);
+        assert_eq!(logged[0].path.as_deref(), Some("/"));
+        assert_eq!(campaign(&logged[0]), (Some("newsletter".into()), Some("email".into()), Some("spring".into())));
+        assert_eq!(logged[1].referrer, None);
+        // empty parameters are the same as none
+        assert_eq!(logged[2].referrer.as_deref(), Some("example.com"));
+        assert_eq!(campaign(&logged[2]), (None, None, None));
+        // a query string that doesn't parse still counts as a view
+        assert_eq!(campaign(&logged[3]), (None, None, None));
+    }
+
+    #[cfg(feature = "geoip")]
+    #[tokio::test]
+    async fn locates_without_keeping_the_ip() {
+        let (mut state, hit_counter, mem_store) = test_state(&PageViewConfig::default());
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/geoip-test.mmdb");
+        state.geoip = Arc::new(crate::utils::geoip::GeoIp::open(fixture).unwrap());
+        let app = Router::new()
+            .route("/", get(fake_statics))
+            .layer(middleware::from_fn_with_state(state, track_page_views));
+
+        for ip in [[203, 0, 113, 7], [127, 0, 0, 1]] {
+            let mut req = Request::get("/").body(Body::empty()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(SocketAddr::from((ip, 54321))));
+            app.clone().oneshot(req).await.unwrap();
+        }
+        hit_counter.flush().await.unwrap();
+
+        let logged = mem_store.hit_log();
+        assert_eq!((logged[0].country(), logged[0].region()), (Some("NZ"), Some("AUK")));
+        // it isn't in the database
+        assert_eq!(logged[1].location, None);
+        assert!(!format!("{logged:?}").contains("203.0.113"));
+    }
+}
@@ -0,0 +1,485 @@
+// Keeps any one client from flooding the guestbook (or hammering the search).
+// Each client IP gets a bucket of tokens for each limited route, which starts
+// out full. Every request takes a token, and tokens come back at a steady
+// rate, so a client can send a burst of requests at once, but not keep it up.
+// Once the bucket's empty, requests get a 429 with Retry-After, and a JSON
+// RateLimited the front end can show, until the next token comes back.
+//
+// How big the buckets are, and how fast they fill back up, is set with:
+//
+//     RATE_LIMIT_GUESTBOOK_BURST / _PER_MIN    POST /guestbook/entries (and replies)
+//     RATE_LIMIT_SEARCH_BURST / _PER_MIN       GET /guestbook/search
+//     RATE_LIMIT_REACTION_BURST / _PER_MIN     POST /guestbook/entries/{id}/reactions
+//     RATE_LIMIT_CHALLENGE_BURST / _PER_MIN    GET /guestbook/challenge
+//
+// A burst of 0 turns that limit off. Behind a proxy, the client's IP comes
+// from its headers, if it's in TRUSTED_PROXIES (see utils/client_ip.rs);
+// otherwise everyone would share the proxy's bucket. An IPv6 client's
+// whole /64 shares one bucket.
+//
+// The buckets are only kept in memory, so with more than one replica, each
+// one gets its own bucket for the same client.
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::{debug, warn};
+use crate::{
+    app_state::AppState,
+    types::db_io_types::RateLimited,
+    utils::{client_ip::client_key, init_utils::get_env_var_or},
+};
+
+
+// how many IPs' buckets are kept at once, so a flood of
+// requests from everywhere can't use up all the memory
+const MAX_TRACKED_IPS: usize = 10_000;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketConfig {
+    pub burst: u32,     // how many tokens a bucket holds
+    pub per_min: f64,   // how many come back each minute
+}
+
+impl BucketConfig {
+
+    // reads {prefix}_BURST and {prefix}_PER_MIN; None if the burst is 0
+    fn from_env(prefix: &str, defaults: BucketConfig) -> Option<BucketConfig> {
+
+        let burst = get_env_var_or(&format!("{prefix}_BURST"), defaults.burst);
+        let per_min = get_env_var_or(&format!("{prefix}_PER_MIN"), defaults.per_min);
+
+        if !per_min.is_finite() || per_min < 0.0 {
+            warn!("{prefix}_PER_MIN needs to be a number that's 0 or more; using {} instead.", defaults.per_min);
+            return (burst > 0).then_some(BucketConfig { burst, per_min: defaults.per_min });
+        }
+
+        (burst > 0).then_some(BucketConfig { burst, per_min })
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    route: &'static str,            // for the logs
+    config: Option<BucketConfig>,   // None means there's no limit
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+
+    pub fn new(route: &'static str, config: BucketConfig) -> RateLimiter {
+        RateLimiter { route, config: Some(config), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn disabled(route: &'static str) -> RateLimiter {
+        RateLimiter { route, config: None, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    fn from_env(route: &'static str, prefix: &str, defaults: BucketConfig) -> RateLimiter {
+        match BucketConfig::from_env(prefix, defaults) {
+            Some(config) => RateLimiter::new(route, config),
+            None => RateLimiter::disabled(route),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    pub fn config(&self) -> Option<BucketConfig> {
+        self.config
+    }
+
+    pub fn route(&self) -> &'static str {
+        self.route
+    }
+
+    // Takes a token from the client's bucket, or says how
+    // long it'll be until there's one to take
+    pub fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+
+        let Some(config) = self.config else {
+            return Ok(());
+        };
+        let client = client_key(client);
+        let burst = f64::from(config.burst);
+        let per_sec = config.per_min / 60.0;
+
+        let mut buckets = self.lock_buckets();
+
+        if buckets.len() >= MAX_TRACKED_IPS && !buckets.contains_key(&client) {
+            self.make_room(&mut buckets, now, burst, per_sec);
+            // Everyone it knows about is throttled. Forgetting any of them would
+            // let them start over, so newcomers wait until one of them isn't.
+            if buckets.len() >= MAX_TRACKED_IPS {
+                let tokens = buckets.values().map(|bucket| refilled(bucket, now, burst, per_sec)).fold(0.0, f64::max);
+                return Err(wait_for_token(tokens, per_sec));
+            }
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket { tokens: burst, updated: now });
+        bucket.tokens = refilled(bucket, now, burst, per_sec);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(wait_for_token(bucket.tokens, per_sec))
+    }
+
+    // A full bucket's the same as a new one, so those go first. Then it's the
+    // ones used longest ago, a tenth of them at a time, but not any that are
+    // throttled, or a flood from enough IPs would get them all a new bucket.
+    fn make_room(&self, buckets: &mut HashMap<IpAddr, Bucket>, now: Instant, burst: f64, per_sec: f64) {
+
+        buckets.retain(|_, bucket| refilled(bucket, now, burst, per_sec) < burst);
+        if buckets.len() < MAX_TRACKED_IPS {
+            return;
+        }
+
+        let mut unthrottled: Vec<(Instant, IpAddr)> = buckets.iter()
+            .filter(|(_, bucket)| refilled(bucket, now, burst, per_sec) >= 1.0)
+            .map(|(client, bucket)| (bucket.updated, *client))
+            .collect();
+        unthrottled.sort_unstable();
+        let excess = buckets.len() + 1 - MAX_TRACKED_IPS + MAX_TRACKED_IPS / 10;
+        for (_, client) in unthrottled.into_iter().take(excess) {
+            buckets.remove(&client);
+        }
+        warn!("Over {MAX_TRACKED_IPS} IPs have used {} lately; forgetting the ones used longest ago.", self.route);
+    }
+
+    fn lock_buckets(&self) -> MutexGuard<'_, HashMap<IpAddr, Bucket>> {
+        // the buckets are always left whole, so a poisoned lock is fine to use
+        self.buckets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// how many tokens the bucket has by now; Instants don't
+// go backwards, but it's a no-op if `now` is older
+fn refilled(bucket: &Bucket, now: Instant, burst: f64, per_sec: f64) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * per_sec).min(burst)
+}
+
+// with nothing coming back, it's never, but a day is long enough
+fn wait_for_token(tokens: f64, per_sec: f64) -> Duration {
+    let wait = if per_sec > 0.0 { (1.0 - tokens) / per_sec } else { 86_400.0 };
+    Duration::from_secs_f64(wait.clamp(0.0, 86_400.0))
+}
+
+
+// one for each limited route
+pub struct RateLimits {
+    pub guestbook_posts: RateLimiter,
+    pub searches: RateLimiter,
+    pub reactions: RateLimiter,
+    pub challenges: RateLimiter,
+}
+
+impl RateLimits {
+
+    pub const GUESTBOOK_DEFAULTS: BucketConfig = BucketConfig { burst: 5, per_min: 1.0 };
+    pub const SEARCH_DEFAULTS: BucketConfig = BucketConfig { burst: 20, per_min: 30.0 };
+    pub const REACTION_DEFAULTS: BucketConfig = BucketConfig { burst: 10, per_min: 10.0 };
+    // twice the posts', so a challenge that's timed out or gone wrong can be tried again
+    pub const CHALLENGE_DEFAULTS: BucketConfig = BucketConfig { burst: 10, per_min: 2.0 };
+
+    pub fn from_env() -> RateLimits {
+        RateLimits {
+            guestbook_posts: RateLimiter::from_env("guestbook posts", "RATE_LIMIT_GUESTBOOK", Self::GUESTBOOK_DEFAULTS),
+            searches: RateLimiter::from_env("guestbook searches", "RATE_LIMIT_SEARCH", Self::SEARCH_DEFAULTS),
+            reactions: RateLimiter::from_env("reactions", "RATE_LIMIT_REACTION", Self::REACTION_DEFAULTS),
+            challenges: RateLimiter::from_env("guestbook challenges", "RATE_LIMIT_CHALLENGE", Self::CHALLENGE_DEFAULTS),
+        }
+    }
+
+    pub fn disabled() -> RateLimits {
+        RateLimits {
+            guestbook_posts: RateLimiter::disabled("guestbook posts"),
+            searches: RateLimiter::disabled("guestbook searches"),
+            reactions: RateLimiter::disabled("reactions"),
+            challenges: RateLimiter::disabled("guestbook challenges"),
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct Throttled {
+    pub retry_after: Duration,
+}
+
+impl IntoResponse for Throttled {
+    fn into_response(self) -> Response {
+
+        let retry_after = self.retry_after.as_millis().div_ceil(1000).max(1) as u64;
+
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, retry_after.to_string())],
+            Json(RateLimited {
+                message: format!(
+                    "Slow down a little! Try again in {retry_after} second{}.",
+                    if retry_after == 1 { "" } else { "s" }
+                ),
+                retry_after,
+            })
+        ).into_response()
+    }
+}
+
+async fn enforce(limiter: &RateLimiter, state: &AppState, peer: SocketAddr, req: Request, next: Next) -> Response {
+
+    let client = state.proxies.client_ip(peer.ip(), req.headers());
+
+    match limiter.check(client, Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => {
+            debug!("Throttled {} from {client} for {retry_after:?}.", limiter.route);
+            Throttled { retry_after }.into_response()
+        },
+    }
+}
+
+// only layered onto the routes when their limits are on (see routes.rs)
+pub async fn limit_guestbook_posts(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    enforce(&state.rate_limits.guestbook_posts, &state, peer, req, next).await
+}
+
+pub async fn limit_searches(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    enforce(&state.rate_limits.searches, &state, peer, req, next).await
+}
+
+pub async fn limit_reactions(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    enforce(&state.rate_limits.reactions, &state, peer, req, next).await
+}
+
+pub async fn limit_challenges(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    enforce(&state.rate_limits.challenges, &state, peer, req, next).await
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::Arc;
+    use axum::{body::Body, http::header, Router};
+    use tower::ServiceExt;
+    use crate::{
+        routes::build_router,
+        srv_io::bot_check::ProofOfWork,
+        utils::client_ip::TrustedProxies,
+    };
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn buckets_fill_back_up() {
+        let limiter = RateLimiter::new("tests", BucketConfig { burst: 3, per_min: 6.0 });
+        let client = IpAddr::from([203, 0, 113, 7]);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client, start), Ok(()));
+        }
+        assert_eq!(limiter.check(client, start), Err(secs(10)));
+        // someone else has their own bucket
+        assert_eq!(limiter.check(IpAddr::from([203, 0, 113, 8]), start), Ok(()));
+
+        assert_eq!(limiter.check(client, start + secs(4)), Err(secs(6)));
+        assert_eq!(limiter.check(client, start + secs(10)), Ok(()));
+        assert_eq!(limiter.check(client, start + secs(10)), Err(secs(10)));
+
+        // it only fills back up to the burst, however long it's been
+        let later = start + secs(3600);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client, later), Ok(()));
+        }
+        assert!(limiter.check(client, later).is_err());
+
+        let no_refills = RateLimiter::new("tests", BucketConfig { burst: 1, per_min: 0.0 });
+        assert_eq!(no_refills.check(client, start), Ok(()));
+        assert_eq!(no_refills.check(client, start + secs(3600)), Err(secs(86_400)));
+
+        let disabled = RateLimiter::disabled("tests");
+        assert!(!disabled.is_enabled());
+        for _ in 0..100 {
+            assert_eq!(disabled.check(client, start), Ok(()));
+        }
+    }
+
+    #[test]
+    fn ipv6_clients_share_their_64() {
+        let limiter = RateLimiter::new("tests", BucketConfig { burst: 1, per_min: 1.0 });
+        let start = Instant::now();
+
+        assert_eq!(limiter.check("2001:db8:1:2::1".parse().unwrap(), start), Ok(()));
+        assert!(limiter.check("2001:db8:1:2:ffff::9".parse().unwrap(), start).is_err());
+        assert_eq!(limiter.check("2001:db8:1:3::1".parse().unwrap(), start), Ok(()));
+
+        // the same as its IPv4 address
+        assert_eq!(limiter.check(IpAddr::from([198, 51, 100, 1]), start), Ok(()));
+        assert!(limiter.check("::ffff:198.51.100.1".parse().unwrap(), start).is_err());
+    }
+
+    #[test]
+    fn forgetting_full_buckets() {
+        let limiter = RateLimiter::new("tests", BucketConfig { burst: 2, per_min: 60.0 });
+        let start = Instant::now();
+
+        for n in 0..MAX_TRACKED_IPS as u32 {
+            limiter.check(IpAddr::from(n.to_be_bytes()), start).unwrap();
+        }
+        // all of them have filled back up by now, so they're dropped
+        limiter.check(IpAddr::from([203, 0, 113, 7]), start + secs(2)).unwrap();
+        assert_eq!(limiter.lock_buckets().len(), 1);
+    }
+
+    #[test]
+    fn throttled_clients_outlast_a_flood() {
+        let limiter = RateLimiter::new("tests", BucketConfig { burst: 2, per_min: 1.0 });
+        let flooder = IpAddr::from([198, 51, 100, 1]);
+        let start = Instant::now();
+
+        limiter.check(flooder, start).unwrap();
+        limiter.check(flooder, start).unwrap();
+        assert_eq!(limiter.check(flooder, start), Err(secs(60)));
+
+        // more IPs than it keeps buckets for, none of them full again yet
+        let later = start + secs(10);
+        for n in 0..MAX_TRACKED_IPS as u32 {
+            limiter.check(IpAddr::from((n + 1).to_be_bytes()), later).unwrap();
+        }
+        assert!(limiter.lock_buckets().len() <= MAX_TRACKED_IPS);
+        assert_eq!(limiter.check(flooder, later), Err(secs(50)));
+
+        // with only throttled clients left, newcomers wait for a spot
+        let limiter = RateLimiter::new("tests", BucketConfig { burst: 1, per_min: 1.0 });
+        for n in 0..MAX_TRACKED_IPS as u32 {
+            limiter.check(IpAddr::from((n + 1).to_be_bytes()), start).unwrap();
+        }
+        assert_eq!(limiter.check(flooder, start + secs(30)), Err(secs(30)));
+        assert_eq!(limiter.check(flooder, start + secs(60)), Ok(()));
+    }
+
+    fn test_app(limits: RateLimits, proxies: TrustedProxies) -> Router {
+        let (state, _, _) = AppState::for_tests();
+        build_router(AppState {
+            rate_limits: Arc::new(limits),
+            proxies: Arc::new(proxies),
+            ..state
+        })
+    }
+
+    fn post_entry(peer: [u8; 4], forwarded_for: Option<&str>) -> Request {
+        let mut req = Request::post("/guestbook/entries")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(forwarded_for) = forwarded_for {
+            req = req.header("x-forwarded-for", forwarded_for);
+        }
+        let mut req = req.body(Body::from(r#"{"name": "Someone", "note": "Hello!"}"#)).unwrap();
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from((peer, 54321))));
+        req
+    }
+
+    #[tokio::test]
+    async fn throttling_guestbook_posts() {
+        let limits = RateLimits {
+            guestbook_posts: RateLimiter::new("guestbook posts", BucketConfig { burst: 2, per_min: 1.0 }),
+            ..RateLimits::disabled()
+        };
+        let app = test_app(limits, TrustedProxies::parse("10.0.0.1").unwrap());
+
+        for _ in 0..2 {
+            let resp = app.clone().oneshot(post_entry([203, 0, 113, 7], None)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let resp = app.clone().oneshot(post_entry([203, 0, 113, 7], None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = resp.headers()[RETRY_AFTER].to_str().unwrap().parse().unwrap();
+        assert!((59..=60).contains(&retry_after));
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let throttled: RateLimited = mysql_common::serde_json::from_slice(&body).unwrap();
+        assert_eq!(throttled.retry_after, retry_after);
+        assert!(throttled.message.contains(&retry_after.to_string()));
+
+        // making up a header doesn't get anyone a new bucket...
+        let resp = app.clone().oneshot(post_entry([203, 0, 113, 7], Some("198.51.100.1"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        // ...but the proxy's clients each have their own
+        let resp = app.clone().oneshot(post_entry([10, 0, 0, 1], Some("198.51.100.1"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = app.clone().oneshot(post_entry([10, 0, 0, 1], Some("203.0.113.7"))).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // the search isn't limited here, so it's not layered on, and doesn't need ConnectInfo
+        let search = Request::get("/guestbook/search?q=hello").body(Body::empty()).unwrap();
+        assert_eq!(app.oneshot(search).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn throttling_challenges() {
+        let (state, _, _) = AppState::for_tests();
+        let app = build_router(AppState {
+            rate_limits: Arc::new(RateLimits {
+                challenges: RateLimiter::new("guestbook challenges", BucketConfig { burst: 2, per_min: 1.0 }),
+                ..RateLimits::disabled()
+            }),
+            pow: Arc::new(ProofOfWork::new(4, Duration::from_secs(60))),
+            ..state
+        });
+        let get_challenge = || {
+            let mut req = Request::get("/guestbook/challenge").body(Body::empty()).unwrap();
+            req.extensions_mut().insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 54321))));
+            req
+        };
+
+        for _ in 0..2 {
+            assert_eq!(app.clone().oneshot(get_challenge()).await.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(app.oneshot(get_challenge()).await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -0,0 +1,381 @@
+// Emoji reactions to guestbook entries. Anyone can leave any of the emoji
+// in REACTION_EMOJI on any entry that's shown:
+//
+//     GET  /guestbook/reactions                  the emoji on offer, in order
+//     POST /guestbook/entries/{id}/reactions     {"emoji": "👍"}
+//
+// REACTION_EMOJI is separated by spaces or commas, and is "👍 ❤️ 😂 🎉 😮"
+// by default; setting it to nothing turns reactions off. Taking an emoji
+// off the list hides its counts, but they're kept, in case it's put back.
+//
+// Each visitor only counts once for each emoji on each entry. Instead of
+// keeping their IP, or setting a cookie, they're told apart the way unique
+// visitors are (see storage/visitors.rs), by a hash of their IP and user
+// agent with the day's salt, hashed along with the entry's ID as well. So
+// the fingerprints can't be matched up across entries, or with visitorDaily,
+// and once the day's salt is gone, there's no getting from an IP back to
+// them. The catch is that a visitor's fingerprint changes from day to day,
+// so they could leave the same reaction again tomorrow; reactions have a
+// rate limit of their own (RATE_LIMIT_REACTION, see rate_limit.rs) too.
+//
+// Everything that lists entries (GET /guestbook/entries, paged or not, and
+// /guestbook/search) has each entry's counts, and so does what a POST gets back.
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header::USER_AGENT, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json
+};
+use mysql_common::chrono::{SubsecRound, Utc};
+use tracing::{debug, warn};
+use crate::{
+    app_state::AppState,
+    srv_io::db_io::DbError,
+    storage::ReactionTally,
+    types::db_io_types::{GuestbookEntry, NewReaction, ReactionCount, ReactionOptions, ReactionReceipt},
+    utils::{err_handling::make_503_resp, init_utils::get_env_var_or},
+};
+
+
+// guestbookReaction.emoji is a VARCHAR(16); that's enough
+// for most emoji made up of a few others, like 👩🏽‍💻
+pub const MAX_EMOJI_CHARS: usize = 16;
+pub const DEFAULT_EMOJI: &str = "👍 ❤️ 😂 🎉 😮";
+
+// the hit counter picks up the day's salt when it flushes, every second or so
+const NO_SALT_RETRY: Duration = Duration::from_secs(5);
+
+
+// the emoji that can be left on entries, in the order they're offered in
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReactionEmoji {
+    emoji: Vec<String>,
+}
+
+impl ReactionEmoji {
+
+    pub fn from_env() -> ReactionEmoji {
+        ReactionEmoji::parse(&get_env_var_or("REACTION_EMOJI", String::from(DEFAULT_EMOJI)))
+    }
+
+    // leaves out any that are too long to store, and any repeats
+    pub fn parse(list: &str) -> ReactionEmoji {
+
+        let mut emoji: Vec<String> = Vec::new();
+        for one in list.split([',', ' ', '\t', '\n']).filter(|one| !one.is_empty()) {
+            if one.chars().count() > MAX_EMOJI_CHARS {
+                warn!("Leaving {one} out of REACTION_EMOJI; reactions can only be up to {MAX_EMOJI_CHARS} characters.");
+            } else if !emoji.iter().any(|seen| seen == one) {
+                emoji.push(one.to_string());
+            }
+        }
+
+        ReactionEmoji { emoji }
+    }
+
+    pub fn disabled() -> ReactionEmoji {
+        ReactionEmoji { emoji: Vec::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.emoji.is_empty()
+    }
+
+    pub fn list(&self) -> &[String] {
+        &self.emoji
+    }
+
+    pub fn allows(&self, emoji: &str) -> bool {
+        self.emoji.iter().any(|allowed| allowed == emoji)
+    }
+
+    // Each entry's counts, by its ID, in the order the emoji are offered
+    // in; the ones that aren't anymore are left out
+    fn counts(&self, tallies: Vec<ReactionTally>) -> HashMap<i64, Vec<ReactionCount>> {
+
+        let mut by_entry: HashMap<i64, Vec<ReactionCount>> = HashMap::new();
+        for tally in tallies.into_iter().filter(|tally| self.allows(&tally.emoji)) {
+            by_entry.entry(tally.entry_id).or_default().push(ReactionCount { emoji: tally.emoji, count: tally.count });
+        }
+        for counts in by_entry.values_mut() {
+            counts.sort_by_key(|count| self.emoji.iter().position(|emoji| *emoji == count.emoji));
+        }
+        by_entry
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ReactionError {
+    DbError(DbError),
+    NotAllowed,             // it isn't in REACTION_EMOJI
+    NoSuchEntry(i64),       // or it isn't shown
+    NoSalt,                 // the database has been down since startup, so there's no fingerprint
+}
+
+impl From<DbError> for ReactionError {
+    fn from(db_err: DbError) -> Self {
+        Self::DbError(db_err)
+    }
+}
+
+impl IntoResponse for ReactionError {
+    fn into_response(self) -> Response {
+
+        match self {
+            ReactionError::DbError(db_err) => db_err.into_response(),
+            ReactionError::NotAllowed => (
+                StatusCode::BAD_REQUEST, "That isn't one of the reactions on offer."
+            ).into_response(),
+            ReactionError::NoSuchEntry(id) => (
+                StatusCode::NOT_FOUND, format!("There's no guestbook entry with the ID {id}.")
+            ).into_response(),
+            ReactionError::NoSalt => {
+                debug!("No visitor salt for today yet; sending 503.");
+                make_503_resp(NO_SALT_RETRY)
+            },
+        }
+    }
+}
+
+
+pub async fn get_reaction_options(State(state): State<AppState>) -> Json<ReactionOptions> {
+    Json(ReactionOptions { emoji: state.reactions.list().to_vec() })
+}
+
+pub async fn post_reaction(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    Json(reaction): Json<NewReaction>
+) -> Result<Json<ReactionReceipt>, ReactionError> {
+
+    if !state.reactions.allows(&reaction.emoji) {
+        return Err(ReactionError::NotAllowed);
+    }
+    if state.guestbook.get_entry(id).await?.is_none() {
+        return Err(ReactionError::NoSuchEntry(id));
+    }
+
+    // the IP goes no further than this
+    let client = state.proxies.client_ip(peer.ip(), &headers);
+    let user_agent = headers.get(USER_AGENT).and_then(|ua| ua.to_str().ok()).unwrap_or_default();
+    let Some(fingerprint) = state.hits.visitor_for(client, user_agent, &format!("reaction to {id}")) else {
+        return Err(ReactionError::NoSalt);
+    };
+
+    let now = Utc::now().naive_utc().trunc_subsecs(0);
+    let counted = state.guestbook.add_reaction(id, &reaction.emoji, &fingerprint.hash, now).await?;
+    if counted {
+        debug!("Reaction {} left on guestbook entry {id}.", reaction.emoji);
+    }
+
+    let tallies = state.guestbook.reaction_counts(Some(id)).await?;
+    let reactions = state.reactions.counts(tallies).remove(&id).unwrap_or_default();
+
+    Ok(Json(ReactionReceipt { id: id.to_string(), counted, reactions }))
+}
+
+// Puts each entry's counts on it, for the whole guestbook (before the
+// replies are nested), its pages, and search results; entries without
+// any are left alone
+pub async fn add_counts(state: &AppState, entries: &mut [GuestbookEntry]) -> Result<(), DbError> {
+
+    if !state.reactions.is_enabled() {
+        return Ok(());
+    }
+    let mut counts = state.reactions.counts(state.guestbook.reaction_counts(None).await?);
+    if counts.is_empty() {
+        return Ok(());
+    }
+
+    for entry in entries {
+        if let Some(id) = entry.id.as_deref().and_then(|id| id.parse::<i64>().ok()) {
+            entry.reactions = counts.remove(&id);
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::Arc;
+    use axum::{body::Body, http::{header, Request}, Router};
+    use mysql_common::chrono::NaiveDateTime;
+    use tower::ServiceExt;
+    use crate::{
+        routes::build_router,
+        srv_io::rate_limit::{BucketConfig, RateLimiter, RateLimits},
+        storage::{mem_store::MemStore, GuestbookStore},
+        types::db_io_types::{EntryStatus, Guestbook, GuestbookPage, GuestbookSearch},
+    };
+
+    #[test]
+    fn parsing_the_list() {
+        assert_eq!(ReactionEmoji::parse(DEFAULT_EMOJI).list(), ["👍", "❤️", "😂", "🎉", "😮"]);
+        assert_eq!(ReactionEmoji::parse(" 👍,🙏  👍,,👩🏽‍💻 ").list(), ["👍", "🙏", "👩🏽‍💻"]);
+        // too long for the column
+        assert_eq!(ReactionEmoji::parse("👍 thisisfartoolongforone").list(), ["👍"]);
+
+        let off = ReactionEmoji::parse(" , ");
+        assert!(!off.is_enabled());
+        assert_eq!(off, ReactionEmoji::disabled());
+
+        // the same emoji, without its variation selector, isn't on the list
+        let emoji = ReactionEmoji::parse(DEFAULT_EMOJI);
+        assert!(emoji.allows("❤️"));
+        assert!(!emoji.allows("❤"));
+        assert!(!emoji.allows(""));
+    }
+
+    #[test]
+    fn counting_in_order() {
+        let emoji = ReactionEmoji::parse("😂 👍");
+        let tally = |entry_id, emoji: &str, count| ReactionTally { entry_id, emoji: String::from(emoji), count };
+        let count = |emoji: &str, count| ReactionCount { emoji: String::from(emoji), count };
+
+        let counts = emoji.counts(vec![tally(1, "👍", 3), tally(1, "😂", 1), tally(1, "🙈", 4), tally(2, "🙈", 1), tally(3, "👍", 2)]);
+        assert_eq!(counts[&1], vec![count("😂", 1), count("👍", 3)]);
+        // ones that aren't offered anymore don't count
+        assert!(!counts.contains_key(&2));
+        assert_eq!(counts[&3], vec![count("👍", 2)]);
+    }
+
+
+    fn entry(time_stamp: &str) -> GuestbookEntry {
+        GuestbookEntry {
+            id: None,
+            time_stamp: Some(NaiveDateTime::parse_from_str(time_stamp, "%Y-%m-%d %H:%M:%S").unwrap()),
+            name: String::from("someone"),
+            note: String::from("hi"),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
+        }
+    }
+
+    // with the default emoji, and two entries, the second of them pending
+    async fn test_app(reactions: ReactionEmoji, limits: RateLimits) -> (Router, Arc<MemStore>) {
+        let (state, hit_counter, mem_store) = AppState::for_tests();
+        mem_store.add_entry(&entry("2025-02-28 00:00:00"), EntryStatus::Approved, None).await.unwrap();
+        mem_store.add_entry(&entry("2025-03-01 00:00:00"), EntryStatus::Pending, None).await.unwrap();
+        hit_counter.flush().await.unwrap();     // picks up today's salt
+        let app = build_router(AppState {
+            reactions: Arc::new(reactions),
+            rate_limits: Arc::new(limits),
+            ..state
+        });
+        (app, mem_store)
+    }
+
+    async fn react(app: &Router, to: &str, emoji: &str, peer: [u8; 4], user_agent: &str) -> (StatusCode, Option<ReactionReceipt>) {
+        let mut req = Request::post(format!("/guestbook/entries/{to}/reactions"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::USER_AGENT, user_agent)
+            .body(Body::from(format!(r#"{{"emoji": "{emoji}"}}"#)))
+            .unwrap();
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from((peer, 54321))));
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, mysql_common::serde_json::from_slice(&body).ok())
+    }
+
+    fn counts(receipt: Option<ReactionReceipt>) -> Vec<(String, u64)> {
+        receipt.unwrap().reactions.into_iter().map(|count| (count.emoji, count.count)).collect()
+    }
+
+    #[tokio::test]
+    async fn reacting_once_each() {
+        let (app, mem_store) = test_app(ReactionEmoji::parse(DEFAULT_EMOJI), RateLimits::disabled()).await;
+        let me = [203, 0, 113, 7];
+
+        let (status, receipt) = react(&app, "1", "🎉", me, "Firefox").await;
+        assert_eq!(status, StatusCode::OK);
+        let receipt = receipt.unwrap();
+        assert_eq!((receipt.id.as_str(), receipt.counted), ("1", true));
+
+        // the same again doesn't count, but anything else from anyone else does
+        let (_, again) = react(&app, "1", "🎉", me, "Firefox").await;
+        assert!(!again.unwrap().counted);
+        react(&app, "1", "🎉", me, "Chrome").await;
+        react(&app, "1", "🎉", [198, 51, 100, 1], "Firefox").await;
+        let (_, receipt) = react(&app, "1", "👍", me, "Firefox").await;
+        assert_eq!(counts(receipt), vec![(String::from("👍"), 1), (String::from("🎉"), 3)]);
+
+        // and they're in the guestbook
+        let resp = app.clone().oneshot(Request::get("/guestbook/entries").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let guestbook: Guestbook = mysql_common::serde_json::from_slice(&body).unwrap();
+        assert_eq!(guestbook.guestbook[0].reactions.as_ref().map(Vec::len), Some(2));
+        // and its pages, and search results
+        let resp = app.clone().oneshot(Request::get("/guestbook/entries?limit=1").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let page: GuestbookPage = mysql_common::serde_json::from_slice(&body).unwrap();
+        assert_eq!(page.entries[0].reactions, guestbook.guestbook[0].reactions);
+        let resp = app.clone().oneshot(Request::get("/guestbook/search?q=someone").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let found: GuestbookSearch = mysql_common::serde_json::from_slice(&body).unwrap();
+        assert_eq!(found.results[0].entry.reactions, guestbook.guestbook[0].reactions);
+
+        // the store only ever sees the fingerprints
+        let tallies = mem_store.reaction_counts(None).await.unwrap();
+        assert_eq!(tallies.iter().map(|tally| tally.count).sum::<u64>(), 4);
+    }
+
+    #[tokio::test]
+    async fn only_whats_on_offer() {
+        let (app, _) = test_app(ReactionEmoji::parse("👍"), RateLimits::disabled()).await;
+        let me = [203, 0, 113, 7];
+
+        assert_eq!(react(&app, "1", "🎉", me, "Firefox").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(react(&app, "1", "a very long note instead", me, "Firefox").await.0, StatusCode::BAD_REQUEST);
+        // and only on what's shown
+        assert_eq!(react(&app, "2", "👍", me, "Firefox").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(react(&app, "99", "👍", me, "Firefox").await.0, StatusCode::NOT_FOUND);
+
+        let resp = app.clone().oneshot(Request::get("/guestbook/reactions").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let options: ReactionOptions = mysql_common::serde_json::from_slice(&body).unwrap();
+        assert_eq!(options.emoji, ["👍"]);
+    }
+
+    #[tokio::test]
+    async fn limited_apart_from_posts() {
+        let limits = RateLimits {
+            reactions: RateLimiter::new("reactions", BucketConfig { burst: 2, per_min: 1.0 }),
+            ..RateLimits::disabled()
+        };
+        let (app, _) = test_app(ReactionEmoji::parse(DEFAULT_EMOJI), limits).await;
+        let me = [203, 0, 113, 7];
+
+        assert_eq!(react(&app, "1", "👍", me, "Firefox").await.0, StatusCode::OK);
+        assert_eq!(react(&app, "1", "👍", me, "Firefox").await.0, StatusCode::OK);
+        assert_eq!(react(&app, "1", "🎉", me, "Firefox").await.0, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(react(&app, "1", "🎉", [198, 51, 100, 1], "Firefox").await.0, StatusCode::OK);
+
+        // new entries still have their own bucket (none here)
+        let req = Request::post("/guestbook/entries")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"name": "someone", "note": "hi"}"#))
+            .unwrap();
+        assert_eq!(app.oneshot(req).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn turned_off() {
+        let (app, mem_store) = test_app(ReactionEmoji::disabled(), RateLimits::disabled()).await;
+
+        assert!(!react(&app, "1", "👍", [203, 0, 113, 7], "Firefox").await.0.is_success());
+        let resp = app.oneshot(Request::get("/guestbook/reactions").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(!resp.status().is_success());
+        assert!(mem_store.reaction_counts(None).await.unwrap().is_empty());
+    }
+}
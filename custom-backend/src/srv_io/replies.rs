@@ -0,0 +1,539 @@
+// Replies to guestbook entries. The site owner, logged in as the admin (see
+// admin_auth.rs), can reply to any entry that's shown, and so can everyone
+// else with GUESTBOOK_GUEST_REPLIES on. Both take the same body as 
+// POST /guestbook/entries:
+//
+//     POST /admin/guestbook/entries/{id}/replies     the owner's
+//     POST /guestbook/entries/{id}/replies           everyone else's
+//
+// The owner's is under /admin, since that's the only place the session
+// cookie gets sent.
+//
+// A reply is an entry like any other, and goes through everything a new
+// entry does in update_guestbook(), from the bot check to moderation, and
+// gets the same receipt. The owner's replies are the exception: they don't
+// need the challenge, and they skip the spam filter and the queue, since
+// the owner would only be approving their own replies. They're audited.
+//
+// The whole guestbook (GET /guestbook/entries without paging) has replies
+// nested under what they reply to, oldest first, up to GUESTBOOK_REPLY_DEPTH
+// (default 3) replies deep, and 0 turns replies off. Pages do too: they're
+// pages of the entries that aren't replies, each with its replies under it.
+// Search results list them like any other entry, with their parentId. A reply
+// to an entry that isn't shown (a pending, rejected or deleted one) isn't
+// shown either.
+use std::collections::{HashMap, HashSet};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json
+};
+use mysql_common::chrono::Utc;
+use tracing::debug;
+use crate::{
+    app_state::AppState,
+    srv_io::{
+        admin_auth::{audit, Admin, AdminAuth},
+        bot_check,
+        db_io::{self, DbError, DbOrUserError},
+    },
+    storage::entry_pages::EntryKey,
+    types::db_io_types::{AuditAction, BotCheckError, EntryReceipt, GuestbookEntry, GuestbookPost},
+    utils::init_utils::get_env_var_or,
+};
+
+
+pub const DEFAULT_MAX_DEPTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplyConfig {
+    pub max_depth: usize,   // GUESTBOOK_REPLY_DEPTH; how many replies deep a thread can go
+    pub from_guests: bool,  // GUESTBOOK_GUEST_REPLIES; otherwise only the owner can reply
+}
+
+impl ReplyConfig {
+
+    pub fn from_env() -> ReplyConfig {
+        ReplyConfig {
+            max_depth: get_env_var_or("GUESTBOOK_REPLY_DEPTH", DEFAULT_MAX_DEPTH),
+            from_guests: get_env_var_or("GUESTBOOK_GUEST_REPLIES", false),
+        }
+    }
+
+    // what AppState::new() starts with; without an admin to log in
+    // as, that still leaves nobody who can reply
+    pub fn owner_only() -> ReplyConfig {
+        ReplyConfig { max_depth: DEFAULT_MAX_DEPTH, from_guests: false }
+    }
+
+    // whether anyone at all could reply
+    pub fn is_enabled(&self, admin: &AdminAuth) -> bool {
+        self.max_depth > 0 && (self.from_guests || admin.is_enabled())
+    }
+
+    // whether guests can, and so whether their route is there
+    pub fn from_guests(&self) -> bool {
+        self.max_depth > 0 && self.from_guests
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ReplyError {
+    Entry(DbOrUserError),   // anything a new entry can run into
+    NoSuchEntry(i64),       // or it, or something above it, isn't shown
+    TooDeep(usize),         // the most replies deep a thread can go
+}
+
+impl From<DbOrUserError> for ReplyError {
+    fn from(err: DbOrUserError) -> Self {
+        Self::Entry(err)
+    }
+}
+
+impl From<DbError> for ReplyError {
+    fn from(db_err: DbError) -> Self {
+        Self::Entry(DbOrUserError::DbError(db_err))
+    }
+}
+
+impl From<BotCheckError> for ReplyError {
+    fn from(bot_err: BotCheckError) -> Self {
+        Self::Entry(DbOrUserError::BotCheck(bot_err))
+    }
+}
+
+impl IntoResponse for ReplyError {
+    fn into_response(self) -> Response {
+
+        match self {
+            ReplyError::Entry(err) => err.into_response(),
+            ReplyError::NoSuchEntry(id) => (
+                StatusCode::NOT_FOUND, format!("There's no guestbook entry with the ID {id}.")
+            ).into_response(),
+            ReplyError::TooDeep(max_depth) => (
+                StatusCode::BAD_REQUEST, format!("Replies only go {max_depth} deep; reply further up instead.")
+            ).into_response(),
+        }
+    }
+}
+
+
+pub async fn post_reply(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(post): Json<GuestbookPost>
+) -> Result<Json<EntryReceipt>, ReplyError> {
+
+    bot_check::check_post(&state.pow, &post, Utc::now().naive_utc())?;
+    check_depth(&state, id).await?;
+
+    let receipt = db_io::add_new_entry(&state, post.entry, Some(id), false).await?;
+
+    Ok(Json(receipt))
+}
+
+// the owner's logged in, so there's no need to check they aren't a bot
+pub async fn post_owner_reply(
+    admin: Admin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(post): Json<GuestbookPost>
+) -> Result<Json<EntryReceipt>, ReplyError> {
+
+    check_depth(&state, id).await?;
+
+    let receipt = db_io::add_new_entry(&state, post.entry, Some(id), true).await?;
+    audit(&state, admin.client, AuditAction::Reply, Some(receipt.id.clone()), Some(format!("to {id}"))).await;
+
+    Ok(Json(receipt))
+}
+
+// Whether a reply to the entry would be shown, and isn't too many replies
+// deep. That only takes looking as far up as GUESTBOOK_REPLY_DEPTH.
+async fn check_depth(state: &AppState, id: i64) -> Result<(), ReplyError> {
+
+    let max_depth = state.replies.max_depth;
+    let mut above = id;
+    for _ in 0..max_depth {
+        let Some(entry) = state.guestbook.get_entry(above).await? else {
+            return Err(ReplyError::NoSuchEntry(id));
+        };
+        match entry.parent_id.as_deref().and_then(|parent_id| parent_id.parse().ok()) {
+            Some(parent_id) => above = parent_id,
+            None => return Ok(()),
+        }
+    }
+
+    debug!("Turned away a reply to guestbook entry {id}, more than {max_depth} deep.");
+    Err(ReplyError::TooDeep(max_depth))
+}
+
+
+// Every shown reply to entries, however deep, to nest() them under a page
+// of them. That takes a trip to the store for each level down.
+pub async fn replies_to(state: &AppState, entries: &[GuestbookEntry]) -> Result<Vec<GuestbookEntry>, DbError> {
+
+    if state.replies.max_depth == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut seen: HashSet<i64> = HashSet::new();
+    let mut parent_ids: Vec<i64> = ids_of(entries);
+    let mut replies = Vec::new();
+    while !parent_ids.is_empty() {
+        seen.extend(&parent_ids);
+        let level = state.guestbook.get_replies(&parent_ids).await?;
+        // same as in place_of(), a loop would take an edited database
+        parent_ids = ids_of(&level).into_iter().filter(|id| !seen.contains(id)).collect();
+        replies.extend(level);
+    }
+    Ok(replies)
+}
+
+fn ids_of(entries: &[GuestbookEntry]) -> Vec<i64> {
+    entries.iter().filter_map(|entry| entry.id.as_deref()?.parse().ok()).collect()
+}
+
+// where an entry goes in the nested guestbook
+#[derive(Debug, PartialEq)]
+enum Place {
+    Top,
+    Under(String),  // the ID of the entry it's listed under
+    Hidden,
+}
+
+// Nests the replies in entries (all of them that are shown, in the order
+// they're shown in) under what they reply to, oldest first. Replies more
+// than max_depth deep, from before it was turned down, go under whichever
+// of their ancestors leaves them max_depth deep.
+pub fn nest(entries: Vec<GuestbookEntry>, max_depth: usize) -> Vec<GuestbookEntry> {
+
+    let parents: HashMap<String, Option<String>> = entries.iter()
+        .filter_map(|entry| Some((entry.id.clone()?, entry.parent_id.clone())))
+        .collect();
+
+    let mut top = Vec::new();
+    let mut replies: HashMap<String, Vec<GuestbookEntry>> = HashMap::new();
+    for entry in entries {
+        match place_of(&entry, &parents, max_depth) {
+            Place::Top => top.push(entry),
+            Place::Under(id) => replies.entry(id).or_default().push(entry),
+            Place::Hidden => {},
+        }
+    }
+    for siblings in replies.values_mut() {
+        siblings.sort_by_key(EntryKey::of);
+    }
+
+    top.into_iter().map(|entry| with_replies(entry, &mut replies)).collect()
+}
+
+fn place_of(entry: &GuestbookEntry, parents: &HashMap<String, Option<String>>, max_depth: usize) -> Place {
+
+    // its ancestors, from its parent on up
+    let mut ancestors: Vec<&str> = Vec::new();
+    let mut above = entry.parent_id.as_deref();
+    while let Some(id) = above {
+        // a reply's parent is always older than it, so a
+        // loop would take an edited database; better safe
+        if ancestors.len() > parents.len() {
+            return Place::Hidden;
+        }
+        let Some(parent_of_above) = parents.get(id) else { return Place::Hidden };
+        ancestors.push(id);
+        above = parent_of_above.as_deref();
+    }
+
+    if ancestors.is_empty() {
+        return Place::Top;
+    }
+    if max_depth == 0 {
+        return Place::Hidden;
+    }
+    // it's ancestors.len() deep, and ancestors[i] is i + 1 less deep than that
+    let under = ancestors.len().saturating_sub(max_depth);
+    Place::Under(ancestors[under].to_string())
+}
+
+fn with_replies(mut entry: GuestbookEntry, replies: &mut HashMap<String, Vec<GuestbookEntry>>) -> GuestbookEntry {
+
+    // left out if there aren't any, like the other optional fields
+    entry.replies = entry.id.as_ref()
+        .and_then(|id| replies.remove(id))
+        .map(|own_replies| own_replies.into_iter().map(|reply| with_replies(reply, replies)).collect());
+    entry
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+    use axum::{body::Body, extract::ConnectInfo, http::{header, Request}, Router};
+    use mysql_common::chrono::NaiveDateTime;
+    use tower::ServiceExt;
+    use crate::{
+        routes::build_router,
+        srv_io::admin_auth::hash_password,
+        storage::{mem_store::MemStore, AuditStore, GuestbookStore},
+        types::db_io_types::{EntryStatus, Guestbook, GuestbookPage},
+        utils::spam_filter::{BannedRules, SpamFilter},
+    };
+
+    fn entry(id: u32, parent_id: Option<u32>, time_stamp: &str) -> GuestbookEntry {
+        GuestbookEntry {
+            id: Some(id.to_string()),
+            time_stamp: Some(NaiveDateTime::parse_from_str(time_stamp, "%Y-%m-%d %H:%M:%S").unwrap()),
+            name: format!("guest {id}"),
+            note: String::new(),
+            pinned: None,
+            spam: None,
+            parent_id: parent_id.map(|id| id.to_string()),
+            replies: None,
+            reactions: None,
+        }
+    }
+
+    // each entry's ID, followed by its replies' in brackets
+    fn shape(entries: &[GuestbookEntry]) -> String {
+        entries.iter()
+            .map(|entry| match entry.replies.as_deref() {
+                None => entry.id.clone().unwrap(),
+                Some(replies) => format!("{}[{}]", entry.id.as_deref().unwrap(), shape(replies)),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // newest first, like GuestbookStore::get_entries()
+    fn shown() -> Vec<GuestbookEntry> {
+        vec![
+            entry(7, Some(6), "2025-03-16 00:00:00"),
+            entry(6, Some(5), "2025-03-15 00:00:00"),
+            entry(5, Some(1), "2025-03-14 00:00:00"),
+            entry(4, None,    "2025-03-13 00:00:00"),
+            entry(3, Some(1), "2025-03-02 00:00:00"),
+            entry(8, Some(9), "2025-03-01 12:00:00"),   // 9 isn't shown
+            entry(2, Some(1), "2025-03-01 00:00:00"),
+            entry(1, None,    "2025-02-28 00:00:00"),
+        ]
+    }
+
+    #[test]
+    fn nesting_replies() {
+        assert_eq!(shape(&nest(shown(), 3)), "4 1[2 3 5[6[7]]]");
+        assert_eq!(shape(&nest(shown(), 10)), "4 1[2 3 5[6[7]]]");
+        // too deep for what it's turned down to now
+        assert_eq!(shape(&nest(shown(), 2)), "4 1[2 3 5[6 7]]");
+        assert_eq!(shape(&nest(shown(), 1)), "4 1[2 3 5 6 7]");
+        assert_eq!(shape(&nest(shown(), 0)), "4 1");
+        // nothing ends up with an empty list of them
+        assert_eq!(nest(shown(), 3)[0].replies, None);
+        assert!(nest(Vec::new(), 3).is_empty());
+    }
+
+    #[test]
+    fn replying_in_circles() {
+        // can't happen without editing the database by hand, but shouldn't hang if it does
+        let circle = vec![entry(1, Some(2), "2025-02-28 00:00:00"), entry(2, Some(1), "2025-03-01 00:00:00"), entry(3, None, "2025-03-02 00:00:00")];
+        assert_eq!(shape(&nest(circle, 3)), "3");
+    }
+
+
+    const PASSWORD: &str = "a password for the tests";
+
+    // with moderation on, the built-in banned words, and one entry
+    // to reply to; guests' replies are only on if asked for
+    async fn test_app(replies: ReplyConfig) -> (Router, Arc<MemStore>) {
+        let (state, _, mem_store) = AppState::for_tests();
+        mem_store.add_entry(&entry(1, None, "2025-02-28 00:00:00"), EntryStatus::Approved, None).await.unwrap();
+        let app = build_router(AppState {
+            admin: Arc::new(AdminAuth::with_password_hash(&hash_password(PASSWORD).unwrap(), Duration::from_secs(3600)).unwrap()),
+            moderation: true,
+            spam: Arc::new(SpamFilter::disabled().with_check(BannedRules::built_in())),
+            replies,
+            ..state
+        });
+        (app, mem_store)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(app: &Router, req: Request<Body>) -> (StatusCode, Option<T>) {
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, mysql_common::serde_json::from_slice(&body).ok())
+    }
+
+    fn from_client(mut req: Request<Body>) -> Request<Body> {
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from(([203, 0, 113, 7], 54321))));
+        req
+    }
+
+    // the session cookie, kept the way a browser keeps it
+    struct Session {
+        cookie: String,
+        path: String,
+    }
+
+    impl Session {
+        // a browser only sends the cookie back to its Path, and what's
+        // under it (RFC 6265, section 5.1.4)
+        fn cookie_for(&self, uri: &str) -> Option<&str> {
+            uri.strip_prefix(self.path.as_str())
+                .filter(|rest| rest.is_empty() || rest.starts_with('/') || self.path.ends_with('/'))
+                .map(|_| self.cookie.as_str())
+        }
+    }
+
+    async fn log_in_as_admin(app: &Router) -> Session {
+        let req = Request::post("/admin/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"password": "{PASSWORD}"}}"#)))
+            .unwrap();
+        let resp = app.clone().oneshot(from_client(req)).await.unwrap();
+        let set_cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        let mut attrs = set_cookie.split(';').map(str::trim);
+        let cookie = attrs.next().unwrap().to_string();
+        let path = attrs.find_map(|attr| attr.strip_prefix("Path=")).unwrap_or("/").to_string();
+        Session { cookie, path }
+    }
+
+    fn owners(to: &str) -> String {
+        format!("/admin/guestbook/entries/{to}/replies")
+    }
+
+    fn guests(to: &str) -> String {
+        format!("/guestbook/entries/{to}/replies")
+    }
+
+    async fn reply(app: &Router, uri: &str, note: &str, session: Option<&Session>) -> (StatusCode, Option<EntryReceipt>) {
+        let mut req = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(cookie) = session.and_then(|session| session.cookie_for(uri)) {
+            req = req.header(header::COOKIE, cookie);
+        }
+        let req = req.body(Body::from(format!(r#"{{"name": "someone", "note": "{note}"}}"#))).unwrap();
+        send(app, from_client(req)).await
+    }
+
+    async fn guestbook(app: &Router) -> String {
+        let (_, guestbook) = send::<Guestbook>(app, Request::get("/guestbook/entries").body(Body::empty()).unwrap()).await;
+        shape(&guestbook.unwrap().guestbook)
+    }
+
+    #[tokio::test]
+    async fn owner_replies() {
+        let (app, mem_store) = test_app(ReplyConfig::owner_only()).await;
+        let session = log_in_as_admin(&app).await;
+
+        assert_eq!(reply(&app, &owners("1"), "hi", None).await.0, StatusCode::UNAUTHORIZED);
+        // and guests have no route of their own
+        assert!(!reply(&app, &guests("1"), "hi", Some(&session)).await.0.is_success());
+
+        // the owner's replies skip the queue and the spam filter
+        let (status, receipt) = reply(&app, &owners("1"), "thanks for the viagra!", Some(&session)).await;
+        let receipt = receipt.unwrap();
+        assert_eq!((status, receipt.status), (StatusCode::OK, EntryStatus::Approved));
+        let (_, receipt) = reply(&app, &owners(&receipt.id), "and another thing", Some(&session)).await;
+        assert_eq!(guestbook(&app).await, format!("1[2[{}]]", receipt.unwrap().id));
+        let events = mem_store.recent_events(10).await.unwrap();
+        assert_eq!((events[0].action, events[0].detail.as_deref()), (AuditAction::Reply, Some("to 2")));
+
+        // but only to what's shown
+        assert_eq!(reply(&app, &owners("99"), "hi", Some(&session)).await.0, StatusCode::NOT_FOUND);
+        mem_store.set_status(1, EntryStatus::Pending).await.unwrap();
+        assert_eq!(reply(&app, &owners("2"), "hi", Some(&session)).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(guestbook(&app).await, "");
+    }
+
+    #[tokio::test]
+    async fn guest_replies() {
+        let (app, mem_store) = test_app(ReplyConfig { max_depth: 2, from_guests: true }).await;
+
+        // they wait for approval, like any other entry
+        let (status, receipt) = reply(&app, &guests("1"), "hi", None).await;
+        let receipt = receipt.unwrap();
+        assert_eq!((status, receipt.status), (StatusCode::OK, EntryStatus::Pending));
+        assert_eq!(guestbook(&app).await, "1");
+        mem_store.set_status(receipt.id.parse().unwrap(), EntryStatus::Approved).await.unwrap();
+        assert_eq!(guestbook(&app).await, "1[2]");
+        let queue = mem_store.entries_with_status(EntryStatus::Approved).await.unwrap();
+        assert_eq!(queue[1].parent_id.as_deref(), Some("1"));
+
+        // and the spam filter
+        let (_, dropped) = reply(&app, &guests("1"), "cheap viagra", None).await;
+        let dropped = dropped.unwrap();
+        assert_eq!(dropped.status, EntryStatus::Pending);
+        assert_eq!(mem_store.entries_with_status(EntryStatus::Rejected).await.unwrap()[0].id, Some(dropped.id));
+
+        // and it only goes so deep
+        let (_, deepest) = reply(&app, &guests("2"), "hi again", None).await;
+        let deepest = deepest.unwrap();
+        mem_store.set_status(deepest.id.parse().unwrap(), EntryStatus::Approved).await.unwrap();
+        assert_eq!(reply(&app, &guests(&deepest.id), "hi again again", None).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(guestbook(&app).await, format!("1[2[{}]]", deepest.id));
+
+        // a parentId in the body doesn't change what it replies to
+        let req = Request::post("/guestbook/entries")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"name": "someone", "note": "hi", "parentId": "2"}"#))
+            .unwrap();
+        let (_, receipt) = send::<EntryReceipt>(&app, req).await;
+        let sent = mem_store.entries_with_status(EntryStatus::Pending).await.unwrap();
+        assert_eq!((sent[0].id.clone(), sent[0].parent_id.clone()), (Some(receipt.unwrap().id), None));
+
+        // the session cookie stays under /admin, so here the owner's a guest too
+        let session = log_in_as_admin(&app).await;
+        let (status, receipt) = reply(&app, &guests("1"), "hi from the owner", Some(&session)).await;
+        assert_eq!((status, receipt.unwrap().status), (StatusCode::OK, EntryStatus::Pending));
+    }
+
+    #[tokio::test]
+    async fn paging_with_replies() {
+        let (app, mem_store) = test_app(ReplyConfig::owner_only()).await;
+        for (parent_id, time_stamp) in [
+            (None,    "2025-03-01 00:00:00"),
+            (Some(1), "2025-03-02 00:00:00"),
+            (Some(3), "2025-03-03 00:00:00"),
+            (None,    "2025-03-04 00:00:00"),
+            (Some(5), "2025-03-05 00:00:00"),
+        ] {
+            mem_store.add_entry(&entry(0, parent_id, time_stamp), EntryStatus::Approved, None).await.unwrap();
+        }
+
+        // replies don't take up a page's room, and they're under what they reply to
+        let mut pages = Vec::new();
+        let mut uri = String::from("/guestbook/entries?limit=1");
+        loop {
+            let (_, page) = send::<GuestbookPage>(&app, Request::get(&uri).body(Body::empty()).unwrap()).await;
+            let page = page.unwrap();
+            pages.push(shape(&page.entries));
+            let Some(cursor) = page.next_cursor else { break };
+            uri = format!("/guestbook/entries?limit=1&before={cursor}");
+        }
+        assert_eq!(pages, vec!["5[6]", "2", "1[3[4]]"]);
+        assert_eq!(guestbook(&app).await, "5[6] 2 1[3[4]]");
+    }
+
+    #[tokio::test]
+    async fn turned_off() {
+        for replies in [ReplyConfig { max_depth: 0, from_guests: true }, ReplyConfig { max_depth: 3, from_guests: false }] {
+            let (state, _, mem_store) = AppState::for_tests();
+            // and no admin, so nobody could reply in the second one
+            let state = AppState {
+                replies,
+                ..state
+            };
+            assert!(!state.replies.is_enabled(&state.admin));
+            mem_store.add_entry(&entry(1, None, "2025-02-28 00:00:00"), EntryStatus::Approved, None).await.unwrap();
+            let app = build_router(state);
+
+            assert!(!reply(&app, &guests("1"), "hi", None).await.0.is_success());
+            assert!(!reply(&app, &owners("1"), "hi", None).await.0.is_success());
+            assert!(mem_store.entries_with_status(EntryStatus::Pending).await.unwrap().is_empty());
+        }
+    }
+}
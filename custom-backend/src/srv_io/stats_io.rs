@@ -0,0 +1,390 @@
+// GET /hits/stats: hits over time, for charting, plus the top user agents
+// and the first and last visit in the range. Everything's in UTC.
+//
+//     /hits/stats?from=2025-03-01&to=2025-03-31&bucket=day&top=10
+//
+// All the parameters are optional. The range defaults to the last 30 days
+// (today included), the buckets to days, and the top user agents to 10.
+//
+// GET /hits/pages and GET /hits/referrers: the pages with the most views,
+// and the sites that sent the most visitors, over the same sort of range.
+//
+//     /hits/pages?from=2025-03-01&to=2025-03-31&top=10
+//
+// The range and top work the same as above, but the range can't be
+// longer than a year, since these are counted from the raw hit log.
+//
+// GET /hits/countries: the countries the most hits came from, with the
+// same parameters. It's empty unless GeoIP is on (see utils/geoip.rs).
+//
+// GET /hits/visitors: unique visitors today and this month (see 
+// storage/visitors.rs for how they're counted).
+use axum::{
+    extract::State, http::StatusCode, response::{IntoResponse, Response}, Json
+};
+use axum_extra::extract::Query;
+use mysql_common::chrono::{Datelike, Days, NaiveDate, Utc};
+use tracing::debug;
+use crate::{
+    app_state::AppState,
+    srv_io::db_io::DbError,
+    storage::hit_stats::{StatsQuery, TopBy, TopQuery, MAX_BUCKETS, MAX_TOP_DAYS},
+    types::db_io_types::{
+        CountryHits, HitStats, PageHits, ReferrerHits, StatsBucket, 
+        TopCountries, TopPages, TopReferrers, UniqueVisitors
+    },
+};
+
+
+const DEFAULT_DAYS: u64 = 30;
+const DEFAULT_TOP: usize = 10;
+const MAX_TOP: usize = 100;
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct StatsParams {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    bucket: Option<StatsBucket>,
+    top: Option<usize>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct TopParams {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    top: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum StatsError {
+    DbError(DbError),
+    BadRange(String),   // sent back as the body of the 400
+}
+
+impl From<DbError> for StatsError {
+    fn from(db_err: DbError) -> Self {
+        Self::DbError(db_err)
+    }
+}
+
+impl IntoResponse for StatsError {
+    fn into_response(self) -> Response {
+
+        match self {
+            StatsError::DbError(db_err) => db_err.into_response(),
+            StatsError::BadRange(msg) => {
+                debug!("Rejected a request for hit stats: {msg}");
+                (StatusCode::BAD_REQUEST, msg).into_response()
+            }
+        }
+    }
+}
+
+
+// fills in the defaults, and turns away ranges that are backwards
+fn make_range(
+    from: Option<NaiveDate>, 
+    to: Option<NaiveDate>, 
+    today: NaiveDate
+) -> Result<(NaiveDate, NaiveDate), StatsError> {
+
+    let to = to.unwrap_or(today);
+    // unwrap_or is for a `to` near the start of time
+    let from = from.unwrap_or(to.checked_sub_days(Days::new(DEFAULT_DAYS - 1)).unwrap_or(to));
+
+    if from > to {
+        return Err(StatsError::BadRange(format!("from ({from}) is after to ({to}).")));
+    }
+    // the range ends at the midnight after `to`, which has to exist
+    if to.succ_opt().is_none() {
+        return Err(StatsError::BadRange(format!("to ({to}) is too far in the future.")));
+    }
+
+    Ok((from, to))
+}
+
+// and these turn away the ones that are too long
+fn make_query(params: StatsParams, today: NaiveDate) -> Result<StatsQuery, StatsError> {
+
+    let (from, to) = make_range(params.from, params.to, today)?;
+    let query = StatsQuery {
+        from,
+        to,
+        bucket: params.bucket.unwrap_or(StatsBucket::Day),
+        top_agents: params.top.unwrap_or(DEFAULT_TOP).min(MAX_TOP),
+    };
+
+    let buckets = query.bucket_count();
+    if buckets > MAX_BUCKETS {
+        return Err(StatsError::BadRange(format!(
+            "That range has {buckets} buckets, and the most a request can have is {MAX_BUCKETS}. \
+            Try a shorter range, or bigger buckets."
+        )));
+    }
+
+    Ok(query)
+}
+
+fn make_top_query(params: TopParams, by: TopBy, today: NaiveDate) -> Result<TopQuery, StatsError> {
+
+    let (from, to) = make_range(params.from, params.to, today)?;
+    let days = (to - from).num_days() + 1;
+    if days > MAX_TOP_DAYS {
+        return Err(StatsError::BadRange(format!(
+            "That range is {days} days long, and the most a request can cover is {MAX_TOP_DAYS}."
+        )));
+    }
+
+    Ok(TopQuery { from, to, by, top: params.top.unwrap_or(DEFAULT_TOP).min(MAX_TOP) })
+}
+
+pub async fn get_hit_stats(
+    State(state): State<AppState>,
+    Query(params): Query<StatsParams>
+) -> Result<Json<HitStats>, StatsError> {
+
+    let query = make_query(params, Utc::now().date_naive())?;
+    let stats = state.hit_log.hit_stats(&query).await?;
+
+    debug!("Hit stats retrieved for {} to {}.", query.from, query.to);
+    Ok(Json(stats))
+}
+
+pub async fn get_top_pages(
+    State(state): State<AppState>,
+    Query(params): Query<TopParams>
+) -> Result<Json<TopPages>, StatsError> {
+
+    let query = make_top_query(params, TopBy::Page, Utc::now().date_naive())?;
+    let top = state.hit_log.top_hits(&query).await?;
+
+    debug!("Top pages retrieved for {} to {}.", query.from, query.to);
+    Ok(Json(TopPages {
+        from: query.from,
+        to: query.to,
+        pages: top.into_iter().map(|(path, hits)| PageHits { path, hits }).collect(),
+    }))
+}
+
+pub async fn get_top_referrers(
+    State(state): State<AppState>,
+    Query(params): Query<TopParams>
+) -> Result<Json<TopReferrers>, StatsError> {
+
+    let query = make_top_query(params, TopBy::Referrer, Utc::now().date_naive())?;
+    let top = state.hit_log.top_hits(&query).await?;
+
+    debug!("Top referrers retrieved for {} to {}.", query.from, query.to);
+    Ok(Json(TopReferrers {
+        from: query.from,
+        to: query.to,
+        referrers: top.into_iter().map(|(referrer, hits)| ReferrerHits { referrer, hits }).collect(),
+    }))
+}
+
+pub async fn get_top_countries(
+    State(state): State<AppState>,
+    Query(params): Query<TopParams>
+) -> Result<Json<TopCountries>, StatsError> {
+
+    let query = make_top_query(params, TopBy::Country, Utc::now().date_naive())?;
+    let top = state.hit_log.top_hits(&query).await?;
+
+    debug!("Top countries retrieved for {} to {}.", query.from, query.to);
+    Ok(Json(TopCountries {
+        from: query.from,
+        to: query.to,
+        countries: top.into_iter().map(|(country, hits)| CountryHits { country, hits }).collect(),
+    }))
+}
+
+pub async fn get_unique_visitors(State(state): State<AppState>) -> Result<Json<UniqueVisitors>, DbError> {
+
+    let today = Utc::now().date_naive();
+    // unwrap can't panic: every month has a first day
+    let per_day = state.hit_log.unique_visitors(today.with_day(1).unwrap()).await?;
+
+    debug!("Unique visitors retrieved.");
+    Ok(Json(UniqueVisitors {
+        today: per_day.iter().filter(|(day, _)| *day == today).map(|(_, visitors)| visitors).sum(),
+        this_month: per_day.iter().map(|(_, visitors)| visitors).sum(),
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::net::IpAddr;
+    use mysql_common::chrono::NaiveDateTime;
+    use crate::{
+        storage::{hit_counter::HitCounter, mem_store::MemStore, visitors::Visitor, HitStore, HitTotals, LoggedHit},
+        types::db_io_types::{UserAgentHits, WebpageHit},
+        utils::geoip::Location,
+    };
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn hit(time_stamp: &str, user_agent: &str) -> WebpageHit {
+        WebpageHit {
+            time_stamp: NaiveDateTime::parse_from_str(time_stamp, "%Y-%m-%d %H:%M:%S").unwrap(),
+            user_agent: user_agent.to_string(),
+            ..WebpageHit::default()
+        }
+    }
+
+    fn view(time_stamp: &str, path: &str, referrer: Option<&str>) -> WebpageHit {
+        WebpageHit {
+            path: Some(path.to_string()),
+            referrer: referrer.map(str::to_string),
+            ..hit(time_stamp, "Firefox")
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let query = make_query(StatsParams::default(), date("2025-03-13")).unwrap();
+        assert_eq!(query, StatsQuery {
+            from: date("2025-02-12"),
+            to: date("2025-03-13"),
+            bucket: StatsBucket::Day,
+            top_agents: DEFAULT_TOP,
+        });
+
+        let params = StatsParams { from: Some(date("2025-03-01")), top: Some(5000), ..Default::default() };
+        let query = make_query(params, date("2025-03-13")).unwrap();
+        assert_eq!((query.from, query.to, query.top_agents), (date("2025-03-01"), date("2025-03-13"), MAX_TOP));
+    }
+
+    #[test]
+    fn bad_ranges() {
+        let backwards = StatsParams { from: Some(date("2025-03-14")), to: Some(date("2025-03-13")), ..Default::default() };
+        assert!(matches!(make_query(backwards, date("2025-03-13")), Err(StatsError::BadRange(_))));
+
+        // 1000 hours is a little under 42 days
+        let hourly = |days| StatsParams {
+            from: Some(date("2025-01-01")),
+            to: date("2025-01-01").checked_add_days(Days::new(days)),
+            bucket: Some(StatsBucket::Hour),
+            top: None
+        };
+        assert!(make_query(hourly(40), date("2025-03-13")).is_ok());
+        let Err(err) = make_query(hourly(42), date("2025-03-13"))
+        else { panic!("43 days of hourly buckets should be too many") };
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn top_ranges() {
+        let query = make_top_query(TopParams::default(), TopBy::Page, date("2025-03-13")).unwrap();
+        assert_eq!(query, TopQuery { from: date("2025-02-12"), to: date("2025-03-13"), by: TopBy::Page, top: DEFAULT_TOP });
+
+        let year = |to| TopParams { from: Some(date("2024-03-13")), to: Some(to), top: Some(5000) };
+        let query = make_top_query(year(date("2025-03-13")), TopBy::Referrer, date("2025-03-13")).unwrap();
+        assert_eq!(query.top, MAX_TOP);
+        assert!(matches!(
+            make_top_query(year(date("2025-03-14")), TopBy::Referrer, date("2025-03-13")), 
+            Err(StatsError::BadRange(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn top_pages_and_referrers() {
+        let mem_store = MemStore::with_data(Vec::new(), vec![
+            view("2025-03-12 10:00:00", "/", Some("https://news.example.org/item?id=1")),
+            view("2025-03-13 03:37:05", "/guestbook", Some("https://www.example.com/")),
+            view("2025-03-13 04:00:00", "/guestbook", None),
+            hit("2025-03-13 05:00:00", "POSTed, so no path"),
+        ]);
+        let (state, _, _) = AppState::for_tests_with(mem_store, HitTotals { all: 4, bots: 0 });
+
+        let params = || TopParams { from: Some(date("2025-03-01")), to: Some(date("2025-03-31")), top: None };
+        let pages = get_top_pages(State(state.clone()), Query(params())).await.unwrap().0;
+        assert_eq!(pages, TopPages { from: date("2025-03-01"), to: date("2025-03-31"), pages: vec![
+            PageHits { path: String::from("/guestbook"), hits: 2 },
+            PageHits { path: String::from("/"), hits: 1 },
+        ]});
+
+        let referrers = get_top_referrers(State(state), Query(params())).await.unwrap().0;
+        assert_eq!(referrers.referrers, vec![
+            ReferrerHits { referrer: String::from("example.com"), hits: 1 },
+            ReferrerHits { referrer: String::from("news.example.org"), hits: 1 },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn top_countries() {
+        let (state, _, mem_store) = AppState::for_tests();
+        let from = |country: &str| LoggedHit {
+            location: Some(Location { country: String::from(country), region: None }),
+            ..LoggedHit::new(hit("2025-03-13 03:37:05", "Firefox"), &state.ua_rules)
+        };
+        mem_store.log_hits(&[
+            from("NZ"), from("DE"), from("NZ"),
+            LoggedHit::new(hit("2025-03-13 04:00:00", "GeoIP didn't know this one"), &state.ua_rules),
+        ]).await.unwrap();
+
+        let params = TopParams { from: Some(date("2025-03-01")), to: Some(date("2025-03-31")), top: None };
+        let countries = get_top_countries(State(state), Query(params)).await.unwrap().0;
+        assert_eq!(countries, TopCountries { from: date("2025-03-01"), to: date("2025-03-31"), countries: vec![
+            CountryHits { country: String::from("NZ"), hits: 2 },
+            CountryHits { country: String::from("DE"), hits: 1 },
+        ]});
+    }
+
+    #[tokio::test]
+    async fn stats_from_the_store() {
+        let mem_store = MemStore::with_data(Vec::new(), vec![
+            hit("2025-03-12 10:00:00", "Firefox"),
+            hit("2025-03-13 03:37:05", "Chrome"),
+            hit("2025-03-13 04:00:00", "Firefox"),
+        ]);
+        let (state, _, _) = AppState::for_tests_with(mem_store, HitTotals { all: 3, bots: 0 });
+
+        let params = StatsParams { from: Some(date("2025-03-13")), to: Some(date("2025-03-13")), ..Default::default() };
+        let stats = get_hit_stats(State(state), Query(params)).await.unwrap().0;
+
+        assert_eq!(stats.total, 2);
+        assert_eq!(stats.series.len(), 1);
+        assert_eq!(stats.top_user_agents, vec![
+            UserAgentHits { user_agent: String::from("Chrome"), hits: 1 },
+            UserAgentHits { user_agent: String::from("Firefox"), hits: 1 },
+        ]);
+        assert_eq!(stats.first_visit, Some(hit("2025-03-13 03:37:05", "").time_stamp));
+    }
+
+    #[tokio::test]
+    async fn visitors_this_month() {
+        let (state, _, mem_store) = AppState::for_tests();
+        // started, for today's salt
+        let hit_counter = HitCounter::start(mem_store.clone()).await.unwrap();
+        let state = AppState { hits: hit_counter.clone(), ..state };
+
+        let now = Utc::now().naive_utc();
+        for (ip, user_agent) in [([203, 0, 113, 7], "a"), ([203, 0, 113, 7], "a"), ([203, 0, 113, 8], "a")] {
+            let mut logged = LoggedHit::new(WebpageHit { time_stamp: now, user_agent: user_agent.into(), ..WebpageHit::default() }, &state.ua_rules);
+            logged.visitor = hit_counter.visitor(IpAddr::from(ip), user_agent);
+            hit_counter.record(logged);
+        }
+        hit_counter.flush().await.unwrap();
+
+        // someone from earlier in the month (unless it's the 1st), and last month
+        let today = now.date();
+        let mut visitors = vec![(today.with_day(1).unwrap(), "earlier"), (today.with_day(1).unwrap() - Days::new(1), "last month")];
+        if today.day() == 1 {
+            visitors.remove(0);
+        }
+        let earlier: Vec<LoggedHit> = visitors.into_iter().map(|(day, hash)| LoggedHit {
+            visitor: Some(Visitor { day, hash: hash.into() }),
+            ..LoggedHit::new(WebpageHit::default(), &state.ua_rules)
+        }).collect();
+        mem_store.log_hits(&earlier).await.unwrap();
+
+        let visitors = get_unique_visitors(State(state)).await.unwrap().0;
+        let this_month = if today.day() == 1 { 2 } else { 3 };
+        assert_eq!(visitors, UniqueVisitors { today: 2, this_month });
+    }
+}
@@ -0,0 +1,140 @@
+// The parts of paging through GET /guestbook/entries that don't depend on
+// the backend.
+//
+// Entries are ordered by (dateSubmitted, id), so two entries sent in the
+// same second still have an order, and a page always picks up right where
+// the last one left off. The bounds of a page are keys in that order, and
+// the entries strictly between them are the ones it can have. A cursor the
+// client sends is either an entry ID, which stands for that entry's key, or
+// a time, which falls between the last entry before it and the first at or
+// after it.
+use mysql_common::chrono::{NaiveDate, NaiveDateTime};
+use crate::types::db_io_types::{GuestbookEntry, SortOrder};
+
+
+// where an entry falls in the order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EntryKey {
+    pub time: NaiveDateTime,
+    pub id: i64,
+}
+
+impl EntryKey {
+
+    // Before and after every entry. MySQL's DATETIME only goes from the
+    // year 1000 to 9999, so these stay inside that.
+    pub fn first() -> EntryKey {
+        // unwraps can't panic: these are valid dates and times
+        EntryKey { time: NaiveDate::from_ymd_opt(1000, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(), id: 0 }
+    }
+
+    pub fn last() -> EntryKey {
+        EntryKey { time: NaiveDate::from_ymd_opt(9999, 12, 31).unwrap().and_hms_opt(23, 59, 59).unwrap(), id: i64::MAX }
+    }
+
+    // None for an entry that didn't come from a store
+    pub fn of(entry: &GuestbookEntry) -> Option<EntryKey> {
+        Some(EntryKey {
+            time: entry.time_stamp?,
+            id: entry.id.as_ref()?.parse().ok()?,
+        })
+    }
+
+    // the keys on either side of every entry sent at `time`
+    pub fn just_before(time: NaiveDateTime) -> EntryKey {
+        EntryKey { time, id: 0 }
+    }
+
+    pub fn just_after(time: NaiveDateTime) -> EntryKey {
+        EntryKey { time, id: i64::MAX }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryPageQuery {
+    pub after: EntryKey,    // both exclusive
+    pub before: EntryKey,
+    pub order: SortOrder,
+    pub limit: usize,
+}
+
+impl EntryPageQuery {
+
+    // "ASC" or "DESC", for the SQL; never from the client as is
+    pub fn direction(&self) -> &'static str {
+        match self.order {
+            SortOrder::Asc  => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+// for the stores that don't have SQL to do this for them
+pub fn page_of(query: &EntryPageQuery, entries: &[GuestbookEntry]) -> Vec<GuestbookEntry> {
+
+    let mut in_range: Vec<(EntryKey, &GuestbookEntry)> = entries.iter()
+        .filter_map(|entry| Some((EntryKey::of(entry)?, entry)))
+        .filter(|(key, _)| *key > query.after && *key < query.before)
+        .collect();
+
+    in_range.sort_by_key(|(key, _)| *key);
+    if query.order == SortOrder::Desc {
+        in_range.reverse();
+    }
+
+    in_range.into_iter()
+        .take(query.limit)
+        .map(|(_, entry)| entry.clone())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn entry(id: i64, time_stamp: &str) -> GuestbookEntry {
+        GuestbookEntry {
+            id: Some(id.to_string()),
+            time_stamp: Some(NaiveDateTime::parse_from_str(time_stamp, "%Y-%m-%d %H:%M:%S").unwrap()),
+            name: format!("guest {id}"),
+            note: String::new(),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
+        }
+    }
+
+    fn ids(entries: &[GuestbookEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.id.as_deref().unwrap()).collect()
+    }
+
+    #[test]
+    fn pages_in_either_order() {
+        // 2 and 3 came in the same second; 5 was added out of order
+        let entries = [
+            entry(1, "2025-02-28 04:22:49"),
+            entry(2, "2025-02-28 04:30:57"),
+            entry(3, "2025-02-28 04:30:57"),
+            entry(5, "2025-03-01 00:00:00"),
+            entry(4, "2025-03-13 03:37:05"),
+        ];
+        let all = EntryPageQuery { after: EntryKey::first(), before: EntryKey::last(), order: SortOrder::Desc, limit: 10 };
+        assert_eq!(ids(&page_of(&all, &entries)), vec!["4", "5", "3", "2", "1"]);
+        assert_eq!(ids(&page_of(&EntryPageQuery { order: SortOrder::Asc, ..all.clone() }, &entries)), vec!["1", "2", "3", "5", "4"]);
+        assert_eq!(ids(&page_of(&EntryPageQuery { limit: 2, ..all.clone() }, &entries)), vec!["4", "5"]);
+
+        // by an entry, which is left out itself
+        let after_2 = EntryKey::of(&entries[1]).unwrap();
+        let before_4 = EntryKey::of(&entries[4]).unwrap();
+        assert_eq!(ids(&page_of(&EntryPageQuery { after: after_2, before: before_4, ..all.clone() }, &entries)), vec!["5", "3"]);
+
+        // and by time, which takes in every entry from that second
+        let time = entries[1].time_stamp.unwrap();
+        assert_eq!(ids(&page_of(&EntryPageQuery { before: EntryKey::just_before(time), ..all.clone() }, &entries)), vec!["1"]);
+        assert_eq!(ids(&page_of(&EntryPageQuery { before: EntryKey::just_after(time), ..all }, &entries)), vec!["3", "2", "1"]);
+    }
+}
@@ -0,0 +1,289 @@
+// The parts of GET /guestbook/search that don't depend on the backend.
+//
+// Each backend has an index for finding the entries that have the search
+// terms in them (see the 0010_guestbook_search migrations), and hands those
+// back, newest first. Everything after that happens here, so they all match,
+// rank, and highlight the same way: an entry matches if every term is in its
+// name or its note, ignoring case, and the more often they come up, the
+// higher it goes, with the name counting for more than the note.
+//
+// The terms go to the backends as they were typed, since how much of
+// Unicode they ignore the case of depends on the backend (and on Postgres,
+// the locale). ASCII always works; past that, it's only certain that typing
+// a word the way it was written will find it.
+//
+// Terms are split up by whitespace, and need at least 2 characters, since
+// that's the shortest MySQL's ngram index can look up. Scripts without
+// spaces (Chinese, say) still work, since a term can be any part of a word.
+use std::ops::Range;
+use crate::types::db_io_types::{GuestbookEntry, SearchResult, TextPart};
+
+
+pub const MIN_TERM_CHARS: usize = 2;
+pub const MAX_TERMS: usize = 8;
+pub const MAX_TERM_CHARS: usize = 50;
+// the most the backends hand back, so a common term can't pull in the whole table
+pub const MAX_CANDIDATES: usize = 500;
+
+const NAME_WEIGHT: u64 = 3;
+// notes longer than this are cut down to the part around the first match
+const SNIPPET_CHARS: usize = 120;
+
+// These are operators in MySQL's boolean mode (and " is in FTS5's queries
+// too), so they're taken out of the terms rather than escaped for each one.
+const SPECIAL_CHARS: &[char] = &['+', '-', '<', '>', '(', ')', '~', '*', '"', '@'];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub terms: Vec<String>,     // free of SPECIAL_CHARS, and no two the same but for case
+    pub limit: usize,
+}
+
+impl SearchQuery {
+
+    // None if there's nothing left to search for
+    pub fn parse(q: &str, limit: usize) -> Option<SearchQuery> {
+
+        let mut terms: Vec<String> = Vec::new();
+        for word in q.split_whitespace() {
+            let term: String = word.chars()
+                .filter(|c| !SPECIAL_CHARS.contains(c))
+                .take(MAX_TERM_CHARS)
+                .collect();
+            let is_new = !terms.iter().any(|other| lowercase(other) == lowercase(&term));
+            if term.chars().count() >= MIN_TERM_CHARS && is_new {
+                terms.push(term);
+            }
+        }
+        terms.truncate(MAX_TERMS);
+
+        (!terms.is_empty()).then_some(SearchQuery { terms, limit })
+    }
+}
+
+// a char at a time, like find_terms()
+fn lowercase(text: &str) -> Vec<char> {
+    text.chars().flat_map(char::to_lowercase).collect()
+}
+
+// where each term comes up in text, as byte ranges in order, and merged
+// where they overlap. Each character is lowercased on its own, which
+// keeps track of where it came from even if its lowercase is longer.
+fn find_terms(text: &str, terms: &[String]) -> Vec<Range<usize>> {
+
+    let lowered: Vec<(char, Range<usize>)> = text.char_indices()
+        .flat_map(|(i, c)| c.to_lowercase().map(move |lower| (lower, i..i + c.len_utf8())))
+        .collect();
+
+    let mut found: Vec<Range<usize>> = Vec::new();
+    for term in terms {
+        let term = lowercase(term);
+        let mut at = 0;
+        while at + term.len() <= lowered.len() {
+            if lowered[at..at + term.len()].iter().map(|(c, _)| *c).eq(term.iter().copied()) {
+                found.push(lowered[at].1.start..lowered[at + term.len() - 1].1.end);
+                at += term.len();
+            } else {
+                at += 1;
+            }
+        }
+    }
+
+    found.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in found {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+fn count_terms(text: &str, terms: &[String]) -> u64 {
+    terms.iter().map(|term| find_terms(text, std::slice::from_ref(term)).len() as u64).sum()
+}
+
+fn has_term(entry: &GuestbookEntry, term: &String) -> bool {
+    let term = std::slice::from_ref(term);
+    !find_terms(&entry.name, term).is_empty() || !find_terms(&entry.note, term).is_empty()
+}
+
+// text split up around the ranges, which are in order and don't overlap
+fn to_parts(text: &str, ranges: &[Range<usize>]) -> Vec<TextPart> {
+
+    let mut parts = Vec::new();
+    let mut at = 0;
+    for range in ranges {
+        if range.start > at {
+            parts.push(TextPart { text: text[at..range.start].to_string(), highlight: false });
+        }
+        parts.push(TextPart { text: text[range.clone()].to_string(), highlight: true });
+        at = range.end;
+    }
+    if at < text.len() {
+        parts.push(TextPart { text: text[at..].to_string(), highlight: false });
+    }
+    parts
+}
+
+// the note, or SNIPPET_CHARS of it, starting a little before the first match
+fn snippet(note: &str, ranges: &[Range<usize>]) -> Vec<TextPart> {
+
+    let bounds: Vec<usize> = note.char_indices().map(|(i, _)| i).chain([note.len()]).collect();
+    let char_count = bounds.len() - 1;
+    if char_count <= SNIPPET_CHARS {
+        return to_parts(note, ranges);
+    }
+
+    let first_match = ranges.first().map_or(0, |range| bounds.partition_point(|i| *i < range.start));
+    let start_char = first_match.saturating_sub(SNIPPET_CHARS / 4).min(char_count - SNIPPET_CHARS);
+    let (start, end) = (bounds[start_char], bounds[start_char + SNIPPET_CHARS]);
+
+    // the ranges that are in the window, cut to fit, and moved to match
+    let in_window: Vec<Range<usize>> = ranges.iter()
+        .filter(|range| range.end > start && range.start < end)
+        .map(|range| range.start.max(start) - start..range.end.min(end) - start)
+        .collect();
+
+    let mut parts = to_parts(&note[start..end], &in_window);
+    if start > 0 {
+        parts.insert(0, TextPart { text: String::from("…"), highlight: false });
+    }
+    if end < note.len() {
+        parts.push(TextPart { text: String::from("…"), highlight: false });
+    }
+    parts
+}
+
+// The candidates a backend found, narrowed down to the ones that really
+// match, best first. Ties stay in the order they came in (newest first).
+pub fn rank(query: &SearchQuery, candidates: Vec<GuestbookEntry>) -> Vec<SearchResult> {
+
+    let mut results: Vec<SearchResult> = candidates.into_iter()
+        .filter(|entry| query.terms.iter().all(|term| has_term(entry, term)))
+        .map(|entry| {
+            let score = NAME_WEIGHT * count_terms(&entry.name, &query.terms) + count_terms(&entry.note, &query.terms);
+            SearchResult {
+                name: to_parts(&entry.name, &find_terms(&entry.name, &query.terms)),
+                snippet: snippet(&entry.note, &find_terms(&entry.note, &query.terms)),
+                score,
+                entry,
+            }
+        })
+        .collect();
+
+    results.sort_by_key(|result| std::cmp::Reverse(result.score));
+    results.truncate(query.limit);
+    results
+}
+
+// for the stores that don't have an index to do this for them
+pub fn candidates_of(query: &SearchQuery, entries: &[GuestbookEntry]) -> Vec<GuestbookEntry> {
+
+    let mut found: Vec<GuestbookEntry> = entries.iter()
+        .filter(|entry| query.terms.iter().all(|term| has_term(entry, term)))
+        .cloned()
+        .collect();
+
+    found.sort_by_key(|entry| std::cmp::Reverse((entry.time_stamp, entry.id.as_ref().and_then(|id| id.parse::<i64>().ok()))));
+    found.truncate(MAX_CANDIDATES);
+    found
+}
+
+// %term%, for LIKE and ILIKE, with \ as the escape character
+pub fn like_pattern(term: &str) -> String {
+    let escaped = term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn entry(id: &str, name: &str, note: &str) -> GuestbookEntry {
+        GuestbookEntry { id: Some(id.to_string()), time_stamp: None, name: name.to_string(), note: note.to_string(), pinned: None, spam: None, parent_id: None, replies: None, reactions: None }
+    }
+
+    fn highlighted(parts: &[TextPart]) -> Vec<&str> {
+        parts.iter().filter(|part| part.highlight).map(|part| part.text.as_str()).collect()
+    }
+
+    fn joined(parts: &[TextPart]) -> String {
+        parts.iter().map(|part| part.text.as_str()).collect()
+    }
+
+    #[test]
+    fn parsing_terms() {
+        let query = SearchQuery::parse("  Nice +OS -(choice)* a \"nice\" 冰淇淋 % ", 10).unwrap();
+        assert_eq!(query.terms, vec!["Nice", "OS", "choice", "冰淇淋"]);
+
+        assert_eq!(SearchQuery::parse("a b c", 10), None);
+        assert_eq!(SearchQuery::parse("", 10), None);
+        assert_eq!(SearchQuery::parse("\"\" ++", 10), None);
+        assert_eq!(SearchQuery::parse("50% snake_case", 10).unwrap().terms, vec!["50%", "snake_case"]);
+        assert_eq!(like_pattern("50%_\\"), "%50\\%\\_\\\\%");
+        assert_eq!(SearchQuery::parse(&"ab ".repeat(20), 10).unwrap().terms.len(), 1);
+        let many: String = (0..20).map(|i| format!("term{i} ")).collect();
+        assert_eq!(SearchQuery::parse(&many, 10).unwrap().terms.len(), MAX_TERMS);
+    }
+
+    #[test]
+    fn finds_terms_in_any_script() {
+        assert_eq!(find_terms("Nice OS choice, nice!", &[String::from("nice")]), vec![0..4, 16..20]);
+        assert_eq!(find_terms("我很喜欢冰淇淋", &[String::from("冰淇淋")]), vec![12..21]);
+        // Cherokee has case too, and its lowercase letters come after
+        assert_eq!(find_terms("ᏣᎳᎩ ᎦᏬᏂᎯᏍᏗ", &[String::from("ꮳꮃꭹ")]), vec![0..9]);
+        assert_eq!(find_terms("ꮳꮃꭹ", &[String::from("ᏣᎳᎩ")]), vec![0..9]);
+        // İ lowercases to two characters, but the range is still İ's
+        assert_eq!(find_terms("İstanbul", &[String::from("stan")]), vec![2..6]);
+        // overlapping terms are merged
+        assert_eq!(find_terms("nice os choice", &[String::from("ice"), String::from("nic")]), vec![0..4, 11..14]);
+    }
+
+    #[test]
+    fn ranks_and_highlights() {
+        let query = SearchQuery::parse("nice", 10).unwrap();
+        let results = rank(&query, vec![
+            entry("4", "约翰·塞纳", "我很喜欢冰淇淋"),
+            entry("3", "Linus", "nice os choice!"),
+            entry("2", "Nice Person", "hello"),
+            entry("1", "Ada", "It's so nice to be here! Nice!"),
+        ]);
+
+        let ids: Vec<&str> = results.iter().map(|result| result.entry.id.as_deref().unwrap()).collect();
+        assert_eq!(ids, vec!["2", "1", "3"]);
+        assert_eq!(results.iter().map(|result| result.score).collect::<Vec<_>>(), vec![3, 2, 1]);
+        assert_eq!(highlighted(&results[0].name), vec!["Nice"]);
+        assert_eq!(joined(&results[0].name), "Nice Person");
+        assert_eq!(highlighted(&results[1].snippet), vec!["nice", "Nice"]);
+        assert_eq!(joined(&results[1].snippet), "It's so nice to be here! Nice!");
+
+        // every term has to be there, in the name or the note
+        let both = SearchQuery::parse("linus choice", 10).unwrap();
+        assert_eq!(rank(&both, vec![entry("3", "Linus", "nice os choice!"), entry("1", "Linus", "hi")]).len(), 1);
+        assert_eq!(rank(&SearchQuery { limit: 1, ..query }, vec![entry("1", "nice", ""), entry("2", "nice", "")]).len(), 1);
+    }
+
+    #[test]
+    fn long_notes_are_cut() {
+        let note = format!("{}ᏣᎳᎩ ᎦᏬᏂᎯᏍᏗ{}", "ക".repeat(200), "ള".repeat(200));
+        let query = SearchQuery::parse("ᎦᏬᏂ", 10).unwrap();
+        let results = rank(&query, vec![entry("1", "someone", &note)]);
+
+        let parts = &results[0].snippet;
+        assert_eq!(highlighted(parts), vec!["ᎦᏬᏂ"]);
+        assert_eq!(parts.first().unwrap().text, "…");
+        assert_eq!(parts.last().unwrap().text, "…");
+        // the two …'s on top of the window
+        assert_eq!(joined(parts).chars().count(), SNIPPET_CHARS + 2);
+        assert!(joined(parts).starts_with(&format!("…{}ᏣᎳᎩ", "ക".repeat(SNIPPET_CHARS / 4 - 4))));
+
+        // with no match in the note, it's the start of it
+        let by_name = rank(&SearchQuery::parse("someone", 10).unwrap(), vec![entry("1", "someone", &note)]);
+        assert!(highlighted(&by_name[0].snippet).is_empty());
+        assert!(joined(&by_name[0].snippet).starts_with('ക'));
+    }
+}
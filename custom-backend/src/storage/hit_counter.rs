@@ -0,0 +1,334 @@
+// Hit counting that doesn't touch the database on every page view.
+//
+// The count GET /hits sends back is kept in an atomic, seeded from the
+// hitCounter row at startup. A POST to /hits bumps it right away, so the
+// GET that follows it already includes the new hit (which is what the old
+// LOCK TABLE dance in log_hit was for), and puts the hit in a buffer.
+// A background task flushes the buffer to the database in batches.
+//
+// Each flush also gets back the totals from the hitCounter row, which other
+// replicas of the server add their own hits to. Whatever the row went up by,
+// beyond the hits this replica flushed, came from the other replicas, so that's
+// added to the count here too. That way every replica's count catches up with
+// the others at least once per flush interval, even if it gets no hits itself.
+//
+// The same goes for the count of hits from bots, which is kept alongside,
+// so GET /hits can leave them out.
+//
+// The salts for the visitor hashes are kept here too (see visitors.rs),
+// and the flushes pick up the new ones each day.
+use std::{
+    mem,
+    net::IpAddr,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use mysql_common::chrono::{NaiveDate, Utc};
+use tokio::{sync::Notify, task::JoinHandle, time::{interval, MissedTickBehavior}};
+use tracing::{debug, warn};
+use crate::srv_io::db_io::DbError;
+use super::{visitors::{Visitor, VisitorSalts}, HitStore, HitTotals, LoggedHit};
+
+
+// If the database is down for a long time, the oldest unflushed
+// hits are dropped past this point, so the buffer can't grow forever
+const MAX_BUFFERED_HITS: usize = 10_000;
+
+pub struct HitCounter {
+    store: Arc<dyn HitStore>,
+    count: AtomicU64,
+    bots: AtomicU64,
+    buffer: Mutex<Vec<LoggedHit>>,
+    // The hitCounter totals as of the last flush. Being async,
+    // this lock also keeps flushes from overlapping.
+    db_totals: tokio::sync::Mutex<HitTotals>,
+    salts: VisitorSalts,
+    stop: Notify,
+}
+
+impl HitCounter {
+
+    // reads the starting counts, and the salts, from the database
+    pub async fn start(store: Arc<dyn HitStore>) -> Result<Arc<HitCounter>, DbError> {
+        let db_totals = store.hit_count().await?;
+        let counter = HitCounter::with_totals(store, db_totals);
+        counter.refresh_salts(Utc::now().date_naive()).await?;
+        Ok(counter)
+    }
+
+    // For when the starting counts are already known. There are no
+    // salts until the first flush, so no visitors until then either.
+    pub fn with_totals(store: Arc<dyn HitStore>, db_totals: HitTotals) -> Arc<HitCounter> {
+        Arc::new(HitCounter {
+            store,
+            count: AtomicU64::new(db_totals.all),
+            bots: AtomicU64::new(db_totals.bots),
+            buffer: Mutex::new(Vec::new()),
+            db_totals: tokio::sync::Mutex::new(db_totals),
+            salts: VisitorSalts::default(),
+            stop: Notify::new(),
+        })
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    // the two loads aren't atomic together, so 
+    // this could be off by a hit, just for a moment
+    pub fn count_without_bots(&self) -> u64 {
+        self.count().saturating_sub(self.bots.load(Ordering::SeqCst))
+    }
+
+    pub fn record(&self, logged_hit: LoggedHit) {
+        let is_bot = logged_hit.agent.is_bot;
+        self.lock_buffer().push(logged_hit);
+        if is_bot {
+            self.bots.fetch_add(1, Ordering::SeqCst);
+        }
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // None until there's a salt for today
+    pub fn visitor(&self, ip: IpAddr, user_agent: &str) -> Option<Visitor> {
+        self.salts.visitor(Utc::now().date_naive(), ip, user_agent)
+    }
+
+    // likewise; see VisitorSalts::visitor_for()
+    pub fn visitor_for(&self, ip: IpAddr, user_agent: &str, what: &str) -> Option<Visitor> {
+        self.salts.visitor_for(Utc::now().date_naive(), ip, user_agent, what)
+    }
+
+    // only goes to the database once a day or so (or until it works)
+    async fn refresh_salts(&self, today: NaiveDate) -> Result<(), DbError> {
+
+        if self.salts.needs_refresh(today) {
+            let salts = self.store.visitor_salts(&VisitorSalts::fresh(today)).await?;
+            debug!("Visitor salts refreshed for {today}.");
+            self.salts.replace(salts);
+        }
+        Ok(())
+    }
+
+    // nothing is ever left half-changed in the buffer by a panic
+    fn lock_buffer(&self) -> MutexGuard<'_, Vec<LoggedHit>> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Writes out everything in the buffer, syncs the count with the
+    // hitCounter row, and gets new salts if it's time. If the write fails,
+    // the hits go back in the buffer for the next flush.
+    pub async fn flush(&self) -> Result<(), DbError> {
+
+        let mut db_totals = self.db_totals.lock().await;
+
+        let batch = mem::take(&mut *self.lock_buffer());
+        let flushed = HitTotals::of(&batch);
+
+        let res = if batch.is_empty() {
+            self.store.hit_count().await
+        } else {
+            self.store.log_hits(&batch).await
+        };
+
+        let new_totals = match res {
+            Ok(new_totals) => new_totals,
+            Err(db_err) => {
+                self.restore(batch);
+                return Err(db_err);
+            }
+        };
+
+        // the counts already have this replica's hits; this is everyone else's
+        let others = new_totals.all as i64 - (db_totals.all + flushed.all) as i64;
+        if others != 0 {
+            debug!("Hit counter moved by {others} from elsewhere.");
+            adjust(&self.count, others);
+        }
+        adjust(&self.bots, new_totals.bots as i64 - (db_totals.bots + flushed.bots) as i64);
+        *db_totals = new_totals;
+
+        self.refresh_salts(Utc::now().date_naive()).await
+    }
+
+    // puts a batch that failed to flush back in front of any hits that came in since
+    fn restore(&self, mut batch: Vec<LoggedHit>) {
+
+        let mut buffer = self.lock_buffer();
+        batch.append(&mut buffer);
+
+        if batch.len() > MAX_BUFFERED_HITS {
+            let dropped = HitTotals::of(&batch[..batch.len() - MAX_BUFFERED_HITS]);
+            warn!("Hit buffer is full; dropping the {} oldest unflushed hit(s).", dropped.all);
+            batch.drain(..dropped.all as usize);
+            adjust(&self.count, -(dropped.all as i64));
+            adjust(&self.bots, -(dropped.bots as i64));
+        }
+
+        *buffer = batch;
+    }
+
+    // Flushes every `every`, until stop() is called, then flushes once
+    // more so no hits are lost on shutdown.
+    pub fn spawn_flusher(self: &Arc<Self>, every: Duration) -> JoinHandle<()> {
+
+        let counter = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(every);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {},
+                    _ = counter.stop.notified() => break,
+                }
+                counter.flush_or_log().await;
+            }
+
+            counter.flush_or_log().await;
+        })
+    }
+
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    async fn flush_or_log(&self) {
+        match self.flush().await {
+            Ok(()) => {},
+            // the circuit breaker already logged this
+            Err(DbError::Unavailable(_)) => debug!("Database unavailable; hits will be flushed later."),
+            Err(db_err) => warn!("Could not flush hits to the database: {db_err}"),
+        }
+    }
+}
+
+// the counts shouldn't go below 0, even if the hitCounter row is reset by hand
+fn adjust(count: &AtomicU64, by: i64) {
+    // unwrap can't panic: the closure always returns Some
+    count.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+        Some(count.saturating_add_signed(by))
+    }).unwrap();
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use mysql_common::chrono::{NaiveDateTime, Utc, SubsecRound};
+    use crate::{
+        storage::{hit_stats::{StatsQuery, TopQuery}, mem_store::MemStore, visitors::DailySalt},
+        types::db_io_types::{HitStats, WebpageHit},
+        utils::user_agent::UaRules,
+    };
+
+    fn page_hit(user_agent: &str) -> WebpageHit {
+        WebpageHit { time_stamp: Utc::now().naive_utc().trunc_subsecs(0), user_agent: user_agent.to_string(), ..WebpageHit::default() }
+    }
+
+    fn hit(user_agent: &str) -> LoggedHit {
+        LoggedHit::new(page_hit(user_agent), &UaRules::built_in())
+    }
+
+    #[tokio::test]
+    async fn counts_before_flushing() {
+        let store = Arc::new(MemStore::with_data(Vec::new(), vec![page_hit("a"), page_hit("b")]));
+        let counter = HitCounter::start(store.clone()).await.unwrap();
+        assert_eq!(counter.count(), 2);
+
+        counter.record(hit("c"));
+        counter.record(hit("d"));
+        assert_eq!(counter.count(), 4);
+        assert_eq!(store.hit_log().len(), 2);   // not written yet
+
+        counter.flush().await.unwrap();
+        assert_eq!(counter.count(), 4);
+        let agents: Vec<String> = store.hit_log().into_iter().map(|logged| logged.hit.user_agent).collect();
+        assert_eq!(agents, vec!["a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn picks_up_other_replicas() {
+        let store = Arc::new(MemStore::new());
+        let here = HitCounter::start(store.clone()).await.unwrap();
+        let there = HitCounter::start(store.clone()).await.unwrap();
+
+        here.record(hit("here"));
+        there.record(hit("there"));
+        there.record(hit("there"));
+        here.flush().await.unwrap();
+        there.flush().await.unwrap();
+        assert_eq!((here.count(), there.count()), (1, 3));
+
+        // a flush with nothing to write still syncs up
+        here.flush().await.unwrap();
+        assert_eq!(here.count(), 3);
+    }
+
+    #[tokio::test]
+    async fn counts_bots_separately() {
+        let store = Arc::new(MemStore::new());
+        let here = HitCounter::start(store.clone()).await.unwrap();
+        let there = HitCounter::start(store.clone()).await.unwrap();
+
+        here.record(hit("Mozilla/5.0 (X11; Linux x86_64; rv:136.0) Gecko/20100101 Firefox/136.0"));
+        here.record(hit("curl/8.5.0"));
+        there.record(hit("Googlebot/2.1 (+http://www.google.com/bot.html)"));
+        assert_eq!((here.count(), here.count_without_bots()), (2, 1));
+
+        there.flush().await.unwrap();
+        here.flush().await.unwrap();
+        assert_eq!((here.count(), here.count_without_bots()), (3, 1));
+        assert_eq!(store.hit_count().await.unwrap(), HitTotals { all: 3, bots: 2 });
+    }
+
+    #[tokio::test]
+    async fn flusher_flushes_on_stop() {
+        let store = Arc::new(MemStore::new());
+        let counter = HitCounter::start(store.clone()).await.unwrap();
+        let flusher = counter.spawn_flusher(Duration::from_secs(3600));
+
+        counter.record(hit("last one out"));
+        counter.stop();
+        flusher.await.unwrap();
+        assert_eq!(store.hit_log().len(), 1);
+    }
+
+    // a store that's always down
+    struct DownStore;
+
+    #[async_trait::async_trait]
+    impl HitStore for DownStore {
+        async fn hit_count(&self) -> Result<HitTotals, DbError> { Err(DbError::PoolTimedOut) }
+        async fn log_hits(&self, _: &[LoggedHit]) -> Result<HitTotals, DbError> { Err(DbError::PoolTimedOut) }
+        async fn hit_stats(&self, _: &StatsQuery) -> Result<HitStats, DbError> { Err(DbError::PoolTimedOut) }
+        async fn top_hits(&self, _: &TopQuery) -> Result<Vec<(String, u64)>, DbError> { Err(DbError::PoolTimedOut) }
+        async fn visitor_salts(&self, _: &[DailySalt]) -> Result<Vec<DailySalt>, DbError> { Err(DbError::PoolTimedOut) }
+        async fn unique_visitors(&self, _: NaiveDate) -> Result<Vec<(NaiveDate, u64)>, DbError> { Err(DbError::PoolTimedOut) }
+    }
+
+    #[tokio::test]
+    async fn keeps_hits_while_db_is_down() {
+        let counter = HitCounter::with_totals(Arc::new(DownStore), HitTotals { all: 100, bots: 10 });
+
+        let old_hit = LoggedHit::new(
+            WebpageHit { time_stamp: NaiveDateTime::default(), user_agent: String::from("old-bot/1.0"), ..WebpageHit::default() },
+            &UaRules::built_in()
+        );
+        assert!(old_hit.agent.is_bot);
+        counter.record(old_hit.clone());
+        let new_hit = hit("new");
+        for _ in 0..MAX_BUFFERED_HITS {
+            counter.record(new_hit.clone());
+        }
+        assert_eq!(counter.count(), 100 + MAX_BUFFERED_HITS as u64 + 1);
+
+        assert!(counter.flush().await.is_err());
+        let buffer = counter.lock_buffer().clone();
+        assert_eq!(buffer.len(), MAX_BUFFERED_HITS);
+        assert!(!buffer.contains(&old_hit));    // the oldest one goes first
+        assert_eq!(counter.count(), 100 + MAX_BUFFERED_HITS as u64);
+        assert_eq!(counter.count_without_bots(), 90 + MAX_BUFFERED_HITS as u64);
+    }
+}
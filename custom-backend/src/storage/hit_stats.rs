@@ -0,0 +1,382 @@
+// The parts of /hits/stats, /hits/pages, /hits/referrers, and
+// /hits/countries that don't depend on the backend.
+//
+// Day and week buckets come out of the hitDaily rollup (one row per day and
+// user agent), which log_hits() adds to in the same transaction as hitLog.
+// Hour buckets can't, so they're counted from hitLog itself, over its
+// hitTime index. That's why the number of buckets a query can ask for is
+// capped: it keeps the hourly ones down to a few weeks of hitLog.
+//
+// There's no rollup for the top pages, referrers, or countries either, so those are
+// counted from hitLog too, and their range is capped the same way.
+use std::{cmp::Reverse, collections::BTreeMap};
+use mysql_common::chrono::{Datelike, Days, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use crate::types::db_io_types::{BucketHits, HitStats, StatsBucket, UserAgentHits, WebpageHit};
+use super::LoggedHit;
+
+
+pub const MAX_BUCKETS: i64 = 1000;
+pub const MAX_TOP_DAYS: i64 = 366;
+
+// what the handler hands the store, once it's been checked
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsQuery {
+    pub from: NaiveDate,
+    pub to: NaiveDate,      // inclusive
+    pub bucket: StatsBucket,
+    pub top_agents: usize,
+}
+
+impl StatsQuery {
+
+    pub fn time_range(&self) -> (NaiveDateTime, NaiveDateTime) {
+        time_range(self.from, self.to)
+    }
+
+    fn first_bucket(&self) -> NaiveDateTime {
+        bucket_start(self.from.and_time(NaiveTime::MIN), self.bucket)
+    }
+
+    // the one the last second of `to` falls in
+    fn last_bucket(&self) -> NaiveDateTime {
+        // unwrap can't panic: 23:59:59 is a valid time
+        bucket_start(self.to.and_hms_opt(23, 59, 59).unwrap(), self.bucket)
+    }
+
+    pub fn bucket_count(&self) -> i64 {
+        (self.last_bucket() - self.first_bucket()).num_seconds() / bucket_len(self.bucket).num_seconds() + 1
+    }
+}
+
+// the range as times, for hitLog: from <= hitTime < until
+fn time_range(from: NaiveDate, to: NaiveDate) -> (NaiveDateTime, NaiveDateTime) {
+    // unwrap can't panic: the handlers turn away a `to` without a next day
+    (from.and_time(NaiveTime::MIN), to.succ_opt().unwrap().and_time(NaiveTime::MIN))
+}
+
+fn bucket_len(bucket: StatsBucket) -> Duration {
+    match bucket {
+        StatsBucket::Hour => Duration::hours(1),
+        StatsBucket::Day  => Duration::days(1),
+        StatsBucket::Week => Duration::weeks(1),
+    }
+}
+
+pub fn bucket_start(time: NaiveDateTime, bucket: StatsBucket) -> NaiveDateTime {
+
+    let midnight = time.date().and_time(NaiveTime::MIN);
+    match bucket {
+        // unwrap can't panic: the hour came from a valid time
+        StatsBucket::Hour => midnight.with_hour(time.hour()).unwrap(),
+        StatsBucket::Day  => midnight,
+        StatsBucket::Week => midnight - Days::new(time.weekday().num_days_from_monday().into()),
+    }
+}
+
+
+// what GET /hits/pages, /hits/referrers, and /hits/countries are counted by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TopBy {
+    Page,
+    Referrer,
+    Country,
+}
+
+impl TopBy {
+    // the hitLog column; never from the client, so it's safe to put in the SQL as is
+    pub fn column(self) -> &'static str {
+        match self {
+            TopBy::Page     => "path",
+            TopBy::Referrer => "referrer",
+            TopBy::Country  => "country",
+        }
+    }
+
+    fn value(self, logged: &LoggedHit) -> Option<&str> {
+        match self {
+            TopBy::Page     => logged.hit.path.as_deref(),
+            TopBy::Referrer => logged.hit.referrer.as_deref(),
+            TopBy::Country  => logged.country(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopQuery {
+    pub from: NaiveDate,
+    pub to: NaiveDate,      // inclusive
+    pub by: TopBy,
+    pub top: usize,
+}
+
+impl TopQuery {
+    pub fn time_range(&self) -> (NaiveDateTime, NaiveDateTime) {
+        time_range(self.from, self.to)
+    }
+}
+
+
+// one row of hitDaily
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyRollup {
+    pub day: NaiveDate,
+    pub user_agent: String,
+    pub hits: u64,
+    pub first_hit: NaiveDateTime,
+    pub last_hit: NaiveDateTime,
+}
+
+// Sums up a batch of hits into what gets added to hitDaily,
+// so each (day, user agent) pair is only upserted once.
+pub fn roll_up(logged_hits: &[LoggedHit]) -> Vec<DailyRollup> {
+
+    let mut rollups: BTreeMap<(NaiveDate, &str), DailyRollup> = BTreeMap::new();
+
+    for LoggedHit { hit: page_hit, .. } in logged_hits {
+        let day = page_hit.time_stamp.date();
+        rollups.entry((day, &page_hit.user_agent))
+            .and_modify(|rollup| {
+                rollup.hits += 1;
+                rollup.first_hit = rollup.first_hit.min(page_hit.time_stamp);
+                rollup.last_hit = rollup.last_hit.max(page_hit.time_stamp);
+            })
+            .or_insert_with(|| DailyRollup {
+                day,
+                user_agent: page_hit.user_agent.clone(),
+                hits: 1,
+                first_hit: page_hit.time_stamp,
+                last_hit: page_hit.time_stamp,
+            });
+    }
+
+    rollups.into_values().collect()
+}
+
+
+// Puts what the backend's queries returned into the shape the handler sends
+// back. counts can be per hour or per day, in any order, and are summed into
+// the query's buckets; buckets with no hits are filled in with 0.
+pub fn assemble(
+    query: &StatsQuery,
+    counts: Vec<(NaiveDateTime, u64)>,
+    top_user_agents: Vec<UserAgentHits>,
+    first_visit: Option<NaiveDateTime>,
+    last_visit: Option<NaiveDateTime>,
+) -> HitStats {
+
+    let mut buckets = BTreeMap::new();
+    let mut start = query.first_bucket();
+    let last = query.last_bucket();
+    while start <= last {
+        buckets.insert(start, 0);
+        start += bucket_len(query.bucket);
+    }
+
+    for (time, hits) in counts {
+        if let Some(bucket_hits) = buckets.get_mut(&bucket_start(time, query.bucket)) {
+            *bucket_hits += hits;
+        }
+    }
+
+    HitStats {
+        from: query.from,
+        to: query.to,
+        bucket: query.bucket,
+        total: buckets.values().sum(),
+        series: buckets.into_iter().map(|(start, hits)| BucketHits { start, hits }).collect(),
+        top_user_agents,
+        first_visit,
+        last_visit,
+    }
+}
+
+// For the backends without a rollup table (MemStore), which just go
+// through the whole log. Same results as the SQL backends.
+pub fn from_hit_log(query: &StatsQuery, hit_log: &[LoggedHit]) -> HitStats {
+
+    let (start, until) = query.time_range();
+    let in_range: Vec<&WebpageHit> = hit_log.iter()
+        .map(|logged| &logged.hit)
+        .filter(|hit| hit.time_stamp >= start && hit.time_stamp < until)
+        .collect();
+
+    let mut agents: BTreeMap<&str, u64> = BTreeMap::new();
+    for hit in &in_range {
+        *agents.entry(&hit.user_agent).or_default() += 1;
+    }
+    let mut top_user_agents: Vec<UserAgentHits> = agents.into_iter()
+        .map(|(user_agent, hits)| UserAgentHits { user_agent: user_agent.to_string(), hits })
+        .collect();
+    // stable, so ties stay in alphabetical order, like the SQL
+    top_user_agents.sort_by_key(|agent| Reverse(agent.hits));
+    top_user_agents.truncate(query.top_agents);
+
+    assemble(
+        query,
+        in_range.iter().map(|hit| (hit.time_stamp, 1)).collect(),
+        top_user_agents,
+        in_range.iter().map(|hit| hit.time_stamp).min(),
+        in_range.iter().map(|hit| hit.time_stamp).max(),
+    )
+}
+
+// The most common paths, referrers, or countries in the range, most hits first, and
+// ties in alphabetical order. Hits without one are left out.
+pub fn top_from_hit_log(query: &TopQuery, hit_log: &[LoggedHit]) -> Vec<(String, u64)> {
+
+    let (start, until) = query.time_range();
+    let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+    for logged in hit_log {
+        if logged.hit.time_stamp >= start && logged.hit.time_stamp < until {
+            if let Some(value) = query.by.value(logged) {
+                *counts.entry(value).or_default() += 1;
+            }
+        }
+    }
+
+    let mut top: Vec<(String, u64)> = counts.into_iter()
+        .map(|(value, hits)| (value.to_string(), hits))
+        .collect();
+    top.sort_by_key(|(_, hits)| Reverse(*hits));
+    top.truncate(query.top);
+
+    top
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::utils::{geoip::Location, user_agent::UaRules};
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn hit(time_stamp: &str, user_agent: &str) -> LoggedHit {
+        let hit = WebpageHit { time_stamp: time(time_stamp), user_agent: user_agent.to_string(), ..WebpageHit::default() };
+        LoggedHit::new(hit, &UaRules::built_in())
+    }
+
+    fn view(time_stamp: &str, path: &str, referrer: Option<&str>) -> LoggedHit {
+        let hit = WebpageHit {
+            time_stamp: time(time_stamp),
+            path: Some(path.to_string()),
+            referrer: referrer.map(str::to_string),
+            ..WebpageHit::default()
+        };
+        LoggedHit::new(hit, &UaRules::built_in())
+    }
+
+    #[test]
+    fn buckets() {
+        // 2025-03-13 was a Thursday
+        let t = time("2025-03-13 03:37:05");
+        assert_eq!(bucket_start(t, StatsBucket::Hour), time("2025-03-13 03:00:00"));
+        assert_eq!(bucket_start(t, StatsBucket::Day), time("2025-03-13 00:00:00"));
+        assert_eq!(bucket_start(t, StatsBucket::Week), time("2025-03-10 00:00:00"));
+
+        let query = StatsQuery { from: date("2025-03-13"), to: date("2025-03-24"), bucket: StatsBucket::Week, top_agents: 5 };
+        assert_eq!(query.bucket_count(), 3);
+        assert_eq!(StatsQuery { bucket: StatsBucket::Day, ..query.clone() }.bucket_count(), 12);
+        assert_eq!(StatsQuery { bucket: StatsBucket::Hour, ..query }.bucket_count(), 12 * 24);
+    }
+
+    #[test]
+    fn rolls_up_by_day_and_agent() {
+        let rollups = roll_up(&[
+            hit("2025-03-13 12:00:00", "b"),
+            hit("2025-03-13 03:37:05", "b"),
+            hit("2025-03-13 20:00:00", "a"),
+            hit("2025-03-14 00:00:00", "b"),
+        ]);
+        assert_eq!(rollups, vec![
+            DailyRollup { day: date("2025-03-13"), user_agent: "a".into(), hits: 1, first_hit: time("2025-03-13 20:00:00"), last_hit: time("2025-03-13 20:00:00") },
+            DailyRollup { day: date("2025-03-13"), user_agent: "b".into(), hits: 2, first_hit: time("2025-03-13 03:37:05"), last_hit: time("2025-03-13 12:00:00") },
+            DailyRollup { day: date("2025-03-14"), user_agent: "b".into(), hits: 1, first_hit: time("2025-03-14 00:00:00"), last_hit: time("2025-03-14 00:00:00") },
+        ]);
+    }
+
+    #[test]
+    fn fills_in_empty_buckets() {
+        let query = StatsQuery { from: date("2025-03-12"), to: date("2025-03-18"), bucket: StatsBucket::Week, top_agents: 5 };
+        let stats = from_hit_log(&query, &[
+            hit("2025-03-11 23:59:59", "too early"),
+            hit("2025-03-12 00:00:00", "a"),
+            hit("2025-03-16 10:00:00", "b"),
+            hit("2025-03-17 10:00:00", "a"),
+            hit("2025-03-18 23:59:59", "c"),
+            hit("2025-03-19 00:00:00", "too late"),
+        ]);
+
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.series, vec![
+            BucketHits { start: time("2025-03-10 00:00:00"), hits: 2 },
+            BucketHits { start: time("2025-03-17 00:00:00"), hits: 2 },
+        ]);
+        assert_eq!(stats.top_user_agents, vec![
+            UserAgentHits { user_agent: "a".into(), hits: 2 },
+            UserAgentHits { user_agent: "b".into(), hits: 1 },
+            UserAgentHits { user_agent: "c".into(), hits: 1 },
+        ]);
+        assert_eq!(stats.first_visit, Some(time("2025-03-12 00:00:00")));
+        assert_eq!(stats.last_visit, Some(time("2025-03-18 23:59:59")));
+
+        let empty = from_hit_log(&StatsQuery { bucket: StatsBucket::Day, ..query }, &[]);
+        assert_eq!(empty.series.len(), 7);
+        assert!(empty.series.iter().all(|bucket| bucket.hits == 0));
+        assert_eq!((empty.total, empty.first_visit), (0, None));
+    }
+
+    #[test]
+    fn top_pages_and_referrers() {
+        let hit_log = [
+            view("2025-03-11 23:59:59", "/too-early", None),
+            view("2025-03-12 00:00:00", "/guestbook", Some("https://www.Example.com/links")),
+            view("2025-03-12 10:00:00", "/", Some("https://news.example.org/")),
+            view("2025-03-13 10:00:00", "/", Some("https://example.com/")),
+            view("2025-03-14 10:00:00", "/guestbook", None),
+            view("2025-03-14 11:00:00", "/lb-list-conv", None),
+            hit("2025-03-14 12:00:00", "POSTed, so no path"),
+            view("2025-03-15 00:00:00", "/too-late", Some("https://too-late.example/")),
+        ];
+        let query = TopQuery { from: date("2025-03-12"), to: date("2025-03-14"), by: TopBy::Page, top: 10 };
+
+        assert_eq!(top_from_hit_log(&query, &hit_log), vec![
+            (String::from("/"), 2),
+            (String::from("/guestbook"), 2),
+            (String::from("/lb-list-conv"), 1),
+        ]);
+        assert_eq!(top_from_hit_log(&TopQuery { top: 1, ..query.clone() }, &hit_log), vec![(String::from("/"), 2)]);
+        assert_eq!(top_from_hit_log(&TopQuery { by: TopBy::Referrer, ..query }, &hit_log), vec![
+            (String::from("example.com"), 2),
+            (String::from("news.example.org"), 1),
+        ]);
+    }
+
+    #[test]
+    fn top_countries() {
+        let from = |time_stamp: &str, country: &str| LoggedHit {
+            location: Some(Location { country: country.to_string(), region: None }),
+            ..hit(time_stamp, "a")
+        };
+        let hit_log = [
+            from("2025-03-11 23:59:59", "US"),
+            from("2025-03-12 00:00:00", "NZ"),
+            from("2025-03-12 10:00:00", "DE"),
+            from("2025-03-13 10:00:00", "NZ"),
+            hit("2025-03-13 11:00:00", "GeoIP didn't know this one"),
+        ];
+        let query = TopQuery { from: date("2025-03-12"), to: date("2025-03-14"), by: TopBy::Country, top: 10 };
+
+        assert_eq!(top_from_hit_log(&query, &hit_log), vec![
+            (String::from("NZ"), 2),
+            (String::from("DE"), 1),
+        ]);
+    }
+}
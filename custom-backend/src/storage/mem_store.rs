@@ -0,0 +1,365 @@
+use std::{cmp::Reverse, collections::{BTreeMap, BTreeSet}, sync::{Mutex, MutexGuard, PoisonError}};
+use async_trait::async_trait;
+use mysql_common::chrono::{NaiveDate, NaiveDateTime};
+use crate::{
+    srv_io::db_io::DbError,
+    types::db_io_types::{AuditEvent, EntryChanges, EntryEdit, EntryStatus, GuestbookEntry, HitStats, SpamDecision, WebpageHit},
+};
+use crate::utils::user_agent::UaRules;
+use super::{
+    entry_pages::{self, EntryKey, EntryPageQuery},
+    entry_search::{self, SearchQuery},
+    hit_stats::{self, StatsQuery, TopQuery}, 
+    visitors::{DailySalt, Visitor}, 
+    AuditStore, AuthorToken, Database, EditReview, GuestbookStore, HitStore, HitTotals, LoggedHit, ReactionTally
+};
+
+
+// Keeps everything in plain Vecs. Nothing survives a restart, so 
+// this is mostly for tests, and for trying the server out without
+// setting up a database.
+#[derive(Default)]
+pub struct MemStore {
+    tables: Mutex<MemTables>,
+}
+
+#[derive(Default)]
+struct MemTables {
+    guestbook: Vec<GuestbookEntry>,
+    statuses: BTreeMap<String, EntryStatus>,    // by entry ID
+    deleted: BTreeMap<String, NaiveDateTime>,   // likewise, only the deleted ones
+    edits: BTreeMap<String, Vec<EntryEdit>>,    // likewise, oldest first
+    spam: BTreeMap<String, SpamDecision>,       // likewise, only for the admin's reads
+    edit_tokens: BTreeMap<String, String>,      // likewise, the hashes of the authors' tokens
+    reactions: BTreeMap<(i64, String, String), NaiveDateTime>,  // by entry ID, emoji, and fingerprint
+    hit_log: Vec<LoggedHit>,
+    last_entry_id: u64,     // like AUTO_INCREMENT, IDs are never reused
+    visitor_salts: BTreeMap<NaiveDate, String>,
+    audit: Vec<AuditEvent>,     // oldest first
+}
+
+impl MemStore {
+
+    pub fn new() -> MemStore {
+        MemStore::default()
+    }
+
+    // Starts the store off with some existing data. Entries without
+    // an ID are given one, in the order they're passed in, and are all
+    // approved. The hits are sorted out with the built-in user agent rules.
+    pub fn with_data(guestbook: Vec<GuestbookEntry>, hit_log: Vec<WebpageHit>) -> MemStore {
+
+        let ua_rules = UaRules::built_in();
+        let hit_log = hit_log.into_iter().map(|hit| LoggedHit::new(hit, &ua_rules)).collect();
+
+        let mut tables = MemTables { guestbook: Vec::new(), hit_log, ..Default::default() };
+        for entry in guestbook {
+            tables.insert_entry(entry, EntryStatus::Approved);
+        }
+
+        MemStore { tables: Mutex::new(tables) }
+    }
+
+    // nothing can be left half-written in here by a panic, 
+    // so a poisoned lock is still safe to use
+    fn lock(&self) -> MutexGuard<'_, MemTables> {
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn hit_log(&self) -> Vec<LoggedHit> {
+        self.lock().hit_log.clone()
+    }
+}
+
+impl MemTables {
+
+    fn insert_entry(&mut self, mut entry: GuestbookEntry, status: EntryStatus) -> String {
+
+        let id = match entry.id.as_ref().and_then(|id| id.parse::<u64>().ok()) {
+            Some(given_id) => given_id,
+            None => self.last_entry_id + 1
+        };
+        self.last_entry_id = self.last_entry_id.max(id);
+
+        entry.id = Some(id.to_string());
+        entry.pinned = entry.pinned.filter(|&pinned| pinned);
+        if let Some(spam) = entry.spam.take() {
+            self.spam.insert(id.to_string(), spam);
+        }
+        self.guestbook.push(entry);
+        self.statuses.insert(id.to_string(), status);
+
+        id.to_string()
+    }
+
+    // leaving out the deleted ones
+    fn with_status(&self, status: EntryStatus) -> Vec<GuestbookEntry> {
+        self.guestbook.iter()
+            .filter(|entry| entry.id.as_ref().and_then(|id| self.statuses.get(id)) == Some(&status))
+            .filter(|entry| entry.id.as_ref().is_some_and(|id| !self.deleted.contains_key(id)))
+            .cloned()
+            .collect()
+    }
+
+    // a copy of the entry, with what the spam filter made of it
+    fn with_spam(&self, entry: &GuestbookEntry) -> GuestbookEntry {
+        let spam = entry.id.as_ref().and_then(|id| self.spam.get(id)).cloned();
+        GuestbookEntry { spam, ..entry.clone() }
+    }
+
+    fn entry_mut(&mut self, id: i64) -> Option<&mut GuestbookEntry> {
+        let id = id.to_string();
+        self.guestbook.iter_mut().find(|entry| entry.id.as_ref() == Some(&id))
+    }
+}
+
+
+#[async_trait]
+impl Database for MemStore {
+    fn backend_name(&self) -> &'static str { "in-memory" }
+}
+
+
+#[async_trait]
+impl GuestbookStore for MemStore {
+
+    async fn get_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let mut entries = self.lock().with_status(EntryStatus::Approved);
+        entries.sort_by_key(|ent| Reverse((ent.pinned == Some(true), ent.time_stamp)));
+
+        Ok(entries)
+    }
+
+    async fn get_page(&self, query: &EntryPageQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+        let mut shown = self.lock().with_status(EntryStatus::Approved);
+        shown.retain(|entry| entry.parent_id.is_none());
+        Ok(entry_pages::page_of(query, &shown))
+    }
+
+    async fn get_replies(&self, parent_ids: &[i64]) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let mut replies: Vec<GuestbookEntry> = self.lock().with_status(EntryStatus::Approved).into_iter()
+            .filter(|entry| entry.parent_id.as_deref().and_then(|id| id.parse().ok()).is_some_and(|id| parent_ids.contains(&id)))
+            .collect();
+        replies.sort_by_key(EntryKey::of);
+        Ok(replies)
+    }
+
+    async fn get_entry(&self, id: i64) -> Result<Option<GuestbookEntry>, DbError> {
+        let id = id.to_string();
+        Ok(self.lock().with_status(EntryStatus::Approved).into_iter().find(|entry| entry.id.as_ref() == Some(&id)))
+    }
+
+    async fn search_entries(&self, query: &SearchQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+        Ok(entry_search::candidates_of(query, &self.lock().with_status(EntryStatus::Approved)))
+    }
+
+    async fn add_entry(&self, entry: &GuestbookEntry, status: EntryStatus, edit_token_hash: Option<&str>) -> Result<String, DbError> {
+
+        // the ID is always assigned by the store, new entries are never pinned,
+        // and replies and reactions are only ever added on the way out
+        let entry = GuestbookEntry { id: None, pinned: None, replies: None, reactions: None, ..entry.clone() };
+        let mut tables = self.lock();
+        let id = tables.insert_entry(entry, status);
+        if let Some(hash) = edit_token_hash {
+            tables.edit_tokens.insert(id.clone(), hash.to_string());
+        }
+        Ok(id)
+    }
+
+    async fn author_token(&self, id: i64) -> Result<Option<AuthorToken>, DbError> {
+
+        let mut tables = self.lock();
+        let Some(entry) = tables.entry_mut(id).cloned() else { return Ok(None) };
+        let id = id.to_string();
+        Ok(Some(AuthorToken {
+            token_hash: tables.edit_tokens.get(&id).cloned(),
+            posted: entry.time_stamp,
+            status: tables.statuses[&id],
+            deleted: tables.deleted.contains_key(&id),
+            name: entry.name,
+            note: entry.note,
+        }))
+    }
+
+    async fn entries_with_status(&self, status: EntryStatus) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let tables = self.lock();
+        let mut entries: Vec<GuestbookEntry> = tables.with_status(status).iter().map(|entry| tables.with_spam(entry)).collect();
+        entries.sort_by_key(|ent| (ent.time_stamp, ent.id.as_ref().and_then(|id| id.parse::<u64>().ok())));
+
+        Ok(entries)
+    }
+
+    async fn set_status(&self, id: i64, status: EntryStatus) -> Result<bool, DbError> {
+
+        let mut tables = self.lock();
+        Ok(match tables.statuses.get_mut(&id.to_string()) {
+            Some(current) => { *current = status; true },
+            None => false,
+        })
+    }
+
+    async fn set_deleted(&self, id: i64, deleted_at: Option<NaiveDateTime>) -> Result<bool, DbError> {
+
+        let mut tables = self.lock();
+        if tables.entry_mut(id).is_none() {
+            return Ok(false);
+        }
+        let id = id.to_string();
+        match deleted_at {
+            Some(deleted_at) => { tables.deleted.entry(id).or_insert(deleted_at); },
+            None => { tables.deleted.remove(&id); },
+        }
+        Ok(true)
+    }
+
+    async fn deleted_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let tables = self.lock();
+        let mut entries: Vec<(NaiveDateTime, GuestbookEntry)> = tables.guestbook.iter()
+            .filter_map(|entry| {
+                let deleted_at = entry.id.as_ref().and_then(|id| tables.deleted.get(id))?;
+                Some((*deleted_at, tables.with_spam(entry)))
+            })
+            .collect();
+        entries.sort_by_key(|(deleted_at, _)| Reverse(*deleted_at));
+
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    async fn edit_entry(&self, id: i64, changes: &EntryChanges, review: Option<&EditReview>, edit_time: NaiveDateTime) -> Result<Option<GuestbookEntry>, DbError> {
+
+        let mut tables = self.lock();
+        let Some(entry) = tables.entry_mut(id) else { return Ok(None) };
+
+        let edit = EntryEdit { edit_time, old_name: entry.name.clone(), old_note: entry.note.clone() };
+        if let Some(name) = &changes.name {
+            entry.name = name.clone();
+        }
+        if let Some(note) = &changes.note {
+            entry.note = note.clone();
+        }
+        let edited = entry.clone();
+        tables.edits.entry(id.to_string()).or_default().push(edit);
+        if let Some(review) = review {
+            tables.statuses.insert(id.to_string(), review.status);
+            tables.spam.insert(id.to_string(), review.spam.clone());
+        }
+
+        Ok(Some(edited))
+    }
+
+    async fn entry_edits(&self, id: i64) -> Result<Option<Vec<EntryEdit>>, DbError> {
+
+        let mut tables = self.lock();
+        if tables.entry_mut(id).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(tables.edits.get(&id.to_string()).cloned().unwrap_or_default()))
+    }
+
+    async fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool, DbError> {
+
+        let mut tables = self.lock();
+        Ok(match tables.entry_mut(id) {
+            Some(entry) => { entry.pinned = pinned.then_some(true); true },
+            None => false,
+        })
+    }
+
+    async fn add_reaction(&self, entry_id: i64, emoji: &str, fingerprint: &str, reacted_at: NaiveDateTime) -> Result<bool, DbError> {
+
+        let key = (entry_id, emoji.to_string(), fingerprint.to_string());
+        let reactions = &mut self.lock().reactions;
+        if reactions.contains_key(&key) {
+            return Ok(false);
+        }
+        reactions.insert(key, reacted_at);
+        Ok(true)
+    }
+
+    async fn reaction_counts(&self, entry_id: Option<i64>) -> Result<Vec<ReactionTally>, DbError> {
+
+        // the keys are already in order, so the same ones are all together
+        let mut tallies: Vec<ReactionTally> = Vec::new();
+        for (id, emoji, _) in self.lock().reactions.keys() {
+            if entry_id.is_some_and(|entry_id| entry_id != *id) {
+                continue;
+            }
+            match tallies.last_mut() {
+                Some(tally) if tally.entry_id == *id && tally.emoji == *emoji => tally.count += 1,
+                _ => tallies.push(ReactionTally { entry_id: *id, emoji: emoji.clone(), count: 1 }),
+            }
+        }
+        Ok(tallies)
+    }
+}
+
+
+#[async_trait]
+impl HitStore for MemStore {
+
+    async fn hit_count(&self) -> Result<HitTotals, DbError> {
+        Ok(HitTotals::of(&self.lock().hit_log))
+    }
+
+    async fn log_hits(&self, logged_hits: &[LoggedHit]) -> Result<HitTotals, DbError> {
+        let mut tables = self.lock();
+        tables.hit_log.extend_from_slice(logged_hits);
+        Ok(HitTotals::of(&tables.hit_log))
+    }
+
+    async fn hit_stats(&self, query: &StatsQuery) -> Result<HitStats, DbError> {
+        Ok(hit_stats::from_hit_log(query, &self.lock().hit_log))
+    }
+
+    async fn top_hits(&self, query: &TopQuery) -> Result<Vec<(String, u64)>, DbError> {
+        Ok(hit_stats::top_from_hit_log(query, &self.lock().hit_log))
+    }
+
+    async fn visitor_salts(&self, fresh: &[DailySalt]) -> Result<Vec<DailySalt>, DbError> {
+
+        let Some(first_day) = fresh.first().map(|salt| salt.day) else { return Ok(Vec::new()) };
+
+        let mut tables = self.lock();
+        tables.visitor_salts.retain(|day, _| *day >= first_day);
+        for DailySalt { day, salt } in fresh {
+            tables.visitor_salts.entry(*day).or_insert_with(|| salt.clone());
+        }
+
+        Ok(tables.visitor_salts.iter().map(|(day, salt)| DailySalt { day: *day, salt: salt.clone() }).collect())
+    }
+
+    // the visitors are just whatever's in the hit log
+    async fn unique_visitors(&self, since: NaiveDate) -> Result<Vec<(NaiveDate, u64)>, DbError> {
+
+        let tables = self.lock();
+        let visitors: BTreeSet<&Visitor> = tables.hit_log.iter()
+            .filter_map(|logged| logged.visitor.as_ref())
+            .filter(|visitor| visitor.day >= since)
+            .collect();
+
+        let mut per_day: BTreeMap<NaiveDate, u64> = BTreeMap::new();
+        for visitor in visitors {
+            *per_day.entry(visitor.day).or_default() += 1;
+        }
+        Ok(per_day.into_iter().collect())
+    }
+}
+
+
+#[async_trait]
+impl AuditStore for MemStore {
+
+    async fn log_event(&self, event: &AuditEvent) -> Result<(), DbError> {
+        self.lock().audit.push(event.clone());
+        Ok(())
+    }
+
+    async fn recent_events(&self, limit: u64) -> Result<Vec<AuditEvent>, DbError> {
+        Ok(self.lock().audit.iter().rev().take(limit as usize).cloned().collect())
+    }
+}
@@ -0,0 +1,290 @@
+// Schema migrations, embedded in the binary so a build always carries the
+// schema it expects. The SQL lives in custom-backend/migrations/<backend>/,
+// as NNNN_name.up.sql and NNNN_name.down.sql, and every backend has the same
+// list of versions (a migration that doesn't apply to one backend is just
+// an empty file there).
+//
+// Which versions have been applied is kept in a schema_migrations table.
+// At startup, the server refuses to run against a database that has a version
+// it doesn't know about, since that means a newer build has changed the schema
+// out from under it. Pending migrations are applied automatically, unless
+// DB_AUTO_MIGRATE=false, in which case `archie-server migrate up` has to be run first.
+use std::fmt;
+use tracing::info;
+use crate::srv_io::db_io::DbError;
+use super::Database;
+
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down
+}
+
+// include_str! only takes a literal path, hence the macro
+macro_rules! migrations {
+    ($backend:literal: $($version:literal => $name:literal),* $(,)?) => {
+        &[$(
+            Migration {
+                version: $version,
+                name: $name,
+                up: include_str!(concat!("../../migrations/", $backend, "/", $name, ".up.sql")),
+                down: include_str!(concat!("../../migrations/", $backend, "/", $name, ".down.sql")),
+            }
+        ),*]
+    };
+}
+
+pub static MYSQL_MIGRATIONS: &[Migration] = migrations!("mysql":
+    1 => "0001_create_tables",
+    2 => "0002_guest_name_100",
+    3 => "0003_hit_counter",
+    4 => "0004_hit_rollup",
+    5 => "0005_user_agent_fields",
+    6 => "0006_unique_visitors",
+    7 => "0007_page_paths",
+    8 => "0008_utm_params",
+    9 => "0009_hit_location",
+    10 => "0010_guestbook_search",
+    11 => "0011_entry_status",
+    12 => "0012_admin_audit",
+    13 => "0013_entry_admin",
+    14 => "0014_spam_checks",
+    15 => "0015_edit_tokens",
+    16 => "0016_entry_replies",
+    17 => "0017_entry_reactions",
+);
+
+pub static PG_MIGRATIONS: &[Migration] = migrations!("postgres":
+    1 => "0001_create_tables",
+    2 => "0002_guest_name_100",
+    3 => "0003_hit_counter",
+    4 => "0004_hit_rollup",
+    5 => "0005_user_agent_fields",
+    6 => "0006_unique_visitors",
+    7 => "0007_page_paths",
+    8 => "0008_utm_params",
+    9 => "0009_hit_location",
+    10 => "0010_guestbook_search",
+    11 => "0011_entry_status",
+    12 => "0012_admin_audit",
+    13 => "0013_entry_admin",
+    14 => "0014_spam_checks",
+    15 => "0015_edit_tokens",
+    16 => "0016_entry_replies",
+    17 => "0017_entry_reactions",
+);
+
+pub static SQLITE_MIGRATIONS: &[Migration] = migrations!("sqlite":
+    1 => "0001_create_tables",
+    2 => "0002_guest_name_100",
+    3 => "0003_hit_counter",
+    4 => "0004_hit_rollup",
+    5 => "0005_user_agent_fields",
+    6 => "0006_unique_visitors",
+    7 => "0007_page_paths",
+    8 => "0008_utm_params",
+    9 => "0009_hit_location",
+    10 => "0010_guestbook_search",
+    11 => "0011_entry_status",
+    12 => "0012_admin_audit",
+    13 => "0013_entry_admin",
+    14 => "0014_spam_checks",
+    15 => "0015_edit_tokens",
+    16 => "0016_entry_replies",
+    17 => "0017_entry_reactions",
+);
+
+
+#[derive(Debug)]
+pub enum MigrationError {
+    DbError(DbError),
+    UnknownVersions(Vec<u32>),  // applied to the DB, but not in this build
+    Pending(Vec<u32>),          // in this build, but not applied, with auto-migration off
+}
+
+impl From<DbError> for MigrationError {
+    fn from(db_err: DbError) -> Self {
+        Self::DbError(db_err)
+    }
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DbError(db_err) => write!(f, "{db_err}"),
+            Self::UnknownVersions(versions) => write!(f,
+                "The database has schema version(s) {versions:?}, which this build doesn't know about. \
+                It was likely migrated by a newer build of the server."
+            ),
+            Self::Pending(versions) => write!(f,
+                "Schema migration(s) {versions:?} haven't been applied, and DB_AUTO_MIGRATE is off. \
+                Run `archie-server migrate up` to apply them."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+
+pub struct MigrationStatus {
+    pub applied: Vec<&'static Migration>,
+    pub pending: Vec<&'static Migration>,
+    pub unknown: Vec<u32>,
+}
+
+pub async fn status(db: &dyn Database) -> Result<MigrationStatus, MigrationError> {
+
+    let applied_versions = db.applied_versions().await?;
+    let (applied, pending) = db.migrations().iter()
+        .partition(|mig| applied_versions.contains(&mig.version));
+    let unknown = applied_versions.into_iter()
+        .filter(|ver| !db.migrations().iter().any(|mig| mig.version == *ver))
+        .collect();
+
+    Ok(MigrationStatus { applied, pending, unknown })
+}
+
+// Applies every pending migration, oldest first, and returns their versions
+pub async fn migrate_up(db: &dyn Database) -> Result<Vec<u32>, MigrationError> {
+
+    let MigrationStatus { pending, unknown, .. } = status(db).await?;
+    if !unknown.is_empty() {
+        return Err(MigrationError::UnknownVersions(unknown));
+    }
+
+    let mut done = Vec::new();
+    for migration in pending {
+        info!("Applying migration {}...", migration.name);
+        db.run_migration(migration, Direction::Up).await?;
+        done.push(migration.version);
+    }
+
+    Ok(done)
+}
+
+// Reverts the last `steps` applied migrations, newest first, and returns their versions
+pub async fn migrate_down(db: &dyn Database, steps: u32) -> Result<Vec<u32>, MigrationError> {
+
+    let MigrationStatus { applied, unknown, .. } = status(db).await?;
+    // there's no down SQL for these, so nothing under them can be reverted either
+    if !unknown.is_empty() {
+        return Err(MigrationError::UnknownVersions(unknown));
+    }
+
+    let mut done = Vec::new();
+    for migration in applied.into_iter().rev().take(steps as usize) {
+        info!("Reverting migration {}...", migration.name);
+        db.run_migration(migration, Direction::Down).await?;
+        done.push(migration.version);
+    }
+
+    Ok(done)
+}
+
+// Called at startup, before any requests are served
+pub async fn prepare_schema(db: &dyn Database, auto_migrate: bool) -> Result<(), MigrationError> {
+
+    let MigrationStatus { applied, pending, unknown } = status(db).await?;
+
+    if !unknown.is_empty() {
+        return Err(MigrationError::UnknownVersions(unknown));
+    }
+    if pending.is_empty() {
+        if let Some(latest) = applied.last() {
+            info!("Database schema is up to date (version {}).", latest.version);
+        }
+        return Ok(());
+    }
+    if !auto_migrate {
+        return Err(MigrationError::Pending(pending.iter().map(|mig| mig.version).collect()));
+    }
+
+    let done = migrate_up(db).await?;
+    info!("Applied {} migration(s) to the database schema.", done.len());
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::{sqlite_store::SqliteStore, Stores};
+
+    #[test]
+    fn backends_have_the_same_versions() {
+        for migrations in [MYSQL_MIGRATIONS, PG_MIGRATIONS] {
+            let versions: Vec<(u32, &str)> = migrations.iter().map(|mig| (mig.version, mig.name)).collect();
+            let sqlite_versions: Vec<(u32, &str)> = SQLITE_MIGRATIONS.iter().map(|mig| (mig.version, mig.name)).collect();
+            assert_eq!(versions, sqlite_versions);
+        }
+        // numbered from 1, with no gaps
+        for (i, migration) in SQLITE_MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version as usize, i + 1);
+            assert!(migration.name.starts_with(&format!("{:04}_", migration.version)));
+        }
+    }
+
+    #[tokio::test]
+    async fn up_down_and_status() {
+        let stores = Stores::from_backend(SqliteStore::open(":memory:").await.unwrap());
+        let db = stores.db.as_ref();
+
+        let before = status(db).await.unwrap();
+        assert!(before.applied.is_empty());
+        assert_eq!(before.pending.len(), SQLITE_MIGRATIONS.len());
+        assert!(stores.guestbook.get_entries().await.is_err());    // no tables yet
+
+        assert_eq!(migrate_up(db).await.unwrap(), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17]);
+        assert_eq!(migrate_up(db).await.unwrap(), Vec::<u32>::new());
+        assert!(status(db).await.unwrap().pending.is_empty());
+        assert!(stores.guestbook.get_entries().await.unwrap().is_empty());
+
+        assert_eq!(migrate_down(db, 1).await.unwrap(), vec![17]);
+        let after_down = status(db).await.unwrap();
+        assert_eq!(after_down.applied.iter().map(|mig| mig.version).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(after_down.pending.len(), 1);
+
+        // asking for more steps than there are just reverts everything
+        assert_eq!(migrate_down(db, 20).await.unwrap(), vec![16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1]);
+        assert!(stores.guestbook.get_entries().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pending_without_auto_migrate() {
+        let store = SqliteStore::open(":memory:").await.unwrap();
+
+        let Err(err) = prepare_schema(&store, false).await
+        else { panic!("pending migrations should stop startup when auto-migration is off") };
+        assert!(matches!(err, MigrationError::Pending(v) if v == vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17]));
+
+        prepare_schema(&store, true).await.unwrap();
+        prepare_schema(&store, false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_unknown_versions() {
+        static FROM_THE_FUTURE: Migration = Migration {
+            version: 999, name: "0999_from_a_newer_build", up: "", down: ""
+        };
+
+        let store = SqliteStore::open(":memory:").await.unwrap();
+        migrate_up(&store).await.unwrap();
+        store.run_migration(&FROM_THE_FUTURE, Direction::Up).await.unwrap();
+
+        let Err(err) = prepare_schema(&store, true).await
+        else { panic!("an unknown schema version should stop startup") };
+        assert!(matches!(err, MigrationError::UnknownVersions(v) if v == vec![999]));
+        assert!(matches!(migrate_down(&store, 1).await, Err(MigrationError::UnknownVersions(_))));
+    }
+}
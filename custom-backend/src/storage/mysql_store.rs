@@ -0,0 +1,829 @@
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use mysql_async::{params, Conn, Opts, OptsBuilder, Pool, PoolConstraints, PoolOpts, TxOpts};
+use mysql_async::prelude::*;
+use mysql_common::chrono::{NaiveDate, NaiveDateTime, NaiveTime, SubsecRound, Utc};
+use tracing::{debug, info};
+use crate::{
+    srv_io::db_io::DbError,
+    types::db_io_types::{
+        AuditAction, AuditEvent, EntryChanges, EntryEdit, EntryStatus, GuestbookEntry, HitStats,
+        SpamDecision, StatsBucket, UserAgentHits
+    },
+};
+use super::{
+    entry_pages::EntryPageQuery,
+    entry_search::{SearchQuery, MAX_CANDIDATES},
+    hit_stats::{self, StatsQuery, TopQuery},
+    HitTotals, LoggedHit,
+    migrations::{Direction, Migration, MYSQL_MIGRATIONS},
+    self_check::{expected_privileges, ColumnInfo},
+    visitors::DailySalt,
+    audit_entry_id, AuditStore, AuthorToken, Database, DbPoolConfig, EditReview, GuestbookStore, HitStore, ReactionTally,
+};
+
+
+const MIGRATIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations
+    (
+        version         INT NOT NULL,
+        name            VARCHAR(100) NOT NULL,
+        appliedAt       DATETIME NOT NULL,
+        PRIMARY KEY     (version)
+    )";
+
+
+pub struct MySqlStore {
+    pool: Pool,     // cheap to clone; it's an Arc on the inside
+}
+
+impl MySqlStore {
+
+    // This only sets up the pool; no connections are made until the pool
+    // is warmed up, or the first request comes in.
+    pub fn new(url: &str, pool_cfg: &DbPoolConfig) -> Result<MySqlStore, DbError> {
+
+        debug!("DB URL to connect with: {url}");
+
+        // unwrap can't panic: DbPoolConfig::from_env() ensures min <= max
+        let constraints = PoolConstraints::new(pool_cfg.min_conns, pool_cfg.max_conns).unwrap();
+
+        let pool_opts = PoolOpts::new()
+            .with_constraints(constraints)
+            .with_inactive_connection_ttl(pool_cfg.idle_timeout)
+            .with_abs_conn_ttl(pool_cfg.conn_lifetime);
+
+        let opts = OptsBuilder::from_opts(Opts::from_url(url)?)
+            .pool_opts(pool_opts);
+
+        Ok(MySqlStore { pool: Pool::new(opts) })
+    }
+
+    // Opens the minimum number of connections all at once, and pings each one,
+    // so they're already sitting in the pool by the time the first request comes in.
+    // At least one connection is always opened, so a bad DB_URL or a database
+    // that's down is caught at startup.
+    pub async fn warm_up(&self, pool_cfg: &DbPoolConfig) -> Result<(), DbError> {
+
+        let n_conns = pool_cfg.min_conns.max(1);
+        let mut conns = try_join_all((0..n_conns).map(|_| self.pool.get_conn())).await?;
+
+        for conn in conns.iter_mut() {
+            conn.ping().await?;
+        }
+
+        // dropping the connections returns them to the pool
+        drop(conns);
+        info!("Database pool warmed up with {n_conns} connection(s).");
+
+        Ok(())
+    }
+
+    // only for tests that need a clean slate; don't point them at anything important!
+    #[cfg(test)]
+    pub(crate) async fn clear_tables(&self) -> Result<(), DbError> {
+        let mut conn = self.pool.get_conn().await?;
+        // TRUNCATE starts the IDs over too, but it only takes one table at a time
+        for table in ["guestbook", "guestbookEdit", "guestbookReaction", "hitLog", "hitDaily", "visitorSalt", "visitorDaily", "adminAudit"] {
+            conn.query_drop(format!("TRUNCATE {table}")).await?;
+        }
+        conn.query_drop("UPDATE hitCounter SET total = 0, bots = 0").await?;
+        Ok(())
+    }
+}
+
+
+#[async_trait]
+impl Database for MySqlStore {
+
+    fn backend_name(&self) -> &'static str { "MySQL" }
+
+    // lets the pool close its connections cleanly
+    async fn close(&self) -> Result<(), DbError> {
+        self.pool.clone().disconnect().await?;
+        Ok(())
+    }
+
+    fn migrations(&self) -> &'static [Migration] { MYSQL_MIGRATIONS }
+
+    async fn applied_versions(&self) -> Result<Vec<u32>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let table_exists = conn.query_first::<u64, &str>(
+            r"SELECT COUNT(*) FROM information_schema.tables 
+                WHERE table_schema = DATABASE() AND table_name = 'schema_migrations'"
+        ).await?.unwrap_or(0) > 0;
+        if !table_exists {
+            return Ok(Vec::new());
+        }
+
+        Ok(conn.query("SELECT version FROM schema_migrations ORDER BY version").await?)
+    }
+
+    // MySQL commits DDL statements as soon as they run, so unlike the other 
+    // backends, a migration that fails partway through can't be rolled back,
+    // and has to be cleaned up by hand. Also, MySQL errors on a query that's
+    // only comments, so there's no such thing as an empty MySQL migration.
+    async fn run_migration(&self, migration: &'static Migration, direction: Direction) -> Result<(), DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        conn.query_drop(MIGRATIONS_TABLE).await?;
+
+        match direction {
+            Direction::Up => {
+                conn.query_drop(migration.up).await?;
+                conn.exec_drop(
+                    r"INSERT INTO schema_migrations (version, name, appliedAt) 
+                        VALUES (:version, :name, :applied_at)",
+                    params! {
+                        "version"    => migration.version,
+                        "name"       => migration.name,
+                        "applied_at" => Utc::now().naive_utc().trunc_subsecs(0)
+                    }
+                ).await?;
+            },
+            Direction::Down => {
+                conn.query_drop(migration.down).await?;
+                conn.exec_drop(
+                    "DELETE FROM schema_migrations WHERE version = :version",
+                    params! { "version" => migration.version }
+                ).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let columns = conn.exec_map(
+            r"SELECT COLUMN_NAME, DATA_TYPE, CHARACTER_MAXIMUM_LENGTH 
+                FROM information_schema.columns
+                WHERE table_schema = DATABASE() AND table_name = :table
+                ORDER BY ORDINAL_POSITION",
+            params! { "table" => table },
+            |(name, data_type, max_chars): (String, String, Option<u64>)| {
+                ColumnInfo { name, data_type: data_type.to_lowercase(), max_chars }
+            }
+        ).await?;
+
+        Ok(columns)
+    }
+
+    // The privileges from db-init/privileges.sql that the queries use. They can
+    // be granted globally, on the database, or per table. 
+    // Privileges that come from a role aren't listed in information_schema, 
+    // so a user that gets them that way will fail this check.
+    async fn missing_privileges(&self) -> Result<Vec<String>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        // CURRENT_USER() gives server1@%, but the GRANTEE columns have 'server1'@'%'
+        let grantee = "CONCAT('''', REPLACE(CURRENT_USER(), '@', '''@'''), '''')";
+
+        let mut broad_grants: Vec<String> = conn.query(format!(
+            "SELECT PRIVILEGE_TYPE FROM information_schema.USER_PRIVILEGES WHERE GRANTEE = {grantee}"
+        )).await?;
+        broad_grants.extend(conn.query::<String, _>(format!(
+            "SELECT PRIVILEGE_TYPE FROM information_schema.SCHEMA_PRIVILEGES 
+                WHERE GRANTEE = {grantee} AND TABLE_SCHEMA = DATABASE()"
+        )).await?);
+        let table_grants: Vec<(String, String)> = conn.query(format!(
+            "SELECT PRIVILEGE_TYPE, TABLE_NAME FROM information_schema.TABLE_PRIVILEGES 
+                WHERE GRANTEE = {grantee} AND TABLE_SCHEMA = DATABASE()"
+        )).await?;
+
+        let mut missing = Vec::new();
+
+        for (table, privileges) in expected_privileges() {
+            for privilege in privileges.iter() {
+                let granted = broad_grants.iter().any(|grant| grant == privilege)
+                    || table_grants.iter().any(|(grant, tbl)| grant == privilege && tbl == table);
+                if !granted {
+                    missing.push(format!("{privilege} on {table}"));
+                }
+            }
+        }
+
+        Ok(missing)
+    }
+}
+
+
+// id, dateSubmitted, guestName, guestNote, pinned, parentId
+type EntryRow = (i64, NaiveDateTime, String, Option<String>, bool, Option<i64>);
+// and then spamVerdict, spamReason
+type AdminEntryRow = (i64, NaiveDateTime, String, Option<String>, bool, Option<i64>, Option<String>, Option<String>);
+
+// for prepared statements, which send the ID back as an integer
+fn entry_from_row((id, time_stamp, name, note, pinned, parent_id): EntryRow) -> GuestbookEntry {
+    GuestbookEntry { 
+        id: Some(id.to_string()), 
+        time_stamp: Some(time_stamp), 
+        name, 
+        note: note.unwrap_or_default(), 
+        pinned: pinned.then_some(true),
+        spam: None,
+        parent_id: parent_id.map(|id| id.to_string()),
+        replies: None,
+        reactions: None,
+    }
+}
+
+// the same, with what the spam filter made of it after the rest, for the admin
+fn admin_entry_from_row((id, time_stamp, name, note, pinned, parent_id, verdict, reason): AdminEntryRow) -> GuestbookEntry {
+    GuestbookEntry {
+        spam: SpamDecision::from_db(verdict.as_deref(), reason),
+        ..entry_from_row((id, time_stamp, name, note, pinned, parent_id))
+    }
+}
+
+// MySQL only counts rows that changed, so an UPDATE that left an entry 
+// as it was would make it look like it isn't there
+async fn updated_or_exists(conn: &mut Conn, id: i64) -> Result<bool, DbError> {
+
+    if conn.affected_rows() > 0 {
+        return Ok(true);
+    }
+    let found: Option<i64> = conn.exec_first("SELECT id FROM guestbook WHERE id = :id", params! { "id" => id }).await?;
+
+    Ok(found.is_some())
+}
+
+#[async_trait]
+impl GuestbookStore for MySqlStore {
+
+    async fn get_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        
+        let guestbook_table = conn.exec_map(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+            FROM guestbook
+            WHERE status = 'approved' AND deletedAt IS NULL
+            ORDER BY pinned DESC, dateSubmitted DESC", // let the DB do the sorting
+            (),
+            entry_from_row
+        ).await?;
+
+        Ok(guestbook_table)
+    }
+
+    async fn get_page(&self, query: &EntryPageQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        let direction = query.direction();
+
+        let page = conn.exec_map(
+            format!("
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+                FROM guestbook
+                WHERE status = 'approved' AND deletedAt IS NULL AND parentId IS NULL
+                    AND (dateSubmitted, id) > (:after_time, :after_id)
+                    AND (dateSubmitted, id) < (:before_time, :before_id)
+                ORDER BY dateSubmitted {direction}, id {direction}
+                LIMIT :limit"
+            ),
+            params! {
+                "after_time"  => query.after.time,
+                "after_id"    => query.after.id,
+                "before_time" => query.before.time,
+                "before_id"   => query.before.id,
+                "limit"       => query.limit as u64,
+            },
+            entry_from_row
+        ).await?;
+
+        Ok(page)
+    }
+
+    async fn get_replies(&self, parent_ids: &[i64]) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        if parent_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.pool.get_conn().await?;
+        let placeholders = vec!["?"; parent_ids.len()].join(", ");
+
+        let replies = conn.exec_map(
+            format!("
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+                FROM guestbook
+                WHERE status = 'approved' AND deletedAt IS NULL AND parentId IN ({placeholders})
+                ORDER BY dateSubmitted, id"
+            ),
+            parent_ids.to_vec(),
+            entry_from_row
+        ).await?;
+
+        Ok(replies)
+    }
+
+    async fn get_entry(&self, id: i64) -> Result<Option<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let row = conn.exec_first(
+            "SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId FROM guestbook WHERE id = :id AND status = 'approved' AND deletedAt IS NULL",
+            params! { "id" => id }
+        ).await?;
+
+        Ok(row.map(entry_from_row))
+    }
+
+    async fn search_entries(&self, query: &SearchQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        // each term as a phrase, which the ngram parser turns into "all of
+        // these ngrams, in a row", and all of them required
+        let against: Vec<String> = query.terms.iter().map(|term| format!("+\"{term}\"")).collect();
+
+        let candidates = conn.exec_map(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+            FROM guestbook
+            WHERE MATCH (guestName, guestNote) AGAINST (:against IN BOOLEAN MODE)
+                AND status = 'approved' AND deletedAt IS NULL
+            ORDER BY dateSubmitted DESC, id DESC
+            LIMIT :limit",
+            params! {
+                "against" => against.join(" "),
+                "limit"   => MAX_CANDIDATES as u64,
+            },
+            entry_from_row
+        ).await?;
+
+        Ok(candidates)
+    }
+
+    async fn add_entry(&self, entry: &GuestbookEntry, status: EntryStatus, edit_token_hash: Option<&str>) -> Result<String, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        let parent_id = entry.parent_id.as_deref().and_then(|id| id.parse::<i64>().ok());
+
+        conn.exec_drop(
+            r"INSERT INTO guestbook (dateSubmitted, guestName, guestNote, status, spamVerdict, spamReason, editTokenHash, parentId)
+                    VALUES (:time_stamp, :name, :note, :status, :spam_verdict, :spam_reason, :edit_token_hash, :parent_id)",
+            params! {
+                "time_stamp"   => entry.time_stamp,
+                "name"         => &entry.name, 
+                "note"         => &entry.note,
+                "status"       => status.as_str(),
+                "spam_verdict" => entry.spam.as_ref().map(|spam| spam.verdict.as_str()),
+                "spam_reason"  => entry.spam.as_ref().and_then(|spam| spam.reason.as_deref()),
+                "edit_token_hash" => edit_token_hash,
+                "parent_id"    => parent_id,
+            }
+        ).await?;
+
+        let new_entry_id: String = match conn.query_first(
+            "SELECT LAST_INSERT_ID()").await? {
+            Some(id) => id,
+            None => { String::from("0") }
+        };
+
+        Ok(new_entry_id)
+    }
+
+    async fn author_token(&self, id: i64) -> Result<Option<AuthorToken>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let row: Option<(Option<String>, Option<NaiveDateTime>, String, bool, String, Option<String>)> = conn.exec_first(
+            "SELECT editTokenHash, dateSubmitted, status, deletedAt IS NOT NULL, guestName, guestNote FROM guestbook WHERE id = :id",
+            params! { "id" => id }
+        ).await?;
+
+        Ok(row.map(|(token_hash, posted, status, deleted, name, note)| AuthorToken::from_db(token_hash, posted, &status, deleted, name, note)))
+    }
+
+    async fn entries_with_status(&self, status: EntryStatus) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let entries = conn.exec_map(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId, spamVerdict, spamReason
+            FROM guestbook
+            WHERE status = :status AND deletedAt IS NULL
+            ORDER BY dateSubmitted, id",
+            params! { "status" => status.as_str() },
+            admin_entry_from_row
+        ).await?;
+
+        Ok(entries)
+    }
+
+    async fn set_status(&self, id: i64, status: EntryStatus) -> Result<bool, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        conn.exec_drop(
+            "UPDATE guestbook SET status = :status WHERE id = :id",
+            params! { "status" => status.as_str(), "id" => id }
+        ).await?;
+
+        updated_or_exists(&mut conn, id).await
+    }
+
+    async fn set_deleted(&self, id: i64, deleted_at: Option<NaiveDateTime>) -> Result<bool, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        match deleted_at {
+            Some(deleted_at) => conn.exec_drop(
+                "UPDATE guestbook SET deletedAt = COALESCE(deletedAt, :deleted_at) WHERE id = :id",
+                params! { "deleted_at" => deleted_at, "id" => id }
+            ).await?,
+            None => conn.exec_drop("UPDATE guestbook SET deletedAt = NULL WHERE id = :id", params! { "id" => id }).await?,
+        }
+
+        updated_or_exists(&mut conn, id).await
+    }
+
+    async fn deleted_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let entries = conn.exec_map(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId, spamVerdict, spamReason
+            FROM guestbook
+            WHERE deletedAt IS NOT NULL
+            ORDER BY deletedAt DESC, id DESC",
+            (),
+            admin_entry_from_row
+        ).await?;
+
+        Ok(entries)
+    }
+
+    async fn edit_entry(&self, id: i64, changes: &EntryChanges, review: Option<&EditReview>, edit_time: NaiveDateTime) -> Result<Option<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+
+        // FOR UPDATE, so two edits at once can't both save the same old name
+        let old: Option<(String, Option<String>)> = tx.exec_first(
+            "SELECT guestName, guestNote FROM guestbook WHERE id = :id FOR UPDATE",
+            params! { "id" => id }
+        ).await?;
+        let Some((old_name, old_note)) = old else { return Ok(None) };
+
+        tx.exec_drop(
+            "INSERT INTO guestbookEdit (entryId, editTime, oldName, oldNote) VALUES (:id, :edit_time, :old_name, :old_note)",
+            params! { "id" => id, "edit_time" => edit_time, "old_name" => old_name, "old_note" => old_note }
+        ).await?;
+        tx.exec_drop(
+            "
+            UPDATE guestbook
+            SET guestName = COALESCE(:name, guestName), guestNote = COALESCE(:note, guestNote)
+            WHERE id = :id",
+            params! { "name" => &changes.name, "note" => &changes.note, "id" => id }
+        ).await?;
+        if let Some(review) = review {
+            tx.exec_drop(
+                "UPDATE guestbook SET status = :status, spamVerdict = :spam_verdict, spamReason = :spam_reason WHERE id = :id",
+                params! {
+                    "status" => review.status.as_str(),
+                    "spam_verdict" => review.spam.verdict.as_str(),
+                    "spam_reason" => &review.spam.reason,
+                    "id" => id
+                }
+            ).await?;
+        }
+        let edited = tx.exec_first(
+            "SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId FROM guestbook WHERE id = :id",
+            params! { "id" => id }
+        ).await?;
+        tx.commit().await?;
+
+        Ok(edited.map(entry_from_row))
+    }
+
+    async fn entry_edits(&self, id: i64) -> Result<Option<Vec<EntryEdit>>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let found: Option<i64> = conn.exec_first("SELECT id FROM guestbook WHERE id = :id", params! { "id" => id }).await?;
+        if found.is_none() {
+            return Ok(None);
+        }
+        let edits = conn.exec_map(
+            "
+            SELECT editTime, oldName, oldNote
+            FROM guestbookEdit
+            WHERE entryId = :id
+            ORDER BY id",
+            params! { "id" => id },
+            |(edit_time, old_name, old_note): (NaiveDateTime, String, Option<String>)| {
+                EntryEdit { edit_time, old_name, old_note: old_note.unwrap_or_default() }
+            }
+        ).await?;
+
+        Ok(Some(edits))
+    }
+
+    async fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        conn.exec_drop(
+            "UPDATE guestbook SET pinned = :pinned WHERE id = :id",
+            params! { "pinned" => pinned, "id" => id }
+        ).await?;
+
+        updated_or_exists(&mut conn, id).await
+    }
+
+    // the no-op update leaves affected_rows() at 0 for one that's already there,
+    // without INSERT IGNORE turning every other error into a warning too
+    async fn add_reaction(&self, entry_id: i64, emoji: &str, fingerprint: &str, reacted_at: NaiveDateTime) -> Result<bool, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        conn.exec_drop(
+            r"
+            INSERT INTO guestbookReaction (entryId, emoji, visitorHash, reactedAt)
+            VALUES (:entry_id, :emoji, :visitor, :reacted_at)
+            ON DUPLICATE KEY UPDATE entryId = entryId",
+            params! { "entry_id" => entry_id, "emoji" => emoji, "visitor" => fingerprint, "reacted_at" => reacted_at }
+        ).await?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
+    async fn reaction_counts(&self, entry_id: Option<i64>) -> Result<Vec<ReactionTally>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let tallies = conn.exec_map(
+            r"
+            SELECT entryId, emoji, COUNT(*) FROM guestbookReaction
+            WHERE :entry_id IS NULL OR entryId = :entry_id
+            GROUP BY entryId, emoji
+            ORDER BY entryId, emoji",
+            params! { "entry_id" => entry_id },
+            |(entry_id, emoji, count): (i64, String, u64)| ReactionTally { entry_id, emoji, count }
+        ).await?;
+
+        Ok(tallies)
+    }
+}
+
+
+#[async_trait]
+impl HitStore for MySqlStore {
+
+    async fn hit_count(&self) -> Result<HitTotals, DbError> {
+        
+        let mut conn = self.pool.get_conn().await?;
+        
+        let (all, bots) = conn.query_first::<(u64, u64), &str>(
+            "SELECT total, bots FROM hitCounter WHERE id = 1"
+        ).await?.unwrap_or_default();
+
+        Ok(HitTotals { all, bots })
+    }
+
+    // This used to take a READ then a WRITE lock on hitLog, so the GET /hits
+    // right after the POST wouldn't get a count 1 behind the DB. The HitCounter
+    // takes care of that now, so all that's needed is a transaction.
+    async fn log_hits(&self, logged_hits: &[LoggedHit]) -> Result<HitTotals, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+
+        tx.exec_batch(
+            r"
+            INSERT INTO hitLog (
+                hitTime, userAgent, browser, browserVersion, os, device, isBot, 
+                path, referrer, utmSource, utmMedium, utmCampaign, country, region
+            ) 
+            VALUES (
+                :time_stamp, :user_agent, :browser, :browser_version, :os, :device, :is_bot, 
+                :path, :referrer, :utm_source, :utm_medium, :utm_campaign, :country, :region
+            )",
+            logged_hits.iter().map(|logged| {
+                let LoggedHit { hit, agent, .. } = logged;
+                params! {
+                    "time_stamp" => hit.time_stamp, 
+                    "user_agent" => &hit.user_agent,
+                    "browser" => &agent.browser,
+                    "browser_version" => &agent.browser_version,
+                    "os" => &agent.os,
+                    "device" => agent.device.as_str(),
+                    "is_bot" => agent.is_bot,
+                    "path" => &hit.path,
+                    "referrer" => &hit.referrer,
+                    "utm_source" => &hit.utm_source,
+                    "utm_medium" => &hit.utm_medium,
+                    "utm_campaign" => &hit.utm_campaign,
+                    "country" => logged.country(),
+                    "region" => logged.region(),
+                }
+            })
+        ).await?;
+
+        // VALUES() is deprecated in MySQL 8, but MariaDB doesn't have the new syntax
+        tx.exec_batch(
+            r"
+            INSERT INTO hitDaily (day, userAgent, hits, firstHit, lastHit) 
+            VALUES (:day, :user_agent, :hits, :first_hit, :last_hit)
+            ON DUPLICATE KEY UPDATE
+                hits = hits + VALUES(hits),
+                firstHit = LEAST(firstHit, VALUES(firstHit)),
+                lastHit = GREATEST(lastHit, VALUES(lastHit))",
+            hit_stats::roll_up(logged_hits).into_iter().map(|rollup| params! {
+                "day" => rollup.day,
+                "user_agent" => rollup.user_agent,
+                "hits" => rollup.hits,
+                "first_hit" => rollup.first_hit,
+                "last_hit" => rollup.last_hit,
+            })
+        ).await?;
+
+        // MySQL has no DO NOTHING, and INSERT IGNORE would hide 
+        // more than duplicate keys, so this "updates" it to itself
+        tx.exec_batch(
+            r"
+            INSERT INTO visitorDaily (day, visitorHash) VALUES (:day, :hash)
+            ON DUPLICATE KEY UPDATE visitorHash = visitorHash",
+            logged_hits.iter().filter_map(|logged| logged.visitor.as_ref()).map(|visitor| params! {
+                "day" => visitor.day,
+                "hash" => &visitor.hash,
+            })
+        ).await?;
+
+        // the UPDATE locks the row until the commit, so
+        // replicas flushing at the same time take turns here
+        let added = HitTotals::of(logged_hits);
+        tx.exec_drop(
+            "UPDATE hitCounter SET total = total + :n_hits, bots = bots + :n_bots WHERE id = 1",
+            params! { "n_hits" => added.all, "n_bots" => added.bots }
+        ).await?;
+        let (all, bots) = tx.query_first::<(u64, u64), &str>(
+            "SELECT total, bots FROM hitCounter WHERE id = 1"
+        ).await?.unwrap_or_default();
+
+        tx.commit().await?;
+        debug!("flushed {} hit(s) to hitLog", logged_hits.len());
+
+        Ok(HitTotals { all, bots })
+    }
+
+    // SUM() gives back a DECIMAL in MySQL, hence the casts
+    async fn hit_stats(&self, query: &StatsQuery) -> Result<HitStats, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let counts: Vec<(NaiveDateTime, u64)> = if query.bucket == StatsBucket::Hour {
+            let (start, until) = query.time_range();
+            conn.exec(
+                r"
+                SELECT DATE_ADD(DATE(hitTime), INTERVAL HOUR(hitTime) HOUR) AS hour, COUNT(*)
+                FROM hitLog
+                WHERE hitTime >= :start AND hitTime < :until
+                GROUP BY hour",
+                params! { "start" => start, "until" => until }
+            ).await?
+        } else {
+            conn.exec_map(
+                r"
+                SELECT day, CAST(SUM(hits) AS UNSIGNED) 
+                FROM hitDaily 
+                WHERE day BETWEEN :from AND :to 
+                GROUP BY day",
+                params! { "from" => query.from, "to" => query.to },
+                |(day, hits): (NaiveDate, u64)| (day.and_time(NaiveTime::MIN), hits)
+            ).await?
+        };
+
+        let top_user_agents = conn.exec_map(
+            r"
+            SELECT userAgent, CAST(SUM(hits) AS UNSIGNED) AS total
+            FROM hitDaily
+            WHERE day BETWEEN :from AND :to
+            GROUP BY userAgent
+            ORDER BY total DESC, userAgent
+            LIMIT :top_agents",
+            params! { "from" => query.from, "to" => query.to, "top_agents" => query.top_agents },
+            |(user_agent, hits)| UserAgentHits { user_agent, hits }
+        ).await?;
+
+        let (first_visit, last_visit) = conn.exec_first(
+            r"SELECT MIN(firstHit), MAX(lastHit) FROM hitDaily WHERE day BETWEEN :from AND :to",
+            params! { "from" => query.from, "to" => query.to }
+        ).await?.unwrap_or((None, None));
+
+        Ok(hit_stats::assemble(query, counts, top_user_agents, first_visit, last_visit))
+    }
+
+    async fn top_hits(&self, query: &TopQuery) -> Result<Vec<(String, u64)>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        let (start, until) = query.time_range();
+        let column = query.by.column();
+        let top = conn.exec(
+            format!(r"
+                SELECT {column}, COUNT(*) AS hits
+                FROM hitLog
+                WHERE hitTime >= :start AND hitTime < :until AND {column} IS NOT NULL
+                GROUP BY {column}
+                ORDER BY hits DESC, {column}
+                LIMIT :top"
+            ),
+            params! { "start" => start, "until" => until, "top" => query.top }
+        ).await?;
+
+        Ok(top)
+    }
+
+    async fn visitor_salts(&self, fresh: &[DailySalt]) -> Result<Vec<DailySalt>, DbError> {
+
+        let Some(first_day) = fresh.first().map(|salt| salt.day) else { return Ok(Vec::new()) };
+
+        let mut conn = self.pool.get_conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+
+        tx.exec_drop("DELETE FROM visitorSalt WHERE day < :first_day", params! { "first_day" => first_day }).await?;
+        tx.exec_batch(
+            r"
+            INSERT INTO visitorSalt (day, salt) VALUES (:day, :salt)
+            ON DUPLICATE KEY UPDATE salt = salt",
+            fresh.iter().map(|DailySalt { day, salt }| params! { "day" => day, "salt" => salt })
+        ).await?;
+        let salts = tx.exec_map(
+            "SELECT day, salt FROM visitorSalt WHERE day >= :first_day ORDER BY day",
+            params! { "first_day" => first_day },
+            |(day, salt)| DailySalt { day, salt }
+        ).await?;
+
+        tx.commit().await?;
+        Ok(salts)
+    }
+
+    async fn unique_visitors(&self, since: NaiveDate) -> Result<Vec<(NaiveDate, u64)>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+        let counts = conn.exec(
+            "SELECT day, COUNT(*) FROM visitorDaily WHERE day >= :since GROUP BY day ORDER BY day",
+            params! { "since" => since }
+        ).await?;
+
+        Ok(counts)
+    }
+}
+
+
+#[async_trait]
+impl AuditStore for MySqlStore {
+
+    async fn log_event(&self, event: &AuditEvent) -> Result<(), DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        conn.exec_drop(
+            r"INSERT INTO adminAudit (eventTime, action, clientIp, entryId, detail)
+                    VALUES (:time_stamp, :action, :client_ip, :entry_id, :detail)",
+            params! {
+                "time_stamp" => event.time_stamp,
+                "action"     => event.action.as_str(),
+                "client_ip"  => &event.client_ip,
+                "entry_id"   => audit_entry_id(event),
+                "detail"     => &event.detail,
+            }
+        ).await?;
+
+        Ok(())
+    }
+
+    async fn recent_events(&self, limit: u64) -> Result<Vec<AuditEvent>, DbError> {
+
+        let mut conn = self.pool.get_conn().await?;
+
+        let rows: Vec<(NaiveDateTime, String, String, Option<i64>, Option<String>)> = conn.exec(
+            "
+            SELECT eventTime, action, clientIp, entryId, detail
+            FROM adminAudit
+            ORDER BY id DESC
+            LIMIT :limit",
+            params! { "limit" => limit }
+        ).await?;
+
+        Ok(rows.into_iter()
+            .filter_map(|(time_stamp, action, client_ip, entry_id, detail)| Some(AuditEvent {
+                time_stamp,
+                action: AuditAction::from_db(&action)?,
+                client_ip,
+                entry_id: entry_id.map(|id| id.to_string()),
+                detail,
+            }))
+            .collect())
+    }
+}
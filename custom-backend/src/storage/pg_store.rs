@@ -0,0 +1,741 @@
+use async_trait::async_trait;
+use bb8::{Pool, RunError};
+use bb8_postgres::PostgresConnectionManager;
+use tokio_postgres::NoTls;
+use mysql_common::chrono::{NaiveDate, NaiveDateTime, NaiveTime, SubsecRound, Utc};
+use tracing::{debug, info};
+use crate::{
+    srv_io::db_io::DbError,
+    types::db_io_types::{
+        AuditAction, AuditEvent, EntryChanges, EntryEdit, EntryStatus, GuestbookEntry, HitStats,
+        SpamDecision, StatsBucket, UserAgentHits
+    },
+};
+use super::{
+    entry_pages::EntryPageQuery,
+    entry_search::{like_pattern, SearchQuery, MAX_CANDIDATES},
+    hit_stats::{self, StatsQuery, TopQuery},
+    HitTotals, LoggedHit,
+    migrations::{Direction, Migration, PG_MIGRATIONS},
+    self_check::{expected_privileges, ColumnInfo},
+    visitors::DailySalt,
+    audit_entry_id, AuditStore, AuthorToken, Database, DbPoolConfig, EditReview, GuestbookStore, HitStore, ReactionTally,
+};
+
+
+const MIGRATIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations
+    (
+        version         INTEGER PRIMARY KEY,
+        name            VARCHAR(100) NOT NULL,
+        appliedAt       TIMESTAMP NOT NULL
+    );";
+
+
+pub struct PgStore {
+    pool: Pool<PostgresConnectionManager<NoTls>>,
+}
+
+impl PgStore {
+
+    // Unlike MySqlStore::new(), this opens the pool's minimum number 
+    // of connections before returning, so there's no separate warm-up.
+    pub async fn connect(url: &str, pool_cfg: &DbPoolConfig) -> Result<PgStore, DbError> {
+
+        debug!("DB URL to connect with: {url}");
+
+        let manager = PostgresConnectionManager::new_from_stringlike(url, NoTls)?;
+
+        // bb8 doesn't take 0 to mean "never" like the other pool does, 
+        // so it's turned into None here
+        let idle_timeout = Some(pool_cfg.idle_timeout).filter(|t| !t.is_zero());
+
+        let pool = Pool::builder()
+            .max_size(pool_cfg.max_conns as u32)
+            .min_idle(pool_cfg.min_conns as u32)
+            .idle_timeout(idle_timeout)
+            .max_lifetime(pool_cfg.conn_lifetime)
+            .build(manager)
+            .await?;
+
+        info!("Database pool warmed up with {} connection(s).", pool.state().connections);
+
+        Ok(PgStore { pool })
+    }
+
+    // only for tests that need a clean slate; don't point them at anything important!
+    #[cfg(test)]
+    pub(crate) async fn clear_tables(&self) -> Result<(), DbError> {
+        self.pool.get().await?
+            .batch_execute("TRUNCATE guestbook, guestbookEdit, guestbookReaction, hitLog, hitDaily, visitorSalt, visitorDaily, adminAudit RESTART IDENTITY; UPDATE hitCounter SET total = 0, bots = 0")
+            .await?;
+        Ok(())
+    }
+}
+
+
+// the totals only ever go up from 0
+fn totals_from_row(row: &tokio_postgres::Row) -> HitTotals {
+    HitTotals { all: row.get::<_, i64>(0) as u64, bots: row.get::<_, i64>(1) as u64 }
+}
+
+
+impl From<RunError<tokio_postgres::Error>> for DbError {
+    fn from(pool_err: RunError<tokio_postgres::Error>) -> Self {
+        match pool_err {
+            RunError::User(pg_err) => Self::PgError(pg_err),
+            RunError::TimedOut     => Self::PoolTimedOut,
+        }
+    }
+}
+
+
+#[async_trait]
+impl Database for PgStore {
+
+    fn backend_name(&self) -> &'static str { "PostgreSQL" }
+
+    fn migrations(&self) -> &'static [Migration] { PG_MIGRATIONS }
+
+    async fn applied_versions(&self) -> Result<Vec<u32>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        // checked first, since the server's DB user may not be allowed
+        // to create it, and doesn't need to once everything's applied
+        let table_exists: bool = conn.query_one(
+            "SELECT to_regclass('schema_migrations') IS NOT NULL", &[]
+        ).await?.get(0);
+        if !table_exists {
+            return Ok(Vec::new());
+        }
+
+        let rows = conn.query("SELECT version FROM schema_migrations ORDER BY version", &[]).await?;
+
+        // versions are never negative
+        Ok(rows.iter().map(|row| row.get::<_, i32>(0) as u32).collect())
+    }
+
+    // DDL is transactional in Postgres too, so a failed migration 
+    // is rolled back as a whole
+    async fn run_migration(&self, migration: &'static Migration, direction: Direction) -> Result<(), DbError> {
+
+        let mut conn = self.pool.get().await?;
+        let tx = conn.transaction().await?;
+        tx.batch_execute(MIGRATIONS_TABLE).await?;
+
+        let version = migration.version as i32;
+        match direction {
+            Direction::Up => {
+                tx.batch_execute(migration.up).await?;
+                tx.execute(
+                    "INSERT INTO schema_migrations (version, name, appliedAt) VALUES ($1, $2, $3)",
+                    &[&version, &migration.name, &Utc::now().naive_utc().trunc_subsecs(0)]
+                ).await?;
+            },
+            Direction::Down => {
+                tx.batch_execute(migration.down).await?;
+                tx.execute("DELETE FROM schema_migrations WHERE version = $1", &[&version]).await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        // the information_schema columns have their own types, 
+        // which tokio_postgres can't read into Rust ones as is
+        let rows = conn.query(
+            "
+            SELECT column_name::text, data_type::text, character_maximum_length::int4 
+            FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1
+            ORDER BY ordinal_position",
+            &[&table.to_lowercase()]
+        ).await?;
+
+        let columns = rows.iter()
+            .map(|row| ColumnInfo {
+                name: row.get(0),
+                data_type: row.get(1),
+                max_chars: row.get::<_, Option<i32>>(2).map(|len| len as u64),
+            })
+            .collect();
+
+        Ok(columns)
+    }
+
+    // the sequences are what hand out the IDs, so INSERT needs them too
+    async fn missing_privileges(&self) -> Result<Vec<String>, DbError> {
+
+        let conn = self.pool.get().await?;
+        let mut missing = Vec::new();
+
+        for (table, privileges) in expected_privileges() {
+            let table_lower = table.to_lowercase();
+
+            for privilege in privileges.iter() {
+                let granted: bool = conn.query_one(
+                    "SELECT has_table_privilege($1, $2)", &[&table_lower, privilege]
+                ).await?.get(0);
+                if !granted {
+                    missing.push(format!("{privilege} on {table}"));
+                }
+            }
+
+            // inserts also need the sequence behind a SERIAL column, if the table has one
+            if privileges.contains(&"INSERT") {
+                let granted: bool = conn.query_one(
+                    "
+                    SELECT COALESCE(bool_and(has_sequence_privilege(pg_get_serial_sequence($1, column_name), 'USAGE')), true)
+                    FROM information_schema.columns
+                    WHERE table_schema = current_schema() AND table_name = $1 AND column_default LIKE 'nextval(%'",
+                    &[&table_lower]
+                ).await?.get(0);
+                if !granted {
+                    missing.push(format!("USAGE on {table}'s id sequence"));
+                }
+            }
+        }
+
+        Ok(missing)
+    }
+}
+
+
+fn entry_from_row(row: &tokio_postgres::Row) -> GuestbookEntry {
+    GuestbookEntry {
+        id: Some(row.get::<_, i32>(0).to_string()),
+        time_stamp: row.get(1),
+        name: row.get(2),
+        note: row.get::<_, Option<String>>(3).unwrap_or_default(),
+        pinned: row.get::<_, bool>(4).then_some(true),
+        spam: None,
+        parent_id: row.get::<_, Option<i32>>(5).map(|id| id.to_string()),
+        replies: None,
+        reactions: None,
+    }
+}
+
+// the same, with what the spam filter made of it after the rest, for the admin
+fn admin_entry_from_row(row: &tokio_postgres::Row) -> GuestbookEntry {
+    GuestbookEntry {
+        spam: SpamDecision::from_db(row.get(6), row.get(7)),
+        ..entry_from_row(row)
+    }
+}
+
+#[async_trait]
+impl GuestbookStore for PgStore {
+
+    async fn get_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let rows = conn.query(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+            FROM guestbook
+            WHERE status = 'approved' AND deletedAt IS NULL
+            ORDER BY pinned DESC, dateSubmitted DESC",
+            &[]
+        ).await?;
+
+        Ok(rows.iter().map(entry_from_row).collect())
+    }
+
+    async fn get_page(&self, query: &EntryPageQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let conn = self.pool.get().await?;
+        let direction = query.direction();
+
+        // id is an INT4, so the casts are for the i64's
+        let rows = conn.query(
+            &format!("
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+                FROM guestbook
+                WHERE status = 'approved' AND deletedAt IS NULL AND parentId IS NULL
+                    AND (dateSubmitted, id) > ($1, $2::INT8)
+                    AND (dateSubmitted, id) < ($3, $4::INT8)
+                ORDER BY dateSubmitted {direction}, id {direction}
+                LIMIT $5"
+            ),
+            &[&query.after.time, &query.after.id, &query.before.time, &query.before.id, &(query.limit as i64)]
+        ).await?;
+
+        Ok(rows.iter().map(entry_from_row).collect())
+    }
+
+    async fn get_replies(&self, parent_ids: &[i64]) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let rows = conn.query(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+            FROM guestbook
+            WHERE status = 'approved' AND deletedAt IS NULL AND parentId = ANY($1::INT8[])
+            ORDER BY dateSubmitted, id",
+            &[&parent_ids]
+        ).await?;
+
+        Ok(rows.iter().map(entry_from_row).collect())
+    }
+
+    async fn search_entries(&self, query: &SearchQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        // the expression has to be the same as the one guestbook_search indexes
+        let patterns: Vec<String> = query.terms.iter().map(|term| like_pattern(term)).collect();
+        let conditions: Vec<String> = (1..=patterns.len())
+            .map(|n| format!("(guestName || ' ' || COALESCE(guestNote, '')) ILIKE ${n}"))
+            .collect();
+        let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = patterns.iter()
+            .map(|pattern| pattern as &(dyn tokio_postgres::types::ToSql + Sync))
+            .collect();
+        let limit = MAX_CANDIDATES as i64;
+        params.push(&limit);
+
+        let rows = conn.query(
+            &format!("
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+                FROM guestbook
+                WHERE status = 'approved' AND deletedAt IS NULL AND {}
+                ORDER BY dateSubmitted DESC, id DESC
+                LIMIT ${}",
+                conditions.join(" AND "), params.len()
+            ),
+            &params
+        ).await?;
+
+        Ok(rows.iter().map(entry_from_row).collect())
+    }
+
+    async fn get_entry(&self, id: i64) -> Result<Option<GuestbookEntry>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let row = conn.query_opt(
+            "SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId FROM guestbook WHERE id = $1::INT8 AND status = 'approved' AND deletedAt IS NULL",
+            &[&id]
+        ).await?;
+
+        Ok(row.as_ref().map(entry_from_row))
+    }
+
+    async fn add_entry(&self, entry: &GuestbookEntry, status: EntryStatus, edit_token_hash: Option<&str>) -> Result<String, DbError> {
+
+        let conn = self.pool.get().await?;
+        let parent_id = entry.parent_id.as_deref().and_then(|id| id.parse::<i64>().ok());
+
+        let row = conn.query_one(
+            "INSERT INTO guestbook (dateSubmitted, guestName, guestNote, status, spamVerdict, spamReason, editTokenHash, parentId) 
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8::INT8) 
+                RETURNING id",
+            &[
+                &entry.time_stamp, &entry.name, &entry.note, &status.as_str(),
+                &entry.spam.as_ref().map(|spam| spam.verdict.as_str()),
+                &entry.spam.as_ref().and_then(|spam| spam.reason.as_deref()),
+                &edit_token_hash,
+                &parent_id,
+            ]
+        ).await?;
+
+        Ok(row.get::<_, i32>(0).to_string())
+    }
+
+    async fn author_token(&self, id: i64) -> Result<Option<AuthorToken>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let row = conn.query_opt(
+            "SELECT editTokenHash, dateSubmitted, status, deletedAt IS NOT NULL, guestName, guestNote FROM guestbook WHERE id = $1::INT8",
+            &[&id]
+        ).await?;
+
+        Ok(row.map(|row| AuthorToken::from_db(row.get(0), row.get(1), row.get(2), row.get(3), row.get(4), row.get(5))))
+    }
+
+    async fn entries_with_status(&self, status: EntryStatus) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let rows = conn.query(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId, spamVerdict, spamReason
+            FROM guestbook
+            WHERE status = $1 AND deletedAt IS NULL
+            ORDER BY dateSubmitted, id",
+            &[&status.as_str()]
+        ).await?;
+
+        Ok(rows.iter().map(admin_entry_from_row).collect())
+    }
+
+    async fn set_status(&self, id: i64, status: EntryStatus) -> Result<bool, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let updated = conn.execute(
+            "UPDATE guestbook SET status = $1 WHERE id = $2::INT8",
+            &[&status.as_str(), &id]
+        ).await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn set_deleted(&self, id: i64, deleted_at: Option<NaiveDateTime>) -> Result<bool, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let updated = match deleted_at {
+            Some(deleted_at) => conn.execute(
+                "UPDATE guestbook SET deletedAt = COALESCE(deletedAt, $1) WHERE id = $2::INT8",
+                &[&deleted_at, &id]
+            ).await?,
+            None => conn.execute("UPDATE guestbook SET deletedAt = NULL WHERE id = $1::INT8", &[&id]).await?,
+        };
+
+        Ok(updated > 0)
+    }
+
+    async fn deleted_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let rows = conn.query(
+            "
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId, spamVerdict, spamReason
+            FROM guestbook
+            WHERE deletedAt IS NOT NULL
+            ORDER BY deletedAt DESC, id DESC",
+            &[]
+        ).await?;
+
+        Ok(rows.iter().map(admin_entry_from_row).collect())
+    }
+
+    async fn edit_entry(&self, id: i64, changes: &EntryChanges, review: Option<&EditReview>, edit_time: NaiveDateTime) -> Result<Option<GuestbookEntry>, DbError> {
+
+        let mut conn = self.pool.get().await?;
+        let tx = conn.transaction().await?;
+
+        // FOR UPDATE, so two edits at once can't both save the same old name
+        let inserted = tx.execute(
+            "
+            INSERT INTO guestbookEdit (entryId, editTime, oldName, oldNote)
+            SELECT id, $1::TIMESTAMP, guestName, guestNote FROM guestbook WHERE id = $2::INT8 FOR UPDATE",
+            &[&edit_time, &id]
+        ).await?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+        let row = tx.query_one(
+            "
+            UPDATE guestbook
+            SET guestName = COALESCE($1, guestName), guestNote = COALESCE($2, guestNote)
+            WHERE id = $3::INT8
+            RETURNING id, dateSubmitted, guestName, guestNote, pinned, parentId",
+            &[&changes.name, &changes.note, &id]
+        ).await?;
+        if let Some(review) = review {
+            tx.execute(
+                "UPDATE guestbook SET status = $1, spamVerdict = $2, spamReason = $3 WHERE id = $4::INT8",
+                &[&review.status.as_str(), &review.spam.verdict.as_str(), &review.spam.reason, &id]
+            ).await?;
+        }
+        tx.commit().await?;
+
+        Ok(Some(entry_from_row(&row)))
+    }
+
+    async fn entry_edits(&self, id: i64) -> Result<Option<Vec<EntryEdit>>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        if conn.query_opt("SELECT id FROM guestbook WHERE id = $1::INT8", &[&id]).await?.is_none() {
+            return Ok(None);
+        }
+        let rows = conn.query(
+            "
+            SELECT editTime, oldName, oldNote
+            FROM guestbookEdit
+            WHERE entryId = $1::INT8
+            ORDER BY id",
+            &[&id]
+        ).await?;
+
+        Ok(Some(rows.iter().map(|row| EntryEdit {
+            edit_time: row.get(0),
+            old_name: row.get(1),
+            old_note: row.get::<_, Option<String>>(2).unwrap_or_default(),
+        }).collect()))
+    }
+
+    async fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let updated = conn.execute("UPDATE guestbook SET pinned = $1 WHERE id = $2::INT8", &[&pinned, &id]).await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn add_reaction(&self, entry_id: i64, emoji: &str, fingerprint: &str, reacted_at: NaiveDateTime) -> Result<bool, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let added = conn.execute(
+            "INSERT INTO guestbookReaction (entryId, emoji, visitorHash, reactedAt) 
+                VALUES ($1::INT8, $2, $3, $4) 
+                ON CONFLICT DO NOTHING",
+            &[&entry_id, &emoji, &fingerprint, &reacted_at]
+        ).await?;
+
+        Ok(added > 0)
+    }
+
+    async fn reaction_counts(&self, entry_id: Option<i64>) -> Result<Vec<ReactionTally>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        // COLLATE "C" so the emoji come back in the same order as on the other backends
+        let rows = conn.query(
+            r#"SELECT entryId, emoji, COUNT(*) FROM guestbookReaction
+                WHERE $1::INT8 IS NULL OR entryId = $1::INT8
+                GROUP BY entryId, emoji
+                ORDER BY entryId, emoji COLLATE "C""#,
+            &[&entry_id]
+        ).await?;
+
+        Ok(rows.iter().map(|row| ReactionTally {
+            entry_id: i64::from(row.get::<_, i32>(0)),
+            emoji: row.get(1),
+            count: row.get::<_, i64>(2) as u64,
+        }).collect())
+    }
+}
+
+
+#[async_trait]
+impl HitStore for PgStore {
+
+    async fn hit_count(&self) -> Result<HitTotals, DbError> {
+
+        let conn = self.pool.get().await?;
+        let row = conn.query_one("SELECT total, bots FROM hitCounter WHERE id = 1", &[]).await?;
+
+        Ok(totals_from_row(&row))
+    }
+
+    async fn log_hits(&self, logged_hits: &[LoggedHit]) -> Result<HitTotals, DbError> {
+
+        let mut conn = self.pool.get().await?;
+        let tx = conn.transaction().await?;
+
+        let insert = tx.prepare(
+            "
+            INSERT INTO hitLog (
+                hitTime, userAgent, browser, browserVersion, os, device, isBot, 
+                path, referrer, utmSource, utmMedium, utmCampaign, country, region
+            ) 
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)"
+        ).await?;
+        for logged in logged_hits {
+            let LoggedHit { hit, agent, .. } = logged;
+            tx.execute(&insert, &[
+                &hit.time_stamp, &hit.user_agent, 
+                &agent.browser, &agent.browser_version, &agent.os, &agent.device.as_str(), &agent.is_bot,
+                &hit.path, &hit.referrer, &hit.utm_source, &hit.utm_medium, &hit.utm_campaign,
+                &logged.country(), &logged.region()
+            ]).await?;
+        }
+
+        let upsert = tx.prepare(
+            "
+            INSERT INTO hitDaily (day, userAgent, hits, firstHit, lastHit) 
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (day, userAgent) DO UPDATE SET
+                hits = hitDaily.hits + excluded.hits,
+                firstHit = LEAST(hitDaily.firstHit, excluded.firstHit),
+                lastHit = GREATEST(hitDaily.lastHit, excluded.lastHit)"
+        ).await?;
+        for rollup in hit_stats::roll_up(logged_hits) {
+            tx.execute(&upsert, &[
+                &rollup.day, &rollup.user_agent, &(rollup.hits as i64), &rollup.first_hit, &rollup.last_hit
+            ]).await?;
+        }
+
+        let insert = tx.prepare(
+            "INSERT INTO visitorDaily (day, visitorHash) VALUES ($1, $2) ON CONFLICT DO NOTHING"
+        ).await?;
+        for visitor in logged_hits.iter().filter_map(|logged| logged.visitor.as_ref()) {
+            tx.execute(&insert, &[&visitor.day, &visitor.hash]).await?;
+        }
+
+        // the UPDATE locks the row until the commit, so
+        // replicas flushing at the same time take turns here
+        let added = HitTotals::of(logged_hits);
+        let row = tx.query_one(
+            "UPDATE hitCounter SET total = total + $1, bots = bots + $2 WHERE id = 1 RETURNING total, bots",
+            &[&(added.all as i64), &(added.bots as i64)]
+        ).await?;
+        tx.commit().await?;
+
+        Ok(totals_from_row(&row))
+    }
+
+    // SUM() of a BIGINT is a NUMERIC in Postgres, hence the casts back
+    async fn hit_stats(&self, query: &StatsQuery) -> Result<HitStats, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let counts: Vec<(NaiveDateTime, u64)> = if query.bucket == StatsBucket::Hour {
+            let (start, until) = query.time_range();
+            conn.query(
+                "
+                SELECT date_trunc('hour', hitTime), COUNT(*)
+                FROM hitLog
+                WHERE hitTime >= $1 AND hitTime < $2
+                GROUP BY 1",
+                &[&start, &until]
+            ).await?
+                .iter()
+                .map(|row| (row.get(0), row.get::<_, i64>(1) as u64))
+                .collect()
+        } else {
+            conn.query(
+                "SELECT day, SUM(hits)::BIGINT FROM hitDaily WHERE day BETWEEN $1 AND $2 GROUP BY day",
+                &[&query.from, &query.to]
+            ).await?
+                .iter()
+                .map(|row| (row.get::<_, NaiveDate>(0).and_time(NaiveTime::MIN), row.get::<_, i64>(1) as u64))
+                .collect()
+        };
+
+        let top_user_agents = conn.query(
+            "
+            SELECT userAgent, SUM(hits)::BIGINT 
+            FROM hitDaily
+            WHERE day BETWEEN $1 AND $2
+            GROUP BY userAgent
+            ORDER BY 2 DESC, userAgent
+            LIMIT $3",
+            &[&query.from, &query.to, &(query.top_agents as i64)]
+        ).await?
+            .iter()
+            .map(|row| UserAgentHits { user_agent: row.get(0), hits: row.get::<_, i64>(1) as u64 })
+            .collect();
+
+        let row = conn.query_one(
+            "SELECT MIN(firstHit), MAX(lastHit) FROM hitDaily WHERE day BETWEEN $1 AND $2",
+            &[&query.from, &query.to]
+        ).await?;
+
+        Ok(hit_stats::assemble(query, counts, top_user_agents, row.get(0), row.get(1)))
+    }
+
+    async fn top_hits(&self, query: &TopQuery) -> Result<Vec<(String, u64)>, DbError> {
+
+        let conn = self.pool.get().await?;
+        let (start, until) = query.time_range();
+        let column = query.by.column();
+        let top = conn.query(
+            &format!("
+                SELECT {column}, COUNT(*)
+                FROM hitLog
+                WHERE hitTime >= $1 AND hitTime < $2 AND {column} IS NOT NULL
+                GROUP BY {column}
+                ORDER BY 2 DESC, {column}
+                LIMIT $3"
+            ),
+            &[&start, &until, &(query.top as i64)]
+        ).await?
+            .iter()
+            .map(|row| (row.get(0), row.get::<_, i64>(1) as u64))
+            .collect();
+
+        Ok(top)
+    }
+
+    async fn visitor_salts(&self, fresh: &[DailySalt]) -> Result<Vec<DailySalt>, DbError> {
+
+        let Some(first_day) = fresh.first().map(|salt| salt.day) else { return Ok(Vec::new()) };
+
+        let mut conn = self.pool.get().await?;
+        let tx = conn.transaction().await?;
+
+        tx.execute("DELETE FROM visitorSalt WHERE day < $1", &[&first_day]).await?;
+        let insert = tx.prepare("INSERT INTO visitorSalt (day, salt) VALUES ($1, $2) ON CONFLICT DO NOTHING").await?;
+        for DailySalt { day, salt } in fresh {
+            tx.execute(&insert, &[day, salt]).await?;
+        }
+        let salts = tx.query("SELECT day, salt FROM visitorSalt WHERE day >= $1 ORDER BY day", &[&first_day]).await?
+            .iter()
+            .map(|row| DailySalt { day: row.get(0), salt: row.get(1) })
+            .collect();
+        tx.commit().await?;
+
+        Ok(salts)
+    }
+
+    async fn unique_visitors(&self, since: NaiveDate) -> Result<Vec<(NaiveDate, u64)>, DbError> {
+
+        let conn = self.pool.get().await?;
+        let counts = conn.query(
+            "SELECT day, COUNT(*) FROM visitorDaily WHERE day >= $1 GROUP BY day ORDER BY day",
+            &[&since]
+        ).await?
+            .iter()
+            .map(|row| (row.get(0), row.get::<_, i64>(1) as u64))
+            .collect();
+
+        Ok(counts)
+    }
+}
+
+
+#[async_trait]
+impl AuditStore for PgStore {
+
+    async fn log_event(&self, event: &AuditEvent) -> Result<(), DbError> {
+
+        let conn = self.pool.get().await?;
+
+        conn.execute(
+            "INSERT INTO adminAudit (eventTime, action, clientIp, entryId, detail) VALUES ($1, $2, $3, $4::INT8, $5)",
+            &[&event.time_stamp, &event.action.as_str(), &event.client_ip, &audit_entry_id(event), &event.detail]
+        ).await?;
+
+        Ok(())
+    }
+
+    async fn recent_events(&self, limit: u64) -> Result<Vec<AuditEvent>, DbError> {
+
+        let conn = self.pool.get().await?;
+
+        let rows = conn.query(
+            "
+            SELECT eventTime, action, clientIp, entryId, detail
+            FROM adminAudit
+            ORDER BY id DESC
+            LIMIT $1::INT8",
+            &[&(limit as i64)]
+        ).await?;
+
+        Ok(rows.iter()
+            .filter_map(|row| Some(AuditEvent {
+                time_stamp: row.get(0),
+                action: AuditAction::from_db(row.get(1))?,
+                client_ip: row.get(2),
+                entry_id: row.get::<_, Option<i32>>(3).map(|id| id.to_string()),
+                detail: row.get(4),
+            }))
+            .collect())
+    }
+}
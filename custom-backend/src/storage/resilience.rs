@@ -0,0 +1,560 @@
+// Keeps the server usable while the database is down or slow to come up.
+//
+// At startup, connecting is retried with exponential backoff, since under k3s
+// (unlike Compose) nothing waits for the database to be healthy first.
+//
+// Once running, every guestbook and hit-log query goes through a GuardedStore,
+// which gives it a time limit, and counts failures in a circuit breaker. After
+// enough failures in a row, the breaker opens, and requests are turned away with
+// a 503 right away, instead of each one waiting on a database that isn't answering.
+// After a cooldown, one request is let through to test the waters: if it works,
+// the breaker closes again, and if it doesn't, it stays open for another cooldown.
+use std::{future::Future, sync::{Arc, Mutex, PoisonError}, time::Duration};
+use async_trait::async_trait;
+use mysql_common::chrono::{NaiveDate, NaiveDateTime};
+use tokio::time::{sleep, timeout, Instant};
+use tracing::{info, warn};
+use crate::{
+    srv_io::db_io::DbError,
+    types::db_io_types::{AuditEvent, EntryChanges, EntryEdit, EntryStatus, GuestbookEntry, HitStats},
+    utils::init_utils::get_env_var_or,
+};
+use super::{
+    entry_pages::EntryPageQuery, entry_search::SearchQuery, hit_stats::{StatsQuery, TopQuery}, visitors::DailySalt, open_stores, 
+    AuditStore, AuthorToken, DbPoolConfig, EditReview, GuestbookStore, HitStore, HitTotals, LoggedHit, ReactionTally, Stores
+};
+
+
+// the first wait between connection attempts, doubled after each one, up to the max
+const BACKOFF_START: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+// Read from the environment at startup, like DbPoolConfig
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbResilienceConfig {
+    pub connect_retries: u32,               // DB_CONNECT_RETRIES; retries after the first attempt
+    pub query_timeout: Option<Duration>,    // DB_QUERY_TIMEOUT_MS; 0 means no timeout
+    pub breaker_threshold: u32,             // DB_BREAKER_THRESHOLD; failures in a row that open the breaker
+    pub breaker_cooldown: Duration,         // DB_BREAKER_COOLDOWN_SECS; how long it stays open
+}
+
+impl Default for DbResilienceConfig {
+    fn default() -> DbResilienceConfig {
+        DbResilienceConfig {
+            connect_retries: 8,     // a bit over a minute and a half, all told
+            query_timeout: Some(Duration::from_secs(5)),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(15),
+        }
+    }
+}
+
+impl DbResilienceConfig {
+
+    pub fn from_env() -> DbResilienceConfig {
+
+        let defaults = DbResilienceConfig::default();
+
+        let query_timeout = match get_env_var_or(
+            "DB_QUERY_TIMEOUT_MS",
+            defaults.query_timeout.map_or(0, |d| d.as_millis() as u64)
+        ) {
+            0  => None,
+            ms => Some(Duration::from_millis(ms))
+        };
+
+        DbResilienceConfig {
+            connect_retries: get_env_var_or("DB_CONNECT_RETRIES", defaults.connect_retries),
+            query_timeout,
+            breaker_threshold: get_env_var_or("DB_BREAKER_THRESHOLD", defaults.breaker_threshold).max(1),
+            breaker_cooldown: Duration::from_secs(
+                get_env_var_or("DB_BREAKER_COOLDOWN_SECS", defaults.breaker_cooldown.as_secs())
+            ),
+        }
+    }
+}
+
+
+// open_stores(), but tried again on failure, waiting longer each time
+pub async fn connect_with_retry(
+    db_url: &str,
+    pool_cfg: &DbPoolConfig,
+    res_cfg: &DbResilienceConfig
+) -> Result<Stores, DbError> {
+
+    let mut wait = BACKOFF_START;
+    let mut attempt = 0;
+
+    loop {
+        match open_stores(db_url, pool_cfg).await {
+            Ok(stores) => return Ok(stores),
+            // no amount of waiting will fix these
+            Err(err @ (DbError::UnknownScheme(_) | DbError::UrlError(_))) => return Err(err),
+            Err(err) if attempt >= res_cfg.connect_retries => return Err(err),
+            Err(err) => {
+                attempt += 1;
+                warn!(
+                    "Could not connect to the database ({err}). Retrying in {wait:?} (retry {attempt} of {}).",
+                    res_cfg.connect_retries
+                );
+                sleep(wait).await;
+                wait = (wait * 2).min(BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    Closed { failures: u32 },   // failures in a row so far
+    Open { until: Instant },
+    // One request is out testing the database. If it never comes back
+    // (say, the client hung up), another one is let through after the deadline.
+    HalfOpen { deadline: Instant },
+}
+
+pub struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+
+    pub fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+            threshold,
+            cooldown,
+        }
+    }
+
+    // the state is just a Copy enum, so it can't be left half-updated
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Called before each query. An error means the query shouldn't be run.
+    fn allow(&self) -> Result<(), DbError> {
+
+        let now = Instant::now();
+        let mut state = self.lock();
+
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } | BreakerState::HalfOpen { deadline: until } if now < until => {
+                Err(DbError::Unavailable(until - now))
+            },
+            BreakerState::Open { .. } => {
+                info!("Database circuit breaker half-open; letting one request through to test the database.");
+                *state = BreakerState::HalfOpen { deadline: now + self.cooldown };
+                Ok(())
+            },
+            BreakerState::HalfOpen { .. } => {
+                *state = BreakerState::HalfOpen { deadline: now + self.cooldown };
+                Ok(())
+            }
+        }
+    }
+
+    // Called after each query that was allowed to run.
+    // Every kind of DbError counts as a failure; the ones that aren't about
+    // the database being down (like a bad query) would fail every time anyway.
+    fn record(&self, succeeded: bool) {
+
+        let mut state = self.lock();
+
+        *state = match (*state, succeeded) {
+            (BreakerState::Closed { .. }, true) => BreakerState::Closed { failures: 0 },
+            (_, true) => {
+                info!("Database circuit breaker closed; the database is back.");
+                BreakerState::Closed { failures: 0 }
+            },
+            (BreakerState::Closed { failures }, false) if failures + 1 < self.threshold => {
+                BreakerState::Closed { failures: failures + 1 }
+            },
+            (BreakerState::Closed { failures }, false) => {
+                warn!(
+                    "Database circuit breaker opened after {} failed queries in a row; \
+                    turning requests away for {:?}.",
+                    failures + 1, self.cooldown
+                );
+                BreakerState::Open { until: Instant::now() + self.cooldown }
+            },
+            (_, false) => {
+                warn!("Database still failing; circuit breaker open for another {:?}.", self.cooldown);
+                BreakerState::Open { until: Instant::now() + self.cooldown }
+            }
+        };
+    }
+}
+
+
+// Wraps a backend's stores with the query timeout and the circuit breaker.
+// All the stores share the one breaker, since they share the one database.
+pub struct GuardedStore {
+    guestbook: Arc<dyn GuestbookStore>,
+    hits: Arc<dyn HitStore>,
+    audit: Arc<dyn AuditStore>,
+    breaker: CircuitBreaker,
+    query_timeout: Option<Duration>,
+}
+
+impl GuardedStore {
+
+    pub fn new(stores: &Stores, res_cfg: &DbResilienceConfig) -> GuardedStore {
+        GuardedStore {
+            guestbook: stores.guestbook.clone(),
+            hits: stores.hits.clone(),
+            audit: stores.audit.clone(),
+            breaker: CircuitBreaker::new(res_cfg.breaker_threshold, res_cfg.breaker_cooldown),
+            query_timeout: res_cfg.query_timeout,
+        }
+    }
+
+    async fn guard<T>(&self, query: impl Future<Output = Result<T, DbError>>) -> Result<T, DbError> {
+
+        self.breaker.allow()?;
+
+        // the timeout covers waiting for a connection from the pool, too
+        let res = match self.query_timeout {
+            Some(limit) => timeout(limit, query).await.unwrap_or(Err(DbError::QueryTimedOut)),
+            None => query.await
+        };
+        self.breaker.record(res.is_ok());
+
+        res
+    }
+}
+
+
+#[async_trait]
+impl GuestbookStore for GuardedStore {
+
+    async fn get_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.get_entries()).await
+    }
+
+    async fn get_page(&self, query: &EntryPageQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.get_page(query)).await
+    }
+
+    async fn get_replies(&self, parent_ids: &[i64]) -> Result<Vec<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.get_replies(parent_ids)).await
+    }
+
+    async fn get_entry(&self, id: i64) -> Result<Option<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.get_entry(id)).await
+    }
+
+    async fn search_entries(&self, query: &SearchQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.search_entries(query)).await
+    }
+
+    async fn add_entry(&self, entry: &GuestbookEntry, status: EntryStatus, edit_token_hash: Option<&str>) -> Result<String, DbError> {
+        self.guard(self.guestbook.add_entry(entry, status, edit_token_hash)).await
+    }
+
+    async fn author_token(&self, id: i64) -> Result<Option<AuthorToken>, DbError> {
+        self.guard(self.guestbook.author_token(id)).await
+    }
+
+    async fn entries_with_status(&self, status: EntryStatus) -> Result<Vec<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.entries_with_status(status)).await
+    }
+
+    async fn set_status(&self, id: i64, status: EntryStatus) -> Result<bool, DbError> {
+        self.guard(self.guestbook.set_status(id, status)).await
+    }
+
+    async fn set_deleted(&self, id: i64, deleted_at: Option<NaiveDateTime>) -> Result<bool, DbError> {
+        self.guard(self.guestbook.set_deleted(id, deleted_at)).await
+    }
+
+    async fn deleted_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.deleted_entries()).await
+    }
+
+    async fn edit_entry(&self, id: i64, changes: &EntryChanges, review: Option<&EditReview>, edit_time: NaiveDateTime) -> Result<Option<GuestbookEntry>, DbError> {
+        self.guard(self.guestbook.edit_entry(id, changes, review, edit_time)).await
+    }
+
+    async fn entry_edits(&self, id: i64) -> Result<Option<Vec<EntryEdit>>, DbError> {
+        self.guard(self.guestbook.entry_edits(id)).await
+    }
+
+    async fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool, DbError> {
+        self.guard(self.guestbook.set_pinned(id, pinned)).await
+    }
+
+    async fn add_reaction(&self, entry_id: i64, emoji: &str, fingerprint: &str, reacted_at: NaiveDateTime) -> Result<bool, DbError> {
+        self.guard(self.guestbook.add_reaction(entry_id, emoji, fingerprint, reacted_at)).await
+    }
+
+    async fn reaction_counts(&self, entry_id: Option<i64>) -> Result<Vec<ReactionTally>, DbError> {
+        self.guard(self.guestbook.reaction_counts(entry_id)).await
+    }
+}
+
+
+#[async_trait]
+impl HitStore for GuardedStore {
+
+    async fn hit_count(&self) -> Result<HitTotals, DbError> {
+        self.guard(self.hits.hit_count()).await
+    }
+
+    async fn log_hits(&self, logged_hits: &[LoggedHit]) -> Result<HitTotals, DbError> {
+        self.guard(self.hits.log_hits(logged_hits)).await
+    }
+
+    async fn hit_stats(&self, query: &StatsQuery) -> Result<HitStats, DbError> {
+        self.guard(self.hits.hit_stats(query)).await
+    }
+
+    async fn top_hits(&self, query: &TopQuery) -> Result<Vec<(String, u64)>, DbError> {
+        self.guard(self.hits.top_hits(query)).await
+    }
+
+    async fn visitor_salts(&self, fresh: &[DailySalt]) -> Result<Vec<DailySalt>, DbError> {
+        self.guard(self.hits.visitor_salts(fresh)).await
+    }
+
+    async fn unique_visitors(&self, since: NaiveDate) -> Result<Vec<(NaiveDate, u64)>, DbError> {
+        self.guard(self.hits.unique_visitors(since)).await
+    }
+}
+
+
+#[async_trait]
+impl AuditStore for GuardedStore {
+
+    async fn log_event(&self, event: &AuditEvent) -> Result<(), DbError> {
+        self.guard(self.audit.log_event(event)).await
+    }
+
+    async fn recent_events(&self, limit: u64) -> Result<Vec<AuditEvent>, DbError> {
+        self.guard(self.audit.recent_events(limit)).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    // These run on tokio's paused clock, so the cooldowns and timeouts
+    // pass instantly (the clock jumps ahead whenever everything's waiting).
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use crate::storage::{mem_store::MemStore, Database};
+
+    // a MemStore that can be told to fail, or to hang
+    #[derive(Default)]
+    struct FlakyStore {
+        inner: MemStore,
+        failing: AtomicBool,
+        hanging: AtomicBool,
+        calls: AtomicUsize,
+    }
+
+    impl FlakyStore {
+        async fn act_up(&self) -> Result<(), DbError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.hanging.load(Ordering::SeqCst) {
+                sleep(Duration::from_secs(3600)).await;
+            }
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(DbError::PoolTimedOut);
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Database for FlakyStore {
+        fn backend_name(&self) -> &'static str { "flaky" }
+    }
+
+    #[async_trait]
+    impl GuestbookStore for FlakyStore {
+        async fn get_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.get_entries().await
+        }
+        async fn get_page(&self, query: &EntryPageQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.get_page(query).await
+        }
+        async fn get_replies(&self, parent_ids: &[i64]) -> Result<Vec<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.get_replies(parent_ids).await
+        }
+        async fn get_entry(&self, id: i64) -> Result<Option<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.get_entry(id).await
+        }
+        async fn search_entries(&self, query: &SearchQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.search_entries(query).await
+        }
+        async fn add_entry(&self, entry: &GuestbookEntry, status: EntryStatus, edit_token_hash: Option<&str>) -> Result<String, DbError> {
+            self.act_up().await?;
+            self.inner.add_entry(entry, status, edit_token_hash).await
+        }
+        async fn author_token(&self, id: i64) -> Result<Option<AuthorToken>, DbError> {
+            self.act_up().await?;
+            self.inner.author_token(id).await
+        }
+        async fn entries_with_status(&self, status: EntryStatus) -> Result<Vec<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.entries_with_status(status).await
+        }
+        async fn set_status(&self, id: i64, status: EntryStatus) -> Result<bool, DbError> {
+            self.act_up().await?;
+            self.inner.set_status(id, status).await
+        }
+        async fn set_deleted(&self, id: i64, deleted_at: Option<NaiveDateTime>) -> Result<bool, DbError> {
+            self.act_up().await?;
+            self.inner.set_deleted(id, deleted_at).await
+        }
+        async fn deleted_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.deleted_entries().await
+        }
+        async fn edit_entry(&self, id: i64, changes: &EntryChanges, review: Option<&EditReview>, edit_time: NaiveDateTime) -> Result<Option<GuestbookEntry>, DbError> {
+            self.act_up().await?;
+            self.inner.edit_entry(id, changes, review, edit_time).await
+        }
+        async fn entry_edits(&self, id: i64) -> Result<Option<Vec<EntryEdit>>, DbError> {
+            self.act_up().await?;
+            self.inner.entry_edits(id).await
+        }
+        async fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool, DbError> {
+            self.act_up().await?;
+            self.inner.set_pinned(id, pinned).await
+        }
+        async fn add_reaction(&self, entry_id: i64, emoji: &str, fingerprint: &str, reacted_at: NaiveDateTime) -> Result<bool, DbError> {
+            self.act_up().await?;
+            self.inner.add_reaction(entry_id, emoji, fingerprint, reacted_at).await
+        }
+        async fn reaction_counts(&self, entry_id: Option<i64>) -> Result<Vec<ReactionTally>, DbError> {
+            self.act_up().await?;
+            self.inner.reaction_counts(entry_id).await
+        }
+    }
+
+    #[async_trait]
+    impl HitStore for FlakyStore {
+        async fn hit_count(&self) -> Result<HitTotals, DbError> {
+            self.act_up().await?;
+            self.inner.hit_count().await
+        }
+        async fn log_hits(&self, logged_hits: &[LoggedHit]) -> Result<HitTotals, DbError> {
+            self.act_up().await?;
+            self.inner.log_hits(logged_hits).await
+        }
+        async fn hit_stats(&self, query: &StatsQuery) -> Result<HitStats, DbError> {
+            self.act_up().await?;
+            self.inner.hit_stats(query).await
+        }
+        async fn top_hits(&self, query: &TopQuery) -> Result<Vec<(String, u64)>, DbError> {
+            self.act_up().await?;
+            self.inner.top_hits(query).await
+        }
+        async fn visitor_salts(&self, fresh: &[DailySalt]) -> Result<Vec<DailySalt>, DbError> {
+            self.act_up().await?;
+            self.inner.visitor_salts(fresh).await
+        }
+        async fn unique_visitors(&self, since: NaiveDate) -> Result<Vec<(NaiveDate, u64)>, DbError> {
+            self.act_up().await?;
+            self.inner.unique_visitors(since).await
+        }
+    }
+
+    fn guarded_flaky() -> (GuardedStore, Arc<FlakyStore>) {
+        let flaky = Arc::new(FlakyStore::default());
+        let stores = Stores { db: flaky.clone(), guestbook: flaky.clone(), hits: flaky.clone(), audit: Arc::new(MemStore::new()) };
+        let res_cfg = DbResilienceConfig {
+            connect_retries: 0,
+            query_timeout: Some(Duration::from_secs(2)),
+            breaker_threshold: 3,
+            breaker_cooldown: Duration::from_secs(10),
+        };
+        (GuardedStore::new(&stores, &res_cfg), flaky)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn breaker_opens_and_recovers() {
+        let (guarded, flaky) = guarded_flaky();
+
+        flaky.failing.store(true, Ordering::SeqCst);
+        for _ in 0..3 {
+            assert!(matches!(guarded.hit_count().await, Err(DbError::PoolTimedOut)));
+        }
+
+        // open now, so the store isn't even asked
+        let Err(DbError::Unavailable(retry_after)) = guarded.get_entries().await
+        else { panic!("the breaker should be open") };
+        assert_eq!(retry_after, Duration::from_secs(10));
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 3);
+
+        // the test request after the cooldown fails, so it's open for another cooldown
+        sleep(Duration::from_secs(10)).await;
+        assert!(matches!(guarded.hit_count().await, Err(DbError::PoolTimedOut)));
+        assert!(matches!(guarded.hit_count().await, Err(DbError::Unavailable(_))));
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 4);
+
+        // then the database comes back
+        flaky.failing.store(false, Ordering::SeqCst);
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(guarded.hit_count().await.unwrap().all, 0);
+        assert_eq!(guarded.hit_count().await.unwrap().all, 0);
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn successes_reset_the_count() {
+        let (guarded, flaky) = guarded_flaky();
+
+        for _ in 0..5 {
+            flaky.failing.store(true, Ordering::SeqCst);
+            assert!(guarded.hit_count().await.is_err());
+            assert!(guarded.hit_count().await.is_err());
+            flaky.failing.store(false, Ordering::SeqCst);
+            assert!(guarded.hit_count().await.is_ok());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_queries_time_out() {
+        let (guarded, flaky) = guarded_flaky();
+        flaky.hanging.store(true, Ordering::SeqCst);
+
+        let started = Instant::now();
+        assert!(matches!(guarded.hit_count().await, Err(DbError::QueryTimedOut)));
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+
+        // timeouts count as failures too
+        assert!(guarded.hit_count().await.is_err());
+        assert!(guarded.hit_count().await.is_err());
+        assert!(matches!(guarded.hit_count().await, Err(DbError::Unavailable(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_retries_back_off() {
+        let res_cfg = DbResilienceConfig { connect_retries: 3, ..DbResilienceConfig::default() };
+
+        // a directory that isn't there, so SQLite can't create the file
+        let started = Instant::now();
+        let res = connect_with_retry("sqlite:///no/such/dir/archie.db", &DbPoolConfig::default(), &res_cfg).await;
+        assert!(matches!(res, Err(DbError::SqliteError(_))));
+        assert_eq!(started.elapsed(), Duration::from_millis(500 + 1000 + 2000));
+
+        // no retries for these
+        let started = Instant::now();
+        let res = connect_with_retry("mongodb://localhost", &DbPoolConfig::default(), &res_cfg).await;
+        assert!(matches!(res, Err(DbError::UnknownScheme(_))));
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
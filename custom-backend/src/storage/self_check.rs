@@ -0,0 +1,415 @@
+// Checks run at startup (and by `archie-server --check`), so a database that
+// doesn't match what the server expects is caught before any requests come in,
+// instead of showing up later as a 500 from some handler.
+//
+// This looks at three things: whether the schema migrations are all applied,
+// whether the tables and columns the queries use are there and wide enough for
+// what db_io.rs lets through, and whether the DB user has the privileges the
+// queries need.
+use std::fmt;
+use crate::{
+    srv_io::{
+        admin_auth::{MAX_ACTION_CHARS, MAX_DETAIL_CHARS, MAX_IP_CHARS},
+        reactions::MAX_EMOJI_CHARS,
+        db_io::{
+            DbError, MAX_NAME_BYTES, MAX_NOTE_BYTES, 
+            MAX_PATH_CHARS, MAX_REFERRER_CHARS, MAX_USER_AGENT_CHARS, MAX_UTM_CHARS,
+        },
+    },
+    utils::{
+        geoip::{COUNTRY_CHARS, MAX_REGION_CHARS},
+        spam_filter::{MAX_SPAM_REASON_CHARS, MAX_VERDICT_CHARS},
+        user_agent::{MAX_NAME_CHARS, MAX_VERSION_CHARS},
+    },
+};
+use super::{visitors::{SALT_CHARS, VISITOR_HASH_CHARS}, Database};
+
+
+// what a backend reports about one column of a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub data_type: String,          // lowercase, without the length, e.g. "varchar"
+    pub max_chars: Option<u64>,     // None if there's no limit, or it isn't enforced (SQLite)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Int,
+    Text(usize),    // the least number of characters it needs to hold
+    DateTime,
+    Bool,
+}
+
+impl ColumnKind {
+
+    fn matches(&self, data_type: &str) -> bool {
+        match self {
+            // covers serial, bigint, etc. too
+            ColumnKind::Int      => data_type.contains("int"),
+            ColumnKind::Text(_)  => data_type.contains("char") || data_type.contains("text"),
+            ColumnKind::DateTime => data_type.contains("date") || data_type.contains("time"),
+            // MySQL's BOOLEAN is really a TINYINT
+            ColumnKind::Bool     => data_type.contains("bool") || data_type.contains("int"),
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            ColumnKind::Int      => "an integer",
+            ColumnKind::Text(_)  => "a text type",
+            ColumnKind::DateTime => "a date/time type",
+            ColumnKind::Bool     => "a boolean",
+        }
+    }
+}
+
+// Only the columns the queries actually use. Extra columns are fine.
+const EXPECTED_TABLES: &[(&str, &[(&str, ColumnKind)])] = &[
+    ("guestbook", &[
+        ("id",            ColumnKind::Int),
+        ("dateSubmitted", ColumnKind::DateTime),
+        ("guestName",     ColumnKind::Text(MAX_NAME_BYTES)),
+        ("guestNote",     ColumnKind::Text(MAX_NOTE_BYTES)),
+        ("status",        ColumnKind::Text(8)),      // "pending", "approved", or "rejected"
+        ("deletedAt",     ColumnKind::DateTime),
+        ("pinned",        ColumnKind::Bool),
+        ("spamVerdict",   ColumnKind::Text(MAX_VERDICT_CHARS)),
+        ("spamReason",    ColumnKind::Text(MAX_SPAM_REASON_CHARS)),
+        ("editTokenHash", ColumnKind::Text(64)),     // a SHA-256, in hex
+        ("parentId",      ColumnKind::Int),
+    ]),
+    ("hitLog", &[
+        ("id",            ColumnKind::Int),
+        ("hitTime",       ColumnKind::DateTime),
+        ("userAgent",     ColumnKind::Text(MAX_USER_AGENT_CHARS)),
+        ("browser",       ColumnKind::Text(MAX_NAME_CHARS)),
+        ("browserVersion",ColumnKind::Text(MAX_VERSION_CHARS)),
+        ("os",            ColumnKind::Text(MAX_NAME_CHARS)),
+        ("device",        ColumnKind::Text(10)),     // "desktop", "mobile", "tablet", or "bot"
+        ("isBot",         ColumnKind::Bool),
+        ("path",          ColumnKind::Text(MAX_PATH_CHARS)),
+        ("referrer",      ColumnKind::Text(MAX_REFERRER_CHARS)),
+        ("utmSource",     ColumnKind::Text(MAX_UTM_CHARS)),
+        ("utmMedium",     ColumnKind::Text(MAX_UTM_CHARS)),
+        ("utmCampaign",   ColumnKind::Text(MAX_UTM_CHARS)),
+        ("country",       ColumnKind::Text(COUNTRY_CHARS)),
+        ("region",        ColumnKind::Text(MAX_REGION_CHARS)),
+    ]),
+    ("hitCounter", &[
+        ("id",            ColumnKind::Int),
+        ("total",         ColumnKind::Int),
+        ("bots",          ColumnKind::Int),
+    ]),
+    ("hitDaily", &[
+        ("day",           ColumnKind::DateTime),
+        ("userAgent",     ColumnKind::Text(0)),
+        ("hits",          ColumnKind::Int),
+        ("firstHit",      ColumnKind::DateTime),
+        ("lastHit",       ColumnKind::DateTime),
+    ]),
+    ("visitorSalt", &[
+        ("day",           ColumnKind::DateTime),
+        ("salt",          ColumnKind::Text(SALT_CHARS)),
+    ]),
+    ("visitorDaily", &[
+        ("day",           ColumnKind::DateTime),
+        ("visitorHash",   ColumnKind::Text(VISITOR_HASH_CHARS)),
+    ]),
+    ("adminAudit", &[
+        ("id",            ColumnKind::Int),
+        ("eventTime",     ColumnKind::DateTime),
+        ("action",        ColumnKind::Text(MAX_ACTION_CHARS)),
+        ("clientIp",      ColumnKind::Text(MAX_IP_CHARS)),
+        ("entryId",       ColumnKind::Int),
+        ("detail",        ColumnKind::Text(MAX_DETAIL_CHARS)),
+    ]),
+    ("guestbookEdit", &[
+        ("id",            ColumnKind::Int),
+        ("entryId",       ColumnKind::Int),
+        ("editTime",      ColumnKind::DateTime),
+        ("oldName",       ColumnKind::Text(MAX_NAME_BYTES)),
+        ("oldNote",       ColumnKind::Text(MAX_NOTE_BYTES)),
+    ]),
+    ("guestbookReaction", &[
+        ("entryId",       ColumnKind::Int),
+        ("emoji",         ColumnKind::Text(MAX_EMOJI_CHARS)),
+        ("visitorHash",   ColumnKind::Text(VISITOR_HASH_CHARS)),
+        ("reactedAt",     ColumnKind::DateTime),
+    ]),
+];
+
+// what the queries do with each table
+const EXPECTED_PRIVILEGES: &[(&str, &[&str])] = &[
+    ("guestbook",  &["SELECT", "INSERT", "UPDATE"]),
+    ("hitLog",     &["SELECT", "INSERT"]),
+    ("hitCounter", &["SELECT", "UPDATE"]),
+    ("hitDaily",   &["SELECT", "INSERT", "UPDATE"]),
+    // MySQL needs UPDATE for ON DUPLICATE KEY UPDATE, even when it's a no-op
+    ("visitorSalt",  &["SELECT", "INSERT", "UPDATE", "DELETE"]),
+    ("visitorDaily", &["SELECT", "INSERT", "UPDATE"]),
+    ("adminAudit",   &["SELECT", "INSERT"]),
+    ("guestbookEdit", &["SELECT", "INSERT"]),
+    ("guestbookReaction", &["SELECT", "INSERT", "UPDATE"]),
+];
+
+// for the backends' privilege checks
+pub fn expected_privileges() -> &'static [(&'static str, &'static [&'static str])] {
+    EXPECTED_PRIVILEGES
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaProblem {
+    PendingMigration(&'static str),
+    UnknownMigration(u32),
+    MissingTable(&'static str),
+    MissingColumn { table: &'static str, column: &'static str },
+    WrongType { table: &'static str, column: &'static str, expected: &'static str, found: String },
+    TooNarrow { table: &'static str, column: &'static str, needed: usize, found: u64 },
+    MissingPrivilege(String),
+}
+
+impl fmt::Display for SchemaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PendingMigration(name) => write!(f, "Migration {name} has not been applied."),
+            Self::UnknownMigration(version) => write!(f, "Schema version {version} is applied, but this build doesn't know about it."),
+            Self::MissingTable(table) => write!(f, "Table {table} does not exist."),
+            Self::MissingColumn { table, column } => write!(f, "Column {table}.{column} does not exist."),
+            Self::WrongType { table, column, expected, found } => write!(f,
+                "Column {table}.{column} should be {expected}, but it's {found}."
+            ),
+            Self::TooNarrow { table, column, needed, found } => write!(f,
+                "Column {table}.{column} holds {found} characters, but the server accepts up to {needed}."
+            ),
+            Self::MissingPrivilege(privilege) => write!(f, "The database user is missing a privilege: {privilege}."),
+        }
+    }
+}
+
+// the error main() returns when the checks turn anything up
+pub struct SchemaMismatch(pub Vec<SchemaProblem>);
+
+// main() prints the error it returns with Debug, and the list 
+// is much easier to read this way
+impl fmt::Debug for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The database failed {} startup check(s):", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n    {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SchemaMismatch {}
+
+
+// Returns everything that's wrong, rather than stopping at the first problem,
+// so it can all be fixed in one go.
+pub async fn find_problems(db: &dyn Database) -> Result<Vec<SchemaProblem>, DbError> {
+
+    // backends without a schema (MemStore) have nothing to check
+    if db.migrations().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut problems = Vec::new();
+
+    let applied = db.applied_versions().await?;
+    for migration in db.migrations() {
+        if !applied.contains(&migration.version) {
+            problems.push(SchemaProblem::PendingMigration(migration.name));
+        }
+    }
+    for version in applied {
+        if !db.migrations().iter().any(|mig| mig.version == version) {
+            problems.push(SchemaProblem::UnknownMigration(version));
+        }
+    }
+
+    let mut all_tables_exist = true;
+    for (table, expected_cols) in EXPECTED_TABLES {
+        let found_cols = db.table_columns(table).await?;
+        if found_cols.is_empty() {
+            all_tables_exist = false;
+            problems.push(SchemaProblem::MissingTable(table));
+        } else {
+            problems.extend(compare_columns(table, expected_cols, &found_cols));
+        }
+    }
+
+    // the privilege checks can error on tables that aren't there
+    if all_tables_exist {
+        problems.extend(db.missing_privileges().await?.into_iter().map(SchemaProblem::MissingPrivilege));
+    }
+
+    Ok(problems)
+}
+
+fn compare_columns(
+    table: &'static str,
+    expected_cols: &[(&'static str, ColumnKind)],
+    found_cols: &[ColumnInfo]
+) -> Vec<SchemaProblem> {
+
+    let mut problems = Vec::new();
+
+    for (column, kind) in expected_cols {
+        // Postgres folds the names to lowercase
+        let Some(found) = found_cols.iter().find(|col| col.name.eq_ignore_ascii_case(column)) else {
+            problems.push(SchemaProblem::MissingColumn { table, column });
+            continue;
+        };
+
+        if !kind.matches(&found.data_type) {
+            problems.push(SchemaProblem::WrongType {
+                table, column, expected: kind.describe(), found: found.data_type.clone()
+            });
+            continue;
+        }
+
+        if let (ColumnKind::Text(needed), Some(max_chars)) = (kind, found.max_chars) {
+            if (max_chars as usize) < *needed {
+                problems.push(SchemaProblem::TooNarrow { table, column, needed: *needed, found: max_chars });
+            }
+        }
+    }
+
+    problems
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::{
+        mem_store::MemStore, 
+        migrations::{self, Direction, Migration}, 
+        sqlite_store::SqliteStore
+    };
+
+    fn col(name: &str, data_type: &str, max_chars: Option<u64>) -> ColumnInfo {
+        ColumnInfo { name: name.to_string(), data_type: data_type.to_string(), max_chars }
+    }
+
+    #[test]
+    fn column_mismatches() {
+        let expected = EXPECTED_TABLES[0].1;
+
+        // what create_db.sql used to make, on MySQL
+        let old_guestbook = vec![
+            col("id", "int", None),
+            col("dateSubmitted", "datetime", None),
+            col("guestName", "varchar", Some(50)),
+            col("guestNote", "varchar", Some(1000)),
+        ];
+        assert_eq!(
+            compare_columns("guestbook", expected, &old_guestbook),
+            vec![
+                SchemaProblem::TooNarrow { table: "guestbook", column: "guestName", needed: MAX_NAME_BYTES, found: 50 },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "status" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "deletedAt" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "pinned" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "spamVerdict" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "spamReason" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "editTokenHash" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "parentId" },
+            ]
+        );
+
+        // Postgres names, with a missing column and a wrong type
+        let broken_guestbook = vec![
+            col("id", "integer", None),
+            col("datesubmitted", "character varying", Some(30)),
+            col("guestname", "text", None),
+        ];
+        assert_eq!(
+            compare_columns("guestbook", expected, &broken_guestbook),
+            vec![
+                SchemaProblem::WrongType {
+                    table: "guestbook", column: "dateSubmitted",
+                    expected: "a date/time type", found: String::from("character varying")
+                },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "guestNote" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "status" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "deletedAt" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "pinned" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "spamVerdict" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "spamReason" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "editTokenHash" },
+                SchemaProblem::MissingColumn { table: "guestbook", column: "parentId" },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn migrated_sqlite_passes() {
+        let store = SqliteStore::open(":memory:").await.unwrap();
+        migrations::migrate_up(&store).await.unwrap();
+        assert_eq!(find_problems(&store).await.unwrap(), vec![]);
+
+        // nothing to check for this one
+        assert_eq!(find_problems(&MemStore::new()).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn unmigrated_sqlite_fails() {
+        static HALF_DONE: Migration = Migration {
+            version: 1,
+            name: "0001_create_tables",
+            up: "CREATE TABLE guestbook (id INTEGER PRIMARY KEY, dateSubmitted DATETIME, guestName INTEGER);",
+            down: "",
+        };
+
+        let store = SqliteStore::open(":memory:").await.unwrap();
+        store.run_migration(&HALF_DONE, Direction::Up).await.unwrap();
+
+        let problems = find_problems(&store).await.unwrap();
+        assert_eq!(problems, vec![
+            SchemaProblem::PendingMigration("0002_guest_name_100"),
+            SchemaProblem::PendingMigration("0003_hit_counter"),
+            SchemaProblem::PendingMigration("0004_hit_rollup"),
+            SchemaProblem::PendingMigration("0005_user_agent_fields"),
+            SchemaProblem::PendingMigration("0006_unique_visitors"),
+            SchemaProblem::PendingMigration("0007_page_paths"),
+            SchemaProblem::PendingMigration("0008_utm_params"),
+            SchemaProblem::PendingMigration("0009_hit_location"),
+            SchemaProblem::PendingMigration("0010_guestbook_search"),
+            SchemaProblem::PendingMigration("0011_entry_status"),
+            SchemaProblem::PendingMigration("0012_admin_audit"),
+            SchemaProblem::PendingMigration("0013_entry_admin"),
+            SchemaProblem::PendingMigration("0014_spam_checks"),
+            SchemaProblem::PendingMigration("0015_edit_tokens"),
+            SchemaProblem::PendingMigration("0016_entry_replies"),
+            SchemaProblem::PendingMigration("0017_entry_reactions"),
+            SchemaProblem::WrongType {
+                table: "guestbook", column: "guestName", expected: "a text type", found: String::from("integer")
+            },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "guestNote" },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "status" },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "deletedAt" },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "pinned" },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "spamVerdict" },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "spamReason" },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "editTokenHash" },
+            SchemaProblem::MissingColumn { table: "guestbook", column: "parentId" },
+            SchemaProblem::MissingTable("hitLog"),
+            SchemaProblem::MissingTable("hitCounter"),
+            SchemaProblem::MissingTable("hitDaily"),
+            SchemaProblem::MissingTable("visitorSalt"),
+            SchemaProblem::MissingTable("visitorDaily"),
+            SchemaProblem::MissingTable("adminAudit"),
+            SchemaProblem::MissingTable("guestbookEdit"),
+            SchemaProblem::MissingTable("guestbookReaction"),
+        ]);
+    }
+}
@@ -0,0 +1,703 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use async_trait::async_trait;
+use mysql_common::chrono::{NaiveDate, NaiveDateTime, NaiveTime, SubsecRound, Utc};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, MAIN_DB};
+use tokio::task::spawn_blocking;
+use crate::{
+    srv_io::db_io::DbError,
+    types::db_io_types::{
+        AuditAction, AuditEvent, EntryChanges, EntryEdit, EntryStatus, GuestbookEntry, HitStats,
+        SpamDecision, StatsBucket, UserAgentHits
+    },
+};
+use super::{
+    entry_pages::EntryPageQuery,
+    entry_search::{like_pattern, SearchQuery, MAX_CANDIDATES},
+    hit_stats::{self, StatsQuery, TopQuery},
+    HitTotals, LoggedHit,
+    migrations::{Direction, Migration, SQLITE_MIGRATIONS},
+    self_check::ColumnInfo,
+    visitors::DailySalt,
+    audit_entry_id, AuditStore, AuthorToken, Database, EditReview, GuestbookStore, HitStore, ReactionTally,
+};
+
+
+const MIGRATIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations
+    (
+        version         INTEGER PRIMARY KEY,
+        name            VARCHAR(100) NOT NULL,
+        appliedAt       DATETIME NOT NULL
+    );";
+
+
+// rusqlite is synchronous, so every query is run on tokio's blocking
+// thread pool, to keep it off the worker threads. SQLite only allows one
+// writer at a time anyway, so a single connection behind a mutex is
+// all the "pool" this needs.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+
+    // path can be ":memory:" for a database that never touches the disk
+    pub async fn open(path: &str) -> Result<SqliteStore, DbError> {
+
+        let path = path.to_string();
+        let conn = spawn_blocking(move || Connection::open(path)).await??;
+        Ok(SqliteStore { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    // runs f with the connection, on the blocking thread pool
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, DbError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let res = spawn_blocking(move || {
+            // a panic while holding the lock can't leave the connection
+            // in a bad state (SQLite rolls back anything half-done), 
+            // so a poisoned lock is safe to keep using
+            let mut conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+            f(&mut conn)
+        }).await??;
+
+        Ok(res)
+    }
+}
+
+
+#[async_trait]
+impl Database for SqliteStore {
+
+    fn backend_name(&self) -> &'static str { "SQLite" }
+
+    fn migrations(&self) -> &'static [Migration] { SQLITE_MIGRATIONS }
+
+    async fn applied_versions(&self) -> Result<Vec<u32>, DbError> {
+
+        self.with_conn(|conn| {
+            let table_exists: bool = conn.query_row(
+                "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'",
+                [],
+                |row| row.get(0)
+            )?;
+            if !table_exists {
+                return Ok(Vec::new());
+            }
+
+            let mut stmt = conn.prepare("SELECT version FROM schema_migrations ORDER BY version")?;
+            let versions = stmt.query_map([], |row| row.get(0))?.collect();
+
+            versions
+        }).await
+    }
+
+    // DDL is transactional in SQLite, so a migration that fails 
+    // partway through leaves nothing behind
+    async fn run_migration(&self, migration: &'static Migration, direction: Direction) -> Result<(), DbError> {
+
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute_batch(MIGRATIONS_TABLE)?;
+
+            match direction {
+                Direction::Up => {
+                    tx.execute_batch(migration.up)?;
+                    tx.execute(
+                        "INSERT INTO schema_migrations (version, name, appliedAt) VALUES (?1, ?2, ?3)",
+                        params![migration.version, migration.name, Utc::now().naive_utc().trunc_subsecs(0)]
+                    )?;
+                },
+                Direction::Down => {
+                    tx.execute_batch(migration.down)?;
+                    tx.execute("DELETE FROM schema_migrations WHERE version = ?1", params![migration.version])?;
+                }
+            }
+
+            tx.commit()
+        }).await
+    }
+
+    async fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>, DbError> {
+
+        let table = table.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare("SELECT name, type FROM pragma_table_info(?1)")?;
+
+            let columns = stmt.query_map([table], |row| {
+                // declared types look like "VARCHAR(100)", but SQLite doesn't 
+                // enforce the length, so only the name of the type is kept
+                let decl_type: String = row.get(1)?;
+                let data_type = decl_type.split('(').next().unwrap_or_default().trim().to_lowercase();
+                Ok(ColumnInfo { name: row.get(0)?, data_type, max_chars: None })
+            })?.collect();
+
+            columns
+        }).await
+    }
+
+    // there are no users or grants in SQLite; the closest thing 
+    // is whether the file could be opened for writing
+    async fn missing_privileges(&self) -> Result<Vec<String>, DbError> {
+
+        let read_only = self.with_conn(|conn| conn.is_readonly(MAIN_DB)).await?;
+
+        Ok(if read_only { vec![String::from("write access to the database file")] } else { Vec::new() })
+    }
+}
+
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<GuestbookEntry> {
+    Ok(GuestbookEntry {
+        id: Some(row.get::<_, i64>(0)?.to_string()),
+        time_stamp: row.get(1)?,
+        name: row.get(2)?,
+        note: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+        pinned: row.get::<_, bool>(4)?.then_some(true),
+        spam: None,
+        parent_id: row.get::<_, Option<i64>>(5)?.map(|id| id.to_string()),
+        replies: None,
+        reactions: None,
+    })
+}
+
+// the same, with what the spam filter made of it after the rest, for the admin
+fn admin_entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<GuestbookEntry> {
+    Ok(GuestbookEntry {
+        spam: SpamDecision::from_db(row.get::<_, Option<String>>(6)?.as_deref(), row.get(7)?),
+        ..entry_from_row(row)?
+    })
+}
+
+#[async_trait]
+impl GuestbookStore for SqliteStore {
+
+    async fn get_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+                FROM guestbook
+                WHERE status = 'approved' AND deletedAt IS NULL
+                ORDER BY pinned DESC, dateSubmitted DESC"
+            )?;
+
+            let guestbook_table = stmt.query_map([], entry_from_row)?.collect();
+
+            guestbook_table
+        }).await
+    }
+
+    async fn get_page(&self, query: &EntryPageQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let query = query.clone();
+        self.with_conn(move |conn| {
+            let direction = query.direction();
+            let mut stmt = conn.prepare(&format!("
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+                FROM guestbook
+                WHERE status = 'approved' AND deletedAt IS NULL AND parentId IS NULL
+                    AND (dateSubmitted, id) > (?1, ?2)
+                    AND (dateSubmitted, id) < (?3, ?4)
+                ORDER BY dateSubmitted {direction}, id {direction}
+                LIMIT ?5"
+            ))?;
+
+            let page = stmt.query_map(
+                params![query.after.time, query.after.id, query.before.time, query.before.id, query.limit as i64],
+                entry_from_row
+            )?.collect();
+
+            page
+        }).await
+    }
+
+    async fn get_replies(&self, parent_ids: &[i64]) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        let parent_ids = parent_ids.to_vec();
+        self.with_conn(move |conn| {
+            let placeholders = vec!["?"; parent_ids.len()].join(", ");
+            let mut stmt = conn.prepare(&format!("
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+                FROM guestbook
+                WHERE status = 'approved' AND deletedAt IS NULL AND parentId IN ({placeholders})
+                ORDER BY dateSubmitted, id"
+            ))?;
+
+            let replies = stmt.query_map(params_from_iter(parent_ids), entry_from_row)?.collect();
+
+            replies
+        }).await
+    }
+
+    async fn get_entry(&self, id: i64) -> Result<Option<GuestbookEntry>, DbError> {
+
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId FROM guestbook WHERE id = ?1 AND status = 'approved' AND deletedAt IS NULL",
+                [id],
+                entry_from_row
+            ).optional()
+        }).await
+    }
+
+    async fn search_entries(&self, query: &SearchQuery) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        // The trigram index can only look up terms of 3 characters or more,
+        // so the rest are LIKE's, which are checked on whatever it finds. 
+        // LIKE only ignores the case of ASCII letters, though.
+        let (long, short): (Vec<&String>, Vec<&String>) = query.terms.iter().partition(|term| term.chars().count() >= 3);
+
+        let mut conditions: Vec<&str> = Vec::new();
+        let mut args: Vec<String> = Vec::new();
+        if !long.is_empty() {
+            conditions.push("id IN (SELECT rowid FROM guestbookSearch WHERE guestbookSearch MATCH ?)");
+            args.push(long.iter().map(|term| format!("\"{term}\"")).collect::<Vec<_>>().join(" AND "));
+        }
+        for term in short {
+            conditions.push("(guestName || ' ' || COALESCE(guestNote, '')) LIKE ? ESCAPE '\\'");
+            args.push(like_pattern(term));
+        }
+        let sql = format!("
+            SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId 
+            FROM guestbook
+            WHERE status = 'approved' AND deletedAt IS NULL AND {}
+            ORDER BY dateSubmitted DESC, id DESC
+            LIMIT {MAX_CANDIDATES}",
+            conditions.join(" AND ")
+        );
+
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+
+            let candidates = stmt.query_map(params_from_iter(args), entry_from_row)?.collect();
+
+            candidates
+        }).await
+    }
+
+    async fn add_entry(&self, entry: &GuestbookEntry, status: EntryStatus, edit_token_hash: Option<&str>) -> Result<String, DbError> {
+
+        let entry = entry.clone();
+        let edit_token_hash = edit_token_hash.map(str::to_string);
+        let parent_id = entry.parent_id.as_deref().and_then(|id| id.parse::<i64>().ok());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "
+                INSERT INTO guestbook (dateSubmitted, guestName, guestNote, status, spamVerdict, spamReason, editTokenHash, parentId)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    entry.time_stamp, entry.name, entry.note, status.as_str(),
+                    entry.spam.as_ref().map(|spam| spam.verdict.as_str()),
+                    entry.spam.as_ref().and_then(|spam| spam.reason.as_deref()),
+                    edit_token_hash,
+                    parent_id,
+                ]
+            )?;
+
+            Ok(conn.last_insert_rowid().to_string())
+        }).await
+    }
+
+    async fn author_token(&self, id: i64) -> Result<Option<AuthorToken>, DbError> {
+
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT editTokenHash, dateSubmitted, status, deletedAt IS NOT NULL, guestName, guestNote FROM guestbook WHERE id = ?1",
+                [id],
+                |row| Ok(AuthorToken::from_db(row.get(0)?, row.get(1)?, &row.get::<_, String>(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+            ).optional()
+        }).await
+    }
+
+    async fn entries_with_status(&self, status: EntryStatus) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId, spamVerdict, spamReason
+                FROM guestbook
+                WHERE status = ?1 AND deletedAt IS NULL
+                ORDER BY dateSubmitted, id"
+            )?;
+
+            let entries = stmt.query_map([status.as_str()], admin_entry_from_row)?.collect();
+
+            entries
+        }).await
+    }
+
+    async fn set_status(&self, id: i64, status: EntryStatus) -> Result<bool, DbError> {
+
+        self.with_conn(move |conn| {
+            let updated = conn.execute(
+                "UPDATE guestbook SET status = ?1 WHERE id = ?2",
+                params![status.as_str(), id]
+            )?;
+
+            Ok(updated > 0)
+        }).await
+    }
+
+    async fn set_deleted(&self, id: i64, deleted_at: Option<NaiveDateTime>) -> Result<bool, DbError> {
+
+        self.with_conn(move |conn| {
+            let updated = match deleted_at {
+                Some(deleted_at) => conn.execute(
+                    "UPDATE guestbook SET deletedAt = COALESCE(deletedAt, ?1) WHERE id = ?2",
+                    params![deleted_at, id]
+                )?,
+                None => conn.execute("UPDATE guestbook SET deletedAt = NULL WHERE id = ?1", [id])?,
+            };
+
+            Ok(updated > 0)
+        }).await
+    }
+
+    async fn deleted_entries(&self) -> Result<Vec<GuestbookEntry>, DbError> {
+
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "
+                SELECT id, dateSubmitted, guestName, guestNote, pinned, parentId, spamVerdict, spamReason
+                FROM guestbook
+                WHERE deletedAt IS NOT NULL
+                ORDER BY deletedAt DESC, id DESC"
+            )?;
+
+            let entries = stmt.query_map([], admin_entry_from_row)?.collect();
+
+            entries
+        }).await
+    }
+
+    async fn edit_entry(&self, id: i64, changes: &EntryChanges, review: Option<&EditReview>, edit_time: NaiveDateTime) -> Result<Option<GuestbookEntry>, DbError> {
+
+        let changes = changes.clone();
+        let review = review.cloned();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let inserted = tx.execute(
+                "
+                INSERT INTO guestbookEdit (entryId, editTime, oldName, oldNote)
+                SELECT id, ?1, guestName, guestNote FROM guestbook WHERE id = ?2",
+                params![edit_time, id]
+            )?;
+            if inserted == 0 {
+                return Ok(None);
+            }
+            let edited = tx.query_row(
+                "
+                UPDATE guestbook
+                SET guestName = COALESCE(?1, guestName), guestNote = COALESCE(?2, guestNote)
+                WHERE id = ?3
+                RETURNING id, dateSubmitted, guestName, guestNote, pinned, parentId",
+                params![changes.name, changes.note, id],
+                entry_from_row
+            )?;
+            if let Some(review) = review {
+                tx.execute(
+                    "UPDATE guestbook SET status = ?1, spamVerdict = ?2, spamReason = ?3 WHERE id = ?4",
+                    params![review.status.as_str(), review.spam.verdict.as_str(), review.spam.reason, id]
+                )?;
+            }
+            tx.commit()?;
+
+            Ok(Some(edited))
+        }).await
+    }
+
+    async fn entry_edits(&self, id: i64) -> Result<Option<Vec<EntryEdit>>, DbError> {
+
+        self.with_conn(move |conn| {
+            let found: Option<i64> = conn.query_row("SELECT id FROM guestbook WHERE id = ?1", [id], |row| row.get(0)).optional()?;
+            if found.is_none() {
+                return Ok(None);
+            }
+
+            let mut stmt = conn.prepare(
+                "
+                SELECT editTime, oldName, oldNote
+                FROM guestbookEdit
+                WHERE entryId = ?1
+                ORDER BY id"
+            )?;
+            let edits = stmt.query_map([id], |row| Ok(EntryEdit {
+                edit_time: row.get(0)?,
+                old_name: row.get(1)?,
+                old_note: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            }))?.collect::<rusqlite::Result<_>>()?;
+
+            Ok(Some(edits))
+        }).await
+    }
+
+    async fn set_pinned(&self, id: i64, pinned: bool) -> Result<bool, DbError> {
+
+        self.with_conn(move |conn| {
+            let updated = conn.execute("UPDATE guestbook SET pinned = ?1 WHERE id = ?2", params![pinned, id])?;
+
+            Ok(updated > 0)
+        }).await
+    }
+
+    async fn add_reaction(&self, entry_id: i64, emoji: &str, fingerprint: &str, reacted_at: NaiveDateTime) -> Result<bool, DbError> {
+
+        let emoji = emoji.to_string();
+        let fingerprint = fingerprint.to_string();
+        self.with_conn(move |conn| {
+            let added = conn.execute(
+                "
+                INSERT INTO guestbookReaction (entryId, emoji, visitorHash, reactedAt)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT DO NOTHING",
+                params![entry_id, emoji, fingerprint, reacted_at]
+            )?;
+
+            Ok(added > 0)
+        }).await
+    }
+
+    async fn reaction_counts(&self, entry_id: Option<i64>) -> Result<Vec<ReactionTally>, DbError> {
+
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "
+                SELECT entryId, emoji, COUNT(*) FROM guestbookReaction
+                WHERE ?1 IS NULL OR entryId = ?1
+                GROUP BY entryId, emoji
+                ORDER BY entryId, emoji"
+            )?;
+            let tallies = stmt.query_map([entry_id], |row| Ok(ReactionTally {
+                entry_id: row.get(0)?,
+                emoji: row.get(1)?,
+                count: row.get::<_, i64>(2)? as u64,
+            }))?.collect::<rusqlite::Result<_>>()?;
+
+            Ok(tallies)
+        }).await
+    }
+}
+
+
+#[async_trait]
+impl HitStore for SqliteStore {
+
+    async fn hit_count(&self) -> Result<HitTotals, DbError> {
+        
+        self.with_conn(|conn| {
+            conn.query_row(
+                "SELECT total, bots FROM hitCounter WHERE id = 1", 
+                [], 
+                |row| Ok(HitTotals { all: row.get(0)?, bots: row.get(1)? })
+            )
+        }).await
+    }
+
+    async fn log_hits(&self, logged_hits: &[LoggedHit]) -> Result<HitTotals, DbError> {
+
+        let logged_hits = logged_hits.to_vec();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(
+                    "
+                    INSERT INTO hitLog (
+                        hitTime, userAgent, browser, browserVersion, os, device, isBot, 
+                        path, referrer, utmSource, utmMedium, utmCampaign, country, region
+                    ) 
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)"
+                )?;
+                for logged in &logged_hits {
+                    let LoggedHit { hit, agent, .. } = logged;
+                    stmt.execute(params![
+                        hit.time_stamp, hit.user_agent, 
+                        agent.browser, agent.browser_version, agent.os, agent.device.as_str(), agent.is_bot,
+                        hit.path, hit.referrer, hit.utm_source, hit.utm_medium, hit.utm_campaign,
+                        logged.country(), logged.region()
+                    ])?;
+                }
+
+                let mut stmt = tx.prepare(
+                    "
+                    INSERT INTO hitDaily (day, userAgent, hits, firstHit, lastHit) 
+                    VALUES (?1, ?2, ?3, ?4, ?5)
+                    ON CONFLICT (day, userAgent) DO UPDATE SET
+                        hits = hitDaily.hits + excluded.hits,
+                        firstHit = MIN(hitDaily.firstHit, excluded.firstHit),
+                        lastHit = MAX(hitDaily.lastHit, excluded.lastHit)"
+                )?;
+                for rollup in hit_stats::roll_up(&logged_hits) {
+                    stmt.execute(params![rollup.day, rollup.user_agent, rollup.hits, rollup.first_hit, rollup.last_hit])?;
+                }
+
+                let mut stmt = tx.prepare(
+                    "INSERT INTO visitorDaily (day, visitorHash) VALUES (?1, ?2) ON CONFLICT DO NOTHING"
+                )?;
+                for visitor in logged_hits.iter().filter_map(|logged| logged.visitor.as_ref()) {
+                    stmt.execute(params![visitor.day, visitor.hash])?;
+                }
+            }
+            let added = HitTotals::of(&logged_hits);
+            let new_totals = tx.query_row(
+                "UPDATE hitCounter SET total = total + ?1, bots = bots + ?2 WHERE id = 1 RETURNING total, bots",
+                params![added.all, added.bots],
+                |row| Ok(HitTotals { all: row.get(0)?, bots: row.get(1)? })
+            )?;
+            tx.commit()?;
+
+            Ok(new_totals)
+        }).await
+    }
+
+    async fn hit_stats(&self, query: &StatsQuery) -> Result<HitStats, DbError> {
+
+        let query = query.clone();
+        self.with_conn(move |conn| {
+            let counts: Vec<(NaiveDateTime, u64)> = if query.bucket == StatsBucket::Hour {
+                let (start, until) = query.time_range();
+                let mut stmt = conn.prepare(
+                    "
+                    SELECT strftime('%Y-%m-%d %H:00:00', hitTime), COUNT(*)
+                    FROM hitLog
+                    WHERE hitTime >= ?1 AND hitTime < ?2
+                    GROUP BY 1"
+                )?;
+                let counts = stmt.query_map(params![start, until], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                counts.collect::<rusqlite::Result<_>>()?
+            } else {
+                let mut stmt = conn.prepare(
+                    "SELECT day, SUM(hits) FROM hitDaily WHERE day BETWEEN ?1 AND ?2 GROUP BY day"
+                )?;
+                let counts = stmt.query_map(params![query.from, query.to], |row| {
+                    Ok((row.get::<_, NaiveDate>(0)?.and_time(NaiveTime::MIN), row.get(1)?))
+                })?;
+                counts.collect::<rusqlite::Result<_>>()?
+            };
+
+            let mut stmt = conn.prepare(
+                "
+                SELECT userAgent, SUM(hits) 
+                FROM hitDaily
+                WHERE day BETWEEN ?1 AND ?2
+                GROUP BY userAgent
+                ORDER BY SUM(hits) DESC, userAgent
+                LIMIT ?3"
+            )?;
+            let top_user_agents = stmt.query_map(params![query.from, query.to, query.top_agents], |row| {
+                Ok(UserAgentHits { user_agent: row.get(0)?, hits: row.get(1)? })
+            })?.collect::<rusqlite::Result<_>>()?;
+
+            let (first_visit, last_visit) = conn.query_row(
+                "SELECT MIN(firstHit), MAX(lastHit) FROM hitDaily WHERE day BETWEEN ?1 AND ?2",
+                params![query.from, query.to],
+                |row| Ok((row.get(0)?, row.get(1)?))
+            )?;
+
+            Ok(hit_stats::assemble(&query, counts, top_user_agents, first_visit, last_visit))
+        }).await
+    }
+
+    async fn top_hits(&self, query: &TopQuery) -> Result<Vec<(String, u64)>, DbError> {
+
+        let query = query.clone();
+        self.with_conn(move |conn| {
+            let (start, until) = query.time_range();
+            let column = query.by.column();
+            let mut stmt = conn.prepare(&format!("
+                SELECT {column}, COUNT(*)
+                FROM hitLog
+                WHERE hitTime >= ?1 AND hitTime < ?2 AND {column} IS NOT NULL
+                GROUP BY {column}
+                ORDER BY 2 DESC, {column}
+                LIMIT ?3"
+            ))?;
+            let top = stmt.query_map(params![start, until, query.top], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            top.collect()
+        }).await
+    }
+
+    async fn visitor_salts(&self, fresh: &[DailySalt]) -> Result<Vec<DailySalt>, DbError> {
+
+        let Some(first_day) = fresh.first().map(|salt| salt.day) else { return Ok(Vec::new()) };
+        let fresh = fresh.to_vec();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM visitorSalt WHERE day < ?1", params![first_day])?;
+            let salts = {
+                let mut stmt = tx.prepare("INSERT INTO visitorSalt (day, salt) VALUES (?1, ?2) ON CONFLICT DO NOTHING")?;
+                for DailySalt { day, salt } in &fresh {
+                    stmt.execute(params![day, salt])?;
+                }
+
+                let mut stmt = tx.prepare("SELECT day, salt FROM visitorSalt WHERE day >= ?1 ORDER BY day")?;
+                let salts = stmt.query_map(params![first_day], |row| Ok(DailySalt { day: row.get(0)?, salt: row.get(1)? }))?;
+                salts.collect::<rusqlite::Result<_>>()?
+            };
+            tx.commit()?;
+
+            Ok(salts)
+        }).await
+    }
+
+    async fn unique_visitors(&self, since: NaiveDate) -> Result<Vec<(NaiveDate, u64)>, DbError> {
+
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT day, COUNT(*) FROM visitorDaily WHERE day >= ?1 GROUP BY day ORDER BY day"
+            )?;
+            let counts = stmt.query_map(params![since], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            counts.collect()
+        }).await
+    }
+}
+
+
+#[async_trait]
+impl AuditStore for SqliteStore {
+
+    async fn log_event(&self, event: &AuditEvent) -> Result<(), DbError> {
+
+        let event = event.clone();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO adminAudit (eventTime, action, clientIp, entryId, detail) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![event.time_stamp, event.action.as_str(), event.client_ip, audit_entry_id(&event), event.detail]
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn recent_events(&self, limit: u64) -> Result<Vec<AuditEvent>, DbError> {
+
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "
+                SELECT eventTime, action, clientIp, entryId, detail
+                FROM adminAudit
+                ORDER BY id DESC
+                LIMIT ?1"
+            )?;
+            let rows = stmt.query_map([limit], |row| Ok((
+                row.get::<_, NaiveDateTime>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            )))?;
+
+            let mut events = Vec::new();
+            for row in rows {
+                let (time_stamp, action, client_ip, entry_id, detail) = row?;
+                if let Some(action) = AuditAction::from_db(&action) {
+                    events.push(AuditEvent { time_stamp, action, client_ip, entry_id: entry_id.map(|id| id.to_string()), detail });
+                }
+            }
+            Ok(events)
+        }).await
+    }
+}
@@ -0,0 +1,165 @@
+// Unique visitors, counted without keeping IPs or setting cookies. This is
+// more or less what Plausible does: each visitor is identified by a hash of
+// their IP and user agent, plus a random salt that changes every day. Only
+// the hash is kept. Once a day is over, its salt is deleted, and from then
+// on there's no way to get from an IP back to its hash, even by trying every
+// IP there is. The catch is that the same visitor gets a different hash each
+// day, so they can only be told apart within a day.
+//
+// The salts live in the visitorSalt table, so every replica of the server
+// hashes the same visitor the same way. log_hit() can't wait on the database,
+// though, so the HitCounter keeps today's and tomorrow's salts in memory,
+// and picks up new ones when it flushes. Having tomorrow's already there
+// means hits right after midnight still get hashed.
+//
+// Clients that send DNT: 1 or Sec-GPC: 1 aren't hashed at all (their hits
+// are still counted), and neither are bots.
+use std::{net::IpAddr, sync::{Mutex, MutexGuard, PoisonError}};
+use mysql_common::chrono::{Days, NaiveDate};
+use sha2::{Digest, Sha256};
+
+
+const SALT_BYTES: usize = 16;
+
+// the widths of the columns these go in, as hex
+pub const SALT_CHARS: usize = SALT_BYTES * 2;
+pub const VISITOR_HASH_CHARS: usize = 64;
+
+// one row of visitorSalt
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailySalt {
+    pub day: NaiveDate,
+    pub salt: String,   // hex
+}
+
+// one row of visitorDaily
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Visitor {
+    pub day: NaiveDate,
+    pub hash: String,   // hex
+}
+
+#[derive(Default)]
+pub struct VisitorSalts {
+    salts: Mutex<Vec<DailySalt>>,
+}
+
+impl VisitorSalts {
+
+    // Brand new salts for today and tomorrow, for the store to add if it
+    // doesn't have them yet. If it does (another replica got there first),
+    // it keeps the ones it has, and hands those back instead.
+    pub fn fresh(today: NaiveDate) -> Vec<DailySalt> {
+
+        days_needed(today).into_iter()
+            .flatten()
+            .map(|day| DailySalt { day, salt: random_salt() })
+            .collect()
+    }
+
+    pub fn needs_refresh(&self, today: NaiveDate) -> bool {
+        let salts = self.lock_salts();
+        !days_needed(today).into_iter().flatten().all(|day| salts.iter().any(|salt| salt.day == day))
+    }
+
+    // takes the salts the store handed back, and forgets the rest
+    pub fn replace(&self, salts: Vec<DailySalt>) {
+        *self.lock_salts() = salts;
+    }
+
+    // None if there's no salt for the day, which happens
+    // if the database has been down since startup
+    pub fn visitor(&self, today: NaiveDate, ip: IpAddr, user_agent: &str) -> Option<Visitor> {
+        self.hash(today, ip, user_agent, None)
+    }
+
+    // The same visitor, but hashed along with what it's for, like a reaction
+    // to some entry (see srv_io/reactions.rs), so it can't be matched up with
+    // their hash in visitorDaily, or their hash for anything else
+    pub fn visitor_for(&self, today: NaiveDate, ip: IpAddr, user_agent: &str, what: &str) -> Option<Visitor> {
+        self.hash(today, ip, user_agent, Some(what))
+    }
+
+    fn hash(&self, today: NaiveDate, ip: IpAddr, user_agent: &str, what: Option<&str>) -> Option<Visitor> {
+
+        let salts = self.lock_salts();
+        let salt = salts.iter().find(|salt| salt.day == today)?;
+
+        // the salt is all hex, and neither an IP nor a header
+        // ever has a \0 in it, so none of these can run into the next
+        let mut hasher = Sha256::new();
+        hasher.update(salt.salt.as_bytes());
+        hasher.update(ip.to_string().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(user_agent.as_bytes());
+        if let Some(what) = what {
+            hasher.update(b"\0");
+            hasher.update(what.as_bytes());
+        }
+
+        let hash = hasher.finalize().iter().map(|byte| format!("{byte:02x}")).collect();
+        Some(Visitor { day: today, hash })
+    }
+
+    // nothing is ever left half-changed in here by a panic
+    fn lock_salts(&self) -> MutexGuard<'_, Vec<DailySalt>> {
+        self.salts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// tomorrow's is None at the end of time
+fn days_needed(today: NaiveDate) -> [Option<NaiveDate>; 2] {
+    [Some(today), today.checked_add_days(Days::new(1))]
+}
+
+fn random_salt() -> String {
+    let mut bytes = [0u8; SALT_BYTES];
+    // unwrap only panics if the OS has no source of randomness at all,
+    // in which case there's nothing sensible to fall back on
+    getrandom::fill(&mut bytes).unwrap();
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn salts_rotate_daily() {
+        let today = date("2025-03-13");
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let salts = VisitorSalts::default();
+        assert!(salts.needs_refresh(today));
+        assert_eq!(salts.visitor(today, ip, "Firefox"), None);
+
+        let fresh = VisitorSalts::fresh(today);
+        assert_eq!(fresh.iter().map(|salt| salt.day).collect::<Vec<_>>(), vec![today, date("2025-03-14")]);
+        assert!(fresh.iter().all(|salt| salt.salt.len() == SALT_CHARS));
+        assert_ne!(fresh[0].salt, fresh[1].salt);
+        salts.replace(fresh);
+        assert!(!salts.needs_refresh(today));
+        assert!(salts.needs_refresh(date("2025-03-14")));
+
+        // the same within a day, but not across days, or for another IP or user agent
+        let visitor = salts.visitor(today, ip, "Firefox").unwrap();
+        assert_eq!(visitor.hash.len(), VISITOR_HASH_CHARS);
+        assert_eq!(salts.visitor(today, ip, "Firefox"), Some(visitor.clone()));
+        assert_ne!(salts.visitor(date("2025-03-14"), ip, "Firefox").unwrap().hash, visitor.hash);
+        assert_ne!(salts.visitor(today, "203.0.113.8".parse().unwrap(), "Firefox").unwrap().hash, visitor.hash);
+        assert_ne!(salts.visitor(today, ip, "Chrome").unwrap().hash, visitor.hash);
+        assert_eq!(salts.visitor(date("2025-03-15"), ip, "Firefox"), None);
+
+        // and hashing it for something else makes a different one each time
+        let for_one = salts.visitor_for(today, ip, "Firefox", "reaction to 1").unwrap();
+        assert_eq!(salts.visitor_for(today, ip, "Firefox", "reaction to 1"), Some(for_one.clone()));
+        assert_ne!(for_one.hash, visitor.hash);
+        assert_ne!(salts.visitor_for(today, ip, "Firefox", "reaction to 2").unwrap().hash, for_one.hash);
+        assert_eq!(salts.visitor_for(date("2025-03-15"), ip, "Firefox", "reaction to 1"), None);
+    }
+}
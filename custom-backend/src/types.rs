@@ -32,7 +32,7 @@ pub mod lb_app_types {
 pub mod db_io_types {
 
     use ts_rs::TS;
-    use mysql_common::chrono::{NaiveDateTime, Utc, SubsecRound};
+    use mysql_common::chrono::{NaiveDate, NaiveDateTime, Utc, SubsecRound};
     
     #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)] 
     #[serde(rename_all = "camelCase")]
@@ -46,6 +46,33 @@ pub mod db_io_types {
         pub time_stamp: Option<NaiveDateTime>,
         pub name: String,
         pub note: String,
+        // only set for the entries an admin has pinned to the 
+        // top of the guestbook; the server ignores it in POSTs
+        #[ts(optional)]
+        #[serde(default)]
+        pub pinned: Option<bool>,
+        // what the spam filter made of it; only sent to the admin,
+        // and the server ignores it in POSTs too
+        #[ts(optional)]
+        #[serde(default)]
+        pub spam: Option<SpamDecision>,
+        // the ID of the entry it replies to, if it's a reply; that comes
+        // from the URL (see srv_io/replies.rs), so it's ignored in POSTs
+        #[ts(optional)]
+        #[serde(default)]
+        pub parent_id: Option<String>,
+        // Its replies, oldest first, in the whole guestbook and in pages,
+        // where they're nested under what they reply to (see srv_io/replies.rs).
+        // Search results don't nest them, so they leave this out.
+        #[ts(optional)]
+        #[serde(default)]
+        pub replies: Option<Vec<GuestbookEntry>>,
+        // How many of each emoji it's gotten, in the order they're offered
+        // in, leaving out any it hasn't (see srv_io/reactions.rs); only
+        // there if any have been left.
+        #[ts(optional)]
+        #[serde(default)]
+        pub reactions: Option<Vec<ReactionCount>>,
     }
 
     #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)] 
@@ -55,6 +82,379 @@ pub mod db_io_types {
     pub struct EntryReceipt {
         pub time_stamp: NaiveDateTime,
         pub id: String,
+        pub status: EntryStatus,    // pending if it's waiting to be approved before it's shown
+        // The author's secret for changing or taking back their entry, with
+        // PATCH or DELETE /guestbook/entries/{id}, until editable_until (see
+        // srv_io/author_edits.rs). Only the server's hash of it is kept, so 
+        // it can't be sent again. Neither's there if that's turned off.
+        #[ts(optional)]
+        #[serde(default)]
+        pub edit_token: Option<String>,
+        #[ts(optional)]
+        #[serde(default)]
+        pub editable_until: Option<NaiveDateTime>,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct ReactionCount {
+        pub emoji: String,
+        #[ts(type = "number")]
+        pub count: u64,
+    }
+
+    // the body of POST /guestbook/entries/{id}/reactions
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct NewReaction {
+        pub emoji: String,
+    }
+
+    // what a reaction's POST gets back; counted is false if the client had
+    // already left that one on the entry, and reactions is all of its counts
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct ReactionReceipt {
+        pub id: String,
+        pub counted: bool,
+        pub reactions: Vec<ReactionCount>,
+    }
+
+    // GET /guestbook/reactions; the emoji that can be left on entries, in order
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct ReactionOptions {
+        pub emoji: Vec<String>,
+    }
+
+    // the body of a 429, for when a client's over its rate limit;
+    // retry_after is the same as the Retry-After header
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct RateLimited {
+        pub message: String,
+        #[ts(type = "number")]
+        pub retry_after: u64,   // in seconds
+    }
+
+    // A proof-of-work challenge for a new guestbook entry (see srv_io/bot_check.rs),
+    // from GET /guestbook/challenge. The client has to find a nonce where the
+    // SHA-256 of "{token}:{nonce}" starts with `difficulty` zero bits, and send
+    // it back with the entry before expires_at. Each token's only good once.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct PowChallenge {
+        pub token: String,
+        pub difficulty: u32,
+        pub expires_at: NaiveDateTime,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct PowSolution {
+        pub token: String,
+        #[ts(type = "number")]
+        pub nonce: u64,     // well under 2^53 for any sensible difficulty, so JS is fine with it
+    }
+
+    // What's POSTed to /guestbook/entries: the entry, as it always was, plus
+    // the solved challenge, if the server's asking for one, and the honeypot.
+    // Nobody sees the website field on the page, so only bots fill it in.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct GuestbookPost {
+        #[serde(flatten)]
+        pub entry: GuestbookEntry,
+        #[ts(optional)]
+        #[serde(default)]
+        pub challenge: Option<PowSolution>,
+        #[ts(optional)]
+        #[serde(default)]
+        pub website: Option<String>,
+    }
+
+    impl From<GuestbookEntry> for GuestbookPost {
+        fn from(entry: GuestbookEntry) -> GuestbookPost {
+            GuestbookPost { entry, challenge: None, website: None }
+        }
+    }
+
+    // why a new entry was turned away before it got anywhere near the database
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub enum BotCheckError {
+        MissingChallenge,   // there wasn't a solved challenge with it
+        UnknownChallenge,   // the token wasn't one the server handed out
+        ReusedChallenge,    // or it was, but it's been used already
+        ExpiredChallenge,
+        WrongSolution,      // the nonce doesn't give enough zero bits
+        Honeypot,           // the hidden field was filled in
+    }
+
+    // the body of the 400 for one of those
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct BotCheckFailed {
+        pub error: BotCheckError,
+        pub message: String,
+    }
+
+    // why an author couldn't change or take back their entry
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub enum AuthorEditError {
+        MissingToken,       // there wasn't an Authorization: Bearer header
+        InvalidToken,       // it isn't the entry's token (or the entry never had one)
+        ExpiredToken,       // it is, but it's too late to use it
+        NoSuchEntry,        // or the entry's been deleted
+        NothingToChange,
+        NameTooLong,
+        NoteTooLong,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct AuthorEditFailed {
+        pub error: AuthorEditError,
+        pub message: String,
+    }
+
+    // Only approved entries are shown. With GUESTBOOK_MODERATION on, new
+    // ones start out pending, until an admin approves or rejects them.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy, TS)]
+    #[serde(rename_all = "lowercase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "lowercase")]
+    pub enum EntryStatus {
+        Pending,
+        Approved,
+        Rejected,
+    }
+
+    impl EntryStatus {
+
+        // as it's kept in guestbook.status
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                EntryStatus::Pending  => "pending",
+                EntryStatus::Approved => "approved",
+                EntryStatus::Rejected => "rejected",
+            }
+        }
+
+        pub fn from_db(status: &str) -> Option<EntryStatus> {
+            match status {
+                "pending"  => Some(EntryStatus::Pending),
+                "approved" => Some(EntryStatus::Approved),
+                "rejected" => Some(EntryStatus::Rejected),
+                _          => None,
+            }
+        }
+    }
+
+    // What the spam filter does with a new entry (see utils/spam_filter.rs).
+    // Held entries wait for the admin, like pending ones, and dropped ones
+    // are rejected, without telling whoever sent it. In order of severity.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, TS)]
+    #[serde(rename_all = "lowercase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "lowercase")]
+    pub enum SpamVerdict {
+        Accept,
+        Hold,
+        Drop,
+    }
+
+    impl SpamVerdict {
+
+        // as it's kept in guestbook.spamVerdict
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                SpamVerdict::Accept => "accept",
+                SpamVerdict::Hold   => "hold",
+                SpamVerdict::Drop   => "drop",
+            }
+        }
+
+        pub fn from_db(verdict: &str) -> Option<SpamVerdict> {
+            match verdict {
+                "accept" => Some(SpamVerdict::Accept),
+                "hold"   => Some(SpamVerdict::Hold),
+                "drop"   => Some(SpamVerdict::Drop),
+                _        => None,
+            }
+        }
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct SpamDecision {
+        pub verdict: SpamVerdict,
+        #[ts(optional)]
+        #[serde(default)]
+        pub reason: Option<String>,     // what gave it away, if it wasn't accepted
+    }
+
+    impl SpamDecision {
+
+        // from guestbook.spamVerdict and spamReason, which
+        // are both NULL for entries from before the filter
+        pub fn from_db(verdict: Option<&str>, reason: Option<String>) -> Option<SpamDecision> {
+            verdict.and_then(SpamVerdict::from_db).map(|verdict| SpamDecision { verdict, reason })
+        }
+    }
+
+    // what POST /admin/guestbook/entries/{id}/approve (or /reject) sends back
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct ModerationReceipt {
+        pub id: String,
+        pub status: EntryStatus,
+    }
+
+    // PATCH /admin/guestbook/entries/{id}; whichever of the two is left out stays as it is
+    #[derive(Debug, Default, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct EntryChanges {
+        #[ts(optional)]
+        #[serde(default)]
+        pub name: Option<String>,
+        #[ts(optional)]
+        #[serde(default)]
+        pub note: Option<String>,
+    }
+
+    // what an entry said before one of those edits
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct EntryEdit {
+        pub edit_time: NaiveDateTime,
+        pub old_name: String,
+        pub old_note: String,
+    }
+
+    // GET /admin/guestbook/entries/{id}/edits
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct EntryHistory {
+        pub id: String,
+        pub edits: Vec<EntryEdit>,      // oldest first
+    }
+
+    // POST /admin/login
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct AdminLogin {
+        pub password: String,
+    }
+
+    // what POST /admin/login and GET /admin/session send back; the
+    // session itself is in a cookie the front end can't read
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct AdminSession {
+        pub expires_at: NaiveDateTime,      // UTC
+    }
+
+    // One row of the admin audit trail (see srv_io/admin_auth.rs).
+    // GET /admin/audit sends back the latest ones, newest first.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct AuditEvent {
+        pub time_stamp: NaiveDateTime,
+        pub action: AuditAction,
+        pub client_ip: String,
+        pub entry_id: Option<String>,   // for the ones about a guestbook entry
+        pub detail: Option<String>,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy, TS)]
+    #[serde(rename_all = "snake_case")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "snake_case")]
+    pub enum AuditAction {
+        Login,
+        LoginFailed,        // the wrong password
+        LoginThrottled,     // turned away without checking, after too many of those
+        Logout,
+        Approve,
+        Reject,
+        Delete,
+        Restore,
+        Edit,
+        Pin,
+        Unpin,
+        Reply,              // the owner replying to an entry
+    }
+
+    impl AuditAction {
+
+        pub const ALL: [AuditAction; 12] = [
+            AuditAction::Login, AuditAction::LoginFailed, AuditAction::LoginThrottled,
+            AuditAction::Logout, AuditAction::Approve, AuditAction::Reject,
+            AuditAction::Delete, AuditAction::Restore, AuditAction::Edit,
+            AuditAction::Pin, AuditAction::Unpin, AuditAction::Reply,
+        ];
+
+        // as it's kept in adminAudit.action
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                AuditAction::Login          => "login",
+                AuditAction::LoginFailed    => "login_failed",
+                AuditAction::LoginThrottled => "login_throttled",
+                AuditAction::Logout         => "logout",
+                AuditAction::Approve        => "approve",
+                AuditAction::Reject         => "reject",
+                AuditAction::Delete         => "delete",
+                AuditAction::Restore        => "restore",
+                AuditAction::Edit           => "edit",
+                AuditAction::Pin            => "pin",
+                AuditAction::Unpin          => "unpin",
+                AuditAction::Reply          => "reply",
+            }
+        }
+
+        // None for anything a newer build might have added
+        pub fn from_db(action: &str) -> Option<AuditAction> {
+            AuditAction::ALL.into_iter().find(|known| known.as_str() == action)
+        }
+    }
+
+    // GET /admin/audit
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct AuditTrail {
+        pub events: Vec<AuditEvent>,    // newest first
     }
 
     // This struct exists for organinzing all the JSON 
@@ -66,6 +466,56 @@ pub mod db_io_types {
         pub guestbook: Vec<GuestbookEntry>,
     }
 
+    // GET /guestbook/entries with any of limit, before, after, or order
+    // (without them, it's still the whole Guestbook, as it always was).
+    // To get the next page, send the same request again, with nextCursor
+    // as `before` if the order is desc, or as `after` if it's asc. It's
+    // null on the last page.
+    #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct GuestbookPage {
+        pub entries: Vec<GuestbookEntry>,
+        pub next_cursor: Option<String>,    // the ID of the last entry
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, TS)]
+    #[serde(rename_all = "lowercase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "lowercase")]
+    pub enum SortOrder {
+        Asc,    // oldest first
+        Desc,   // newest first
+    }
+
+    // GET /guestbook/search?q=, best matches first (see storage/entry_search.rs)
+    #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct GuestbookSearch {
+        pub terms: Vec<String>,     // what was searched for, after cleaning up q
+        pub results: Vec<SearchResult>,
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct SearchResult {
+        pub entry: GuestbookEntry,
+        pub name: Vec<TextPart>,        // the entry's name, split up around the matches
+        pub snippet: Vec<TextPart>,     // the same, for the note, or the part of it around the first match
+        #[ts(type = "number")]
+        pub score: u64,
+    }
+
+    // Highlights are sent as parts rather than markup, so the client
+    // doesn't have to trust any HTML that came out of someone's note
+    #[derive(Debug, serde::Serialize, serde::Deserialize, PartialEq, Clone, TS)]
+    #[ts(export, export_to="server-types.ts")]
+    pub struct TextPart {
+        pub text: String,
+        pub highlight: bool,
+    }
+
     #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
     #[serde(rename_all = "camelCase")]
     #[ts(export, export_to="server-types.ts")]
@@ -73,6 +523,24 @@ pub mod db_io_types {
     pub struct WebpageHit {
         pub time_stamp: NaiveDateTime,
         pub user_agent: String,
+        // The rest are filled in by the server, from the request for the 
+        // page (see srv_io/page_views.rs). Hits POSTed to /hits can leave 
+        // them out, and the old front end did.
+        #[ts(optional)]
+        #[serde(default)]
+        pub path: Option<String>,
+        #[ts(optional)]
+        #[serde(default)]
+        pub referrer: Option<String>,       // just the host, without any "www."
+        #[ts(optional)]
+        #[serde(default)]
+        pub utm_source: Option<String>,
+        #[ts(optional)]
+        #[serde(default)]
+        pub utm_medium: Option<String>,
+        #[ts(optional)]
+        #[serde(default)]
+        pub utm_campaign: Option<String>,
     }
 
     impl Default for WebpageHit {
@@ -81,8 +549,139 @@ pub mod db_io_types {
                 time_stamp: Utc::now()
                     .naive_utc()
                     .trunc_subsecs(0), 
-                user_agent: String::from("Mozilla user agent") 
+                user_agent: String::from("Mozilla user agent"),
+                path: None,
+                referrer: None,
+                utm_source: None,
+                utm_medium: None,
+                utm_campaign: None,
             }
         }
     }
-}
\ No newline at end of file
+
+    // What GET /hits/stats sends back. The counts are u64s on this side,
+    // but they'll never get near 2^53, so they're plain numbers in TS.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, TS)]
+    #[serde(rename_all = "lowercase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "lowercase")]
+    pub enum StatsBucket {
+        Hour,
+        Day,
+        Week,   // starting on Monday
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct HitStats {
+        pub from: NaiveDate,
+        pub to: NaiveDate,          // inclusive
+        pub bucket: StatsBucket,
+        #[ts(type = "number")]
+        pub total: u64,
+        pub series: Vec<BucketHits>,    // every bucket in the range, empty ones included
+        pub top_user_agents: Vec<UserAgentHits>,
+        pub first_visit: Option<NaiveDateTime>,
+        pub last_visit: Option<NaiveDateTime>,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct BucketHits {
+        pub start: NaiveDateTime,
+        #[ts(type = "number")]
+        pub hits: u64,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct UserAgentHits {
+        pub user_agent: String,
+        #[ts(type = "number")]
+        pub hits: u64,
+    }
+
+    // GET /hits/pages and GET /hits/referrers. Hits without a path (the ones
+    // POSTed to /hits) or a referrer (typed in, or bookmarked) are left out.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct TopPages {
+        pub from: NaiveDate,
+        pub to: NaiveDate,          // inclusive
+        pub pages: Vec<PageHits>,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct PageHits {
+        pub path: String,
+        #[ts(type = "number")]
+        pub hits: u64,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct TopReferrers {
+        pub from: NaiveDate,
+        pub to: NaiveDate,          // inclusive
+        pub referrers: Vec<ReferrerHits>,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct ReferrerHits {
+        pub referrer: String,       // a host
+        #[ts(type = "number")]
+        pub hits: u64,
+    }
+
+    // GET /hits/countries. Hits GeoIP couldn't place (or all
+    // of them, if it's off) are left out.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct TopCountries {
+        pub from: NaiveDate,
+        pub to: NaiveDate,          // inclusive
+        pub countries: Vec<CountryHits>,
+    }
+
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct CountryHits {
+        pub country: String,        // ISO 3166-1 alpha-2, e.g. "NZ"
+        #[ts(type = "number")]
+        pub hits: u64,
+    }
+
+    // GET /hits/visitors. A visitor is only recognized within a day
+    // (see storage/visitors.rs), so the month's count is the sum of 
+    // each day's, and someone who visits on two days counts twice.
+    #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Clone, TS)]
+    #[serde(rename_all = "camelCase")]
+    #[ts(export, export_to="server-types.ts")]
+    #[ts(rename_all = "camelCase")]
+    pub struct UniqueVisitors {
+        #[ts(type = "number")]
+        pub today: u64,
+        #[ts(type = "number")]
+        pub this_month: u64,
+    }
+}
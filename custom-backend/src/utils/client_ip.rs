@@ -0,0 +1,202 @@
+// Works out which IP a request really came from, when the server sits behind
+// a proxy (Cloudflare, or a load balancer). The peer is then the proxy, and
+// the client is only in a header the proxy added. Anyone can send those
+// headers, though, so they're only believed when the peer is one of the
+// proxies in TRUSTED_PROXIES, a comma-separated list of IPs and CIDR ranges:
+//
+//     TRUSTED_PROXIES=173.245.48.0/20,2400:cb00::/32,10.0.0.7
+//
+// Cloudflare's CF-Connecting-IP is used if it's there. Otherwise it's
+// X-Forwarded-For, read from the right (the end the proxies append to),
+// skipping over any of the trusted proxies, since the entries before the
+// first one that isn't could have come from the client. Without the list,
+// the peer's IP is used as is, like it always was.
+//
+// Everything that goes by the client's IP goes by this one: the rate limits,
+// reactions, the page views (the dedupe, visitor hashes and GeoIP), and the
+// admin's logins and audit trail. What's counted against a client (the rate
+// limits and the login attempts) goes by its client_key(), so an IPv6 client
+// can't get around them by hopping between its addresses.
+use std::{fmt, net::{IpAddr, Ipv6Addr}};
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use super::init_utils::get_env_var_or;
+
+
+pub const CF_CONNECTING_IP: &str = "cf-connecting-ip";
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[derive(Debug)]
+pub enum ClientIpError {
+    BadProxy(String),   // an entry in TRUSTED_PROXIES that isn't an IP or a range
+}
+
+impl fmt::Display for ClientIpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadProxy(entry) => write!(f, "\"{entry}\" in TRUSTED_PROXIES isn't an IP or a CIDR range"),
+        }
+    }
+}
+
+impl std::error::Error for ClientIpError {}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+
+    // trusts no one, so it's always the peer's IP
+    pub fn none() -> TrustedProxies {
+        TrustedProxies::default()
+    }
+
+    pub fn from_env() -> Result<TrustedProxies, ClientIpError> {
+        TrustedProxies::parse(&get_env_var_or("TRUSTED_PROXIES", String::new()))
+    }
+
+    pub fn parse(list: &str) -> Result<TrustedProxies, ClientIpError> {
+
+        let nets = list.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                // a lone IP is a range of one
+                entry.parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| ClientIpError::BadProxy(entry.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(TrustedProxies { nets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nets.is_empty()
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    // the client's IP, for a request from `peer` with these headers
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+
+        if !self.trusts(peer) {
+            return peer;
+        }
+
+        if let Some(cf_ip) = header_ip(headers, CF_CONNECTING_IP) {
+            return cf_ip;
+        }
+
+        // every X-Forwarded-For header, in order, as one list
+        let forwarded: Vec<&str> = headers.get_all(X_FORWARDED_FOR).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+
+        let mut client = peer;
+        for hop in forwarded.iter().rev() {
+            // anything that isn't an IP was made up by someone, so
+            // the last one that was trusted is as far as it goes
+            let Ok(hop) = hop.parse::<IpAddr>() else { break };
+            client = hop;
+            if !self.trusts(hop) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+fn header_ip(headers: &HeaderMap, name: &str) -> Option<IpAddr> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+// anyone with an IPv6 address usually has the whole /64
+// it's in, so that's what counts as one client, not each address
+pub fn client_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(v6.to_bits() & !u128::from(u64::MAX))),
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn reading_the_list() {
+        let proxies = TrustedProxies::parse(" 173.245.48.0/20, 2400:cb00::/32,10.0.0.7,").unwrap();
+        assert_eq!(proxies.len(), 3);
+        assert!(proxies.trusts(ip("173.245.63.255")));
+        assert!(!proxies.trusts(ip("173.245.64.0")));
+        assert!(proxies.trusts(ip("2400:cb00::1")));
+        assert!(proxies.trusts(ip("10.0.0.7")));
+        assert!(!proxies.trusts(ip("10.0.0.8")));
+
+        assert!(TrustedProxies::parse("").unwrap().is_empty());
+        assert!(matches!(TrustedProxies::parse("10.0.0.1,cloudflare"), Err(ClientIpError::BadProxy(entry)) if entry == "cloudflare"));
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn only_believing_trusted_proxies() {
+        let proxies = TrustedProxies::parse("173.245.48.0/20,10.0.0.0/8").unwrap();
+        let cloudflare = ip("173.245.48.1");
+        let client = ip("203.0.113.7");
+
+        // straight from the client, whatever it says
+        let spoofed = headers(&[(CF_CONNECTING_IP, "198.51.100.1"), (X_FORWARDED_FOR, "198.51.100.1")]);
+        assert_eq!(proxies.client_ip(client, &spoofed), client);
+        assert_eq!(TrustedProxies::none().client_ip(cloudflare, &spoofed), cloudflare);
+
+        assert_eq!(proxies.client_ip(cloudflare, &headers(&[(CF_CONNECTING_IP, "203.0.113.7")])), client);
+        assert_eq!(proxies.client_ip(cloudflare, &headers(&[(X_FORWARDED_FOR, "203.0.113.7")])), client);
+        assert_eq!(proxies.client_ip(cloudflare, &headers(&[])), cloudflare);
+
+        // the client made up the first one, and the load balancer
+        // sent it on from Cloudflare, which added the real one
+        let chain = headers(&[(X_FORWARDED_FOR, "198.51.100.1, 203.0.113.7"), (X_FORWARDED_FOR, "173.245.48.1")]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.2"), &chain), client);
+        assert_eq!(proxies.client_ip(ip("10.0.0.2"), &headers(&[(X_FORWARDED_FOR, "junk, 173.245.48.1")])), cloudflare);
+        assert_eq!(proxies.client_ip(ip("10.0.0.2"), &headers(&[(X_FORWARDED_FOR, "2001:db8::1")])), ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn grouping_clients() {
+        assert_eq!(client_key(ip("203.0.113.7")), ip("203.0.113.7"));
+        assert_eq!(client_key(ip("2001:db8:1:2:ffff::9")), ip("2001:db8:1:2::"));
+        assert_eq!(client_key(ip("2001:db8:1:2::1")), client_key(ip("2001:db8:1:2:ffff::9")));
+        assert_ne!(client_key(ip("2001:db8:1:2::1")), client_key(ip("2001:db8:1:3::1")));
+        assert_eq!(client_key(ip("::ffff:198.51.100.1")), ip("198.51.100.1"));
+    }
+}
@@ -1,5 +1,6 @@
+use std::time::Duration;
 use axum::{
-    http::StatusCode, 
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode}, 
     response::{Html, IntoResponse, Response}
 };
 
@@ -35,4 +36,31 @@ pub fn make_500_resp() -> Response {
     *err_resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
 
     err_resp
-}
\ No newline at end of file
+}
+// for when the database is known to be down, so the request isn't even tried;
+// Retry-After is in whole seconds, rounded up
+pub fn make_503_resp(retry_after: Duration) -> Response {
+
+    let default_error = "<!DOCTYPE html>\n\
+                    <html><head>\n\
+                    <title>503 Error</title>\n\
+                    </head>\n\
+                    <p>503 Error: Service Unavailable</p>\n\
+                    </html>".to_string();
+
+    let html_503_err = match ErrPage::get("static/errors/503.html") {
+        Some(err_page) => {
+            String::from_utf8(err_page.bytes.to_vec())
+                .unwrap_or(default_error)
+        },
+        None => default_error
+    };
+
+    let retry_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+    let mut err_resp = Html(html_503_err).into_response();
+    *err_resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    err_resp.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_secs));
+
+    err_resp
+}
@@ -0,0 +1,179 @@
+// Works out which country (and region of it) each hit came from, by looking
+// the IP up in a MaxMind database file (GeoLite2-Country, GeoIP2-City, or
+// anything else in that format), so no outside service is ever asked. Only
+// the ISO codes are kept, in hitLog.country and hitLog.region; the IP itself
+// goes no further than the lookup.
+//
+// This is behind the geoip cargo feature, since most setups won't have a
+// database to point it at, and builds without it don't need the reader.
+// With it, GEOIP_DB_PATH is the path to the .mmdb file, which is read into
+// memory at startup. Without either, nothing's looked up, and the columns
+// are left NULL.
+use std::{fmt, net::IpAddr};
+#[cfg(not(feature = "geoip"))]
+use tracing::warn;
+use super::init_utils::get_env_var_or;
+
+
+// the widths of the columns these go in
+pub const COUNTRY_CHARS: usize = 2;         // ISO 3166-1 alpha-2
+pub const MAX_REGION_CHARS: usize = 3;      // the part of an ISO 3166-2 code after the dash
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub country: String,
+    pub region: Option<String>,     // the first (biggest) subdivision, if the database has them
+}
+
+#[derive(Debug)]
+pub enum GeoIpError {
+    OpenFailed(String, String),     // the path, and what went wrong
+}
+
+impl fmt::Display for GeoIpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OpenFailed(path, e) => write!(f, "couldn't open the GeoIP database at {path}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for GeoIpError {}
+
+
+// the parts of a record this looks at, which the
+// Country and City databases both have
+#[cfg(feature = "geoip")]
+#[derive(serde::Deserialize)]
+struct MmdbRecord {
+    country: Option<IsoCode>,
+    subdivisions: Option<Vec<IsoCode>>,
+}
+
+#[cfg(feature = "geoip")]
+#[derive(serde::Deserialize)]
+struct IsoCode {
+    iso_code: Option<String>,
+}
+
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    reader: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+
+    pub fn disabled() -> GeoIp {
+        GeoIp {
+            #[cfg(feature = "geoip")]
+            reader: None,
+        }
+    }
+
+    // the database at GEOIP_DB_PATH, if it's set
+    pub fn from_env() -> Result<GeoIp, GeoIpError> {
+
+        let path: String = get_env_var_or("GEOIP_DB_PATH", String::new());
+        if path.is_empty() {
+            return Ok(GeoIp::disabled());
+        }
+
+        GeoIp::open(&path)
+    }
+
+    #[cfg(feature = "geoip")]
+    pub fn open(path: &str) -> Result<GeoIp, GeoIpError> {
+
+        let reader = maxminddb::Reader::open_readfile(path)
+            .map_err(|e| GeoIpError::OpenFailed(path.to_string(), e.to_string()))?;
+
+        Ok(GeoIp { reader: Some(reader) })
+    }
+
+    // it'd be easy to miss that the setting isn't doing anything, so it's
+    // pointed out, but it isn't worth refusing to start over
+    #[cfg(not(feature = "geoip"))]
+    pub fn open(path: &str) -> Result<GeoIp, GeoIpError> {
+        warn!("GEOIP_DB_PATH is set to {path}, but this build doesn't have the geoip feature, so it's ignored.");
+        Ok(GeoIp::disabled())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "geoip")]
+        return self.reader.is_some();
+        #[cfg(not(feature = "geoip"))]
+        false
+    }
+
+    // None if it's off, or the IP isn't in the database (private
+    // addresses never are), or the record has no usable country
+    #[cfg(feature = "geoip")]
+    pub fn locate(&self, ip: IpAddr) -> Option<Location> {
+
+        let record: MmdbRecord = self.reader.as_ref()?.lookup(ip).ok()?;
+
+        let country = record.country?.iso_code.filter(|code| is_code(code, COUNTRY_CHARS, COUNTRY_CHARS))?;
+        let region = record.subdivisions
+            .and_then(|subdivisions| subdivisions.into_iter().next())
+            .and_then(|subdivision| subdivision.iso_code)
+            .filter(|code| is_code(code, 1, MAX_REGION_CHARS));
+
+        Some(Location { country, region })
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn locate(&self, _ip: IpAddr) -> Option<Location> {
+        None
+    }
+}
+
+// anything else would be a strange database, and might not fit the column
+#[cfg(feature = "geoip")]
+fn is_code(code: &str, min_chars: usize, max_chars: usize) -> bool {
+    (min_chars..=max_chars).contains(&code.len())
+        && code.bytes().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn disabled_finds_nothing() {
+        let geoip = GeoIp::disabled();
+        assert!(!geoip.is_enabled());
+        assert_eq!(geoip.locate("203.0.113.7".parse().unwrap()), None);
+    }
+
+    // tests/fixtures/geoip-test.mmdb only has the documentation ranges in it
+    // (see make_geoip_fixture.py, next to it)
+    #[cfg(feature = "geoip")]
+    pub const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/geoip-test.mmdb");
+
+    #[cfg(feature = "geoip")]
+    #[test]
+    fn looks_up_the_fixture() {
+        let geoip = GeoIp::open(FIXTURE).unwrap();
+        assert!(geoip.is_enabled());
+
+        let locate = |ip: &str| geoip.locate(ip.parse().unwrap());
+        let at = |country: &str, region: Option<&str>| Some(Location {
+            country: country.to_string(),
+            region: region.map(str::to_string),
+        });
+        assert_eq!(locate("203.0.113.7"), at("NZ", Some("AUK")));
+        assert_eq!(locate("198.51.100.1"), at("DE", None));
+        assert_eq!(locate("2001:db8::1"), at("JP", Some("13")));
+        assert_eq!(locate("192.0.2.1"), None);
+        assert_eq!(locate("127.0.0.1"), None);
+    }
+
+    #[cfg(feature = "geoip")]
+    #[test]
+    fn bad_database() {
+        assert!(matches!(GeoIp::open("/nowhere/at/all.mmdb"), Err(GeoIpError::OpenFailed(..))));
+        assert!(matches!(GeoIp::open(file!()), Err(GeoIpError::OpenFailed(..))));
+    }
+}
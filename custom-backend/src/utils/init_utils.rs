@@ -1,9 +1,10 @@
 use std::{
     env::{self, VarError},
     fs::OpenOptions,
-    io::{Error, ErrorKind}
+    io::{Error, ErrorKind},
+    str::FromStr
 };
-use tracing::error;
+use tracing::{error, warn};
 use tracing_subscriber::{
     fmt::{
         format::{DefaultFields, Format}, 
@@ -17,7 +18,18 @@ use tracing_subscriber::{
 pub enum RunMode {
     Tls,
     NoTls,
-    PrintHelp
+    PrintHelp,
+    Check,
+    HashPassword,
+    Migrate(MigrateCmd)
+}
+
+// `archie-server migrate ...`; these run, then the server quits
+#[derive(Debug, PartialEq)]
+pub enum MigrateCmd {
+    Up,
+    Down(u32),      // how many migrations to revert
+    Status
 }
 
 
@@ -62,6 +74,21 @@ pub fn get_env_var(env_var: &str) -> Result<String, VarError> {
     }
 }
 
+// for the optional settings: an unset variable quietly gets the default,
+// but one that's set to something unparsable gets a warning too
+pub fn get_env_var_or<T: FromStr>(env_var: &str, default: T) -> T {
+
+    let Some(os_val) = env::var_os(env_var) else { return default; };
+
+    match os_val.into_string().ok().and_then(|s| s.parse::<T>().ok()) {
+        Some(val) => val,
+        None => {
+            warn!("Could not parse the value of {env_var}; using the default instead.");
+            default
+        }
+    }
+}
+
 // unwraps cannot panic here either; Results don't have errors in them
 // expect() may panic, but this function is only ever called in main
 pub fn get_auth_paths() -> (String, String) {
@@ -81,11 +108,42 @@ pub fn get_auth_paths() -> (String, String) {
 
 
 fn print_help() {
-    println!("\nUsage:  archie-server [OPTION]\n");
+    println!("\nUsage:  archie-server [OPTION]");
+    println!("        archie-server migrate <up | down [N] | status>");
+    println!("        archie-server hash-password\n");
     println!("The executable that runs the server.\n");
     println!("Options:");
     println!("    --no-tls     Run without TLS; serve over HTTP");
+    println!("    --check      Check the database's schema and privileges, then quit");
     println!("    --help, -h   Print this help message and quit\n");
+    println!("Migrations:");
+    println!("    up           Apply all pending schema migrations");
+    println!("    down [N]     Revert the last N applied migrations (default: 1)");
+    println!("    status       List applied and pending migrations\n");
+    println!("The server applies pending migrations on startup, unless DB_AUTO_MIGRATE=false.\n");
+    println!("hash-password reads the admin password from stdin, and prints");
+    println!("the hash to put in ADMIN_PASSWORD_HASH.\n");
+}
+
+// args are whatever comes after `migrate`
+fn parse_migrate_cmd(args: &[String]) -> Result<MigrateCmd, Error> {
+
+    let bad_input = |msg: String| {
+        print_help();
+        Err(Error::new(ErrorKind::InvalidInput, msg))
+    };
+
+    match args {
+        [cmd] if cmd == "up"     => Ok(MigrateCmd::Up),
+        [cmd] if cmd == "status" => Ok(MigrateCmd::Status),
+        [cmd] if cmd == "down"   => Ok(MigrateCmd::Down(1)),
+        [cmd, steps] if cmd == "down" => match steps.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(MigrateCmd::Down(n)),
+            _ => bad_input(format!("\"{steps}\" is not a number of migrations to revert.")),
+        },
+        [] => bad_input(String::from("migrate needs one of: up, down, status.")),
+        other => bad_input(format!("\"migrate {}\" is not recognized.", other.join(" "))),
+    }
 }
 
 //arg1: Option<String>
@@ -96,18 +154,24 @@ pub fn process_cli_args() -> Result<RunMode, Error> {
         Some(arg) => {
             match arg.as_str() {
                 "--no-tls"      => Ok(RunMode::NoTls),
+                "--check"       => Ok(RunMode::Check),
+                "hash-password" => Ok(RunMode::HashPassword),
+                "migrate"       => {
+                    let args: Vec<String> = std::env::args().skip(2).collect();
+                    Ok(RunMode::Migrate(parse_migrate_cmd(&args)?))
+                }
                 "--help" | "-h" => {
                     print_help();
-                    return Ok(RunMode::PrintHelp);
+                    Ok(RunMode::PrintHelp)
                 }
                 other => {
                     print_help();
-                    return Err(
+                    Err(
                         Error::new(
                             ErrorKind::InvalidInput,
                             format!("Option \"{other}\" is not recognized.")
                         )
-                    );
+                    )
                 }
             }
         },
@@ -139,6 +203,27 @@ mod tests {
         assert_eq!(get_env_var("NONEX_VAR"), Err(VarError::NotPresent));
     }
 
+    #[test]
+    fn get_env_var_with_default() {
+        // EX_VAR is set, but isn't a number
+        assert_eq!(get_env_var_or("EX_VAR", 5_usize), 5);
+        assert_eq!(get_env_var_or("NONEX_VAR", 5_usize), 5);
+        assert_eq!(get_env_var_or("EX_VAR", String::new()), "a value/here");
+    }
+
+    #[test]
+    fn migrate_args() {
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+        assert_eq!(parse_migrate_cmd(&args("up")).unwrap(), MigrateCmd::Up);
+        assert_eq!(parse_migrate_cmd(&args("status")).unwrap(), MigrateCmd::Status);
+        assert_eq!(parse_migrate_cmd(&args("down")).unwrap(), MigrateCmd::Down(1));
+        assert_eq!(parse_migrate_cmd(&args("down 3")).unwrap(), MigrateCmd::Down(3));
+        assert!(parse_migrate_cmd(&args("down 0")).is_err());
+        assert!(parse_migrate_cmd(&args("down all")).is_err());
+        assert!(parse_migrate_cmd(&args("up 2")).is_err());
+        assert!(parse_migrate_cmd(&args("")).is_err());
+    }
+
     #[test]
     fn logging_to_file() -> Result<(), Error> {
         let test_log_path = String::from("./test.log");
@@ -1,3 +1,8 @@
 pub mod init_utils;
+pub mod client_ip;
 pub mod err_handling;
-pub mod shutdown;
\ No newline at end of file
+pub mod geoip;
+pub mod shutdown;
+pub mod spam_bayes;
+pub mod spam_filter;
+pub mod user_agent;
\ No newline at end of file
@@ -0,0 +1,166 @@
+// A small naive Bayes spam scorer, for the spam filter (see spam_filter.rs).
+// It learns from the guestbook itself: entries the admin rejected are spam,
+// and approved ones aren't. The filter retrains it from scratch at startup,
+// and again a little while after the admin approves or rejects something (in
+// the background, once for however many they get through), which means
+// nothing has to be kept in sync.
+//
+// It's the usual Graham-style filter, with Robinson's fix for words that have
+// hardly been seen: each word gets the chance an entry with it is spam, and
+// the most telling of them (the ones furthest from a coin toss) are combined.
+use std::collections::{HashMap, HashSet};
+
+
+// shorter words are mostly "the" and "and", and longer ones are mostly junk
+const MIN_TOKEN_CHARS: usize = 3;
+const MAX_TOKEN_CHARS: usize = 30;
+// how many of an entry's words count towards its score
+const MAX_TELLING_TOKENS: usize = 15;
+// words closer than this to a coin toss don't say anything
+const MIN_TELLING: f64 = 0.1;
+// Robinson's: how much a word that's hardly been seen leans towards 0.5
+const PRIOR_WEIGHT: f64 = 1.0;
+const PRIOR: f64 = 0.5;
+
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TokenCounts {
+    spam: u32,      // how many spam entries had it
+    ham: u32,       // and how many of the rest
+}
+
+#[derive(Debug, Default)]
+pub struct BayesModel {
+    tokens: HashMap<String, TokenCounts>,
+    spam_entries: u32,
+    ham_entries: u32,
+}
+
+impl BayesModel {
+
+    pub fn new() -> BayesModel {
+        BayesModel::default()
+    }
+
+    pub fn learn(&mut self, text: &str, is_spam: bool) {
+
+        if is_spam { self.spam_entries += 1 } else { self.ham_entries += 1 }
+
+        for token in tokenize(text) {
+            let counts = self.tokens.entry(token).or_default();
+            if is_spam { counts.spam += 1 } else { counts.ham += 1 }
+        }
+    }
+
+    pub fn spam_entries(&self) -> u32 {
+        self.spam_entries
+    }
+
+    pub fn ham_entries(&self) -> u32 {
+        self.ham_entries
+    }
+
+    // The chance the text is spam, from 0 to 1. It's 0.5 if there's
+    // nothing to go on, like with nothing learned, or only new words.
+    pub fn score(&self, text: &str) -> f64 {
+
+        if self.spam_entries == 0 || self.ham_entries == 0 {
+            return PRIOR;
+        }
+
+        let mut telling: Vec<f64> = tokenize(text).iter()
+            .filter_map(|token| self.token_spamminess(token))
+            .filter(|p| (p - PRIOR).abs() >= MIN_TELLING)
+            .collect();
+        telling.sort_by(|a, b| (b - PRIOR).abs().total_cmp(&(a - PRIOR).abs()));
+        telling.truncate(MAX_TELLING_TOKENS);
+
+        if telling.is_empty() {
+            return PRIOR;
+        }
+
+        // in logs, so lots of small numbers multiplied together don't go to 0
+        let (ln_spam, ln_ham) = telling.iter()
+            .fold((0.0, 0.0), |(ln_spam, ln_ham), p| (ln_spam + p.ln(), ln_ham + (1.0 - p).ln()));
+
+        1.0 / (1.0 + (ln_ham - ln_spam).exp())
+    }
+
+    fn token_spamminess(&self, token: &str) -> Option<f64> {
+
+        let counts = self.tokens.get(token)?;
+        let spam_freq = f64::from(counts.spam) / f64::from(self.spam_entries);
+        let ham_freq = f64::from(counts.ham) / f64::from(self.ham_entries);
+        let seen = f64::from(counts.spam + counts.ham);
+
+        let p = spam_freq / (spam_freq + ham_freq);
+        // never quite sure, so one word can't decide it all on its own
+        Some(((PRIOR_WEIGHT * PRIOR + seen * p) / (PRIOR_WEIGHT + seen)).clamp(0.01, 0.99))
+    }
+}
+
+// Each distinct word, lowercased, once. Links get split up into their parts,
+// which is what's wanted, since the domain's what gives them away.
+fn tokenize(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| (MIN_TOKEN_CHARS..=MAX_TOKEN_CHARS).contains(&word.chars().count()))
+        .filter(|word| !word.chars().all(|c| c.is_ascii_digit()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn trained() -> BayesModel {
+        let mut model = BayesModel::new();
+        for spam in [
+            "Cheap replica watches at http://watches.example, best prices online",
+            "Best prices on replica handbags, visit http://bags.example now",
+            "Make money fast with our online casino, visit http://casino.example",
+            "Online casino bonus! Visit http://casino.example for free spins",
+        ] {
+            model.learn(spam, true);
+        }
+        for ham in [
+            "Love the site! The photos from the hike are great.",
+            "Hi Archie, found your blog through a friend. Great write-up on the homelab.",
+            "Thanks for the Postgres notes, they helped me set up my own server.",
+            "Great photos, and the guestbook is a fun throwback!",
+        ] {
+            model.learn(ham, false);
+        }
+        model
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = tokenize("Visit http://Casino.example NOW!! it's 2025, don't wait");
+        let mut tokens: Vec<_> = tokens.into_iter().collect();
+        tokens.sort();
+        assert_eq!(tokens, vec!["casino", "don't", "example", "http", "it's", "now", "visit", "wait"]);
+    }
+
+    #[test]
+    fn scoring() {
+        let model = trained();
+        assert_eq!((model.spam_entries(), model.ham_entries()), (4, 4));
+
+        let spam = model.score("Visit http://casino.example for the best online prices");
+        let ham = model.score("Great photos! Found the site through the homelab write-up.");
+        assert!(spam > 0.95, "{spam}");
+        assert!(ham < 0.05, "{ham}");
+
+        // nothing it's seen before
+        assert_eq!(model.score("Zebras quietly juggling"), 0.5);
+        assert_eq!(model.score(""), 0.5);
+        // nor anything to compare with
+        let mut spam_only = BayesModel::new();
+        spam_only.learn("casino casino casino", true);
+        assert_eq!(spam_only.score("casino"), 0.5);
+    }
+}
@@ -0,0 +1,580 @@
+// Looks over each new guestbook entry for spam, on top of the length checks
+// in update_guestbook(). The filter is a list of checks, each of which can
+// flag an entry to be held for the admin, or dropped, and say why; the worst
+// of what they say goes, and the reasons are kept with the entry, in
+// guestbook.spamVerdict and spamReason. Dropped entries are stored rejected,
+// and whoever sent one gets the same receipt as if it'd gone through. Held
+// ones are pending, like with GUESTBOOK_MODERATION on.
+//
+// The checks that come with it, from main(), are:
+//
+//   - banned words and patterns, from rules/spam.json (or the file at
+//     SPAM_RULES_PATH), some of which drop an entry, and some hold it
+//   - a cap on links, SPAM_MAX_LINKS (default 2); entries with more are held
+//   - the same note as one sent within SPAM_REPEAT_WINDOW_SECS (default an
+//     hour; 0 turns it off) is dropped, since it's usually a script
+//   - a naive Bayes score (see spam_bayes.rs), learned from what the admin
+//     rejects and approves; entries that score SPAM_BAYES_HOLD (default 0.9)
+//     are held, and SPAM_BAYES_DROP (default 0.99) are dropped. It only kicks
+//     in once there are SPAM_BAYES_MIN_ENTRIES (default 5) of each to go on.
+//
+// SPAM_FILTER=false turns the whole thing off. Anything else that implements
+// SpamCheck can be added on with SpamFilter::with_check().
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+use mysql_common::serde_json;
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use crate::{
+    srv_io::db_io::DbError,
+    storage::GuestbookStore,
+    types::db_io_types::{EntryStatus, GuestbookEntry, SpamDecision, SpamVerdict},
+};
+use super::{init_utils::get_env_var_or, spam_bayes::BayesModel, user_agent::truncate_chars};
+
+
+const BUILT_IN_RULES: &str = include_str!("../../rules/spam.json");
+
+// the widths of the columns these go in
+pub const MAX_VERDICT_CHARS: usize = 10;
+pub const MAX_SPAM_REASON_CHARS: usize = 200;
+
+// shorter notes ("Hi!", "Great site") are sent by plenty of people
+const MIN_REPEAT_CHARS: usize = 20;
+// how many notes are kept track of at once, for the repeats
+const MAX_TRACKED_NOTES: usize = 10_000;
+// how long after an approval or rejection the Bayes scorer's retrained, so
+// going through the whole queue only takes the one retrain
+const RETRAIN_AFTER: Duration = Duration::from_secs(10);
+
+
+// One step of the filter. It says what it'd do with the entry, and why,
+// or None if it doesn't see anything wrong. `now` is for anything that
+// keeps track of time, so the tests don't have to wait around.
+pub trait SpamCheck: Send + Sync {
+    fn check(&self, entry: &GuestbookEntry, now: Instant) -> Option<(SpamVerdict, String)>;
+}
+
+
+#[derive(Debug)]
+pub enum SpamFilterError {
+    FileError(String, io::Error),
+    JsonError(serde_json::Error),
+    BadPattern(String, regex::Error),
+}
+
+impl fmt::Display for SpamFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FileError(path, e) => write!(f, "couldn't read the spam rules at {path}: {e}"),
+            Self::JsonError(e) => write!(f, "the spam rules aren't valid: {e}"),
+            Self::BadPattern(pattern, e) => write!(f, "bad pattern in the spam rules ({pattern}): {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SpamFilterError {}
+
+
+#[derive(Default)]
+pub struct SpamFilter {
+    checks: Vec<Arc<dyn SpamCheck>>,
+    bayes: Option<Arc<BayesCheck>>,     // also in checks; kept here to retrain it
+    retrain_queued: AtomicBool,         // whether retrain_soon() has one waiting to start
+    retraining: tokio::sync::Mutex<()>, // so they go one at a time, in the order they're queued
+}
+
+impl SpamFilter {
+
+    // lets everything through
+    pub fn disabled() -> SpamFilter {
+        SpamFilter::default()
+    }
+
+    pub fn from_env() -> Result<SpamFilter, SpamFilterError> {
+
+        if !get_env_var_or("SPAM_FILTER", true) {
+            return Ok(SpamFilter::disabled());
+        }
+
+        let path: String = get_env_var_or("SPAM_RULES_PATH", String::new());
+        let rules = if path.is_empty() {
+            BannedRules::built_in()
+        } else {
+            let json = fs::read_to_string(&path).map_err(|e| SpamFilterError::FileError(path, e))?;
+            BannedRules::from_json(&json)?
+        };
+
+        let mut filter = SpamFilter::disabled()
+            .with_check(rules)
+            .with_check(LinkLimit { max_links: get_env_var_or("SPAM_MAX_LINKS", 2) });
+
+        let repeat_window = Duration::from_secs(get_env_var_or("SPAM_REPEAT_WINDOW_SECS", 3600));
+        if !repeat_window.is_zero() {
+            filter = filter.with_check(RepeatedNotes::new(repeat_window));
+        }
+
+        Ok(filter.with_bayes(BayesCheck::new(
+            get_env_var_or("SPAM_BAYES_HOLD", 0.9),
+            get_env_var_or("SPAM_BAYES_DROP", 0.99),
+            get_env_var_or("SPAM_BAYES_MIN_ENTRIES", 5),
+        )))
+    }
+
+    pub fn with_check(mut self, check: impl SpamCheck + 'static) -> SpamFilter {
+        self.checks.push(Arc::new(check));
+        self
+    }
+
+    pub fn with_bayes(mut self, bayes: BayesCheck) -> SpamFilter {
+        let bayes = Arc::new(bayes);
+        self.checks.push(bayes.clone());
+        self.bayes = Some(bayes);
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.checks.is_empty()
+    }
+
+    // for the logs
+    pub fn check_count(&self) -> usize {
+        self.checks.len()
+    }
+
+    // Every check gets a look (so the repeats see every note), and
+    // the worst verdict wins, with the reasons of all that gave it
+    pub fn check(&self, entry: &GuestbookEntry, now: Instant) -> SpamDecision {
+
+        let flags: Vec<(SpamVerdict, String)> = self.checks.iter()
+            .filter_map(|check| check.check(entry, now))
+            .collect();
+
+        let verdict = flags.iter().map(|(verdict, _)| *verdict).max().unwrap_or(SpamVerdict::Accept);
+        if verdict == SpamVerdict::Accept {
+            return SpamDecision { verdict, reason: None };
+        }
+
+        let reasons = flags.into_iter()
+            .filter(|(flagged, _)| *flagged == verdict)
+            .map(|(_, reason)| reason)
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        SpamDecision { verdict, reason: Some(truncate_chars(&reasons, MAX_SPAM_REASON_CHARS).to_string()) }
+    }
+
+    // Starts the Bayes scorer over from what's in the guestbook now: rejected
+    // entries are spam, and approved ones aren't. The ones the filter dropped
+    // itself are left out, or it'd only ever be learning from itself.
+    pub async fn retrain(&self, guestbook: &dyn GuestbookStore) -> Result<(), DbError> {
+
+        let Some(bayes) = &self.bayes else {
+            return Ok(());
+        };
+
+        let spam = guestbook.entries_with_status(EntryStatus::Rejected).await?;
+        let ham = guestbook.entries_with_status(EntryStatus::Approved).await?;
+
+        let mut model = BayesModel::new();
+        for entry in spam.iter().filter(|entry| entry.spam.as_ref().is_none_or(|spam| spam.verdict != SpamVerdict::Drop)) {
+            model.learn(&entry_text(entry), true);
+        }
+        for entry in &ham {
+            model.learn(&entry_text(entry), false);
+        }
+
+        bayes.replace_model(model);
+        Ok(())
+    }
+
+    // For after the admin approves or rejects something. A retrain reads the
+    // whole guestbook, so it's done in the background, RETRAIN_AFTER from now,
+    // and whatever else they get through by then is picked up by the same one.
+    pub fn retrain_soon(self: Arc<Self>, guestbook: Arc<dyn GuestbookStore>) {
+
+        if self.bayes.is_none() || self.retrain_queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            tokio::time::sleep(RETRAIN_AFTER).await;
+            let _turn = self.retraining.lock().await;
+            // anything from here on might be after what this one reads, so it queues another
+            self.retrain_queued.store(false, Ordering::Release);
+            if let Err(db_err) = self.retrain(guestbook.as_ref()).await {
+                warn!("Couldn't retrain the spam filter: {db_err}");
+            }
+        });
+    }
+
+    // how many (spam, other) entries the Bayes scorer's learned from, if it's on
+    pub fn bayes_trained_on(&self) -> Option<(u32, u32)> {
+        self.bayes.as_ref().map(|bayes| bayes.trained_on())
+    }
+}
+
+fn entry_text(entry: &GuestbookEntry) -> String {
+    format!("{}\n{}", entry.name, entry.note)
+}
+
+
+// Words and patterns that drop an entry, and ones that hold it
+pub struct BannedRules {
+    drop: RuleSet,
+    hold: RuleSet,
+}
+
+struct RuleSet {
+    words: Option<Regex>,   // all of them, as one regex
+    patterns: Vec<Regex>,
+}
+
+#[derive(serde::Deserialize)]
+struct RulesFile {
+    drop: RuleSetFile,
+    hold: RuleSetFile,
+}
+
+#[derive(serde::Deserialize)]
+struct RuleSetFile {
+    #[serde(default)]
+    words: Vec<String>,
+    #[serde(default)]
+    patterns: Vec<String>,
+}
+
+impl BannedRules {
+
+    pub fn built_in() -> BannedRules {
+        // unwrap can't panic: the built-in rules are checked by the tests
+        BannedRules::from_json(BUILT_IN_RULES).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<BannedRules, SpamFilterError> {
+        let file: RulesFile = serde_json::from_str(json).map_err(SpamFilterError::JsonError)?;
+        Ok(BannedRules { drop: RuleSet::compile(file.drop)?, hold: RuleSet::compile(file.hold)? })
+    }
+}
+
+impl RuleSet {
+
+    fn compile(file: RuleSetFile) -> Result<RuleSet, SpamFilterError> {
+
+        let compile = |pattern: String| {
+            Regex::new(&pattern).map_err(|e| SpamFilterError::BadPattern(pattern, e))
+        };
+
+        let words: Vec<String> = file.words.iter()
+            .map(|word| word.trim())
+            .filter(|word| !word.is_empty())
+            .map(regex::escape)
+            .collect();
+        let words = if words.is_empty() {
+            None
+        } else {
+            let pattern = format!(r"\b(?:{})\b", words.join("|"));
+            // unwrap can't panic: they're all escaped
+            Some(RegexBuilder::new(&pattern).case_insensitive(true).build().unwrap())
+        };
+
+        Ok(RuleSet {
+            words,
+            patterns: file.patterns.into_iter().map(compile).collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn find(&self, text: &str) -> Option<String> {
+
+        if let Some(word) = self.words.as_ref().and_then(|words| words.find(text)) {
+            return Some(format!("banned word \"{}\"", word.as_str().to_lowercase()));
+        }
+
+        self.patterns.iter()
+            .find(|pattern| pattern.is_match(text))
+            .map(|pattern| format!("matches /{}/", pattern.as_str()))
+    }
+}
+
+impl SpamCheck for BannedRules {
+    fn check(&self, entry: &GuestbookEntry, _now: Instant) -> Option<(SpamVerdict, String)> {
+
+        let text = entry_text(entry);
+
+        self.drop.find(&text).map(|reason| (SpamVerdict::Drop, reason))
+            .or_else(|| self.hold.find(&text).map(|reason| (SpamVerdict::Hold, reason)))
+    }
+}
+
+
+pub struct LinkLimit {
+    pub max_links: usize,
+}
+
+impl SpamCheck for LinkLimit {
+    fn check(&self, entry: &GuestbookEntry, _now: Instant) -> Option<(SpamVerdict, String)> {
+
+        let links = count_links(&entry.name) + count_links(&entry.note);
+
+        (links > self.max_links).then(|| (SpamVerdict::Hold, format!("{links} links (more than {})", self.max_links)))
+    }
+}
+
+fn count_links(text: &str) -> usize {
+    text.split_whitespace()
+        .map(|word| word.to_lowercase())
+        .filter(|word| word.contains("http://") || word.contains("https://") || word.contains("www."))
+        .count()
+}
+
+
+// The same note (give or take case and spacing) as one that came in within
+// the window, from anyone. Every note counts, so a script that keeps
+// sending the same one keeps getting dropped.
+pub struct RepeatedNotes {
+    window: Duration,
+    last_seen: Mutex<HashMap<[u8; 32], Instant>>,   // by a hash of the note
+}
+
+impl RepeatedNotes {
+
+    pub fn new(window: Duration) -> RepeatedNotes {
+        RepeatedNotes { window, last_seen: Mutex::new(HashMap::new()) }
+    }
+
+    fn lock_last_seen(&self) -> MutexGuard<'_, HashMap<[u8; 32], Instant>> {
+        // the map's always left whole, so a poisoned lock is fine to use
+        self.last_seen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SpamCheck for RepeatedNotes {
+    fn check(&self, entry: &GuestbookEntry, now: Instant) -> Option<(SpamVerdict, String)> {
+
+        let note = entry.note.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        if note.chars().count() < MIN_REPEAT_CHARS {
+            return None;
+        }
+        let key: [u8; 32] = Sha256::digest(note.as_bytes()).into();
+
+        let mut last_seen = self.lock_last_seen();
+        if last_seen.len() >= MAX_TRACKED_NOTES && !last_seen.contains_key(&key) {
+            last_seen.retain(|_, seen| now.saturating_duration_since(*seen) < self.window);
+            if last_seen.len() >= MAX_TRACKED_NOTES {
+                warn!("Over {MAX_TRACKED_NOTES} different notes were sent to the guestbook lately; forgetting them all.");
+                last_seen.clear();
+            }
+        }
+
+        let repeated = last_seen.insert(key, now)
+            .is_some_and(|seen| now.saturating_duration_since(seen) < self.window);
+
+        repeated.then(|| (SpamVerdict::Drop, format!("the same note as one from the last {} minutes", self.window.as_secs().div_ceil(60))))
+    }
+}
+
+
+pub struct BayesCheck {
+    model: RwLock<BayesModel>,
+    hold_at: f64,
+    drop_at: f64,
+    min_entries: u32,   // of spam, and of the rest, before it says anything
+}
+
+impl BayesCheck {
+
+    pub fn new(hold_at: f64, drop_at: f64, min_entries: u32) -> BayesCheck {
+        BayesCheck { model: RwLock::new(BayesModel::new()), hold_at, drop_at, min_entries: min_entries.max(1) }
+    }
+
+    fn replace_model(&self, model: BayesModel) {
+        *self.model.write().unwrap_or_else(PoisonError::into_inner) = model;
+    }
+
+    fn trained_on(&self) -> (u32, u32) {
+        let model = self.model.read().unwrap_or_else(PoisonError::into_inner);
+        (model.spam_entries(), model.ham_entries())
+    }
+}
+
+impl SpamCheck for BayesCheck {
+    fn check(&self, entry: &GuestbookEntry, _now: Instant) -> Option<(SpamVerdict, String)> {
+
+        let model = self.model.read().unwrap_or_else(PoisonError::into_inner);
+        if model.spam_entries() < self.min_entries || model.ham_entries() < self.min_entries {
+            return None;
+        }
+
+        let score = model.score(&entry_text(entry));
+        let verdict = if score >= self.drop_at {
+            SpamVerdict::Drop
+        } else if score >= self.hold_at {
+            SpamVerdict::Hold
+        } else {
+            return None;
+        };
+
+        Some((verdict, format!("spam score {score:.2}")))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::mem_store::MemStore;
+
+    fn entry(name: &str, note: &str) -> GuestbookEntry {
+        GuestbookEntry { id: None, time_stamp: None, name: name.to_string(), note: note.to_string(), pinned: None, spam: None, parent_id: None, replies: None, reactions: None }
+    }
+
+    fn verdict(filter: &SpamFilter, name: &str, note: &str) -> SpamVerdict {
+        filter.check(&entry(name, note), Instant::now()).verdict
+    }
+
+    #[test]
+    fn built_in_rules() {
+        let filter = SpamFilter::disabled().with_check(BannedRules::built_in());
+
+        assert_eq!(verdict(&filter, "Sam", "Love the photos from the hike!"), SpamVerdict::Accept);
+        assert_eq!(verdict(&filter, "Sam", "Cheap VIAGRA here"), SpamVerdict::Drop);
+        assert_eq!(verdict(&filter, "Crypto King", "Hello!"), SpamVerdict::Hold);
+        assert_eq!(verdict(&filter, "Sam", "Try my bitcoin-doubler"), SpamVerdict::Drop);
+        assert_eq!(verdict(&filter, "Sam", "[URL=http://spam.example]click[/URL]"), SpamVerdict::Drop);
+        // whole words only
+        assert_eq!(verdict(&filter, "Sam", "Seoul was lovely this time of year"), SpamVerdict::Accept);
+
+        let decision = filter.check(&entry("Sam", "Great SEO, and cheap xanax"), Instant::now());
+        assert_eq!(decision, SpamDecision { verdict: SpamVerdict::Drop, reason: Some(String::from("banned word \"xanax\"")) });
+    }
+
+    #[test]
+    fn reading_rules() {
+        let rules = BannedRules::from_json(r#"{ "drop": { "patterns": ["(?i)free\\s+money"] }, "hold": { "words": ["", "Spam"] } }"#).unwrap();
+        let filter = SpamFilter::disabled().with_check(rules);
+        assert_eq!(verdict(&filter, "Sam", "FREE  money!"), SpamVerdict::Drop);
+        assert_eq!(verdict(&filter, "Sam", "no spam here"), SpamVerdict::Hold);
+        assert_eq!(verdict(&filter, "Sam", "Spammy"), SpamVerdict::Accept);
+
+        assert!(matches!(BannedRules::from_json(r#"{ "drop": {}, "hold": { "patterns": ["(unclosed"] } }"#), Err(SpamFilterError::BadPattern(..))));
+        assert!(matches!(BannedRules::from_json(r#"{ "drop": {} }"#), Err(SpamFilterError::JsonError(_))));
+    }
+
+    #[test]
+    fn too_many_links() {
+        let filter = SpamFilter::disabled().with_check(LinkLimit { max_links: 2 });
+
+        assert_eq!(verdict(&filter, "Sam", "My site's https://sam.example, and www.sam.example/blog"), SpamVerdict::Accept);
+        let decision = filter.check(&entry("http://a.example", "HTTP://b.example https://c.example"), Instant::now());
+        assert_eq!(decision, SpamDecision { verdict: SpamVerdict::Hold, reason: Some(String::from("3 links (more than 2)")) });
+    }
+
+    #[test]
+    fn repeated_notes() {
+        let filter = SpamFilter::disabled().with_check(RepeatedNotes::new(Duration::from_secs(600)));
+        let start = Instant::now();
+        let note = "Check out my amazing site for great deals!";
+        let check = |note: &str, after: u64| filter.check(&entry("Sam", note), start + Duration::from_secs(after)).verdict;
+
+        assert_eq!(check(note, 0), SpamVerdict::Accept);
+        assert_eq!(check("check out my amazing   site for GREAT deals!", 60), SpamVerdict::Drop);
+        // the window starts over with each one
+        assert_eq!(check(note, 600), SpamVerdict::Drop);
+        assert_eq!(check(note, 1201), SpamVerdict::Accept);
+        // short ones can repeat
+        assert_eq!(check("Great site!", 0), SpamVerdict::Accept);
+        assert_eq!(check("Great site!", 1), SpamVerdict::Accept);
+    }
+
+    #[test]
+    fn worst_verdict_wins() {
+        let filter = SpamFilter::disabled()
+            .with_check(BannedRules::built_in())
+            .with_check(LinkLimit { max_links: 0 })
+            .with_check(RepeatedNotes::new(Duration::from_secs(600)));
+        let start = Instant::now();
+
+        let decision = filter.check(&entry("Sam", "crypto tips at https://sam.example"), start);
+        assert_eq!(decision.verdict, SpamVerdict::Hold);
+        assert_eq!(decision.reason.as_deref(), Some("banned word \"crypto\"; 1 links (more than 0)"));
+
+        let decision = filter.check(&entry("Sam", "crypto tips at https://sam.example"), start);
+        assert_eq!(decision.verdict, SpamVerdict::Drop);
+        assert!(decision.reason.unwrap().starts_with("the same note"));
+
+        assert_eq!(SpamFilter::disabled().check(&entry("Sam", "cheap viagra"), start), SpamDecision { verdict: SpamVerdict::Accept, reason: None });
+        assert!(!SpamFilter::disabled().is_enabled());
+    }
+
+    #[tokio::test]
+    async fn learning_from_the_admin() {
+        let store = MemStore::new();
+        let add = |name: &'static str, note: &'static str, status, spam: Option<SpamDecision>| {
+            let store = &store;
+            async move { store.add_entry(&GuestbookEntry { spam, ..entry(name, note) }, status, None).await.unwrap() }
+        };
+        for note in ["Replica watches, best prices online", "Best prices on replica bags online", "Online casino, best bonus"] {
+            add("Deals", note, EntryStatus::Rejected, None).await;
+        }
+        // the filter's own drops don't count
+        let dropped = SpamDecision { verdict: SpamVerdict::Drop, reason: Some(String::from("banned word \"viagra\"")) };
+        add("Deals", "cheap viagra", EntryStatus::Rejected, Some(dropped)).await;
+        for note in ["Love the photos from the hike", "Great write-up on the homelab", "Thanks for the photos, and the notes"] {
+            add("Sam", note, EntryStatus::Approved, None).await;
+        }
+
+        let filter = SpamFilter::disabled().with_bayes(BayesCheck::new(0.9, 0.99, 3));
+        assert_eq!(filter.bayes_trained_on(), Some((0, 0)));
+        assert_eq!(verdict(&filter, "Deals", "Best replica prices online"), SpamVerdict::Accept);
+
+        filter.retrain(&store).await.unwrap();
+        assert_eq!(filter.bayes_trained_on(), Some((3, 3)));
+        let decision = filter.check(&entry("Deals", "Best replica prices online"), Instant::now());
+        assert_eq!(decision.verdict, SpamVerdict::Drop);
+        assert!(decision.reason.unwrap().starts_with("spam score "));
+        assert_eq!(verdict(&filter, "Sam", "Great photos from the homelab"), SpamVerdict::Accept);
+
+        // not enough to go on
+        let picky = SpamFilter::disabled().with_bayes(BayesCheck::new(0.9, 0.99, 4));
+        picky.retrain(&store).await.unwrap();
+        assert_eq!(verdict(&picky, "Deals", "Best replica prices online"), SpamVerdict::Accept);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retraining_in_the_background() {
+        let store = Arc::new(MemStore::new());
+        let add = |note: &'static str, status| {
+            let store = store.clone();
+            async move { store.add_entry(&entry("Deals", note), status, None).await.unwrap() }
+        };
+        let filter = Arc::new(SpamFilter::disabled().with_bayes(BayesCheck::new(0.9, 0.99, 3)));
+
+        add("Replica watches, best prices online", EntryStatus::Rejected).await;
+        filter.clone().retrain_soon(store.clone());
+        assert_eq!(filter.bayes_trained_on(), Some((0, 0)));
+        // whatever comes in before it starts goes in the same one
+        add("Love the photos from the hike", EntryStatus::Approved).await;
+        filter.clone().retrain_soon(store.clone());
+        tokio::time::sleep(RETRAIN_AFTER + Duration::from_secs(1)).await;
+        assert_eq!(filter.bayes_trained_on(), Some((1, 1)));
+
+        // and there's another after that
+        add("Online casino, best bonus", EntryStatus::Rejected).await;
+        filter.clone().retrain_soon(store.clone());
+        tokio::time::sleep(RETRAIN_AFTER / 2).await;
+        assert_eq!(filter.bayes_trained_on(), Some((1, 1)));
+        tokio::time::sleep(RETRAIN_AFTER).await;
+        assert_eq!(filter.bayes_trained_on(), Some((2, 1)));
+
+        // without the scorer, there's nothing to do
+        let without = Arc::new(SpamFilter::disabled().with_check(BannedRules::built_in()));
+        without.clone().retrain_soon(store);
+        assert!(!without.retrain_queued.load(Ordering::Acquire));
+    }
+}
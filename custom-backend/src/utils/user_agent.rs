@@ -0,0 +1,274 @@
+// Sorts user agent strings out into browser, OS, and device class, and flags
+// the ones from bots and crawlers, so they can be left out of the hit count.
+// This is done once, as each hit comes in, and the results are stored
+// alongside the hit in hitLog.
+//
+// The rules are regexes, kept in rules/user-agents.json (see the comment at
+// the top of it). A copy is built into the server, but UA_RULES_PATH can point
+// to another file, which is read at startup, so the rules can be kept up to
+// date with new browsers and bots without rebuilding anything.
+use std::{fmt, fs, io};
+use mysql_common::serde_json;
+use regex::Regex;
+use super::init_utils::get_env_var_or;
+
+
+const BUILT_IN_RULES: &str = include_str!("../../rules/user-agents.json");
+
+// the widths of the columns these go in
+pub const MAX_NAME_CHARS: usize = 50;
+pub const MAX_VERSION_CHARS: usize = 30;
+
+// what's left when nothing matches
+const OTHER: &str = "Other";
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceClass {
+    Desktop,
+    Mobile,
+    Tablet,
+    Bot,
+}
+
+impl DeviceClass {
+    // as it's stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceClass::Desktop => "desktop",
+            DeviceClass::Mobile  => "mobile",
+            DeviceClass::Tablet  => "tablet",
+            DeviceClass::Bot     => "bot",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgentInfo {
+    pub browser: String,                    // for bots, the name of the bot
+    pub browser_version: Option<String>,
+    pub os: String,
+    pub device: DeviceClass,
+    pub is_bot: bool,
+}
+
+
+// the rules file, as it's written
+#[derive(serde::Deserialize)]
+struct RulesFile {
+    bots: Vec<NamedRule>,
+    browsers: Vec<NamedRule>,
+    oses: Vec<NamedRule>,
+    devices: Vec<DeviceRule>,
+}
+
+#[derive(serde::Deserialize)]
+struct NamedRule {
+    name: String,
+    pattern: String,
+}
+
+#[derive(serde::Deserialize)]
+struct DeviceRule {
+    class: DeviceClass,
+    pattern: String,
+}
+
+// the rules file, with the patterns compiled
+pub struct UaRules {
+    bots: Vec<(String, Regex)>,
+    browsers: Vec<(String, Regex)>,
+    oses: Vec<(String, Regex)>,
+    devices: Vec<(DeviceClass, Regex)>,
+}
+
+#[derive(Debug)]
+pub enum UaRulesError {
+    FileError(String, io::Error),
+    JsonError(serde_json::Error),
+    BadPattern(String, regex::Error),
+}
+
+impl fmt::Display for UaRulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FileError(path, e) => write!(f, "couldn't read the user agent rules at {path}: {e}"),
+            Self::JsonError(e) => write!(f, "the user agent rules aren't valid: {e}"),
+            Self::BadPattern(pattern, e) => write!(f, "bad pattern in the user agent rules ({pattern}): {e}"),
+        }
+    }
+}
+
+impl std::error::Error for UaRulesError {}
+
+
+impl UaRules {
+
+    // the file at UA_RULES_PATH, if it's set, and the built-in rules if not
+    pub fn from_env() -> Result<UaRules, UaRulesError> {
+
+        let path: String = get_env_var_or("UA_RULES_PATH", String::new());
+        if path.is_empty() {
+            return Ok(UaRules::built_in());
+        }
+
+        let json = fs::read_to_string(&path).map_err(|e| UaRulesError::FileError(path, e))?;
+        UaRules::from_json(&json)
+    }
+
+    pub fn built_in() -> UaRules {
+        // unwrap can't panic: the built-in rules are checked by the tests
+        UaRules::from_json(BUILT_IN_RULES).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<UaRules, UaRulesError> {
+
+        let file: RulesFile = serde_json::from_str(json).map_err(UaRulesError::JsonError)?;
+
+        let compile = |pattern: String| {
+            Regex::new(&pattern).map_err(|e| UaRulesError::BadPattern(pattern, e))
+        };
+        let compile_named = |rules: Vec<NamedRule>| -> Result<Vec<(String, Regex)>, UaRulesError> {
+            rules.into_iter()
+                .map(|rule| Ok((truncate_chars(&rule.name, MAX_NAME_CHARS).to_string(), compile(rule.pattern)?)))
+                .collect()
+        };
+
+        Ok(UaRules {
+            bots: compile_named(file.bots)?,
+            browsers: compile_named(file.browsers)?,
+            oses: compile_named(file.oses)?,
+            devices: file.devices.into_iter()
+                .map(|rule| Ok((rule.class, compile(rule.pattern)?)))
+                .collect::<Result<_, UaRulesError>>()?,
+        })
+    }
+
+    // for the logs
+    pub fn rule_count(&self) -> usize {
+        self.bots.len() + self.browsers.len() + self.oses.len() + self.devices.len()
+    }
+
+    pub fn parse(&self, user_agent: &str) -> UserAgentInfo {
+
+        let os = first_match(&self.oses, user_agent)
+            .map_or(OTHER, |(name, _)| name)
+            .to_string();
+
+        if let Some((bot, _)) = first_match(&self.bots, user_agent) {
+            return UserAgentInfo {
+                browser: bot.clone(),
+                browser_version: None,
+                os,
+                device: DeviceClass::Bot,
+                is_bot: true,
+            };
+        }
+
+        let (browser, browser_version) = match first_match(&self.browsers, user_agent) {
+            Some((name, version)) => (name.clone(), version),
+            None => (OTHER.to_string(), None),
+        };
+
+        let device = self.devices.iter()
+            .find(|(_, pattern)| pattern.is_match(user_agent))
+            .map_or(DeviceClass::Desktop, |(class, _)| *class);
+
+        UserAgentInfo { browser, browser_version, os, device, is_bot: false }
+    }
+}
+
+// the name of the first rule that matches, and its first capture group, if it has one
+fn first_match<'a>(rules: &'a [(String, Regex)], user_agent: &str) -> Option<(&'a String, Option<String>)> {
+
+    rules.iter().find_map(|(name, pattern)| {
+        let caps = pattern.captures(user_agent)?;
+        let version = caps.get(1).map(|ver| truncate_chars(ver.as_str(), MAX_VERSION_CHARS).to_string());
+        Some((name, version))
+    })
+}
+
+// cuts s down to max_chars characters (not bytes), like a VARCHAR column would
+pub fn truncate_chars(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((byte_idx, _)) => &s[..byte_idx],
+        None => s
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn parsed(user_agent: &str) -> (String, Option<String>, String, DeviceClass, bool) {
+        let info = UaRules::built_in().parse(user_agent);
+        (info.browser, info.browser_version, info.os, info.device, info.is_bot)
+    }
+
+    fn expected(browser: &str, version: Option<&str>, os: &str, device: DeviceClass, is_bot: bool) -> (String, Option<String>, String, DeviceClass, bool) {
+        (browser.to_string(), version.map(String::from), os.to_string(), device, is_bot)
+    }
+
+    #[test]
+    fn browsers() {
+        assert_eq!(
+            parsed("Mozilla/5.0 (X11; Linux x86_64; rv:136.0) Gecko/20100101 Firefox/136.0"),
+            expected("Firefox", Some("136.0"), "Linux", DeviceClass::Desktop, false)
+        );
+        // Edge and Chrome both say Chrome, and Chrome says Safari
+        assert_eq!(
+            parsed("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.36 Edg/134.0.0.0"),
+            expected("Edge", Some("134.0.0.0"), "Windows", DeviceClass::Desktop, false)
+        );
+        assert_eq!(
+            parsed("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.6998.135 Mobile Safari/537.36"),
+            expected("Chrome", Some("134.0.6998.135"), "Android", DeviceClass::Mobile, false)
+        );
+        assert_eq!(
+            parsed("Mozilla/5.0 (iPad; CPU OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1"),
+            expected("Safari", Some("17.4"), "iOS", DeviceClass::Tablet, false)
+        );
+        assert_eq!(
+            parsed("Mozilla/5.0 (Linux; Android 13; SM-X700) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.36"),
+            expected("Chrome", Some("134.0.0.0"), "Android", DeviceClass::Tablet, false)
+        );
+        assert_eq!(parsed("Mozilla user agent"), expected("Other", None, "Other", DeviceClass::Desktop, false));
+    }
+
+    #[test]
+    fn bots() {
+        assert_eq!(
+            parsed("Mozilla/5.0 (Linux; Android 6.0.1; Nexus 5X Build/MMB29P) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.6998.165 Mobile Safari/537.36 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"),
+            expected("Googlebot", None, "Android", DeviceClass::Bot, true)
+        );
+        assert_eq!(parsed("curl/8.5.0"), expected("curl", None, "Other", DeviceClass::Bot, true));
+        assert!(UaRules::built_in().parse("Mozilla/5.0 (compatible; SomeNewCrawler/1.0)").is_bot);
+    }
+
+    #[test]
+    fn rules_from_json() {
+        let rules = UaRules::from_json(r#"{
+            "bots": [],
+            "browsers": [{ "name": "Archie's Browser", "pattern": "Archie/(\\d+)" }],
+            "oses": [],
+            "devices": [{ "class": "mobile", "pattern": "Phone" }]
+        }"#).unwrap();
+        assert_eq!(rules.rule_count(), 2);
+        let info = rules.parse("Archie/12 (Phone)");
+        assert_eq!((info.browser.as_str(), info.browser_version.as_deref(), info.device), ("Archie's Browser", Some("12"), DeviceClass::Mobile));
+
+        let bad_pattern = r#"{ "bots": [{ "name": "x", "pattern": "(" }], "browsers": [], "oses": [], "devices": [] }"#;
+        assert!(matches!(UaRules::from_json(bad_pattern), Err(UaRulesError::BadPattern(p, _)) if p == "("));
+        let bad_class = r#"{ "bots": [], "browsers": [], "oses": [], "devices": [{ "class": "toaster", "pattern": "x" }] }"#;
+        assert!(matches!(UaRules::from_json(bad_class), Err(UaRulesError::JsonError(_))));
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate_chars("Firefox", 3), "Fir");
+        assert_eq!(truncate_chars("约翰·塞纳", 2), "约翰");
+        assert_eq!(truncate_chars("short", 150), "short");
+    }
+}
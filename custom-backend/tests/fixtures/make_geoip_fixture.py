@@ -0,0 +1,107 @@
+# Writes geoip-test.mmdb, the tiny MaxMind database the geoip tests look
+# things up in. It only covers the documentation ranges (RFC 5737 and
+# 3849), so none of it is real, and it's small enough to check in.
+#
+#     python3 make_geoip_fixture.py
+#
+# The format is described at https://maxmind.github.io/MaxMind-DB/. This
+# only writes the parts of it the tests need: an IPv6 tree (with IPv4 in
+# ::/96, like the real databases), 24-bit records, and maps, strings,
+# arrays, and unsigned ints in the data section.
+import ipaddress
+import os
+import struct
+
+NETWORKS = {
+    "203.0.113.0/24":  {"country": {"iso_code": "NZ"}, "subdivisions": [{"iso_code": "AUK"}]},
+    "198.51.100.0/24": {"country": {"iso_code": "DE"}},
+    "2001:db8::/32":   {"country": {"iso_code": "JP"}, "subdivisions": [{"iso_code": "13"}]},
+}
+
+METADATA = {
+    "binary_format_major_version": ("u16", 2),
+    "binary_format_minor_version": ("u16", 0),
+    "build_epoch": ("u64", 1741836225),
+    "database_type": "GeoIP2-Country-Test",
+    "description": {"en": "archie-server test fixture"},
+    "ip_version": ("u16", 6),
+    "languages": ["en"],
+    "node_count": None,     # filled in below
+    "record_size": ("u16", 24),
+}
+
+
+def control(type_num, size):
+    if size < 29:
+        size_bits, extra = size, b""
+    elif size < 285:
+        size_bits, extra = 29, bytes([size - 29])
+    else:
+        size_bits, extra = 30, struct.pack(">H", size - 285)
+    if type_num <= 7:
+        return bytes([(type_num << 5) | size_bits]) + extra
+    return bytes([size_bits, type_num - 7]) + extra
+
+
+def encode(value):
+    if isinstance(value, tuple):
+        kind, num = value
+        raw = num.to_bytes(8, "big").lstrip(b"\0")
+        return control({"u16": 5, "u32": 6, "u64": 9}[kind], len(raw)) + raw
+    if isinstance(value, str):
+        raw = value.encode()
+        return control(2, len(raw)) + raw
+    if isinstance(value, dict):
+        return control(7, len(value)) + b"".join(encode(k) + encode(v) for k, v in value.items())
+    if isinstance(value, list):
+        return control(11, len(value)) + b"".join(encode(v) for v in value)
+    raise TypeError(value)
+
+
+def bits(network):
+    net = ipaddress.ip_network(network)
+    addr = int(net.network_address)
+    if net.version == 4:
+        return [0] * 96 + [(addr >> (31 - i)) & 1 for i in range(net.prefixlen)]
+    return [(addr >> (127 - i)) & 1 for i in range(net.prefixlen)]
+
+
+def main():
+    data = b""
+    # each node is [left, right], where a child is another node, a
+    # ("data", offset), or None for nothing there
+    nodes = [[None, None]]
+    for network, record in NETWORKS.items():
+        offset = len(data)
+        data += encode(record)
+        node = 0
+        path = bits(network)
+        for bit in path[:-1]:
+            if nodes[node][bit] is None:
+                nodes.append([None, None])
+                nodes[node][bit] = len(nodes) - 1
+            node = nodes[node][bit]
+        nodes[node][path[-1]] = ("data", offset)
+
+    node_count = len(nodes)
+
+    def record(child):
+        if child is None:
+            return node_count
+        if isinstance(child, tuple):
+            return node_count + 16 + child[1]
+        return child
+
+    tree = b"".join(
+        record(left).to_bytes(3, "big") + record(right).to_bytes(3, "big")
+        for left, right in nodes
+    )
+    metadata = dict(METADATA, node_count=("u32", node_count))
+
+    path = os.path.join(os.path.dirname(os.path.abspath(__file__)), "geoip-test.mmdb")
+    with open(path, "wb") as out:
+        out.write(tree + b"\0" * 16 + data + b"\xab\xcd\xefMaxMind.com" + encode(metadata))
+
+
+if __name__ == "__main__":
+    main()
@@ -1,12 +1,15 @@
-use mysql_common::serde_json;
-use tokio;
+use mysql_common::serde_json::{self, Value};
 use reqwest::StatusCode;
-use custom_backend::types::db_io_types::{EntryReceipt, Guestbook, GuestbookEntry};
+use sha2::{Digest, Sha256};
+use custom_backend::types::db_io_types::{
+    AuthorEditError, AuthorEditFailed, BotCheckError, BotCheckFailed, EntryReceipt, EntryStatus, Guestbook, GuestbookEntry, GuestbookPage, GuestbookSearch, PowChallenge,
+    ReactionOptions, ReactionReceipt
+};
 mod client_config;
 
-// WARNING: these tests will fail if they are run before the library tests.
-// These tests expect the data posted to the data during those tests
-// to be there.
+// These tests expect the database to start out with the demo data
+// (see db-init-demo/). The library tests don't touch the database,
+// so they can be run in any order relative to these.
 
 #[tokio::main]
 async fn main() {
@@ -16,12 +19,36 @@ async fn main() {
     let client = client_config::config_client(protocol);
 
     post_valid_entry(&client, &url).await;
+    post_without_challenge(&client, &url).await;
+    changing_own_entry(&client, &url).await;
+    replying_as_guest(&client, &url).await;
+    reacting_to_entries(&client, &url).await;
     getting_guestbook(&client, &url).await;
+    paging_through_guestbook(&client, &url).await;
+    searching_guestbook(&client, &url).await;
     post_overlong_entry_note(&client, &url).await;
     post_overlong_entry_name(&client, &url).await;
 }
 
 
+// With GUESTBOOK_POW_DIFFICULTY on (it is by default), each new entry needs
+// a solved challenge, same as the page sends (see srv_io/bot_check.rs).
+async fn with_challenge(client: &reqwest::Client, url: &str, mut body: Value) -> String {
+
+    let resp = client.get(url.replace("/entries", "/challenge")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK, "these tests need GUESTBOOK_POW_DIFFICULTY on");
+    let challenge: PowChallenge = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    let nonce = (0u64..).find(|nonce| leading_zero_bits(&challenge.token, *nonce) >= challenge.difficulty).unwrap();
+    body["challenge"] = serde_json::json!({ "token": challenge.token, "nonce": nonce });
+    body.to_string()
+}
+
+fn leading_zero_bits(token: &str, nonce: u64) -> u32 {
+    let hash = Sha256::digest(format!("{token}:{nonce}"));
+    let zero_bytes = hash.iter().take_while(|&&byte| byte == 0).count();
+    8 * zero_bytes as u32 + hash.get(zero_bytes).map_or(0, |byte| byte.leading_zeros())
+}
+
 // This checks whether the entry can be deserialized from camelCase,
 // and that an EntryReceipt is sent
 async fn post_valid_entry(client: &reqwest::Client, url: &String) {
@@ -32,7 +59,7 @@ async fn post_valid_entry(client: &reqwest::Client, url: &String) {
 
     let resp = client
         .post(url)
-        .body(valid_entry)
+        .body(with_challenge(client, url, serde_json::from_str(&valid_entry).unwrap()).await)
         .send()
         .await
         .unwrap();
@@ -46,67 +73,171 @@ async fn post_valid_entry(client: &reqwest::Client, url: &String) {
     // test both that the response body can be deserialized into an EntryReceipt
     // and that it is camelCase
     assert!(resp_body_text.contains("timeStamp"));
-    serde_json::from_str::<EntryReceipt>(&resp_body_text).unwrap();  
+    let receipt = serde_json::from_str::<EntryReceipt>(&resp_body_text).unwrap();  
+    // the rest of these expect it to be shown right away
+    assert_eq!(receipt.status, EntryStatus::Approved, "these tests need GUESTBOOK_MODERATION off");
+}
+
+// turned away, with a BotCheckFailed, before it's added
+async fn post_without_challenge(client: &reqwest::Client, url: &str) {
+
+    let challenge_route = client.get(url.replace("/entries", "/challenge")).send().await.unwrap();
+    assert_eq!(challenge_route.status(), StatusCode::OK, "these tests need GUESTBOOK_POW_DIFFICULTY on");
+
+    let resp = client
+        .post(url)
+        .body("{\"name\": \"a bot\", \"note\": \"no challenge here\"}")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let failed: BotCheckFailed = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(failed.error, BotCheckError::MissingChallenge);
+}
+
+// With AUTHOR_EDIT_GRACE_SECS on (it is by default), the receipt has a token
+// for changing the entry. It's deleted in the end, so it isn't in the guestbook
+// for the rest of these.
+async fn changing_own_entry(client: &reqwest::Client, url: &str) {
+
+    let entry = serde_json::json!({ "name": "a typo", "note": "teh note" });
+    let resp = client.post(url).body(with_challenge(client, url, entry).await).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let receipt: EntryReceipt = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    let token = receipt.edit_token.expect("these tests need AUTHOR_EDIT_GRACE_SECS on");
+    let entry_url = format!("{url}/{}", receipt.id);
+
+    let resp = client.patch(&entry_url).bearer_auth("not-the-token").body("{\"note\": \"the note\"}").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let failed: AuthorEditFailed = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!(failed.error, AuthorEditError::InvalidToken);
+
+    let resp = client.patch(&entry_url).bearer_auth(&token).body("{\"note\": \"the note\"}").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let edited: GuestbookEntry = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert_eq!((edited.name.as_str(), edited.note.as_str()), ("a typo", "the note"));
+
+    let resp = client.delete(&entry_url).bearer_auth(&token).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = client.delete(&entry_url).bearer_auth(&token).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+// Needs GUESTBOOK_GUEST_REPLIES on (it's off by default, but CI turns it on).
+// The reply's deleted in the end too.
+async fn replying_as_guest(client: &reqwest::Client, url: &str) {
+
+    let reply = serde_json::json!({ "name": "a reply", "note": "to Ada" });
+    let resp = client.post(format!("{url}/1/replies")).body(with_challenge(client, url, reply).await).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK, "these tests need GUESTBOOK_GUEST_REPLIES on");
+    let receipt: EntryReceipt = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+
+    let resp = client.get(url).send().await.unwrap();
+    let guestbook: Guestbook = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    let ada = guestbook.guestbook.iter().find(|entry| entry.id.as_deref() == Some("1")).unwrap();
+    let replies = ada.replies.as_deref().unwrap();
+    assert_eq!((replies[0].id.as_ref(), replies[0].parent_id.as_deref()), (Some(&receipt.id), Some("1")));
+    // only under what it replies to
+    assert!(guestbook.guestbook.iter().all(|entry| entry.id.as_ref() != Some(&receipt.id)));
+
+    let token = receipt.edit_token.unwrap();
+    let resp = client.delete(format!("{url}/{}", receipt.id)).bearer_auth(&token).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+}
+
+// With REACTION_EMOJI empty, neither route is there, so that's a failure
+// here. Earlier runs (or the same client today) may already have left this one.
+async fn reacting_to_entries(client: &reqwest::Client, url: &str) {
+
+    let options_url = url.replace("/entries", "/reactions");
+    let resp = client.get(options_url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK, "these tests need REACTION_EMOJI set (it is by default)");
+    let options: ReactionOptions = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    let emoji = &options.emoji[0];
+
+    let reaction = serde_json::json!({ "emoji": emoji }).to_string();
+    let resp = client.post(format!("{url}/1/reactions")).body(reaction.clone()).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let first: ReactionReceipt = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    let resp = client.post(format!("{url}/1/reactions")).body(reaction).send().await.unwrap();
+    let again: ReactionReceipt = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    assert!(!again.counted);
+    assert_eq!(again.reactions, first.reactions);
+    assert!(first.reactions.iter().any(|count| count.emoji == *emoji && count.count >= 1));
+
+    let resp = client.get(url).send().await.unwrap();
+    let guestbook: Guestbook = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+    let ada = guestbook.guestbook.iter().find(|entry| entry.id.as_deref() == Some("1")).unwrap();
+    assert_eq!(ada.reactions.as_ref(), Some(&first.reactions));
+
+    // only what's on offer
+    let resp = client.post(format!("{url}/1/reactions")).body(r#"{"emoji": "not an emoji"}"#).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 }
 
 async fn getting_guestbook(client: &reqwest::Client, url: &String) {
 
     // since I don't know when the extra entries were added,
-    // and the accuracy is checked in the get_guestbook() unit test
+    // and the accuracy is checked in the getting_guestbook() unit test
     // we're only comparing content without timestamps
 
     let latest_entry = GuestbookEntry {
         id: None,
 		time_stamp: None,
         name: String::from("a normal name"),
-        note: String::from("Some non-ASCII Unicode: ગુજરાતી લિપિ.")
+        note: String::from("Some non-ASCII Unicode: ગુજરાતી લિપિ."),
+        pinned: None,
+        spam: None,
+        parent_id: None,
+        replies: None,
+        reactions: None,
     };
     let test_guestbook_vec0 = vec![
         latest_entry.clone(),
-        GuestbookEntry {
-            id: None,
-            time_stamp: None,
-            name: String::from("(anonymous)"),
-            note: String::new()
-        },
-        GuestbookEntry {
-            id: None,
-            time_stamp: None,
-            name: String::from("Lettuce % % \\% \\' break some sTuff ⌠ 	⌡ 	⌢ 	⌣ 	⌤"),
-            note: String::from(
-                "ᏣᎳᎩ ᎦᏬᏂᎯᏍᏗ (Cherokee!) \n\\\\% %%' ''\\n\
-                മനുഷ്യരെല്ലാവരും തുല്യാവകാശങ്ങളോടും അന്തസ്സോടും സ്വാതന്ത്ര്യത്തോടുംകൂടി \
-                ജനിച്ചിട്ടുള്ളവരാണ്‌. അന്യോന്യം ഭ്രാതൃഭാവത്തോടെ പെരുമാറുവാനാണ്‌ മനുഷ്യന് \
-                വിവേകബുദ്ധിയും മനസാക്ഷിയും സിദ്ധമായിരിക്കുന്നത്‌ \
-                (this says 'All human beings are born free and equal in dignity and rights. \
-                They are endowed with reason and conscience and should act towards one \
-                another in a spirit of brotherhood.' in Malayalam. It comes from the \
-                UN's Universal Declaration on Human Rights)"
-            )
-        },
         GuestbookEntry {
             id: None,
             time_stamp: None,
             name: String::from("约翰·塞纳"),
-            note: String::from("我很喜欢冰淇淋")
+            note: String::from("我很喜欢冰淇淋"),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         },
         GuestbookEntry {
             id: None,
             time_stamp: None,
             name: String::from("Linus"),
-            note: String::from("nice os choice!")
+            note: String::from("nice os choice!"),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         },
         GuestbookEntry {
             id: None,
             time_stamp: None,
             name: String::from("(anonymous)"),
-            note: String::from("you'll never know...")
+            note: String::from("you'll never know..."),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         },
         GuestbookEntry {
             id: None,
             time_stamp: None,
             name: String::from("Ada"),
-            note: String::from("It's so nice to be here!")
+            note: String::from("It's so nice to be here!"),
+            pinned: None,
+            spam: None,
+            parent_id: None,
+            replies: None,
+            reactions: None,
         },
     ];
 
@@ -142,7 +273,12 @@ async fn getting_guestbook(client: &reqwest::Client, url: &String) {
                 id: None,
                 time_stamp: None,
                 name: ent.name,
-                note: ent.note
+                note: ent.note,
+                pinned: None,
+                spam: None,
+                parent_id: None,
+                replies: None,
+                reactions: None,
             }
         })
         .collect();
@@ -150,6 +286,58 @@ async fn getting_guestbook(client: &reqwest::Client, url: &String) {
     assert!(test_guestbook_vec0 == gb_no_ts_vec || test_guestbook_vec1 == gb_no_ts_vec);
 }
 
+// the pages should line up with the whole guestbook, however many
+// entries earlier runs have added to it
+async fn paging_through_guestbook(client: &reqwest::Client, url: &String) {
+
+    let get = |query: String| async move {
+        let resp = client.get(format!("{url}{query}")).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.text().await.unwrap()
+    };
+
+    let whole: Guestbook = serde_json::from_str(&get(String::new()).await).unwrap();
+
+    let first_body = get(String::from("?limit=2")).await;
+    assert!(first_body.contains("nextCursor"));
+    let first: GuestbookPage = serde_json::from_str(&first_body).unwrap();
+    let cursor = first.next_cursor.clone().unwrap();
+    let second: GuestbookPage = serde_json::from_str(&get(format!("?limit=2&before={cursor}")).await).unwrap();
+
+    let paged: Vec<GuestbookEntry> = first.entries.into_iter().chain(second.entries).collect();
+    assert_eq!(paged, whole.guestbook[..4]);
+
+    let resp = client.get(format!("{url}?before=not-a-cursor")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+// goes through each backend's index, so the Gujarati in the entry
+// post_valid_entry() added has to be found without any spaces to split on
+async fn searching_guestbook(client: &reqwest::Client, url: &str) {
+
+    let url = url.replace("/entries", "/search");
+    let search = |q: &str| {
+        let req = client.get(&url).query(&[("q", q)]);
+        async move {
+            let resp = req.send().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            serde_json::from_str::<GuestbookSearch>(&resp.text().await.unwrap()).unwrap()
+        }
+    };
+
+    let found = search("જરા").await;
+    assert!(!found.results.is_empty());
+    assert!(found.results.iter().all(|result| result.entry.note.contains("ગુજરાતી")));
+    assert!(found.results[0].snippet.iter().any(|part| part.highlight && part.text == "જરા"));
+
+    let found = search("NICE choice").await;
+    let names: Vec<&str> = found.results.iter().map(|result| result.entry.name.as_str()).collect();
+    assert_eq!(names, vec!["Linus"]);
+
+    let resp = client.get(&url).query(&[("q", "a")]).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
 async fn post_overlong_entry_note(client: &reqwest::Client, url: &String) {
 
     // gonna get real weird with it
@@ -171,11 +359,16 @@ async fn post_overlong_entry_note(client: &reqwest::Client, url: &String) {
             Let's stick with this and go further. We need to make sure we have this
             data exceed 1KB. And now it does."
         ),
+        pinned: None,
+        spam: None,
+        parent_id: None,
+        replies: None,
+        reactions: None,
     };
 
     let resp = client
         .post(url)
-        .body(serde_json::to_string(&overlong_entry).unwrap())
+        .body(with_challenge(client, url, serde_json::to_value(&overlong_entry).unwrap()).await)
         .send()
         .await
         .unwrap();
@@ -195,11 +388,16 @@ async fn post_overlong_entry_name(client: &reqwest::Client, url: &String) {
             "A name മനുഷ്യരെല്ലാവരും തുല്യാവകാശങ്ങളോടും that is too ᎦᏬᏂᎯᏍᏗ long.
             so long, in fact, I needed to add all this stuff!"),
         note: String::from("a brief note"),
+        pinned: None,
+        spam: None,
+        parent_id: None,
+        replies: None,
+        reactions: None,
     };
 
     let resp = client
         .post(url)
-        .body(serde_json::to_string(&overlong_name).unwrap())
+        .body(with_challenge(client, url, serde_json::to_value(&overlong_name).unwrap()).await)
         .send()
         .await
         .unwrap();
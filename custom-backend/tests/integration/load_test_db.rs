@@ -0,0 +1,110 @@
+// Checks that a database that's stuck only holds up the requests that 
+// actually need it. The app runs in-process on a deliberately small
+// runtime (2 worker threads), pointed at a "database" that accepts
+// connections and then never says a word. A pile of /guestbook 
+// requests get stuck waiting on it, and while they're stuck, the static
+// pages (from vite_get::serve_statics) and the hit count (served from
+// memory) still need to come back promptly. If the DB calls were blocking, the stuck requests 
+// would tie up both worker threads, and nothing else would get served.
+// 
+// Unlike the integ_test_* tests, this one needs neither a running 
+// server nor a running database.
+use std::{net::SocketAddr, sync::Arc, time::{Duration, Instant}};
+use reqwest::StatusCode;
+use tokio::{net::TcpListener, runtime, task::JoinHandle, time::sleep};
+use custom_backend::{
+    app_state::AppState,
+    routes::build_router,
+    srv_io::page_views::PageViewConfig,
+    storage::{hit_counter::HitCounter, mysql_store::MySqlStore, DbPoolConfig, HitTotals, Stores},
+    utils::{geoip::GeoIp, user_agent::UaRules},
+};
+
+const WORKER_THREADS: usize = 2;
+const STUCK_REQS: usize = 32;
+const MAX_STATIC_LATENCY: Duration = Duration::from_secs(2);
+
+
+fn main() {
+
+    runtime::Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(stuck_db_does_not_stall_statics());
+}
+
+
+async fn stuck_db_does_not_stall_statics() {
+
+    let db_addr = start_silent_db().await;
+    let db_url = format!("mysql://server1:thepass@{db_addr}/archie");
+    let stores = Stores::from_backend(MySqlStore::new(&db_url, &DbPoolConfig::default()).unwrap());
+    // HitCounter::start would get stuck reading the count, so start from 0
+    let hit_counter = HitCounter::with_totals(stores.hits.clone(), HitTotals::default());
+    let ua_rules = Arc::new(UaRules::built_in());
+
+    let app_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", app_listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let app = build_router(AppState::new(&stores, hit_counter, ua_rules, Arc::new(GeoIp::disabled()), &PageViewConfig::default()));
+        axum::serve(app_listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+    });
+
+    let client = reqwest::Client::new();
+
+    let mut stuck_reqs: Vec<JoinHandle<()>> = Vec::new();
+    for _ in 0..STUCK_REQS {
+        let req = client.get(format!("{base_url}/guestbook/entries")).send();
+        stuck_reqs.push(tokio::spawn(async move { let _ = req.await; }));
+    }
+
+    // give the DB requests time to reach the handlers and get stuck
+    sleep(Duration::from_millis(500)).await;
+
+    for page in ["/", "/guestbook", "/static/styles/std-style.css", "/hits"] {
+
+        let start = Instant::now();
+        let resp = client
+            .get(format!("{base_url}{page}"))
+            .timeout(MAX_STATIC_LATENCY * 5)
+            .send()
+            .await
+            .unwrap();
+        let latency = start.elapsed();
+
+        // debug builds proxy assets to the Vite dev server, which isn't
+        // running here, so a 404 is fine; only the latency matters
+        assert_ne!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(
+            latency < MAX_STATIC_LATENCY, 
+            "GET {page} took {latency:?} while the database was stuck"
+        );
+    }
+
+    // make sure the DB requests were actually stuck the whole time
+    assert!(stuck_reqs.iter().all(|req| !req.is_finished()));
+
+    for req in stuck_reqs { req.abort(); }
+}
+
+
+// A stand-in for a database that has stopped responding: it accepts 
+// TCP connections, but never sends the MySQL handshake.
+async fn start_silent_db() -> std::net::SocketAddr {
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut open_socks = Vec::new();
+        while let Ok((sock, _)) = listener.accept().await {
+            open_socks.push(sock);
+        }
+    });
+
+    addr
+}
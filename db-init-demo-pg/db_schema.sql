@@ -0,0 +1,52 @@
+-- PostgreSQL version of db-init-demo/db_schema.sql: same tables, same demo data.
+-- Scripts in this directory are run by the postgres image on first startup.
+--
+-- Postgres folds unquoted names to lowercase, so hitLog ends up as hitlog, etc.
+-- The server uses the same unquoted names, so this doesn't matter to it.
+
+SET TIME ZONE 'UTC';
+
+CREATE TABLE guestbook
+(
+    id              SERIAL PRIMARY KEY,
+    dateSubmitted   TIMESTAMP,
+    guestName       VARCHAR(50)  NOT NULL,
+    guestNote       VARCHAR(1000)
+);
+
+CREATE TABLE hitLog
+(
+    id              SERIAL PRIMARY KEY,
+    hitTime         TIMESTAMP,
+    userAgent       VARCHAR(150)
+);
+
+INSERT INTO guestbook (id, dateSubmitted, guestName, guestNote) VALUES
+    (1, '2025-02-28 04:22:49', 'Ada',         'It''s so nice to be here!'),
+    (2, '2025-02-28 04:30:57', '(anonymous)', 'you''ll never know...'),
+    (3, '2025-03-13 03:37:05', 'Linus',       'nice os choice!'),
+    (4, '2025-04-20 13:03:59', '约翰·塞纳',    '我很喜欢冰淇淋');
+
+INSERT INTO hitLog (id, hitTime, userAgent) VALUES
+    (1, '2025-02-27 23:21:46', 'Mozilla/5.0 (X11; Linux x86_64; rv:135.0) Gecko/20100101 Firefox/135.0'),
+    (2, '2025-02-28 03:45:35', 'Mozilla/5.0 (X11; Linux x86_64; rv:135.0) Gecko/20100101 Firefox/135.0'),
+    (3, '2025-02-28 04:19:10', 'Mozilla/5.0 (X11; Linux x86_64; rv:135.0) Gecko/20100101 Firefox/135.0'),
+    (4, '2025-03-13 03:48:12', 'Mozilla/5.0 (X11; Linux x86_64; rv:136.0) Gecko/20100101 Firefox/136.0'),
+    (5, '2025-03-13 03:48:15', 'Mozilla/5.0 (X11; Linux x86_64; rv:136.0) Gecko/20100101 Firefox/136.0'),
+    (6, '2025-03-13 03:56:22', 'Mozilla/5.0 (X11; Linux x86_64; rv:136.0) Gecko/20100101 Firefox/136.0');
+
+-- explicit IDs don't move the sequences along, so catch them up
+SELECT setval('guestbook_id_seq', (SELECT MAX(id) FROM guestbook));
+SELECT setval('hitlog_id_seq',    (SELECT MAX(id) FROM hitLog));
+
+-- same privileges the MySQL user gets in privileges.sql
+GRANT SELECT, INSERT ON guestbook, hitLog TO server1;
+-- moderating entries changes their status
+GRANT UPDATE ON guestbook TO server1;
+GRANT USAGE ON SEQUENCE guestbook_id_seq, hitlog_id_seq TO server1;
+-- the server reads schema_migrations at startup, writes to hitCounter, and
+-- deletes old salts from visitorSalt, and none of them are created until 
+-- migrations are run (by whoever runs this script)
+ALTER DEFAULT PRIVILEGES GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO server1;
+-- and adminAudit's id sequence, for the same reason
+ALTER DEFAULT PRIVILEGES GRANT USAGE ON SEQUENCES TO server1;
@@ -1,5 +1,8 @@
-GRANT INSERT,SELECT,EXECUTE,PROCESS,LOCK TABLES
+-- CREATE, ALTER, DROP and INDEX are for the schema migrations the server
+-- runs at startup (see custom-backend/migrations/). Without them, set
+-- DB_AUTO_MIGRATE=false and run `archie-server migrate up` as a user that has them.
+GRANT INSERT,SELECT,UPDATE,DELETE,EXECUTE,PROCESS,CREATE,ALTER,DROP,INDEX
     ON *.* 
     TO 'server1'@'%';
 
-FLUSH PRIVILEGES;
\ No newline at end of file
+FLUSH PRIVILEGES;
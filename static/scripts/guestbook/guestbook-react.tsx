@@ -1,6 +1,15 @@
 import React from 'react';
 import { useState, useRef, useEffect } from 'react';
-import { type Guestbook, type GuestbookEntry, type EntryReceipt } from '../server-types.ts';
+import { 
+    type Guestbook, 
+    type GuestbookEntry, 
+    type GuestbookPost, 
+    type EntryReceipt, 
+    type PowChallenge, 
+    type PowSolution,
+    type ReactionOptions,
+    type ReactionReceipt
+} from '../server-types.ts';
 
 
 /* 
@@ -31,6 +40,50 @@ function logAlertErrorSending(errorMessage: string) {
         \nI'll look into this issue as soon as I can!");
 }
 
+// The server can ask for a proof of work with each entry (see 
+// srv_io/bot_check.rs): a nonce where the SHA-256 of "token:nonce" 
+// starts with `difficulty` zero bits. If it isn't asking, there's 
+// no challenge route, so this just comes back undefined.
+async function getChallenge(): Promise<PowSolution | undefined> {
+
+    const resp = await fetch("/guestbook/challenge")
+        .catch(err => console.error(err));
+    if (!resp || !resp.ok) {
+        return undefined;
+    }
+    const challenge: PowChallenge = await resp.json();
+    if (typeof challenge.token !== "string") {
+        return undefined;
+    }
+    return solveChallenge(challenge);
+}
+
+async function solveChallenge(challenge: PowChallenge): Promise<PowSolution> {
+
+    for (let nonce = 0; ; nonce++) {
+        const hash = await crypto.subtle.digest(
+            "SHA-256", 
+            textEncoder.encode(`${challenge.token}:${nonce}`)
+        );
+        if (leadingZeroBits(new Uint8Array(hash)) >= challenge.difficulty) {
+            return { token: challenge.token, nonce: nonce };
+        }
+    }
+}
+
+function leadingZeroBits(hash: Uint8Array): number {
+
+    let zeros = 0;
+    for (const byte of hash) {
+        if (byte === 0) {
+            zeros += 8;
+        } else {
+            return zeros + Math.clz32(byte) - 24;
+        }
+    }
+    return zeros;
+}
+
 function entryIsTooLong(newEntry: GuestbookEntry): boolean {
     // measures byte-length of data, courtesy of this SO post:
     // https://stackoverflow.com/questions/5515869/string-length-in-bytes-in-javascript
@@ -96,6 +149,19 @@ export default function GuestbookApp() {
     // is what carries the component state)
     const latestEntryId: React.RefObject<string> = useRef('');
     
+    // the emoji visitors can react with; none if the server has them off
+    const [reactionEmoji, setReactionEmoji]: [Array<string>, Function] = useState([]);
+
+    useEffect(
+        () => {
+            fetch("/guestbook/reactions")
+            .then(resp => resp.ok ? resp.json() : { emoji: [] })
+            .then((options: ReactionOptions) => setReactionEmoji(options.emoji))
+            .catch(err => console.error(err));
+        },
+        []
+    );
+
     useEffect(
         () => {
             console.debug("Guestbook refreshed from server.");
@@ -114,6 +180,71 @@ export default function GuestbookApp() {
         if (guestbook === emptyGuestbook) {
             return <h2><i>(unable to fetch other entries)</i></h2>
         }
+        // one button per emoji, with how many times it's been left; the
+        // server only counts each one once per visitor, so clicking again is harmless
+        function EntryReactions({ entry }: { entry: GuestbookEntry }) {
+            if (!reactionEmoji.length || !entry.id) {
+                return null;
+            }
+            function react(emoji: string) {
+                fetch(`/guestbook/entries/${entry.id}/reactions`, {
+                    method: "POST",
+                    headers: { "Content-Type": "application/json" },
+                    body: JSON.stringify({ emoji })
+                })
+                .then(async resp => {
+                    if (!resp.ok) {
+                        console.error(`Couldn't react to entry ${entry.id}: ${resp.status} ${await resp.text()}`);
+                        return;
+                    }
+                    const receipt: ReactionReceipt = await resp.json();
+                    entry.reactions = receipt.reactions;
+                    setGuestbook({ guestbook: guestbook.guestbook });
+                })
+                .catch(err => console.error(err));
+            }
+            return (
+                <p className="entry entry-reactions" data-testid={"entry-reactions"+entry.id}>
+                    {reactionEmoji.map(
+                        (emoji: string) => {
+                            const count = entry.reactions?.find(reaction => reaction.emoji === emoji)?.count;
+                            return (
+                                <button key={emoji} type="button" className="reaction" onClick={() => react(emoji)}>
+                                    {emoji}{count ? ` ${count}` : ""}
+                                </button>
+                            );
+                        }
+                    )}
+                </p>
+            );
+        }
+        // the server nests replies under what they reply to, oldest first
+        function EntryReplies({ replies }: { replies?: Array<GuestbookEntry> }) {
+            if (!replies?.length) {
+                return null;
+            }
+            return (
+                <div className="entry-replies">
+                    {replies.map(
+                        (reply: GuestbookEntry) => (
+                            <div key={reply.id} className="guestbook-entry entry-reply">
+                                <p className="entry entry-time" data-testid={"entry-time"+reply.id}>
+                                    {getDate(reply).toLocaleString("en-US", timeOptions)}
+                                </p>
+                                <p className="entry guest-note" data-testid={"entry-note"+reply.id}>
+                                    {reply.note}
+                                </p>
+                                <p className="entry guest-name" data-testid={"entry-name"+reply.id}>
+                                    — {reply.name}
+                                </p>
+                                <EntryReactions entry={reply} />
+                                <EntryReplies replies={reply.replies} />
+                            </div>
+                        )
+                    )}
+                </div>
+            );
+        }
         const allEntries = guestbook.guestbook.map(
             (entry: GuestbookEntry) => {
                 const entryDate: Date = getDate(entry);
@@ -135,6 +266,8 @@ export default function GuestbookApp() {
                             <p className="entry guest-name" data-testid={"entry-name"+entry.id}>
                                 — {entry.name}
                             </p>
+                            <EntryReactions entry={entry} />
+                            <EntryReplies replies={entry.replies} />
                         </div>
                     </div>
                 );
@@ -170,16 +303,34 @@ export default function GuestbookApp() {
             const tooLong = entryIsTooLong(newEntry);
 
             if (!tooLong) {
-                fetch("/guestbook/entries", 
-                    {
-                        method: 'POST',
-                        headers: {
-                            'Content-Type': 'application/json'
-                        },
-                        body: JSON.stringify(newEntry),
-                        credentials: "same-origin"          // for local testing
+                getChallenge()
+                    .then(challenge => {
+                        const newPost: GuestbookPost = {
+                            ...newEntry,
+                            challenge: challenge,
+                            // the honeypot; people can't see it, so it should be empty
+                            website: formData.get("website") as string,
+                        };
+                        return fetch("/guestbook/entries", 
+                            {
+                                method: 'POST',
+                                headers: {
+                                    'Content-Type': 'application/json'
+                                },
+                                body: JSON.stringify(newPost),
+                                credentials: "same-origin"          // for local testing
+                            });
                     })
-                    .then(resp => {
+                    .then(async resp => {
+                        // turned away as a bot (a BotCheckFailed), so there's a message for them
+                        if (resp.status === 400) {
+                            const failed = await resp.json().catch(() => undefined);
+                            if (failed?.message) {
+                                console.error(`POST to server failed. Error status: 400 ${failed.error}`);
+                                alert(`Your entry couldn't be sent, sorry. ${failed.message}`);
+                                return undefined;
+                            }
+                        }
                         if (!resp.ok) {
                             throw new Error(
                                 `Error status: \
@@ -189,7 +340,17 @@ export default function GuestbookApp() {
                             return resp.json()
                         }
                     })
-                    .then((newIdJson: EntryReceipt) => { latestEntryId.current = newIdJson.id; })
+                    .then((newIdJson?: EntryReceipt) => { 
+                        if (!newIdJson) {
+                            return;
+                        }
+                        latestEntryId.current = newIdJson.id; 
+                        // it's only shown here until the page is reloaded, 
+                        // so they should know it isn't up for everyone yet
+                        if (newIdJson.status === "pending") {
+                            alert("Thanks! Your entry will show up for everyone once it's been approved.");
+                        }
+                    })
                     .catch(err => { logAlertErrorSending(`POST to server failed. ${err}`) });
                 
                 // THE BIG FIX: this Guestbook object needs to be set by passing 
@@ -237,6 +398,16 @@ export default function GuestbookApp() {
                     >
                     Note size (in bytes): {charCount} / 1000
                 </p>
+                {/* the honeypot; only bots fill in fields nobody can see */}
+                <p className="website-field" aria-hidden="true">
+                    <label>Leave this empty</label>
+                    <input 
+                        type="text" 
+                        name="website" 
+                        tabIndex={-1}
+                        autoComplete="off"
+                    />
+                </p>
                 <div className="buttons">
                     <button type="submit">Submit</button>
                 </div>
@@ -1,35 +1,15 @@
 import React from 'react';
-import { useState, useRef, useEffect } from 'react';
-import { type WebpageHit } from '../server-types.ts';
+import { useState, useEffect } from 'react';
 
 export default function HitCounter() {
 
-    const hit: WebpageHit = {
-        timeStamp: new Date().toISOString().slice(0, -1),  // shave off Z
-        userAgent: navigator.userAgent
-    };
-
-    const postOptions = {
-        method: 'POST',
-        headers: {
-        'Content-Type': 'application/json'
-        },
-        body: JSON.stringify(hit),
-    };
-
-    const hitPosted: React.RefObject<boolean> = useRef(false);
+    // the visit itself is logged by the server, as it sends this page,
+    // so all that's left to do here is get the count
 
     const [hits, setHits]: [number, Function] = useState(0);
-    // for display purposes, we only care if the GET fails
-    // POST errors are simply logged, on client and server
     const [getErrorOccured, setGetErrorOccured]: [boolean, Function] = useState(false);
 
     useEffect(() => {
-            if (!hitPosted.current && !getErrorOccured) {
-
-                postHit().catch(error => console.error(error));
-                hitPosted.current = true;
-            }
             getHits()
                 .then((hitCount: number) => { 
                     if (hitCount > hits) {
@@ -40,16 +20,6 @@ export default function HitCounter() {
         [hits]
     )
 
-    async function postHit(): Promise<void | Error> {
-        return fetch(window.location.origin+"/hits", postOptions)
-            .then(resp => {
-                if (!resp.ok) throw new Error(
-                    `POST hit to /hits failed with status code ${resp.status}`
-                );
-            })
-            .then(_ => console.debug("Webpage hit posted."))
-    }
-
     async function getHits(): Promise<number> {
         return fetch(window.location.origin+"/hits")
             .then((resp) => { 
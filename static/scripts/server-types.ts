@@ -1,13 +1,93 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type EntryReceipt = { timeStamp: string, id: string, };
+export type AdminLogin = { password: string, };
+
+export type AdminSession = { expiresAt: string, };
+
+export type AuditAction = "login" | "login_failed" | "login_throttled" | "logout" | "approve" | "reject" | "delete" | "restore" | "edit" | "pin" | "unpin" | "reply";
+
+export type AuditEvent = { timeStamp: string, action: AuditAction, clientIp: string, entryId: string | null, detail: string | null, };
+
+export type AuditTrail = { events: Array<AuditEvent>, };
+
+export type AuthorEditError = "missingToken" | "invalidToken" | "expiredToken" | "noSuchEntry" | "nothingToChange" | "nameTooLong" | "noteTooLong";
+
+export type AuthorEditFailed = { error: AuthorEditError, message: string, };
+
+export type BotCheckError = "missingChallenge" | "unknownChallenge" | "reusedChallenge" | "expiredChallenge" | "wrongSolution" | "honeypot";
+
+export type BotCheckFailed = { error: BotCheckError, message: string, };
+
+export type BucketHits = { start: string, hits: number, };
+
+export type CountryHits = { country: string, hits: number, };
+
+export type EntryChanges = { name?: string, note?: string, };
+
+export type EntryEdit = { editTime: string, oldName: string, oldNote: string, };
+
+export type EntryHistory = { id: string, edits: Array<EntryEdit>, };
+
+export type EntryReceipt = { timeStamp: string, id: string, status: EntryStatus, editToken?: string, editableUntil?: string, };
+
+export type EntryStatus = "pending" | "approved" | "rejected";
 
 export type Guestbook = { guestbook: Array<GuestbookEntry>, };
 
-export type GuestbookEntry = { id?: string, timeStamp?: string, name: string, note: string, };
+export type GuestbookEntry = { id?: string, timeStamp?: string, name: string, note: string, pinned?: boolean, spam?: SpamDecision, parentId?: string, replies?: Array<GuestbookEntry>, reactions?: Array<ReactionCount>, };
+
+export type GuestbookPage = { entries: Array<GuestbookEntry>, nextCursor: string | null, };
+
+export type GuestbookPost = { challenge?: PowSolution, website?: string, id?: string, timeStamp?: string, name: string, note: string, pinned?: boolean, spam?: SpamDecision, parentId?: string, replies?: Array<GuestbookEntry>, reactions?: Array<ReactionCount>, };
+
+export type GuestbookSearch = { terms: Array<string>, results: Array<SearchResult>, };
+
+export type HitStats = { from: string, to: string, bucket: StatsBucket, total: number, series: Array<BucketHits>, topUserAgents: Array<UserAgentHits>, firstVisit: string | null, lastVisit: string | null, };
 
 export type ListInfo = { listName: string, authorUser: string, attrs: Array<string>, };
 
 export type ListRow = { totalRows: number, rowData: string, };
 
-export type WebpageHit = { timeStamp: string, userAgent: string, };
+export type ModerationReceipt = { id: string, status: EntryStatus, };
+
+export type NewReaction = { emoji: string, };
+
+export type PageHits = { path: string, hits: number, };
+
+export type PowChallenge = { token: string, difficulty: number, expiresAt: string, };
+
+export type PowSolution = { token: string, nonce: number, };
+
+export type RateLimited = { message: string, retryAfter: number, };
+
+export type ReactionCount = { emoji: string, count: number, };
+
+export type ReactionOptions = { emoji: Array<string>, };
+
+export type ReactionReceipt = { id: string, counted: boolean, reactions: Array<ReactionCount>, };
+
+export type ReferrerHits = { referrer: string, hits: number, };
+
+export type SearchResult = { entry: GuestbookEntry, name: Array<TextPart>, snippet: Array<TextPart>, score: number, };
+
+export type SortOrder = "asc" | "desc";
+
+export type SpamDecision = { verdict: SpamVerdict, reason?: string, };
+
+export type SpamVerdict = "accept" | "hold" | "drop";
+
+export type StatsBucket = "hour" | "day" | "week";
+
+export type TextPart = { text: string, highlight: boolean, };
+
+export type TopCountries = { from: string, to: string, countries: Array<CountryHits>, };
+
+export type TopPages = { from: string, to: string, pages: Array<PageHits>, };
+
+export type TopReferrers = { from: string, to: string, referrers: Array<ReferrerHits>, };
+
+export type UniqueVisitors = { today: number, thisMonth: number, };
+
+export type UserAgentHits = { userAgent: string, hits: number, };
+
+export type WebpageHit = { timeStamp: string, userAgent: string, path?: string, referrer?: string, utmSource?: string, utmMedium?: string, utmCampaign?: string, };
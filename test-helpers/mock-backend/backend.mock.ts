@@ -5,7 +5,9 @@ import {
     type GuestbookEntry, 
     type Guestbook, 
     type ListRow, 
-    type EntryReceipt 
+    type EntryReceipt, 
+    type GuestbookPost, 
+    type PowChallenge 
 } from '../../static/scripts/server-types.ts';
 
 
@@ -51,13 +53,6 @@ const mocks: MockHandler[] = [
             res.end('8002934')
         }
     },
-    {
-        pattern: "/hits", 
-        method: 'POST',
-        handle: (req, res) => {
-            res.end("Thanks for stopping by!\n");
-        }
-    },
     {
         pattern: "/guestbook", 
         method: 'GET',
@@ -74,12 +69,35 @@ const mocks: MockHandler[] = [
             res.end(JSON.stringify(guestbookDb)); 
         }
     },
+    {
+        pattern: "/guestbook/challenge", 
+        method: 'GET',
+        handle: (_req, res) => {
+            // easy, so it doesn't slow anyone down; the answer isn't checked anyway
+            const challenge: PowChallenge = {
+                token: Math.random().toString(16).slice(2),
+                difficulty: 4,
+                expiresAt: (new Date(Date.now() + 300_000)).toISOString().slice(0,-1)
+            };
+            res.end(JSON.stringify(challenge));
+        }
+    },
     {
         pattern: "/guestbook/entries", 
         method: 'POST',
         handle: (req, res) => {
 
-            var newEntry: GuestbookEntry = req.body;
+            const { challenge, website, ...entry }: GuestbookPost = req.body;
+            var newEntry: GuestbookEntry = entry;
+
+            if (!challenge || website) {
+                res.statusCode = 400;
+                res.end(JSON.stringify({
+                    error: website ? "honeypot" : "missingChallenge",
+                    message: website ? "This entry looks like it was sent by a bot." : "This entry needs a solved challenge from /guestbook/challenge."
+                }));
+                return;
+            }
 
             // from this SO post: https://stackoverflow.com/questions/5515869/string-length-in-bytes-in-javascript
             // For measuring the length in bytes
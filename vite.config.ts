@@ -68,6 +68,7 @@ export default defineConfig({
         resolve(__dirname, 'static/errors/403.html'),
         resolve(__dirname, 'static/errors/404.html'),
         resolve(__dirname, 'static/errors/500.html'),
+        resolve(__dirname, 'static/errors/503.html'),
       ],
     },
   },
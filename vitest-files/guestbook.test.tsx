@@ -71,7 +71,8 @@ vi.spyOn(global, 'fetch')
 
             const reciept: EntryReceipt = {
                 timeStamp: newEntryTimeStamp, 
-                id: newEntryId
+                id: newEntryId,
+                status: "approved"
             };
 
             return Promise.resolve(